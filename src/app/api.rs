@@ -0,0 +1,63 @@
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+
+use crate::blog::types::StoreError;
+
+/// Every JSON API response is wrapped in this envelope
+///
+/// `code` mirrors the HTTP status so clients can handle errors without looking at headers
+#[derive(Serialize)]
+pub struct ApiResponse<T> {
+	pub ok: bool,
+	pub data: Option<T>,
+	pub error: Option<String>,
+	pub code: u16,
+}
+
+impl<T: serde::Serialize> ApiResponse<T> {
+	/// The envelope for a successful response, use this when the response needs extra headers or cookies
+	pub fn success(data: T) -> ApiResponse<T> {
+		ApiResponse { ok: true, data: Some(data), error: None, code: 200 }
+	}
+}
+
+
+/// Successful response carrying the given data
+pub fn api_ok<T: serde::Serialize>(data: T) -> HttpResponse {
+	HttpResponse::Ok().json(ApiResponse::success(data))
+}
+
+/// Failed response with the given status and message
+pub fn api_error(status: StatusCode, error: &str) -> HttpResponse {
+	HttpResponse::build(status).json(ApiResponse::<()> { ok: false, data: None, error: Some(String::from(error)), code: status.as_u16() })
+}
+
+/// The caller is not logged in or lacks the required permission
+pub fn api_unauthorized() -> HttpResponse {
+	api_error(StatusCode::UNAUTHORIZED, "unauthorized")
+}
+
+/// Wrap an optional value, a missing value is reported with the given status
+pub fn api_option<T: serde::Serialize>(data: Option<T>, status: StatusCode, error: &str) -> HttpResponse {
+	match data {
+		Some(tmp) => { api_ok(tmp) }
+		_ => { api_error(status, error) }
+	}
+}
+
+/// Wrap a result, errors are reported as internal server errors
+pub fn api_result<T: serde::Serialize>(res: Result<T, String>) -> HttpResponse {
+	match res {
+		Ok(tmp) => { api_ok(tmp) }
+		Err(err) => { api_error(StatusCode::INTERNAL_SERVER_ERROR, &err) }
+	}
+}
+
+/// Wrap the result of storing an item, conflicting edits are reported as such
+pub fn api_store_result<T: serde::Serialize>(res: Result<T, StoreError>) -> HttpResponse {
+	match res {
+		Ok(tmp) => { api_ok(tmp) }
+		Err(StoreError::Conflict) => { api_error(StatusCode::CONFLICT, "the item was changed by someone else, reload it before saving") }
+		Err(StoreError::Database(err)) => { api_error(StatusCode::INTERNAL_SERVER_ERROR, &err) }
+	}
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+use actix_web::{Error, HttpRequest, HttpResponse, http, web};
+use actix_web::dev::RequestHead;
+use sha2::{Digest, Sha256};
+
+/// Characters of the content hash that go into a file name
+const FINGERPRINT_LENGTH: usize = 10;
+
+/// Fingerprinted files never change, a new version has a new name
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+lazy_static! {
+	static ref ASSETS: RwLock<Assets> = RwLock::new(Assets::default());
+}
+
+#[derive(Default)]
+struct Assets {
+	/// The static dir the files were hashed in
+	dir: String,
+	/// `css/site.css` to `css/site.3f2a1c9b0d.css`
+	fingerprinted: HashMap<String, String>,
+	/// The other way round, for serving them
+	logical: HashMap<String, String>,
+}
+
+
+// ------------------------------
+// ----------- ASSETS -----------
+// ------------------------------
+
+/// Hash every file in the static dir, replacing what was hashed before
+///
+/// Returns the number of files
+pub fn assets_load(dir: &str) -> Result<usize, String> {
+	let mut assets = Assets { dir: String::from(dir), ..Assets::default() };
+	collect_assets(Path::new(dir), "", &mut assets)?;
+
+	let count = assets.fingerprinted.len();
+	match ASSETS.write() {
+		Ok(mut guard) => { *guard = assets; }
+		Err(err) => { return Err(err.to_string()); }
+	}
+
+	Ok(count)
+}
+
+/// The fingerprinted URL of a file in the static dir, e.g. `css/site.css`
+///
+/// Files that were not there at startup get their plain `/static` URL
+pub fn asset_url(name: &str) -> String {
+	let name = name.trim_start_matches('/');
+	match ASSETS.read() {
+		Ok(guard) => {
+			match guard.fingerprinted.get(name) {
+				Some(tmp) => { return format!("/static/{}", tmp); }
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	format!("/static/{}", name)
+}
+
+/// Route guard, true for fingerprinted paths below `/static`
+///
+/// Everything else falls through to the plain static files
+pub fn is_fingerprinted(head: &RequestHead) -> bool {
+	let path = head.uri.path().trim_start_matches("/static/");
+	match ASSETS.read() {
+		Ok(guard) => guard.logical.contains_key(path),
+		_ => false
+	}
+}
+
+/// Route: a fingerprinted static file, cached for good
+pub async fn asset(path: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let local_path = match ASSETS.read() {
+		Ok(guard) => guard.logical.get(path.as_str()).map(|name| format!("{}/{}", guard.dir, name)),
+		_ => None
+	};
+
+	let local_path = match local_path {
+		Some(tmp) => tmp,
+		_ => { return Ok(HttpResponse::NotFound().finish()); }
+	};
+
+	let mut res = actix_files::NamedFile::open(local_path)?.use_etag(true).into_response(&req)?;
+	res.headers_mut().insert(http::header::CACHE_CONTROL, http::HeaderValue::from_static(IMMUTABLE));
+	Ok(res)
+}
+
+fn collect_assets(dir: &Path, prefix: &str, assets: &mut Assets) -> Result<(), String> {
+	let entries = fs::read_dir(dir).map_err(|err| format!("Could not read {}: {}", dir.to_string_lossy(), err))?;
+
+	for entry in entries.filter_map(|tmp| tmp.ok()) {
+		let file_name = entry.file_name().to_string_lossy().to_string();
+		let name = if prefix.len() == 0 { file_name.clone() } else { format!("{}/{}", prefix, file_name) };
+		let path = entry.path();
+
+		if path.is_dir() {
+			collect_assets(&path, &name, assets)?;
+			continue;
+		}
+
+		let data = match fs::read(&path) {
+			Ok(tmp) => tmp,
+			Err(err) => {
+				println!("Could not fingerprint {}: {}", name, err);
+				continue;
+			}
+		};
+		let hash = format!("{:x}", Sha256::digest(&data));
+		let fingerprint = &hash[..FINGERPRINT_LENGTH];
+
+		// `site.css` becomes `site.<fingerprint>.css`, names without an extension get it at the end
+		let fingerprinted = match name.rfind('.') {
+			Some(pos) if pos > name.rfind('/').map_or(0, |tmp| tmp + 1) => format!("{}.{}{}", &name[..pos], fingerprint, &name[pos..]),
+			_ => format!("{}.{}", name, fingerprint)
+		};
+
+		assets.logical.insert(fingerprinted.clone(), name.clone());
+		assets.fingerprinted.insert(name, fingerprinted);
+	}
+
+	Ok(())
+}
@@ -0,0 +1,91 @@
+use crate::app::config::config_get_string;
+use crate::app::utils::curl_post_json;
+
+/// Cloudflare takes at most this many URLs per purge request
+const CLOUDFLARE_URLS_PER_PURGE: usize = 30;
+
+/// Purges should not hold up a blocking thread for long
+const CDN_TIMEOUT_SECONDS: u64 = 10;
+
+
+// ------------------------------
+// ------------ PURGE -----------
+// ------------------------------
+
+/// The CDN in front of the blog, from `cdn_provider`
+///
+/// Cloudflare needs `cdn_zone_id` and `cdn_api_token`, Fastly needs `cdn_service_id` and `cdn_api_token`
+enum CdnProvider {
+	Cloudflare { zone_id: String, token: String },
+	Fastly { service_id: String, token: String },
+}
+
+fn cdn_provider() -> Option<CdnProvider> {
+	let token = config_get_string("cdn_api_token");
+
+	match config_get_string("cdn_provider").to_lowercase().as_str() {
+		"cloudflare" => Some(CdnProvider::Cloudflare { zone_id: config_get_string("cdn_zone_id"), token }),
+		"fastly" => Some(CdnProvider::Fastly { service_id: config_get_string("cdn_service_id"), token }),
+		_ => None
+	}
+}
+
+/// Returns true if a CDN is configured, purging does nothing otherwise
+pub fn cdn_enabled() -> bool {
+	cdn_provider().is_some()
+}
+
+/// Purge some pages, paths like `/my-post` are turned into URLs on our `fqdn`
+pub fn cdn_purge_paths(paths: &[String]) -> Result<(), String> {
+	let fqdn = config_get_string("fqdn");
+	let urls: Vec<String> = paths.iter().map(|path| format!("https://{}{}", fqdn, path)).collect();
+
+	match cdn_provider() {
+		Some(CdnProvider::Cloudflare { zone_id, token }) => {
+			for chunk in urls.chunks(CLOUDFLARE_URLS_PER_PURGE) {
+				cloudflare_purge(&zone_id, &token, json!({ "files": chunk }))?;
+			}
+			Ok(())
+		}
+		Some(CdnProvider::Fastly { service_id: _, token }) => {
+			// Fastly purges one URL at a time, by host and path
+			for path in paths {
+				fastly_purge(&format!("https://api.fastly.com/purge/{}{}", fqdn, path), &token)?;
+			}
+			Ok(())
+		}
+		_ => Ok(())
+	}
+}
+
+/// Purge everything the CDN cached for the blog
+pub fn cdn_purge_all() -> Result<(), String> {
+	match cdn_provider() {
+		Some(CdnProvider::Cloudflare { zone_id, token }) => {
+			cloudflare_purge(&zone_id, &token, json!({ "purge_everything": true }))
+		}
+		Some(CdnProvider::Fastly { service_id, token }) => {
+			fastly_purge(&format!("https://api.fastly.com/service/{}/purge_all", service_id), &token)
+		}
+		_ => Ok(())
+	}
+}
+
+fn cloudflare_purge(zone_id: &str, token: &str, body: serde_json::Value) -> Result<(), String> {
+	let url = format!("https://api.cloudflare.com/client/v4/zones/{}/purge_cache", zone_id);
+	let headers = vec![format!("Authorization: Bearer {}", token)];
+
+	match curl_post_json(&url, &body.to_string(), &headers, CDN_TIMEOUT_SECONDS)? {
+		200 => Ok(()),
+		code => Err(format!("Cloudflare purge failed with HTTP status {}", code))
+	}
+}
+
+fn fastly_purge(url: &str, token: &str) -> Result<(), String> {
+	let headers = vec![format!("Fastly-Key: {}", token)];
+
+	match curl_post_json(url, "", &headers, CDN_TIMEOUT_SECONDS)? {
+		200 => Ok(()),
+		code => Err(format!("Fastly purge failed with HTTP status {}", code))
+	}
+}
@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+
+use crate::app::config::{config_get_bool, config_get_string_vec};
+
+
+/// Find the address of the actual client
+///
+/// Proxy headers are only honored if the connection comes from one of the `trusted_proxies`,
+/// otherwise anybody could claim any address. `CF-Connecting-IP` is only used with `cloudflare_client_ip`
+/// set, when the proxies only take requests from Cloudflare. Connections over a unix socket have no address,
+/// only the local proxy can reach that socket, so they are always trusted
+pub fn get_client_ip(req: &HttpRequest) -> String {
+	let peer = req.peer_addr().map(|addr| addr.ip());
+
+	let trusted = config_get_string_vec("trusted_proxies");
+	match &peer {
+		Some(ip) if !is_trusted_proxy(ip, &trusted) => { return ip.to_string(); }
+		_ => {}
+	}
+
+	// Cloudflare tells us directly, but a plain proxy passes the header on as the visitor sent it
+	if config_get_bool("cloudflare_client_ip") {
+		match header_ip(req, "CF-Connecting-IP") {
+			Some(ip) => return ip.to_string(),
+			_ => {}
+		}
+	}
+
+	// Every proxy appends the address it got the request from, walk back until we leave our proxies
+	match req.headers().get("X-Forwarded-For").and_then(|val| val.to_str().ok()) {
+		Some(forwarded) => {
+			for hop in forwarded.rsplit(',') {
+				match hop.trim().parse::<IpAddr>() {
+					Ok(ip) => {
+						if !is_trusted_proxy(&ip, &trusted) { return ip.to_string(); }
+					}
+					_ => break
+				}
+			}
+		}
+		_ => {}
+	}
+
+	match peer {
+		Some(ip) => ip.to_string(),
+		_ => String::from("")
+	}
+}
+
+/// Returns true if the connection comes from one of the `trusted_proxies` or over the unix socket, their headers can be believed
+pub fn is_from_trusted_proxy(req: &HttpRequest) -> bool {
+	match req.peer_addr() {
+		Some(addr) => is_trusted_proxy(&addr.ip(), &config_get_string_vec("trusted_proxies")),
+		_ => true
+	}
+}
+
+/// Read a header holding a single address
+fn header_ip(req: &HttpRequest, name: &str) -> Option<IpAddr> {
+	req.headers().get(name)?.to_str().ok()?.trim().parse::<IpAddr>().ok()
+}
+
+/// Check an address against a list of addresses and CIDR ranges like `10.0.0.0/8`
+fn is_trusted_proxy(ip: &IpAddr, trusted: &Vec<String>) -> bool {
+	trusted.iter().any(|entry| ip_in_network(ip, entry))
+}
+
+/// Check an address against a single address or CIDR range, invalid entries never match
+pub fn ip_in_network(ip: &IpAddr, entry: &str) -> bool {
+	let mut parts = entry.splitn(2, '/');
+	let network = match parts.next().and_then(|tmp| tmp.trim().parse::<IpAddr>().ok()) {
+		Some(tmp) => tmp,
+		_ => return false
+	};
+	let prefix = match parts.next() {
+		Some(tmp) => match tmp.trim().parse::<u32>() {
+			Ok(prefix) => prefix,
+			_ => return false
+		},
+		_ => if network.is_ipv4() { 32 } else { 128 }
+	};
+
+	match (ip, network) {
+		(IpAddr::V4(ip), IpAddr::V4(network)) => {
+			prefix <= 32 && mask_matches(u32::from(*ip) as u128, u32::from(network) as u128, prefix, 32)
+		}
+		(IpAddr::V6(ip), IpAddr::V6(network)) => {
+			prefix <= 128 && mask_matches(u128::from(*ip), u128::from(network), prefix, 128)
+		}
+		_ => false
+	}
+}
+
+/// Compare the first `prefix` bits of two addresses with the given width
+fn mask_matches(ip: u128, network: u128, prefix: u32, width: u32) -> bool {
+	if prefix == 0 { return true; }
+	let shift = width - prefix;
+	(ip >> shift) == (network >> shift)
+}
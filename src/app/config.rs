@@ -1,15 +1,48 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
 use std::error::Error;
 use std::sync::RwLock;
 
-use config::Config;
+use config::{Config, Source};
+
+use crate::app::settings::Settings;
 
 lazy_static! {
 	static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
 }
 
+/// Prefix of environment variables that override config keys, e.g. `BLOG__SERVER_DATABASE`
+const ENV_PREFIX: &str = "BLOG__";
+
+/// Keys ending in this are paths to a file holding the value of the key without it, e.g. `jwt_hmac_secret_file`
+const FILE_SUFFIX: &str = "_file";
+
+
 /// Load the configuration from a file
+///
+/// Environment variables are applied on top, then secrets are read from their files
 pub fn config_load_from_file() -> Result<(), Box<dyn Error>> {
-	CONFIG.write()?.merge(config::File::with_name("config"))?;
+	let mut guard = CONFIG.write()?;
+	guard.merge(config::File::with_name("config"))?;
+
+	// Environment overrides, a double underscore separates nested keys
+	for (name, value) in env::vars() {
+		if !name.starts_with(ENV_PREFIX) { continue; }
+
+		let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+		guard.set(&key, value)?;
+	}
+
+	// Secrets mounted as files, so they do not have to be in the config file
+	let keys: Vec<String> = guard.collect()?.keys().filter(|key| key.ends_with(FILE_SUFFIX)).cloned().collect();
+	for key in keys {
+		let path = guard.get_str(&key)?;
+		let secret = fs::read_to_string(&path).map_err(|err| format!("Can not read '{}' for '{}': {}", path, key, err))?;
+
+		guard.set(&key[..key.len() - FILE_SUFFIX.len()], secret.trim_end_matches(['\n', '\r']).to_string())?;
+	}
+
 	Ok(())
 }
 
@@ -45,4 +78,62 @@ pub fn config_get_i64(k: &str) -> i64 {
 	}
 
 	0
-}
\ No newline at end of file
+}
+
+/// Retrieve a boolean from the config, missing keys are false
+pub fn config_get_bool(k: &str) -> bool {
+	match CONFIG.read() {
+		Ok(guard) => {
+			match guard.get_bool(k) {
+				Ok(tmp) => {
+					return tmp;
+				}
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	false
+}
+
+/// Retrieve a table of strings from the config, missing keys give an empty map
+pub fn config_get_string_map(k: &str) -> HashMap<String, String> {
+	match CONFIG.read() {
+		Ok(guard) => {
+			match guard.get::<HashMap<String, String>>(k) {
+				Ok(tmp) => {
+					return tmp;
+				}
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	HashMap::new()
+}
+/// Retrieve a list of strings from the config, missing keys give an empty list
+pub fn config_get_string_vec(k: &str) -> Vec<String> {
+	match CONFIG.read() {
+		Ok(guard) => {
+			match guard.get::<Vec<String>>(k) {
+				Ok(tmp) => {
+					return tmp;
+				}
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	Vec::new()
+}
+
+/// Deserialize the whole config into the typed settings
+pub fn config_get_settings() -> Result<Settings, String> {
+	match CONFIG.read() {
+		Ok(guard) => guard.clone().try_into::<Settings>().map_err(|err| format!("The configuration cannot be read: {}", err)),
+		_ => Err(String::from("The configuration is not available"))
+	}
+}
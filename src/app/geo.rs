@@ -0,0 +1,30 @@
+use actix_web::HttpRequest;
+
+use crate::app::client_ip::is_from_trusted_proxy;
+use crate::app::config::config_get_string;
+
+/// Header used when `geo_country_header` is not configured, Cloudflare sets it
+const GEO_COUNTRY_HEADER: &str = "CF-IPCountry";
+
+
+/// The country of the client as an upper case ISO code like `DE`, looked up by the CDN in front of us
+///
+/// Like the client address this is only believed from `trusted_proxies`. Fastly or a proxy with a
+/// GeoIP database can send the country in any header, see `geo_country_header`
+pub fn get_client_country(req: &HttpRequest) -> Option<String> {
+	if !is_from_trusted_proxy(req) { return None; }
+
+	let header = match config_get_string("geo_country_header") {
+		tmp if tmp.len() > 0 => tmp,
+		_ => String::from(GEO_COUNTRY_HEADER)
+	};
+
+	let country = req.headers().get(header.as_str())?.to_str().ok()?.trim().to_uppercase();
+
+	// Cloudflare uses XX for unknown and T1 for Tor
+	match country.as_str() {
+		"XX" | "T1" => None,
+		tmp if tmp.len() == 2 && tmp.chars().all(|c| c.is_ascii_alphabetic()) => Some(country),
+		_ => None
+	}
+}
@@ -0,0 +1,168 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_service::{Service, Transform};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use futures::Stream;
+use futures::future::{Either, Future, ok, Ready};
+use tokio::time::{delay_for, Delay};
+
+use crate::app::settings::settings;
+
+/// Time to send the request head, actix closes the connection after that
+const DEFAULT_CLIENT_TIMEOUT_MS: u64 = 5000;
+
+/// Time to close a connection cleanly once the response is sent
+const DEFAULT_CLIENT_SHUTDOWN_MS: u64 = 5000;
+
+/// Time to send the whole request body
+const DEFAULT_BODY_TIMEOUT_MS: u64 = 60000;
+
+/// Largest request body, uploads included
+const DEFAULT_BODY_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Open connections per worker
+const DEFAULT_MAX_CONNECTIONS: usize = 25000;
+
+/// Connections per worker that may be in their TLS handshake at the same time
+const DEFAULT_MAX_CONNECTION_RATE: usize = 256;
+
+
+// ------------------------------
+// ----------- LIMITS -----------
+// ------------------------------
+
+/// Milliseconds a client has for the request head, `server_client_timeout`
+pub fn client_timeout() -> u64 {
+	positive_or(settings().server_client_timeout, DEFAULT_CLIENT_TIMEOUT_MS)
+}
+
+/// Milliseconds a client has to close the connection, `server_client_shutdown`
+pub fn client_shutdown() -> u64 {
+	positive_or(settings().server_client_shutdown, DEFAULT_CLIENT_SHUTDOWN_MS)
+}
+
+/// Open connections per worker, `server_max_connections`
+pub fn max_connections() -> usize {
+	positive_or(settings().server_max_connections, DEFAULT_MAX_CONNECTIONS as u64) as usize
+}
+
+/// TLS handshakes per worker at the same time, `server_max_connection_rate`
+pub fn max_connection_rate() -> usize {
+	positive_or(settings().server_max_connection_rate, DEFAULT_MAX_CONNECTION_RATE as u64) as usize
+}
+
+fn positive_or(value: i64, default: u64) -> u64 {
+	if value > 0 { value as u64 } else { default }
+}
+
+
+/// Middleware that keeps slow or oversized request bodies from holding on to a connection
+///
+/// The body has to arrive within `server_body_timeout` milliseconds and may be at most
+/// `server_body_limit` bytes, a declared length above that is refused right away
+pub struct RequestLimits;
+
+impl<S, B> Transform<S> for RequestLimits
+	where
+		S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+		S::Future: 'static,
+		B: 'static,
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = RequestLimitsMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		let settings = settings();
+		ok(RequestLimitsMiddleware {
+			service,
+			body_timeout: Duration::from_millis(positive_or(settings.server_body_timeout, DEFAULT_BODY_TIMEOUT_MS)),
+			body_limit: positive_or(settings.server_body_limit, DEFAULT_BODY_LIMIT),
+		})
+	}
+}
+
+pub struct RequestLimitsMiddleware<S> {
+	service: S,
+	body_timeout: Duration,
+	body_limit: u64,
+}
+
+impl<S, B> Service for RequestLimitsMiddleware<S>
+	where
+		S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+		S::Future: 'static,
+		B: 'static,
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+		let content_length = req.headers().get(header::CONTENT_LENGTH)
+			.and_then(|tmp| tmp.to_str().ok())
+			.and_then(|tmp| tmp.parse::<u64>().ok());
+
+		match content_length {
+			Some(length) if length > self.body_limit => {
+				return Either::Right(ok(req.into_response(HttpResponse::PayloadTooLarge().finish().into_body())));
+			}
+			// Nothing to wait for
+			Some(0) => { return Either::Left(self.service.call(req)); }
+			None if !req.headers().contains_key(header::TRANSFER_ENCODING) => { return Either::Left(self.service.call(req)); }
+			_ => {}
+		}
+
+		let payload = LimitedPayload {
+			payload: req.take_payload(),
+			deadline: Box::pin(delay_for(self.body_timeout)),
+			remaining: self.body_limit,
+		};
+		req.set_payload(Payload::Stream(Box::pin(payload)));
+
+		Either::Left(self.service.call(req))
+	}
+}
+
+/// A request body that fails once it takes too long or grows too large
+struct LimitedPayload {
+	payload: Payload,
+	deadline: Pin<Box<Delay>>,
+	remaining: u64,
+}
+
+impl Stream for LimitedPayload {
+	type Item = Result<Bytes, PayloadError>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		if self.deadline.as_mut().poll(cx).is_ready() {
+			return Poll::Ready(Some(Err(PayloadError::Io(io::Error::new(io::ErrorKind::TimedOut, "request body took too long")))));
+		}
+
+		match Pin::new(&mut self.payload).poll_next(cx) {
+			Poll::Ready(Some(Ok(chunk))) => {
+				if chunk.len() as u64 > self.remaining {
+					return Poll::Ready(Some(Err(PayloadError::Overflow)));
+				}
+				self.remaining -= chunk.len() as u64;
+				Poll::Ready(Some(Ok(chunk)))
+			}
+			other => other
+		}
+	}
+}
@@ -0,0 +1,30 @@
+use lettre::{SmtpClient, Transport};
+use lettre::smtp::authentication::Credentials;
+use lettre_email::EmailBuilder;
+
+use crate::app::config::config_get_string;
+
+
+/// Send a plain text email through the configured SMTP server
+pub fn send_mail(to: &str, subject: &str, body: &str) -> Result<(), String> {
+	let email = EmailBuilder::new()
+		.to(to)
+		.from(config_get_string("smtp_from"))
+		.subject(subject)
+		.text(body)
+		.build()
+		.map_err(|err| err.to_string())?;
+
+	let mut mailer = SmtpClient::new_simple(&config_get_string("smtp_host"))
+		.map_err(|err| err.to_string())?
+		.credentials(Credentials::new(config_get_string("smtp_user"), config_get_string("smtp_pass")))
+		.transport();
+
+	match mailer.send(email.into()) {
+		Ok(_) => Ok(()),
+		Err(err) => {
+			println!("Error sending mail: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
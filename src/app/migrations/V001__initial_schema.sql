@@ -0,0 +1,108 @@
+-- The schema as it was maintained by hand before migrations existed.
+-- Everything uses IF NOT EXISTS, so existing databases are simply baselined at this version.
+
+CREATE TABLE IF NOT EXISTS users (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	login VARCHAR(64) NOT NULL,
+	pass VARCHAR(255) NOT NULL,
+	salt VARCHAR(255) NOT NULL DEFAULT '',
+	sn TINYINT UNSIGNED NOT NULL DEFAULT 0,
+	sr INT UNSIGNED NOT NULL DEFAULT 0,
+	sp INT UNSIGNED NOT NULL DEFAULT 0,
+	display_name VARCHAR(128) NOT NULL,
+	home_post INT UNSIGNED NOT NULL DEFAULT 0,
+	permissions TEXT NOT NULL,
+	PRIMARY KEY (id),
+	UNIQUE KEY users_login (login)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS posts (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	author_id INT UNSIGNED NOT NULL,
+	date_posted DATETIME NOT NULL,
+	date_modified DATETIME NOT NULL,
+	state VARCHAR(32) NOT NULL DEFAULT 'draft',
+	title VARCHAR(255) NOT NULL,
+	content LONGTEXT NOT NULL,
+	meta_title VARCHAR(255) NOT NULL DEFAULT '',
+	meta_description TEXT NOT NULL,
+	meta_keywords TEXT NOT NULL,
+	url_canonical VARCHAR(255) NOT NULL,
+	url_historic TEXT NOT NULL,
+	tags TEXT NOT NULL,
+	media TEXT NOT NULL,
+	locations TEXT NOT NULL,
+	related_posts TEXT NOT NULL,
+	PRIMARY KEY (id),
+	KEY posts_state (state)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS post_views (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	post_id INT UNSIGNED NOT NULL,
+	viewed_at DATETIME NOT NULL,
+	remote_ip VARCHAR(64) NOT NULL DEFAULT '',
+	user_agent VARCHAR(512) NOT NULL DEFAULT '',
+	referer VARCHAR(1024) NOT NULL DEFAULT '',
+	PRIMARY KEY (id),
+	KEY post_views_viewed_at (viewed_at),
+	KEY post_views_post_id (post_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS post_comments (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	parent_id INT UNSIGNED NOT NULL DEFAULT 0,
+	post_id INT UNSIGNED NOT NULL,
+	status VARCHAR(16) NOT NULL DEFAULT 'new',
+	author_name VARCHAR(128) NOT NULL,
+	author_email VARCHAR(255) NOT NULL DEFAULT '',
+	date_posted DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	content TEXT NOT NULL,
+	PRIMARY KEY (id),
+	KEY post_comments_post_id (post_id),
+	KEY post_comments_status (status)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS tags (
+	id VARCHAR(128) NOT NULL,
+	title VARCHAR(255) NOT NULL,
+	content TEXT NOT NULL,
+	meta_title VARCHAR(255) NOT NULL DEFAULT '',
+	meta_description TEXT NOT NULL,
+	media TEXT NOT NULL,
+	PRIMARY KEY (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS menus (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	name VARCHAR(64) NOT NULL,
+	items TEXT NOT NULL,
+	PRIMARY KEY (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS snippets (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	name VARCHAR(64) NOT NULL,
+	replacement TEXT NOT NULL,
+	variables TEXT NOT NULL,
+	PRIMARY KEY (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS redirects (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	name VARCHAR(128) NOT NULL,
+	target VARCHAR(1024) NOT NULL,
+	PRIMARY KEY (id),
+	UNIQUE KEY redirects_name (name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS gallery (
+	guid VARCHAR(32) NOT NULL,
+	hash VARCHAR(32) NOT NULL DEFAULT '',
+	extension VARCHAR(8) NOT NULL,
+	sizeX INT UNSIGNED NOT NULL,
+	sizeY INT UNSIGNED NOT NULL,
+	uploadedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (guid),
+	KEY gallery_hash (hash)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
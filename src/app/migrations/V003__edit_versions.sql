@@ -0,0 +1,5 @@
+-- Versions reject stale admin edits of posts, comments and tags
+
+ALTER TABLE posts ADD COLUMN version INT UNSIGNED NOT NULL DEFAULT 1 AFTER pinned;
+ALTER TABLE post_comments ADD COLUMN version INT UNSIGNED NOT NULL DEFAULT 1 AFTER content;
+ALTER TABLE tags ADD COLUMN version INT UNSIGNED NOT NULL DEFAULT 1 AFTER media;
@@ -0,0 +1,9 @@
+-- Editor autosaves, one per post and user
+
+CREATE TABLE IF NOT EXISTS post_drafts (
+	post_id INT UNSIGNED NOT NULL,
+	user_id INT UNSIGNED NOT NULL,
+	saved_at DATETIME NOT NULL,
+	data LONGTEXT NOT NULL,
+	PRIMARY KEY (post_id, user_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,5 @@
+-- Focal points for cropped gallery variants, in percent of the width and height
+
+ALTER TABLE gallery
+	ADD COLUMN focal_x FLOAT NOT NULL DEFAULT 50 AFTER sizeY,
+	ADD COLUMN focal_y FLOAT NOT NULL DEFAULT 50 AFTER focal_x;
@@ -0,0 +1,7 @@
+-- Capture date, camera and location read from the EXIF data of uploads
+
+ALTER TABLE gallery
+	ADD COLUMN captured_at DATETIME NULL AFTER focal_y,
+	ADD COLUMN camera VARCHAR(128) NULL AFTER captured_at,
+	ADD COLUMN lat DOUBLE NULL AFTER camera,
+	ADD COLUMN lng DOUBLE NULL AFTER lat;
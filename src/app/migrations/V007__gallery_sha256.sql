@@ -0,0 +1,10 @@
+-- Gallery files are hashed with SHA-256, every variant on its own
+
+ALTER TABLE gallery MODIFY COLUMN hash VARCHAR(64) NOT NULL DEFAULT '';
+
+CREATE TABLE IF NOT EXISTS gallery_variants (
+	guid VARCHAR(32) NOT NULL,
+	size VARCHAR(16) NOT NULL,
+	hash VARCHAR(64) NOT NULL,
+	PRIMARY KEY (guid, size)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
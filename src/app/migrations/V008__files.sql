@@ -0,0 +1,10 @@
+-- Attachments like PDF and GPX files
+
+CREATE TABLE IF NOT EXISTS files (
+	guid VARCHAR(32) NOT NULL,
+	extension VARCHAR(8) NOT NULL,
+	name VARCHAR(255) NOT NULL,
+	size BIGINT UNSIGNED NOT NULL,
+	uploadedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (guid)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
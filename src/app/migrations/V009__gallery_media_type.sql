@@ -0,0 +1,3 @@
+-- The gallery holds video clips next to images
+
+ALTER TABLE gallery ADD COLUMN media_type VARCHAR(8) NOT NULL DEFAULT 'image' AFTER extension;
@@ -0,0 +1,3 @@
+-- Raising the serial revokes every token issued to a user
+
+ALTER TABLE users ADD COLUMN token_serial INT UNSIGNED NOT NULL DEFAULT 0 AFTER permissions;
@@ -0,0 +1,11 @@
+-- Users get password reset links by email, each token works once
+
+ALTER TABLE users ADD COLUMN email VARCHAR(255) NULL AFTER display_name;
+
+CREATE TABLE IF NOT EXISTS password_resets (
+	token_hash CHAR(64) NOT NULL,
+	user_id INT UNSIGNED NOT NULL,
+	expires_at DATETIME NOT NULL,
+	used TINYINT(1) NOT NULL DEFAULT 0,
+	PRIMARY KEY (token_hash)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,11 @@
+-- Admin actions like impersonating another user are logged
+
+CREATE TABLE IF NOT EXISTS audit_log (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	user_id INT UNSIGNED NOT NULL,
+	action VARCHAR(64) NOT NULL,
+	detail TEXT NOT NULL,
+	created_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	KEY audit_log_created_at (created_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
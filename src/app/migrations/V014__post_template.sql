@@ -0,0 +1,3 @@
+-- Posts may render with their own template instead of post.html
+
+ALTER TABLE posts ADD COLUMN template VARCHAR(128) NULL AFTER version;
@@ -0,0 +1,3 @@
+-- Structured content, a JSON list of typed blocks the HTML content is rendered from
+
+ALTER TABLE posts ADD COLUMN blocks LONGTEXT NULL AFTER custom_fields;
@@ -0,0 +1,13 @@
+-- Addresses, ranges and email domains that may not use the public forms
+
+CREATE TABLE IF NOT EXISTS blocklist (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	kind VARCHAR(16) NOT NULL,
+	value VARCHAR(255) NOT NULL,
+	note VARCHAR(255) NOT NULL DEFAULT '',
+	hits BIGINT UNSIGNED NOT NULL DEFAULT 0,
+	last_hit_at DATETIME NULL,
+	created_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	UNIQUE KEY blocklist_kind_value (kind, value)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
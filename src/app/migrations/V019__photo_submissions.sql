@@ -0,0 +1,18 @@
+-- Photos sent in by readers, kept in quarantine until they are moderated
+
+CREATE TABLE IF NOT EXISTS photo_submissions (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	status VARCHAR(16) NOT NULL DEFAULT 'pending',
+	file VARCHAR(64) NOT NULL,
+	author_name VARCHAR(128) NOT NULL,
+	author_email VARCHAR(255) NOT NULL DEFAULT '',
+	title VARCHAR(255) NOT NULL DEFAULT '',
+	caption TEXT NOT NULL,
+	remote_ip VARCHAR(64) NOT NULL DEFAULT '',
+	submitted_at DATETIME NOT NULL,
+	guid VARCHAR(32) NULL,
+	post_id INT UNSIGNED NULL,
+	PRIMARY KEY (id),
+	KEY photo_submissions_status (status),
+	KEY photo_submissions_remote_ip (remote_ip, submitted_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,17 @@
+-- Polls embedded in posts and their votes, one per address and poll
+
+CREATE TABLE IF NOT EXISTS polls (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	question VARCHAR(255) NOT NULL,
+	options TEXT NOT NULL,
+	open TINYINT(1) NOT NULL DEFAULT 1,
+	PRIMARY KEY (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+	poll_id INT UNSIGNED NOT NULL,
+	remote_ip VARCHAR(64) NOT NULL,
+	option_index INT UNSIGNED NOT NULL,
+	voted_at DATETIME NOT NULL,
+	PRIMARY KEY (poll_id, remote_ip)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
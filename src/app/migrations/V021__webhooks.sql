@@ -0,0 +1,25 @@
+-- Outgoing webhooks and the deliveries waiting to be sent or retried
+
+CREATE TABLE IF NOT EXISTS webhooks (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	url VARCHAR(1024) NOT NULL,
+	secret VARCHAR(255) NOT NULL DEFAULT '',
+	events TEXT NOT NULL,
+	active TINYINT(1) NOT NULL DEFAULT 1,
+	created_at DATETIME NOT NULL,
+	PRIMARY KEY (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	webhook_id INT UNSIGNED NOT NULL,
+	event VARCHAR(64) NOT NULL,
+	payload MEDIUMTEXT NOT NULL,
+	status VARCHAR(16) NOT NULL,
+	attempts INT UNSIGNED NOT NULL DEFAULT 0,
+	last_error VARCHAR(1024) NOT NULL DEFAULT '',
+	next_attempt_at DATETIME NOT NULL,
+	created_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	KEY webhook_deliveries_status (status, next_attempt_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,3 @@
+-- Tracking parameters like utm_source that were stripped from the URL of a view
+
+ALTER TABLE post_views ADD COLUMN campaign VARCHAR(255) NOT NULL DEFAULT '' AFTER referer;
@@ -0,0 +1,12 @@
+-- The content of a post before a bulk edit changed it
+
+CREATE TABLE IF NOT EXISTS post_revisions (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	post_id INT UNSIGNED NOT NULL,
+	user_id INT UNSIGNED NOT NULL,
+	reason VARCHAR(1024) NOT NULL DEFAULT '',
+	content MEDIUMTEXT NOT NULL,
+	created_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	KEY post_revisions_post (post_id, created_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
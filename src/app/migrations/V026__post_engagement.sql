@@ -0,0 +1,13 @@
+-- How far readers scrolled into a post and how long they stayed, sent by the page when it is left
+
+CREATE TABLE IF NOT EXISTS post_engagement (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	post_id INT UNSIGNED NOT NULL,
+	recorded_at DATETIME NOT NULL,
+	scroll_depth TINYINT UNSIGNED NOT NULL DEFAULT 0,
+	read_time INT UNSIGNED NOT NULL DEFAULT 0,
+	remote_ip VARCHAR(64) NOT NULL DEFAULT '',
+	PRIMARY KEY (id),
+	KEY post_engagement_recorded_at (recorded_at),
+	KEY post_engagement_post_id (post_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
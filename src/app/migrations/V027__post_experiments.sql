@@ -0,0 +1,23 @@
+-- A/B tests of the title and thumbnail of posts on list pages, with every exposure and click
+
+CREATE TABLE IF NOT EXISTS post_experiments (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	post_id INT UNSIGNED NOT NULL,
+	title_b VARCHAR(255) NOT NULL DEFAULT '',
+	thumbnail_b VARCHAR(1024) NOT NULL DEFAULT '',
+	running TINYINT(1) NOT NULL DEFAULT 1,
+	winner VARCHAR(1) NOT NULL DEFAULT '',
+	started_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	KEY post_experiments_post_id (post_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS post_experiment_events (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	experiment_id INT UNSIGNED NOT NULL,
+	variant VARCHAR(1) NOT NULL,
+	kind VARCHAR(16) NOT NULL,
+	recorded_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	KEY post_experiment_events_experiment_id (experiment_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,16 @@
+-- Things the admins should look at, like new comments or failing jobs
+
+CREATE TABLE IF NOT EXISTS notifications (
+	id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+	kind VARCHAR(32) NOT NULL,
+	title VARCHAR(255) NOT NULL,
+	detail TEXT NOT NULL,
+	link VARCHAR(255) NOT NULL DEFAULT '',
+	count INT UNSIGNED NOT NULL DEFAULT 1,
+	created_at DATETIME NOT NULL,
+	updated_at DATETIME NOT NULL,
+	read_at DATETIME NULL,
+	PRIMARY KEY (id),
+	KEY notifications_updated_at (updated_at),
+	KEY notifications_read_at (read_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
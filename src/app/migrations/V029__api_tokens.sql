@@ -0,0 +1,14 @@
+-- Tokens scripts and CI jobs send instead of logging in, only their hash is stored
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	name VARCHAR(64) NOT NULL,
+	token_hash CHAR(64) NOT NULL,
+	scopes VARCHAR(255) NOT NULL,
+	user_id INT UNSIGNED NOT NULL,
+	created_at DATETIME NOT NULL,
+	last_used_at DATETIME NULL,
+	revoked TINYINT(1) NOT NULL DEFAULT 0,
+	PRIMARY KEY (id),
+	UNIQUE KEY api_tokens_token_hash (token_hash)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,10 @@
+-- Who has a post open in the editor, the panel warns everybody else
+
+CREATE TABLE IF NOT EXISTS post_locks (
+	post_id INT UNSIGNED NOT NULL,
+	user_id INT UNSIGNED NOT NULL,
+	user_name VARCHAR(255) NOT NULL,
+	expires_at DATETIME NOT NULL,
+	PRIMARY KEY (post_id),
+	KEY post_locks_expires_at (expires_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,5 @@
+-- Alt text of gallery images, suggestions from the captioning service wait for an admin to confirm them
+
+ALTER TABLE gallery ADD COLUMN alt_text VARCHAR(512) NOT NULL DEFAULT '' AFTER lng;
+ALTER TABLE gallery ADD COLUMN alt_text_suggested VARCHAR(512) NULL AFTER alt_text;
+ALTER TABLE gallery ADD COLUMN alt_text_checked_at DATETIME NULL AFTER alt_text_suggested;
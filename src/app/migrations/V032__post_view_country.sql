@@ -0,0 +1,3 @@
+-- The country of the reader as an ISO code like DE, empty if the CDN did not tell
+
+ALTER TABLE post_views ADD COLUMN country CHAR(2) NOT NULL DEFAULT '' AFTER campaign;
@@ -0,0 +1,18 @@
+-- Pingbacks and trackbacks from other sites, shown with the post once they are approved
+
+CREATE TABLE IF NOT EXISTS post_mentions (
+	id INT UNSIGNED NOT NULL AUTO_INCREMENT,
+	post_id INT UNSIGNED NOT NULL,
+	kind VARCHAR(16) NOT NULL,
+	status VARCHAR(16) NOT NULL DEFAULT 'pending',
+	source_url VARCHAR(512) NOT NULL,
+	title VARCHAR(255) NOT NULL DEFAULT '',
+	excerpt TEXT NOT NULL,
+	blog_name VARCHAR(255) NOT NULL DEFAULT '',
+	remote_ip VARCHAR(64) NOT NULL DEFAULT '',
+	received_at DATETIME NOT NULL,
+	PRIMARY KEY (id),
+	UNIQUE KEY post_mentions_source (post_id, source_url),
+	KEY post_mentions_status (status),
+	KEY post_mentions_remote_ip (remote_ip, received_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
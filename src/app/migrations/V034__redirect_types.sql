@@ -0,0 +1,6 @@
+-- Redirects answer with their own status, 410 for removed content, and may match paths by prefix or wildcard
+
+ALTER TABLE redirects
+	MODIFY COLUMN name VARCHAR(255) NOT NULL,
+	ADD COLUMN kind VARCHAR(16) NOT NULL DEFAULT 'name' AFTER target,
+	ADD COLUMN status SMALLINT UNSIGNED NOT NULL DEFAULT 302 AFTER kind;
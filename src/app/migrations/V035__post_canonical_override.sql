@@ -0,0 +1,3 @@
+-- The original URL of a post republished from elsewhere, search engines are pointed there
+
+ALTER TABLE posts ADD COLUMN canonical_override VARCHAR(1024) NULL AFTER url_historic;
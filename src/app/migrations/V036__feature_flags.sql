@@ -0,0 +1,10 @@
+-- Feature flags switched in the admin panel, they override the [flags] section of the config
+
+CREATE TABLE IF NOT EXISTS feature_flags (
+	name VARCHAR(64) NOT NULL,
+	enabled TINYINT(1) NOT NULL DEFAULT 0,
+	rollout TINYINT UNSIGNED NOT NULL DEFAULT 100,
+	note VARCHAR(255) NOT NULL DEFAULT '',
+	updated_at DATETIME NOT NULL,
+	PRIMARY KEY (name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
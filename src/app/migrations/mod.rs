@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::storage::{Dialect, Storage};
+
+/// All schema migrations in order: version, name and the SQL to run on MySQL and on SQLite
+///
+/// Never change a migration that was released, add a new one instead
+const MIGRATIONS: &[(u32, &str, &str, &str)] = &[
+	(1, "initial_schema", include_str!("V001__initial_schema.sql"), include_str!("sqlite/V001__initial_schema.sql")),
+	(2, "post_pinned", include_str!("V002__post_pinned.sql"), include_str!("sqlite/V002__post_pinned.sql")),
+	(3, "edit_versions", include_str!("V003__edit_versions.sql"), include_str!("sqlite/V003__edit_versions.sql")),
+	(4, "post_drafts", include_str!("V004__post_drafts.sql"), include_str!("sqlite/V004__post_drafts.sql")),
+	(5, "gallery_focal_point", include_str!("V005__gallery_focal_point.sql"), include_str!("sqlite/V005__gallery_focal_point.sql")),
+	(6, "gallery_exif", include_str!("V006__gallery_exif.sql"), include_str!("sqlite/V006__gallery_exif.sql")),
+	(7, "gallery_sha256", include_str!("V007__gallery_sha256.sql"), include_str!("sqlite/V007__gallery_sha256.sql")),
+	(8, "files", include_str!("V008__files.sql"), include_str!("sqlite/V008__files.sql")),
+	(9, "gallery_media_type", include_str!("V009__gallery_media_type.sql"), include_str!("sqlite/V009__gallery_media_type.sql")),
+	(10, "user_token_serial", include_str!("V010__user_token_serial.sql"), include_str!("sqlite/V010__user_token_serial.sql")),
+	(11, "password_resets", include_str!("V011__password_resets.sql"), include_str!("sqlite/V011__password_resets.sql")),
+	(12, "audit_log", include_str!("V012__audit_log.sql"), include_str!("sqlite/V012__audit_log.sql")),
+	(13, "post_authors", include_str!("V013__post_authors.sql"), include_str!("sqlite/V013__post_authors.sql")),
+	(14, "post_template", include_str!("V014__post_template.sql"), include_str!("sqlite/V014__post_template.sql")),
+	(15, "post_custom_fields", include_str!("V015__post_custom_fields.sql"), include_str!("sqlite/V015__post_custom_fields.sql")),
+	(16, "post_blocks", include_str!("V016__post_blocks.sql"), include_str!("sqlite/V016__post_blocks.sql")),
+	(17, "comment_spam", include_str!("V017__comment_spam.sql"), include_str!("sqlite/V017__comment_spam.sql")),
+	(18, "blocklist", include_str!("V018__blocklist.sql"), include_str!("sqlite/V018__blocklist.sql")),
+	(19, "photo_submissions", include_str!("V019__photo_submissions.sql"), include_str!("sqlite/V019__photo_submissions.sql")),
+	(20, "polls", include_str!("V020__polls.sql"), include_str!("sqlite/V020__polls.sql")),
+	(21, "webhooks", include_str!("V021__webhooks.sql"), include_str!("sqlite/V021__webhooks.sql")),
+	(22, "post_view_campaign", include_str!("V022__post_view_campaign.sql"), include_str!("sqlite/V022__post_view_campaign.sql")),
+	(23, "post_primary_tag", include_str!("V023__post_primary_tag.sql"), include_str!("sqlite/V023__post_primary_tag.sql")),
+	(24, "post_revisions", include_str!("V024__post_revisions.sql"), include_str!("sqlite/V024__post_revisions.sql")),
+	(25, "post_regions", include_str!("V025__post_regions.sql"), include_str!("sqlite/V025__post_regions.sql")),
+	(26, "post_engagement", include_str!("V026__post_engagement.sql"), include_str!("sqlite/V026__post_engagement.sql")),
+	(27, "post_experiments", include_str!("V027__post_experiments.sql"), include_str!("sqlite/V027__post_experiments.sql")),
+	(28, "notifications", include_str!("V028__notifications.sql"), include_str!("sqlite/V028__notifications.sql")),
+	(29, "api_tokens", include_str!("V029__api_tokens.sql"), include_str!("sqlite/V029__api_tokens.sql")),
+	(30, "post_locks", include_str!("V030__post_locks.sql"), include_str!("sqlite/V030__post_locks.sql")),
+	(31, "gallery_alt_text", include_str!("V031__gallery_alt_text.sql"), include_str!("sqlite/V031__gallery_alt_text.sql")),
+	(32, "post_view_country", include_str!("V032__post_view_country.sql"), include_str!("sqlite/V032__post_view_country.sql")),
+	(33, "post_mentions", include_str!("V033__post_mentions.sql"), include_str!("sqlite/V033__post_mentions.sql")),
+	(34, "redirect_types", include_str!("V034__redirect_types.sql"), include_str!("sqlite/V034__redirect_types.sql")),
+	(35, "post_canonical_override", include_str!("V035__post_canonical_override.sql"), include_str!("sqlite/V035__post_canonical_override.sql")),
+	(36, "feature_flags", include_str!("V036__feature_flags.sql"), include_str!("sqlite/V036__feature_flags.sql")),
+];
+
+
+/// Bring the database schema up to date
+///
+/// Returns the number of migrations that were applied
+pub fn run_migrations(db: &Arc<dyn Storage>) -> Result<usize, String> {
+	// Keeps track of what has been applied
+	let query = match db.dialect() {
+		Dialect::MySql => {
+			r##"CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INT UNSIGNED NOT NULL, name VARCHAR(128) NOT NULL, applied_at DATETIME NOT NULL, PRIMARY KEY (version)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4"##
+		}
+		Dialect::Sqlite => {
+			r##"CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER NOT NULL, name TEXT NOT NULL, applied_at DATETIME NOT NULL, PRIMARY KEY (version)
+        )"##
+		}
+	};
+	db.exec(query, db_params!()).map_err(|err| format!("Could not create the migrations table: {}", err))?;
+
+	let current = current_version(db)?;
+	let mut applied = 0;
+
+	for (version, name, sql_mysql, sql_sqlite) in MIGRATIONS.iter() {
+		if *version <= current { continue; }
+
+		println!("Applying migration {} ({})", version, name);
+
+		let sql = match db.dialect() {
+			Dialect::MySql => sql_mysql,
+			Dialect::Sqlite => sql_sqlite,
+		};
+
+		// MySQL commits DDL right away, so a failing migration has to be fixed by hand
+		for statement in split_statements(sql) {
+			match db.exec(&statement, db_params!()) {
+				Ok(_) => {}
+				Err(err) => { return Err(format!("Migration {} ({}) failed: {}\n{}", version, name, err, statement)); }
+			}
+		}
+
+		db.exec("INSERT INTO schema_migrations (version, name, applied_at) VALUES (:version, :name, :applied_at)", db_params! {"version" => version, "name" => name, "applied_at" => Utc::now().naive_utc()})
+			.map_err(|err| format!("Could not record migration {}: {}", version, err))?;
+
+		applied += 1;
+	}
+
+	Ok(applied)
+}
+
+/// The version of the latest applied migration, 0 for a fresh database
+fn current_version(db: &Arc<dyn Storage>) -> Result<u32, String> {
+	let row = db.query_first("SELECT COALESCE(MAX(version), 0) AS version FROM schema_migrations", db_params!())
+		.map_err(|err| format!("Could not read the schema version: {}", err))?;
+
+	Ok(row.and_then(|mut row| row.take("version")).unwrap_or(0))
+}
+
+/// Split a migration into single statements, dropping comments and empty lines
+fn split_statements(sql: &str) -> Vec<String> {
+	let cleaned: Vec<&str> = sql.lines().filter(|line| !line.trim_start().starts_with("--")).collect();
+
+	cleaned.join("\n").split(';')
+		.map(|statement| statement.trim().to_string())
+		.filter(|statement| statement.len() > 0)
+		.collect()
+}
@@ -0,0 +1,102 @@
+-- The initial schema for SQLite, kept in step with the MySQL one.
+-- Dates are stored as text in the CURRENT_TIMESTAMP format.
+
+CREATE TABLE IF NOT EXISTS users (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	login TEXT NOT NULL,
+	pass TEXT NOT NULL,
+	salt TEXT NOT NULL DEFAULT '',
+	sn INTEGER NOT NULL DEFAULT 0,
+	sr INTEGER NOT NULL DEFAULT 0,
+	sp INTEGER NOT NULL DEFAULT 0,
+	display_name TEXT NOT NULL,
+	home_post INTEGER NOT NULL DEFAULT 0,
+	permissions TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS posts (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	author_id INTEGER NOT NULL,
+	date_posted DATETIME NOT NULL,
+	date_modified DATETIME NOT NULL,
+	state TEXT NOT NULL DEFAULT 'draft',
+	title TEXT NOT NULL,
+	content TEXT NOT NULL,
+	meta_title TEXT NOT NULL DEFAULT '',
+	meta_description TEXT NOT NULL,
+	meta_keywords TEXT NOT NULL,
+	url_canonical TEXT NOT NULL,
+	url_historic TEXT NOT NULL,
+	tags TEXT NOT NULL,
+	media TEXT NOT NULL,
+	locations TEXT NOT NULL,
+	related_posts TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS post_views (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	post_id INTEGER NOT NULL,
+	viewed_at DATETIME NOT NULL,
+	remote_ip TEXT NOT NULL DEFAULT '',
+	user_agent TEXT NOT NULL DEFAULT '',
+	referer TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS post_comments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	parent_id INTEGER NOT NULL DEFAULT 0,
+	post_id INTEGER NOT NULL,
+	status TEXT NOT NULL DEFAULT 'new',
+	author_name TEXT NOT NULL,
+	author_email TEXT NOT NULL DEFAULT '',
+	date_posted DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	content TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tags (
+	id TEXT NOT NULL,
+	title TEXT NOT NULL,
+	content TEXT NOT NULL,
+	meta_title TEXT NOT NULL DEFAULT '',
+	meta_description TEXT NOT NULL,
+	media TEXT NOT NULL,
+	PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS menus (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT NOT NULL,
+	items TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS snippets (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT NOT NULL,
+	replacement TEXT NOT NULL,
+	variables TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS redirects (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name TEXT NOT NULL,
+	target TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS gallery (
+	guid TEXT NOT NULL,
+	hash TEXT NOT NULL DEFAULT '',
+	extension TEXT NOT NULL,
+	sizeX INTEGER NOT NULL,
+	sizeY INTEGER NOT NULL,
+	uploadedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (guid)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_login ON users (login);
+CREATE INDEX IF NOT EXISTS posts_state ON posts (state);
+CREATE INDEX IF NOT EXISTS post_views_viewed_at ON post_views (viewed_at);
+CREATE INDEX IF NOT EXISTS post_views_post_id ON post_views (post_id);
+CREATE INDEX IF NOT EXISTS post_comments_post_id ON post_comments (post_id);
+CREATE INDEX IF NOT EXISTS post_comments_status ON post_comments (status);
+CREATE UNIQUE INDEX IF NOT EXISTS redirects_name ON redirects (name);
+CREATE INDEX IF NOT EXISTS gallery_hash ON gallery (hash);
@@ -0,0 +1,3 @@
+-- Pinned posts stay on top of the index and tag listings
+
+ALTER TABLE posts ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
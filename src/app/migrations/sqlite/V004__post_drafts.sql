@@ -0,0 +1,9 @@
+-- Editor autosaves, one per post and user
+
+CREATE TABLE IF NOT EXISTS post_drafts (
+	post_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	saved_at DATETIME NOT NULL,
+	data TEXT NOT NULL,
+	PRIMARY KEY (post_id, user_id)
+);
@@ -0,0 +1,4 @@
+-- Focal points for cropped gallery variants, in percent of the width and height
+
+ALTER TABLE gallery ADD COLUMN focal_x REAL NOT NULL DEFAULT 50;
+ALTER TABLE gallery ADD COLUMN focal_y REAL NOT NULL DEFAULT 50;
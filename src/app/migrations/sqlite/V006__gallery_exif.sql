@@ -0,0 +1,6 @@
+-- Capture date, camera and location read from the EXIF data of uploads
+
+ALTER TABLE gallery ADD COLUMN captured_at DATETIME NULL;
+ALTER TABLE gallery ADD COLUMN camera TEXT NULL;
+ALTER TABLE gallery ADD COLUMN lat REAL NULL;
+ALTER TABLE gallery ADD COLUMN lng REAL NULL;
@@ -0,0 +1,8 @@
+-- Gallery files are hashed with SHA-256, every variant on its own
+
+CREATE TABLE IF NOT EXISTS gallery_variants (
+	guid TEXT NOT NULL,
+	size TEXT NOT NULL,
+	hash TEXT NOT NULL,
+	PRIMARY KEY (guid, size)
+);
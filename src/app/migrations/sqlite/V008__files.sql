@@ -0,0 +1,10 @@
+-- Attachments like PDF and GPX files
+
+CREATE TABLE IF NOT EXISTS files (
+	guid TEXT NOT NULL,
+	extension TEXT NOT NULL,
+	name TEXT NOT NULL,
+	size INTEGER NOT NULL,
+	uploadedAt DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (guid)
+);
@@ -0,0 +1,3 @@
+-- The gallery holds video clips next to images
+
+ALTER TABLE gallery ADD COLUMN media_type TEXT NOT NULL DEFAULT 'image';
@@ -0,0 +1,3 @@
+-- Raising the serial revokes every token issued to a user
+
+ALTER TABLE users ADD COLUMN token_serial INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,11 @@
+-- Users get password reset links by email, each token works once
+
+ALTER TABLE users ADD COLUMN email TEXT NULL;
+
+CREATE TABLE IF NOT EXISTS password_resets (
+	token_hash TEXT NOT NULL,
+	user_id INTEGER NOT NULL,
+	expires_at DATETIME NOT NULL,
+	used INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (token_hash)
+);
@@ -0,0 +1,3 @@
+-- Co-authors of a post, a JSON list of user ids
+
+ALTER TABLE posts ADD COLUMN authors TEXT NULL;
@@ -0,0 +1,3 @@
+-- Free-form key/value data on posts, stored as a JSON object
+
+ALTER TABLE posts ADD COLUMN custom_fields TEXT NULL;
@@ -0,0 +1,10 @@
+-- Spam score of every comment and the scoring rules edited in the admin panel
+
+ALTER TABLE post_comments ADD COLUMN spam_score INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS spam_rules (
+	id INTEGER NOT NULL,
+	rules TEXT NOT NULL,
+	updated_at DATETIME NOT NULL,
+	PRIMARY KEY (id)
+);
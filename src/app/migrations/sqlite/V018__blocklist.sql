@@ -0,0 +1,12 @@
+-- Addresses, ranges and email domains that may not use the public forms
+
+CREATE TABLE IF NOT EXISTS blocklist (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	kind TEXT NOT NULL,
+	value TEXT NOT NULL,
+	note TEXT NOT NULL DEFAULT '',
+	hits INTEGER NOT NULL DEFAULT 0,
+	last_hit_at DATETIME NULL,
+	created_at DATETIME NOT NULL,
+	UNIQUE (kind, value)
+);
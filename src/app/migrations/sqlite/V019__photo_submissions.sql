@@ -0,0 +1,18 @@
+-- Photos sent in by readers, kept in quarantine until they are moderated
+
+CREATE TABLE IF NOT EXISTS photo_submissions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	status TEXT NOT NULL DEFAULT 'pending',
+	file TEXT NOT NULL,
+	author_name TEXT NOT NULL,
+	author_email TEXT NOT NULL DEFAULT '',
+	title TEXT NOT NULL DEFAULT '',
+	caption TEXT NOT NULL,
+	remote_ip TEXT NOT NULL DEFAULT '',
+	submitted_at DATETIME NOT NULL,
+	guid TEXT NULL,
+	post_id INTEGER NULL
+);
+
+CREATE INDEX IF NOT EXISTS photo_submissions_status ON photo_submissions (status);
+CREATE INDEX IF NOT EXISTS photo_submissions_remote_ip ON photo_submissions (remote_ip, submitted_at);
@@ -0,0 +1,16 @@
+-- Polls embedded in posts and their votes, one per address and poll
+
+CREATE TABLE IF NOT EXISTS polls (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	question TEXT NOT NULL,
+	options TEXT NOT NULL,
+	open INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+	poll_id INTEGER NOT NULL,
+	remote_ip TEXT NOT NULL,
+	option_index INTEGER NOT NULL,
+	voted_at DATETIME NOT NULL,
+	PRIMARY KEY (poll_id, remote_ip)
+);
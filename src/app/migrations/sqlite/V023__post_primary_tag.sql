@@ -0,0 +1,3 @@
+-- The tag a post belongs to first, for breadcrumbs and the link back to its category
+
+ALTER TABLE posts ADD COLUMN primary_tag TEXT NULL;
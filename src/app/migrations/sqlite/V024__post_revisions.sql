@@ -0,0 +1,12 @@
+-- The content of a post before a bulk edit changed it
+
+CREATE TABLE IF NOT EXISTS post_revisions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	post_id INTEGER NOT NULL,
+	user_id INTEGER NOT NULL,
+	reason TEXT NOT NULL DEFAULT '',
+	content TEXT NOT NULL,
+	created_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS post_revisions_post ON post_revisions (post_id, created_at);
@@ -0,0 +1,3 @@
+-- Countries a post may or may not be shown in, e.g. for sponsored content
+
+ALTER TABLE posts ADD COLUMN regions TEXT NULL;
@@ -0,0 +1,13 @@
+-- How far readers scrolled into a post and how long they stayed, sent by the page when it is left
+
+CREATE TABLE IF NOT EXISTS post_engagement (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	post_id INTEGER NOT NULL,
+	recorded_at DATETIME NOT NULL,
+	scroll_depth INTEGER NOT NULL DEFAULT 0,
+	read_time INTEGER NOT NULL DEFAULT 0,
+	remote_ip TEXT NOT NULL DEFAULT ''
+);
+
+CREATE INDEX IF NOT EXISTS post_engagement_recorded_at ON post_engagement (recorded_at);
+CREATE INDEX IF NOT EXISTS post_engagement_post_id ON post_engagement (post_id);
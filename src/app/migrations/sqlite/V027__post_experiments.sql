@@ -0,0 +1,22 @@
+-- A/B tests of the title and thumbnail of posts on list pages, with every exposure and click
+
+CREATE TABLE IF NOT EXISTS post_experiments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	post_id INTEGER NOT NULL,
+	title_b TEXT NOT NULL DEFAULT '',
+	thumbnail_b TEXT NOT NULL DEFAULT '',
+	running INTEGER NOT NULL DEFAULT 1,
+	winner TEXT NOT NULL DEFAULT '',
+	started_at DATETIME NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS post_experiment_events (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	experiment_id INTEGER NOT NULL,
+	variant TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	recorded_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS post_experiments_post_id ON post_experiments (post_id);
+CREATE INDEX IF NOT EXISTS post_experiment_events_experiment_id ON post_experiment_events (experiment_id);
@@ -0,0 +1,16 @@
+-- Things the admins should look at, like new comments or failing jobs
+
+CREATE TABLE IF NOT EXISTS notifications (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	kind TEXT NOT NULL,
+	title TEXT NOT NULL,
+	detail TEXT NOT NULL,
+	link TEXT NOT NULL DEFAULT '',
+	count INTEGER NOT NULL DEFAULT 1,
+	created_at DATETIME NOT NULL,
+	updated_at DATETIME NOT NULL,
+	read_at DATETIME NULL
+);
+
+CREATE INDEX IF NOT EXISTS notifications_updated_at ON notifications (updated_at);
+CREATE INDEX IF NOT EXISTS notifications_read_at ON notifications (read_at);
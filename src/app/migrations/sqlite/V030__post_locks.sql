@@ -0,0 +1,10 @@
+-- Who has a post open in the editor, the panel warns everybody else
+
+CREATE TABLE IF NOT EXISTS post_locks (
+	post_id INTEGER PRIMARY KEY,
+	user_id INTEGER NOT NULL,
+	user_name TEXT NOT NULL,
+	expires_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS post_locks_expires_at ON post_locks (expires_at);
@@ -0,0 +1,5 @@
+-- Alt text of gallery images, suggestions from the captioning service wait for an admin to confirm them
+
+ALTER TABLE gallery ADD COLUMN alt_text TEXT NOT NULL DEFAULT '';
+ALTER TABLE gallery ADD COLUMN alt_text_suggested TEXT NULL;
+ALTER TABLE gallery ADD COLUMN alt_text_checked_at DATETIME NULL;
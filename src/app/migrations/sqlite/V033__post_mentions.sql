@@ -0,0 +1,18 @@
+-- Pingbacks and trackbacks from other sites, shown with the post once they are approved
+
+CREATE TABLE IF NOT EXISTS post_mentions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	post_id INTEGER NOT NULL,
+	kind TEXT NOT NULL,
+	status TEXT NOT NULL DEFAULT 'pending',
+	source_url TEXT NOT NULL,
+	title TEXT NOT NULL DEFAULT '',
+	excerpt TEXT NOT NULL,
+	blog_name TEXT NOT NULL DEFAULT '',
+	remote_ip TEXT NOT NULL DEFAULT '',
+	received_at DATETIME NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS post_mentions_source ON post_mentions (post_id, source_url);
+CREATE INDEX IF NOT EXISTS post_mentions_status ON post_mentions (status);
+CREATE INDEX IF NOT EXISTS post_mentions_remote_ip ON post_mentions (remote_ip, received_at);
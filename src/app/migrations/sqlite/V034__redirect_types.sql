@@ -0,0 +1,4 @@
+-- Redirects answer with their own status, 410 for removed content, and may match paths by prefix or wildcard
+
+ALTER TABLE redirects ADD COLUMN kind TEXT NOT NULL DEFAULT 'name';
+ALTER TABLE redirects ADD COLUMN status INTEGER NOT NULL DEFAULT 302;
@@ -0,0 +1,9 @@
+-- Feature flags switched in the admin panel, they override the [flags] section of the config
+
+CREATE TABLE IF NOT EXISTS feature_flags (
+	name TEXT NOT NULL PRIMARY KEY,
+	enabled INTEGER NOT NULL DEFAULT 0,
+	rollout INTEGER NOT NULL DEFAULT 100,
+	note TEXT NOT NULL DEFAULT '',
+	updated_at DATETIME NOT NULL
+);
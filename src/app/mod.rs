@@ -5,21 +5,41 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use actix_cors::Cors;
-use actix_files;
-use actix_web::{App, Error, HttpRequest, HttpResponse, HttpServer, middleware, web};
-use mysql;
+use actix_web::{App, Error, guard, http, HttpRequest, HttpResponse, HttpServer, middleware, web};
+use actix_web::dev::RequestHead;
+use listenfd::ListenFd;
 use rustls::{NoClientAuth, ServerConfig};
 use rustls::internal::pemfile::{certs, pkcs8_private_keys};
 use tera::Tera;
-use tokio::{task, time};
+use tokio::task;
 
-use crate::app::config::{config_get_i64, config_get_string, config_load_from_file};
+use crate::app::scheduler::{Schedule, Scheduler};
+use crate::app::settings::settings;
 use crate::blog::Blog;
-
+use crate::blog::notifications::notify;
+use crate::blog::resolver::PostMatch;
+use crate::storage::{ReadStorage, storage_open};
+
+pub mod api;
+pub mod assets;
+pub mod cdn;
+pub mod client_ip;
 pub mod config;
+pub mod geo;
+pub mod limits;
+pub mod mail;
+pub mod migrations;
+pub mod normalize;
+pub mod scheduler;
+pub mod settings;
+pub mod templates;
+pub mod timezone;
 pub mod utils;
 
 
+/// The shortest HSTS max age the preload list accepts, one year
+const HSTS_PRELOAD_MIN_AGE: i64 = 31536000;
+
 lazy_static! {
 	static ref BLOG: Arc<Blog> = Arc::new(Blog::new());
 }
@@ -30,6 +50,8 @@ lazy_static! {
 // ------------------------------
 
 /// Route: redirect http requests to https
+///
+/// Permanently, GET and HEAD with a 301, everything else with a 308 so the method and body are kept
 fn forward_to_https(req: HttpRequest) -> HttpResponse {
 	let mut target = format!("https://{}", self::config::config_get_string("fqdn"));
 
@@ -41,44 +63,174 @@ fn forward_to_https(req: HttpRequest) -> HttpResponse {
 		target = format!("{}?{}", &target, req.query_string());
 	}
 
-	HttpResponse::Found()
-		.header("LOCATION", target.as_str())
-		.finish()
+	let method = req.method();
+	if method != http::Method::GET && method != http::Method::HEAD {
+		return HttpResponse::PermanentRedirect().header(http::header::LOCATION, target.as_str()).finish();
+	}
+
+	// A HEAD request gets the same headers, but no body
+	let mut res = HttpResponse::MovedPermanently();
+	res.header(http::header::LOCATION, target.as_str());
+	if method == http::Method::HEAD {
+		return res.finish();
+	}
+	res.content_type("text/html; charset=utf-8").body(format!("<a href=\"{0}\">{0}</a>", tera::escape_html(&target)))
+}
+
+/// Route: ACME http-01 challenges, answered over plain HTTP from `acme_challenge_dir`
+///
+/// Certificates can be renewed without the redirect, even while the HTTPS side is down
+async fn acme_challenge(req: HttpRequest, token: web::Path<String>) -> HttpResponse {
+	let dir = self::config::config_get_string("acme_challenge_dir");
+
+	// Tokens are base64url, anything else could walk out of the directory
+	if dir.len() == 0 || token.len() == 0 || !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+		return forward_to_https(req);
+	}
+
+	match std::fs::read(format!("{}/{}", dir, token)) {
+		Ok(data) => HttpResponse::Ok().content_type("text/plain").body(data),
+		_ => HttpResponse::NotFound().finish()
+	}
+}
+
+/// The Strict-Transport-Security header, if `hsts_max_age` is set
+///
+/// `hsts_preload` is only sent along if the max age is at least a year and subdomains are included,
+/// browsers would not accept the preload otherwise
+fn hsts_header() -> Option<String> {
+	let max_age = self::config::config_get_i64("hsts_max_age");
+	if max_age <= 0 { return None; }
+
+	let mut value = format!("max-age={}", max_age);
+	let include_subdomains = self::config::config_get_bool("hsts_include_subdomains");
+	if include_subdomains { value.push_str("; includeSubDomains"); }
+
+	if self::config::config_get_bool("hsts_preload") {
+		if max_age >= HSTS_PRELOAD_MIN_AGE && include_subdomains {
+			value.push_str("; preload");
+		} else {
+			println!("Not sending 'preload' with HSTS, it needs hsts_max_age of at least {} and hsts_include_subdomains", HSTS_PRELOAD_MIN_AGE);
+		}
+	}
+
+	Some(value)
+}
+
+/// JSON configuration for editing posts: size limit of 16mb
+fn post_json_config() -> web::JsonConfig {
+	web::JsonConfig::default().limit(16777216)
 }
 
 /// Route: robots.txt
 fn robots() -> HttpResponse {
 	HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(
-		format!("Sitemap: https://{}/sitemap.xml\nUser-agent: *\nDisallow: /admin", self::config::config_get_string("fqdn"))
+		format!("Sitemap: https://{}/sitemap_index.xml\nUser-agent: *\nDisallow: /admin", self::config::config_get_string("fqdn"))
 	)
 }
 
+/// Route guard, true for the canonical SEO url of a post
+///
+/// Historic urls are left to the catch all, it moves them to the canonical one
+fn is_post_path(head: &RequestHead) -> bool {
+	let path = head.uri.path().trim_start_matches('/');
+	match BLOG.resolve_post(path) {
+		Some(PostMatch::Canonical(_)) => path.len() > 0,
+		_ => false
+	}
+}
+
 /// Route: favicon
 pub async fn favicon() -> Result<actix_files::NamedFile, Error> {
 	Ok(actix_files::NamedFile::open("./data/static/favicon.ico")?)
 }
 
 
+/// A schedule from an interval in milliseconds in the config, or the default if it is not set
+fn config_every(key: &str, default_ms: u64) -> Schedule {
+	match self::config::config_get_i64(key) {
+		tmp if tmp > 0 => Schedule::Every(Duration::from_millis(tmp as u64)),
+		_ => Schedule::Every(Duration::from_millis(default_ms))
+	}
+}
+
+
 /// This function will setup the blog
 /// Load all blog posts
 /// And start the server
+///
+/// With `server_plain_http` set, the app is served over plain HTTP on the HTTP port instead, for local development
 pub async fn start_https_server() -> std::io::Result<()> {
-	// Load the config
-	config_load_from_file().unwrap();
+	let settings = settings();
 
 	// Address we will bind to
-	let host_https = format!("{}:{}", config_get_string("server_host"), config_get_i64("server_ssl_port"));
+	let host_https = format!("{}:{}", settings.server_host, settings.server_ssl_port);
+	let host_http = format!("{}:{}", settings.server_host, settings.server_port);
 
 	// Directories for static and template files
-	let dir_static = config_get_string("server_dir_static");
+	let dir_static = settings.server_dir_static.clone();
 	let path = env::current_dir().unwrap();
-	let dir_templates = format!("{}/{}/**/*", path.to_string_lossy(), config_get_string("server_dir_templates"));
+	let dir_templates = format!("{}/{}/**/*", path.to_string_lossy(), settings.server_dir_templates);
+
+	// Setup the database, MySQL or SQLite depending on the DSN
+	let storage = match storage_open(settings.server_database.as_str()) {
+		Ok(tmp) => tmp,
+		Err(err) => {
+			println!("Error while opening the database: {}", err);
+			return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
+		}
+	};
+
+	// Search, the dashboard and admin lists read from the replica, if there is one
+	let replica = if settings.server_database_replica.is_empty() {
+		ReadStorage(storage.clone())
+	} else {
+		match storage_open(settings.server_database_replica.as_str()) {
+			Ok(tmp) => ReadStorage(tmp),
+			Err(err) => {
+				println!("Error while opening the database replica: {}", err);
+				return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
+			}
+		}
+	};
+
+	// Dates are shown and grouped in the timezone of the blog
+	match self::timezone::timezone_check() {
+		Err(err) => { println!("{}", err); }
+		_ => {}
+	}
+
+	// Create or upgrade the tables
+	match self::migrations::run_migrations(&storage) {
+		Ok(count) => {
+			if count > 0 { println!("Applied {} database migrations", count); }
+		}
+		Err(err) => {
+			println!("Error while migrating the database: {}", err);
+			return Err(std::io::Error::new(std::io::ErrorKind::Other, err));
+		}
+	}
+
+	// Blocked addresses and email domains
+	match crate::blog::blocklist::blocklist_load(&storage) {
+		Err(err) => { println!("Could not load the blocklist: {}", err); }
+		_ => {}
+	}
 
-	// Setup database and connection pool
-	let pool_mysql = Arc::new(mysql::Pool::new_manual(3, 10, config_get_string("server_database")).unwrap());
+	// Feature flags switched in the admin panel
+	match crate::blog::flags::flags_load(&storage) {
+		Err(err) => { println!("Could not load the feature flags, using the config: {}", err); }
+		_ => {}
+	}
+
+	// Spam rules saved in the admin panel
+	match crate::blog::spam::spam_rules_load(&storage) {
+		Err(err) => { println!("Could not load the spam rules, using the defaults: {}", err); }
+		_ => {}
+	}
 
 	// Start up the blog
-	match BLOG.startup(&pool_mysql.clone()) {
+	match BLOG.startup(&storage) {
 		Err(err) => {
 			println!("Error while setting up the blog: {}", err);
 			return Err(err);
@@ -86,17 +238,93 @@ pub async fn start_https_server() -> std::io::Result<()> {
 		_ => {}
 	}
 
-	// Create a maintenance task
-	let db_copy = pool_mysql.clone();
-	let _join_handle = task::spawn(async move {
-		let mut interval = time::interval(Duration::from_millis(self::config::config_get_i64("maintenance_interval") as u64));
+	// Fingerprint the static files, templates link them through `asset(name=...)`
+	match self::assets::assets_load(&dir_static) {
+		Ok(count) => { println!("Fingerprinted {} static files", count); }
+		Err(err) => { println!("Could not fingerprint the static files: {}", err); }
+	}
 
-		loop {
-			interval.tick().await;
-			BLOG.maintenance_task(&db_copy);
+	// Setup tera templates
+	let mut tera = Tera::new(&dir_templates).unwrap();
+	self::templates::register_template_functions(&mut tera);
+	let tera_arc = Arc::new(tera);
+
+	// Background jobs, each on its own schedule, cron expressions in `job_schedules` override the defaults
+	let mut scheduler = Scheduler::new();
+
+	// Failed jobs show up in the admin panel's notifications
+	let db_copy = storage.clone();
+	scheduler.on_failure(move |name, err| notify(&db_copy, "job", &format!("Job '{}' failed", name), err, "jobs"));
+
+	// Queued post views go to the database often, so a stall never loses many of them
+	let db_copy = storage.clone();
+	scheduler.add("flush_messages", config_every("message_flush_interval", 10 * 1000), move || {
+		BLOG.flush_messages(&db_copy);
+		Ok(())
+	});
+
+	// Latest, featured and tagged posts
+	let db_copy = storage.clone();
+	scheduler.add("refresh_post_caches", Schedule::Every(Duration::from_millis(settings.maintenance_interval as u64)), move || {
+		BLOG.refresh_post_caches(&db_copy);
+		Ok(())
+	});
+
+	// Locks of editors that were closed without unlocking the post, same interval as the post caches
+	let db_copy = storage.clone();
+	scheduler.add("expire_post_locks", Schedule::Every(Duration::from_millis(settings.maintenance_interval as u64)), move || {
+		crate::blog::types::lock::delete_expired_post_locks(&db_copy).map(|_| ())
+	});
+
+	// Pinterest and Instagram are slow to ask and change rarely
+	// A feed that is down is retried on the next run, repeated failures count up the same notification
+	let db_copy = storage.clone();
+	scheduler.add("refresh_social_feeds", config_every("social_refresh_interval", 60 * 60 * 1000), move || {
+		match BLOG.refresh_social_feeds() {
+			Err(err) => { notify(&db_copy, "social", "Social feeds could not be fetched", &err, ""); }
+			_ => {}
 		}
+		Ok(())
+	});
+
+	// Hashing all gallery files takes a while
+	let db_copy = storage.clone();
+	scheduler.add("check_gallery_integrity", config_every("gallery_integrity_interval", 24 * 60 * 60 * 1000), move || {
+		BLOG.check_gallery_integrity(&db_copy);
+		Ok(())
+	});
+
+	// Orphaned gallery files are only reported, purging them is up to the admins
+	let db_copy = storage.clone();
+	scheduler.add("find_gallery_orphans", config_every("gallery_orphans_interval", 24 * 60 * 60 * 1000), move || {
+		BLOG.find_gallery_orphans(&db_copy)
+	});
+
+	// Alt text suggestions for new images, only if a captioning service is configured
+	let db_copy = storage.clone();
+	scheduler.add("suggest_alt_texts", config_every("alt_text_interval", 5 * 60 * 1000), move || {
+		crate::blog::alt_text::suggest_alt_texts(&db_copy).map(|_| ())
 	});
 
+	// Webhook deliveries, failed ones are retried with a growing delay
+	let db_copy = storage.clone();
+	scheduler.add("deliver_webhooks", config_every("webhook_interval", 15 * 1000), move || {
+		crate::blog::webhooks::webhook_deliver_pending(&db_copy)
+	});
+
+	// Expired pages are served while they are rendered again here, see `cache_stale_html`
+	let db_copy = storage.clone();
+	let tera_copy = web::Data::new(tera_arc.clone());
+	scheduler.add("revalidate_html", config_every("html_revalidate_interval", 1000), move || {
+		BLOG.revalidate_html(&db_copy, &tera_copy);
+		Ok(())
+	});
+
+	// Only runs if scheduled in the config
+	scheduler.add("ping_sitemap", Schedule::Never, self::utils::ping_sitemap);
+
+	let _join_handle = task::spawn(scheduler.run());
+
 //    let _join_handle = thread::spawn(move || {
 //        // https://tokio.rs/docs/going-deeper/timers/#running-code-on-an-interval
 //        let task = Interval::new(Instant::now(), Duration::from_millis(self::config::config_get_i64("maintenance_interval") as u64))
@@ -109,28 +337,18 @@ pub async fn start_https_server() -> std::io::Result<()> {
 //        tokio::run(task);
 //    });
 
-	// Load SSL keys
-	let mut config = ServerConfig::new(NoClientAuth::new());
-	let cert_file = &mut BufReader::new(File::open(config_get_string("server_ssl_crt")).unwrap());
-	let key_file = &mut BufReader::new(File::open(config_get_string("server_ssl_key")).unwrap());
-	let cert_chain = certs(cert_file).unwrap();
-	let mut keys = pkcs8_private_keys(key_file).unwrap();
-	config.set_single_cert(cert_chain, keys.remove(0)).unwrap();
+	// Browsers should only come back over HTTPS, not sent when we serve plain HTTP ourselves
+	let hsts = if settings.server_plain_http { None } else { hsts_header() };
 
-	// Setup tera templates
-	let tera_arc = Arc::new(Tera::new(&dir_templates).unwrap());
-
-	// Initialize and start the threads for the https server
-	HttpServer::new(move || App::new()
+	// Initialize the threads for the https server
+	let server = HttpServer::new(move || App::new()
 		.data(tera_arc.clone())
 		.data(BLOG.clone())
-		.data(pool_mysql.clone())
-
-		// JSON configuration: size limit of 4mb
-		.data(web::JsonConfig::default().limit(4194304))
+		.data(storage.clone())
+		.data(replica.clone())
 
-		// JSON configuration: size limit of 16mb for editing posts
-		.data(web::Json::<super::blog::types::post::Post>::configure(|cfg| cfg.limit(16777216)))
+		// JSON configuration: size limit of 4mb, editing posts allows more, see `post_json_config`
+		.app_data(web::JsonConfig::default().limit(4194304))
 
 		// CORS policy
 		.wrap(
@@ -138,45 +356,94 @@ pub async fn start_https_server() -> std::io::Result<()> {
 		)
 		.wrap(middleware::Logger::default())
 		.wrap(middleware::Compress::default())
-
-		// STATIC resources
+		.wrap(match &hsts {
+			Some(value) => middleware::DefaultHeaders::new().header(http::header::STRICT_TRANSPORT_SECURITY, value.as_str()),
+			_ => middleware::DefaultHeaders::new()
+		})
+		.wrap(self::normalize::NormalizePath)
+		.wrap(self::limits::RequestLimits)
+
+		// STATIC resources, fingerprinted names are cached for good
+		.service(web::resource("/static/{tail:.*}").guard(guard::fn_guard(self::assets::is_fingerprinted)).route(web::get().to(self::assets::asset)))
 		.service(actix_files::Files::new("/static", dir_static.clone()))
 
+		// INDEX pages
+		.service(web::resource("/").route(web::get().to(crate::blog::routes::index)))
+		.service(web::resource("/page/{page}").route(web::get().to(crate::blog::routes::index_page)))
+
 		// CATEGORY & SEARCH
 		.service(web::resource("/tag/{name:.*}").route(web::get().to(crate::blog::routes::list_by_tag)))
 		.service(web::resource("/search").route(web::get().to(crate::blog::routes::list_by_search)))
+		.service(web::resource("/stats").route(web::get().to(crate::blog::routes::stats)))
+		.service(web::resource("/lite/{url:.*}").route(web::get().to(crate::blog::routes::post_lite)))
+		.service(web::resource("/print/{url:.*}").route(web::get().to(crate::blog::routes::post_print)))
+		.service(web::resource("/author/{id}").route(web::get().to(crate::blog::routes::list_by_author)))
 
 		// SITEMAP & ROBOTS & favicon
 		.service(web::resource("/sitemap.xml").route(web::get().to(crate::blog::routes::sitemap)))
-		.service(web::resource("/feed/").route(web::get().to(crate::blog::routes::feed)))
+		.service(web::resource("/sitemap_index.xml").route(web::get().to(crate::blog::routes::sitemap_index)))
+		.service(web::resource("/sitemap/news.xml").route(web::get().to(crate::blog::routes::sitemap_news)))
+		.service(web::resource("/sitemap/{region}").route(web::get().to(crate::blog::routes::sitemap_region)))
+		.service(web::resource("/feed").route(web::get().to(crate::blog::routes::feed)))
+		.service(web::resource("/feed/tag/{name}").route(web::get().to(crate::blog::routes::feed_tag)))
 		.service(web::resource("/robots.txt").route(web::get().to(robots)))
 		.service(web::resource("/favicon.ico").route(web::get().to(favicon)))
 
 		// COMMENTS (let's users add unapproved comments to some blog post)
 		.service(web::resource("/comment").route(web::post().to(crate::blog::routes::comment)))
+		.service(web::resource("/comment/edit").route(web::post().to(crate::blog::routes::comment_edit)))
+		.service(web::resource("/comment/delete").route(web::post().to(crate::blog::routes::comment_delete)))
+		.service(web::resource("/hooks/reload").route(web::post().to(crate::blog::routes_admin::reload_hook)))
+		.service(web::resource("/poll/vote").route(web::post().to(crate::blog::routes::poll_vote)))
+		.service(web::resource("/poll/results").route(web::get().to(crate::blog::routes::poll_results)))
+		.service(web::resource("/beacon").route(web::post().to(crate::blog::routes::beacon)))
+		.service(web::resource("/photo/submit").route(web::post().to(crate::blog::routes::photo_submit)))
+
+		// MENTIONS (pingbacks and trackbacks from other sites, moderated like comments)
+		.service(web::resource("/xmlrpc").route(web::post().to(crate::blog::routes::pingback)))
+		.service(web::resource("/trackback/{id}").route(web::post().to(crate::blog::routes::trackback)))
 
 		// GALLERY
 		.service(web::resource("/gallery/{guid}/{size}/{tail:.*}").route(web::get().to(crate::blog::routes::gallery)))
 		.service(web::resource("/gallery/{tail:.*}").route(web::get().to(crate::blog::routes::gallery_direct)))
+		.service(web::resource("/og/{name}").route(web::get().to(crate::blog::routes::og_image)))
+
+		// ATTACHMENTS
+		.service(web::resource("/files/{guid}/{name}").route(web::get().to(crate::blog::routes::files)))
 
 		// REDIRECT
+		.service(web::resource("/fwd").route(web::get().to(crate::blog::routes::forward_url)))
 		.service(web::resource("/fwd/{name}").route(web::get().to(crate::blog::routes::forward)))
 		.service(web::resource("/ama/{id}").route(web::get().to(crate::blog::routes::forward_amazon)))
 
 		// AUTH routes
 		.service(
 			web::scope("/auth")
+				.wrap(crate::auth::audit::AuditImpersonation)
 				.service(web::resource("/check").route(web::get().to(crate::auth::auth_check)))
 				.service(web::resource("/login").route(web::post().to(crate::auth::auth_login)))
 				.service(web::resource("/logout").route(web::get().to(crate::auth::auth_logout)))
+				.service(web::resource("/logout_all").route(web::post().to(crate::auth::auth_logout_all)))
+				.service(web::resource("/change_password").route(web::post().to(crate::auth::auth_change_password)))
+				.service(web::resource("/request_reset").route(web::post().to(crate::auth::auth_request_reset)))
+				.service(web::resource("/reset_password").route(web::post().to(crate::auth::auth_reset_password)))
+				.service(web::resource("/revoke_user").route(web::post().to(crate::auth::auth_revoke_user)))
+				.service(web::resource("/impersonate").route(web::post().to(crate::auth::auth_impersonate)))
+				.service(web::resource("/api_tokens").route(web::get().to(crate::auth::auth_get_api_tokens)))
+				.service(web::resource("/create_api_token").route(web::post().to(crate::auth::auth_create_api_token)))
+				.service(web::resource("/revoke_api_token").route(web::post().to(crate::auth::auth_revoke_api_token)))
 		)
 
 		// ADMIN routes
 		.service(
 			web::scope("/admin")
+				.wrap(crate::auth::audit::AuditImpersonation)
 				.service(web::resource("/dashboard").route(web::get().to(crate::blog::routes_admin::dashboard)))
+				.service(web::resource("/search").route(web::get().to(crate::blog::routes_admin::search)))
 				.service(web::resource("/get_posts").route(web::get().to(crate::blog::routes_admin::get_posts)))
 				.service(web::resource("/get_post").route(web::get().to(crate::blog::routes_admin::get_post)))
+				.service(web::resource("/get_revisions").route(web::get().to(crate::blog::routes_admin::get_revisions)))
+				.service(web::resource("/export_post").route(web::get().to(crate::blog::routes_admin::export_post)))
 				.service(web::resource("/get_tags").route(web::get().to(crate::blog::routes_admin::get_tags)))
 				.service(web::resource("/get_tag").route(web::get().to(crate::blog::routes_admin::get_tag)))
 				.service(web::resource("/get_comments").route(web::get().to(crate::blog::routes_admin::get_comments)))
@@ -185,15 +452,57 @@ pub async fn start_https_server() -> std::io::Result<()> {
 				.service(web::resource("/get_snippets").route(web::get().to(crate::blog::routes_admin::get_snippets)))
 				.service(web::resource("/get_redirects").route(web::get().to(crate::blog::routes_admin::get_redirects)))
 				.service(web::resource("/get_gallery").route(web::get().to(crate::blog::routes_admin::get_gallery)))
+				.service(web::resource("/get_data_issues").route(web::get().to(crate::blog::routes_admin::get_data_issues)))
+				.service(web::resource("/get_queue_stats").route(web::get().to(crate::blog::routes_admin::get_queue_stats)))
+				.service(web::resource("/get_jobs").route(web::get().to(crate::blog::routes_admin::get_jobs)))
+				.service(web::resource("/get_notifications").route(web::get().to(crate::blog::routes_admin::get_notifications)))
+				.service(web::resource("/get_spam_rules").route(web::get().to(crate::blog::routes_admin::get_spam_rules)))
+				.service(web::resource("/a11y_audit").route(web::get().to(crate::blog::routes_admin::a11y_audit)))
+				.service(web::resource("/profile_render").route(web::get().to(crate::blog::routes_admin::profile_render)))
+				.service(web::resource("/get_polls").route(web::get().to(crate::blog::routes_admin::get_polls)))
+				.service(web::resource("/get_experiments").route(web::get().to(crate::blog::routes_admin::get_experiments)))
+				.service(web::resource("/get_photo_submissions").route(web::get().to(crate::blog::routes_admin::get_photo_submissions)))
+				.service(web::resource("/photo_submission/file").route(web::get().to(crate::blog::routes_admin::get_photo_submission_file)))
+				.service(web::resource("/get_mentions").route(web::get().to(crate::blog::routes_admin::get_mentions)))
+				.service(web::resource("/get_webhooks").route(web::get().to(crate::blog::routes_admin::get_webhooks)))
+				.service(web::resource("/get_blocklist").route(web::get().to(crate::blog::routes_admin::get_blocklist)))
+				.service(web::resource("/get_flags").route(web::get().to(crate::blog::routes_admin::get_flags)))
+				.service(web::resource("/get_autosave").route(web::get().to(crate::blog::routes_admin::get_autosave)))
+				.service(web::resource("/gallery/metadata").route(web::get().to(crate::blog::routes_admin::gallery_metadata)))
+				.service(web::resource("/gallery/duplicates").route(web::get().to(crate::blog::routes_admin::gallery_duplicates)))
+				.service(web::resource("/gallery/usage").route(web::get().to(crate::blog::routes_admin::gallery_usage)))
+				.service(web::resource("/gallery/orphans").route(web::get().to(crate::blog::routes_admin::gallery_orphans)))
+				.service(web::resource("/gallery/alt_text_suggestions").route(web::get().to(crate::blog::routes_admin::gallery_alt_text_suggestions)))
+				.service(web::resource("/get_files").route(web::get().to(crate::blog::routes_admin::get_files)))
 				.service(web::resource("/reload_data").route(web::get().to(crate::blog::routes_admin::reload_data)))
 
-				.service(web::resource("/set_post").route(web::post().to(crate::blog::routes_admin::set_post)))
+				.service(web::resource("/set_post").app_data(post_json_config()).route(web::post().to(crate::blog::routes_admin::set_post)))
+				.service(web::resource("/replace").route(web::post().to(crate::blog::routes_admin::replace_content)))
+				.service(web::resource("/autosave_post").app_data(post_json_config()).route(web::post().to(crate::blog::routes_admin::autosave_post)))
+				.service(web::resource("/lock_post").route(web::post().to(crate::blog::routes_admin::lock_post)))
 				.service(web::resource("/set_tag").route(web::post().to(crate::blog::routes_admin::set_tag)))
 				.service(web::resource("/set_comment").route(web::post().to(crate::blog::routes_admin::set_comment)))
 				.service(web::resource("/set_menu").route(web::post().to(crate::blog::routes_admin::set_menu)))
 				.service(web::resource("/set_snippet").route(web::post().to(crate::blog::routes_admin::set_snippet)))
 				.service(web::resource("/set_redirect").route(web::post().to(crate::blog::routes_admin::set_redirect)))
+				.service(web::resource("/set_spam_rules").route(web::post().to(crate::blog::routes_admin::set_spam_rules)))
+				.service(web::resource("/set_poll").route(web::post().to(crate::blog::routes_admin::set_poll)))
+				.service(web::resource("/set_experiment").route(web::post().to(crate::blog::routes_admin::set_experiment)))
+				.service(web::resource("/read_notifications").route(web::post().to(crate::blog::routes_admin::read_notifications)))
+				.service(web::resource("/photo_submission/approve").route(web::post().to(crate::blog::routes_admin::approve_photo_submission)))
+				.service(web::resource("/photo_submission/reject").route(web::post().to(crate::blog::routes_admin::reject_photo_submission)))
+				.service(web::resource("/moderate_mention").route(web::post().to(crate::blog::routes_admin::moderate_mention)))
+				.service(web::resource("/set_blocklist_entry").route(web::post().to(crate::blog::routes_admin::set_blocklist_entry)))
+				.service(web::resource("/set_webhook").route(web::post().to(crate::blog::routes_admin::set_webhook)))
+				.service(web::resource("/delete_webhook").route(web::post().to(crate::blog::routes_admin::delete_webhook_entry)))
+				.service(web::resource("/delete_blocklist_entry").route(web::post().to(crate::blog::routes_admin::delete_blocklist_entry)))
+				.service(web::resource("/set_flag").route(web::post().to(crate::blog::routes_admin::set_flag)))
+				.service(web::resource("/delete_flag").route(web::post().to(crate::blog::routes_admin::delete_flag)))
 				.service(web::resource("/gallery/upload").route(web::post().to(crate::blog::routes_admin::gallery_upload)))
+				.service(web::resource("/gallery/focal_point").route(web::post().to(crate::blog::routes_admin::gallery_set_focal_point)))
+				.service(web::resource("/gallery/purge_orphans").route(web::post().to(crate::blog::routes_admin::gallery_purge_orphans)))
+				.service(web::resource("/gallery/confirm_alt_text").route(web::post().to(crate::blog::routes_admin::gallery_confirm_alt_text)))
+				.service(web::resource("/files/upload").route(web::post().to(crate::blog::routes_admin::files_upload)))
 				.service(web::resource("/preview_post").route(web::post().to(crate::blog::routes_admin::preview_post)))
 
 				.default_service(web::route().to(crate::blog::routes_admin::index))
@@ -208,30 +517,115 @@ pub async fn start_https_server() -> std::io::Result<()> {
 				.default_service(web::route().to(crate::blog::routes_admin::index2))
 		)
 
-		// CATCH ALL | SEO fallback
-		.service(web::resource("{tail:.*}").route(web::get().to(crate::blog::routes::index)))
+		// POSTS by their canonical SEO url
+		.service(web::resource("/{url:.*}").guard(guard::fn_guard(is_post_path)).route(web::get().to(crate::blog::routes::post)))
+
+		// CATCH ALL | historic urls, redirect rules and 404
+		.service(web::resource("{tail:.*}").route(web::get().to(crate::blog::routes::fallback)))
 
 		// Just in case the CATCH ALL didn't pick something up?
-		.default_service(web::route().to(crate::blog::routes::index))
-	)
-		.bind_rustls(host_https.clone(), config)
-		.expect(format!("Can not bind to '{}'", host_https).as_ref())
+		.default_service(web::route().to(crate::blog::routes::fallback))
+	);
+
+	let unix_socket = settings.server_unix_socket.clone();
+
+	let server = match inherited_listener(0) {
+		// Listener passed in by systemd socket activation
+		Some(InheritedListener::Tcp(listener)) => {
+			if settings.server_plain_http {
+				server.listen(listener).expect("Can not listen on the inherited socket")
+			} else {
+				server.listen_rustls(listener, load_ssl_config()).expect("Can not listen on the inherited socket")
+			}
+		}
+		Some(InheritedListener::Unix(listener)) => {
+			server.listen_uds(listener).expect("Can not listen on the inherited unix socket")
+		}
+
+		// Unix sockets sit behind a local reverse proxy, which also terminates TLS
+		_ if unix_socket.len() > 0 => {
+			let _ = std::fs::remove_file(&unix_socket);
+			server.bind_uds(&unix_socket).unwrap_or_else(|_| panic!("Can not bind to '{}'", unix_socket))
+		}
+
+		_ if settings.server_plain_http => {
+			println!("Serving over plain HTTP on '{}', do not use this in production", host_http);
+			server.bind(host_http.clone()).unwrap_or_else(|_| panic!("Can not bind to '{}'", host_http))
+		}
+
+		_ => {
+			server.bind_rustls(host_https.clone(), load_ssl_config()).unwrap_or_else(|_| panic!("Can not bind to '{}'", host_https))
+		}
+	};
+
+	// Start the server, slow clients are cut off instead of holding on to a connection
+	server
 		.shutdown_timeout(60)
 		.keep_alive(5)
+		.client_timeout(self::limits::client_timeout())
+		.client_shutdown(self::limits::client_shutdown())
+		.maxconn(self::limits::max_connections())
+		.maxconnrate(self::limits::max_connection_rate())
 		.run()
 		.await
 }
 
 /// This server will forward all http requests to the https server
 pub async fn start_http_server() -> std::io::Result<()> {
-	let host_http = format!("{}:{}", config_get_string("server_host"), config_get_i64("server_port"));
+	let host_http = format!("{}:{}", settings().server_host, settings().server_port);
 
     // Start the http server that forwards all requests to https
-    HttpServer::new(move || App::new()
+    let server = HttpServer::new(move || App::new()
+        .service(web::resource("/.well-known/acme-challenge/{token}").route(web::get().to(acme_challenge)))
         .service(web::resource("{tail:.*}").to(forward_to_https))
-    )
-        .bind(host_http.clone()).expect(format!("Can not bind to '{}'", host_http).as_ref())
+    );
+
+    // systemd passes this server's socket second
+    let server = match inherited_listener(1) {
+        Some(InheritedListener::Tcp(listener)) => server.listen(listener).expect("Can not listen on the inherited socket"),
+        Some(InheritedListener::Unix(listener)) => server.listen_uds(listener).expect("Can not listen on the inherited unix socket"),
+        _ => server.bind(host_http.clone()).unwrap_or_else(|_| panic!("Can not bind to '{}'", host_http))
+    };
+
+    server
         .shutdown_timeout(60)    // <- Set shutdown timeout to 60 seconds
+        .client_timeout(self::limits::client_timeout())
+        .client_shutdown(self::limits::client_shutdown())
+        .maxconn(self::limits::max_connections())
         .run()
         .await
+}
+
+/// Load the certificate and key for the HTTPS server
+fn load_ssl_config() -> ServerConfig {
+	let mut config = ServerConfig::new(NoClientAuth::new());
+	let cert_file = &mut BufReader::new(File::open(&settings().server_ssl_crt).unwrap());
+	let key_file = &mut BufReader::new(File::open(&settings().server_ssl_key).unwrap());
+	let cert_chain = certs(cert_file).unwrap();
+	let mut keys = pkcs8_private_keys(key_file).unwrap();
+	config.set_single_cert(cert_chain, keys.remove(0)).unwrap();
+	config
+}
+
+/// A socket handed to us by systemd socket activation
+enum InheritedListener {
+	Tcp(std::net::TcpListener),
+	Unix(std::os::unix::net::UnixListener),
+}
+
+/// Take the listener systemd passed at the given index (sd_listen_fds), if any
+fn inherited_listener(index: usize) -> Option<InheritedListener> {
+	let mut listenfd = ListenFd::from_env();
+
+	match listenfd.take_tcp_listener(index) {
+		Ok(Some(listener)) => return Some(InheritedListener::Tcp(listener)),
+		Ok(None) => return None,
+		_ => {}
+	}
+
+	// Not a TCP socket, maybe a unix one
+	match listenfd.take_unix_listener(index) {
+		Ok(Some(listener)) => Some(InheritedListener::Unix(listener)),
+		_ => None
+	}
 }
\ No newline at end of file
@@ -0,0 +1,146 @@
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Uri};
+use futures::future::{Either, ok, Ready};
+
+use crate::app::config::config_get_string_vec;
+
+/// Paths that are case sensitive or not meant for search engines, these are left alone
+const EXEMPT_PREFIXES: &[&str] = &["/static/", "/gallery/", "/files/", "/admin/", "/ndadmin/", "/auth/", "/fwd/", "/ama/", "/comment"];
+
+/// Paths that only get their slashes cleaned up, tag names keep their case, see `get_html_tag`
+const CASE_SENSITIVE_PREFIXES: &[&str] = &["/tag/", "/feed/tag/"];
+
+/// Click ids added by ad networks and newsletters, every `utm_*` parameter is stripped as well
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_ga"];
+
+/// Longest campaign stored with a post view
+const CAMPAIGN_MAX_LENGTH: usize = 255;
+
+
+/// The tracking parameters removed from a page request, e.g. `utm_source=newsletter&utm_medium=email`
+#[derive(Clone, Debug)]
+pub struct Campaign(pub String);
+
+
+/// Middleware that 301-redirects page requests to their canonical path
+///
+/// Canonical paths are lowercase except for tag names, use single slashes and have no trailing slash. Tracking parameters
+/// are removed from the query before routing, they are kept as `Campaign` for the view statistics
+pub struct NormalizePath;
+
+impl<S, B> Transform<S> for NormalizePath
+	where
+		S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+		S::Future: 'static,
+		B: 'static,
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = NormalizePathMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(NormalizePathMiddleware { service })
+	}
+}
+
+pub struct NormalizePathMiddleware<S> {
+	service: S,
+}
+
+impl<S, B> Service for NormalizePathMiddleware<S>
+	where
+		S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+		S::Future: 'static,
+		B: 'static,
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+		strip_tracking_params(&mut req);
+
+		match canonical_path(req.path()) {
+			Some(path) => {
+				// Keep the query string
+				let target = if req.query_string().len() > 0 { format!("{}?{}", path, req.query_string()) } else { path };
+
+				Either::Right(ok(req.into_response(
+					HttpResponse::MovedPermanently()
+						.header(header::LOCATION, target)
+						.finish()
+						.into_body()
+				)))
+			}
+			_ => { Either::Left(self.service.call(req)) }
+		}
+	}
+}
+
+/// Returns the canonical form of the path, or None if it already is canonical
+pub fn canonical_path(path: &str) -> Option<String> {
+	let lower = path.to_lowercase();
+	if EXEMPT_PREFIXES.iter().any(|prefix| lower.starts_with(prefix)) { return None; }
+
+	// Collapse repeated slashes and drop the trailing one
+	let segments: Vec<&str> = path.split('/').filter(|segment| segment.len() > 0).collect();
+	let canonical = format!("/{}", segments.join("/"));
+	let canonical = match CASE_SENSITIVE_PREFIXES.iter().find(|prefix| canonical.to_lowercase().starts_with(*prefix)) {
+		Some(prefix) => format!("{}{}", prefix, &canonical[prefix.len()..]),
+		_ => canonical.to_lowercase()
+	};
+
+	if canonical == path { None } else { Some(canonical) }
+}
+
+/// Rewrite the request without its tracking parameters, so every visitor sees the same page
+fn strip_tracking_params(req: &mut ServiceRequest) {
+	let lower = req.path().to_lowercase();
+	if EXEMPT_PREFIXES.iter().any(|prefix| lower.starts_with(prefix)) { return; }
+
+	let (kept, stripped) = match split_tracking_params(req.query_string()) {
+		Some(tmp) => tmp,
+		_ => { return; }
+	};
+
+	let target = if kept.len() > 0 { format!("{}?{}", req.path(), kept) } else { String::from(req.path()) };
+	match target.parse::<Uri>() {
+		Ok(uri) => {
+			req.match_info_mut().get_mut().update(&uri);
+			req.head_mut().uri = uri;
+			req.extensions_mut().insert(Campaign(stripped.chars().take(CAMPAIGN_MAX_LENGTH).collect()));
+		}
+		_ => {}
+	}
+}
+
+/// Split a query string into the parameters to keep and the tracking ones, None if there are no tracking parameters
+///
+/// `tracking_params` in the config adds more parameter names
+pub fn split_tracking_params(query: &str) -> Option<(String, String)> {
+	if query.len() == 0 { return None; }
+
+	let extra = config_get_string_vec("tracking_params");
+	let (stripped, kept): (Vec<&str>, Vec<&str>) = query.split('&')
+		.filter(|param| param.len() > 0)
+		.partition(|param| {
+			let name = param.split('=').next().unwrap_or("").to_lowercase();
+			name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str()) || extra.iter().any(|tmp| tmp.to_lowercase() == name)
+		});
+
+	if stripped.len() == 0 { return None; }
+
+	Some((kept.join("&"), stripped.join("&")))
+}
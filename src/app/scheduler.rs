@@ -0,0 +1,365 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDateTime, Timelike, Utc};
+use tokio::{task, time};
+
+use crate::app::config::config_get_string_map;
+use crate::app::timezone::{site_time, site_time_to_utc};
+
+lazy_static! {
+	static ref JOB_STATUS: RwLock<Vec<JobStatus>> = RwLock::new(Vec::new());
+}
+
+
+// ------------------------------
+// ---------- SCHEDULES ---------
+// ------------------------------
+
+/// When a job runs
+#[derive(Clone, Debug)]
+pub enum Schedule {
+	/// Right away and then every so often
+	Every(Duration),
+	/// Whenever the cron expression matches
+	Cron(CronSchedule),
+	/// Only if the config schedules it
+	Never,
+}
+
+impl Schedule {
+	/// The first run after the scheduler started
+	fn first_run(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+		match self {
+			Schedule::Every(_) => Some(now),
+			_ => self.next_after(now)
+		}
+	}
+
+	fn next_after(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+		match self {
+			Schedule::Every(interval) => Some(now + chrono::Duration::from_std(*interval).ok()?),
+			Schedule::Cron(cron) => {
+				// A time skipped when the clocks go forward never comes, the next match is taken instead
+				let mut local = site_time(now).naive_local();
+				for _ in 0..3 {
+					local = cron.next_after(local)?;
+					match site_time_to_utc(local) {
+						Some(tmp) => { return Some(tmp); }
+						_ => {}
+					}
+				}
+				None
+			}
+			Schedule::Never => None,
+		}
+	}
+
+	fn describe(&self) -> String {
+		match self {
+			Schedule::Every(interval) => format!("every {} seconds", interval.as_secs_f32()),
+			Schedule::Cron(cron) => cron.expression.clone(),
+			Schedule::Never => String::from("disabled"),
+		}
+	}
+}
+
+
+/// A cron expression in the timezone of the site, see `timezone`, with an optional leading seconds field
+///
+/// `minute hour day month weekday` or `second minute hour day month weekday`,
+/// each field takes `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and lists `a,b`
+#[derive(Clone, Debug)]
+pub struct CronSchedule {
+	expression: String,
+	seconds: u64,
+	minutes: u64,
+	hours: u64,
+	days: u64,
+	months: u64,
+	weekdays: u64,
+	any_day: bool,
+	any_weekday: bool,
+}
+
+impl CronSchedule {
+	pub fn parse(expression: &str) -> Result<CronSchedule, String> {
+		let mut fields: Vec<&str> = expression.split_whitespace().collect();
+
+		match fields.len() {
+			5 => { fields.insert(0, "0"); }
+			6 => {}
+			_ => { return Err(format!("'{}' needs 5 or 6 fields", expression)); }
+		}
+
+		// Sunday is 0 and 7
+		let mut weekdays = parse_cron_field(fields[5], 0, 7)?;
+		if weekdays & (1 << 7) != 0 { weekdays |= 1; }
+
+		Ok(CronSchedule {
+			expression: String::from(expression.trim()),
+			seconds: parse_cron_field(fields[0], 0, 59)?,
+			minutes: parse_cron_field(fields[1], 0, 59)?,
+			hours: parse_cron_field(fields[2], 0, 23)?,
+			days: parse_cron_field(fields[3], 1, 31)?,
+			months: parse_cron_field(fields[4], 1, 12)?,
+			weekdays,
+			any_day: fields[3].starts_with('*'),
+			any_weekday: fields[5].starts_with('*'),
+		})
+	}
+
+	/// The next matching second after `now`, `None` if there is none in the next few years
+	pub fn next_after(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+		let mut candidate = (now + chrono::Duration::seconds(1)).with_nanosecond(0)?;
+
+		for _ in 0..100000 {
+			if !self.matches_day(candidate) {
+				candidate = candidate.date().succ().and_hms(0, 0, 0);
+				continue;
+			}
+
+			if self.hours & (1 << candidate.hour()) == 0 {
+				candidate = candidate.date().and_hms(candidate.hour(), 0, 0) + chrono::Duration::hours(1);
+				continue;
+			}
+
+			if self.minutes & (1 << candidate.minute()) == 0 {
+				candidate = candidate.date().and_hms(candidate.hour(), candidate.minute(), 0) + chrono::Duration::minutes(1);
+				continue;
+			}
+
+			match (candidate.second()..60).find(|second| self.seconds & (1 << second) != 0) {
+				Some(second) => { return candidate.with_second(second); }
+				None => {
+					candidate = candidate.date().and_hms(candidate.hour(), candidate.minute(), 0) + chrono::Duration::minutes(1);
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Like cron, a day matches either field if both are restricted
+	fn matches_day(&self, candidate: NaiveDateTime) -> bool {
+		if self.months & (1 << candidate.month()) == 0 { return false; }
+
+		let day = self.days & (1 << candidate.day()) != 0;
+		let weekday = self.weekdays & (1 << candidate.weekday().num_days_from_sunday()) != 0;
+
+		match (self.any_day, self.any_weekday) {
+			(true, true) => true,
+			(true, false) => weekday,
+			(false, true) => day,
+			(false, false) => day || weekday,
+		}
+	}
+}
+
+/// Turn a single cron field into a bit mask of the allowed values
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+	let mut mask = 0u64;
+
+	for part in field.split(',') {
+		let (range, step) = match part.find('/') {
+			Some(pos) => (&part[..pos], part[pos + 1..].parse::<u32>().map_err(|_| format!("Invalid step in '{}'", part))?),
+			None => (part, 1)
+		};
+
+		let (from, to) = if range == "*" {
+			(min, max)
+		} else {
+			let mut bounds = range.splitn(2, '-').map(|tmp| tmp.parse::<u32>().map_err(|_| format!("Invalid value in '{}'", part)));
+			let from = bounds.next().unwrap_or(Err(format!("Empty value in '{}'", field)))?;
+			match bounds.next() {
+				Some(to) => (from, to?),
+				// `5/10` means from 5 to the end
+				None if step > 1 => (from, max),
+				None => (from, from),
+			}
+		};
+
+		if from < min || to > max || from > to || step == 0 {
+			return Err(format!("'{}' is out of range, allowed is {}-{}", part, min, max));
+		}
+
+		for value in (from..=to).step_by(step as usize) {
+			mask |= 1 << value;
+		}
+	}
+
+	Ok(mask)
+}
+
+
+// ------------------------------
+// ---------- SCHEDULER ---------
+// ------------------------------
+
+/// What the admin panel shows about a job
+#[derive(Clone, Debug, Serialize)]
+pub struct JobStatus {
+	name: &'static str,
+	schedule: String,
+	running: bool,
+	runs: u64,
+	last_run: Option<i64>,
+	last_duration_ms: u64,
+	last_error: Option<String>,
+	next_run: Option<i64>,
+}
+
+struct Job {
+	name: &'static str,
+	schedule: Schedule,
+	next_run: Option<NaiveDateTime>,
+	running: Arc<AtomicBool>,
+	run: Arc<dyn Fn() -> Result<(), String> + Send + Sync>,
+}
+
+/// Runs the background jobs on their schedules
+///
+/// Jobs run on blocking threads, a job that is still busy skips its next run
+pub struct Scheduler {
+	jobs: Vec<Job>,
+	on_failure: Option<Arc<dyn Fn(&str, &str) + Send + Sync>>,
+}
+
+impl Scheduler {
+	/// Constructor
+	pub fn new() -> Scheduler {
+		Scheduler { jobs: Vec::new(), on_failure: None }
+	}
+
+	/// Called with the name of the job and the error whenever a job fails
+	pub fn on_failure<F: Fn(&str, &str) + Send + Sync + 'static>(&mut self, callback: F) {
+		self.on_failure = Some(Arc::new(callback));
+	}
+
+	/// Add a job, a cron expression in `job_schedules.<name>` of the config replaces the default schedule
+	pub fn add<F: Fn() -> Result<(), String> + Send + Sync + 'static>(&mut self, name: &'static str, default: Schedule, run: F) {
+		let schedule = match config_get_string_map("job_schedules").get(name) {
+			Some(expression) => {
+				match CronSchedule::parse(expression) {
+					Ok(tmp) => Schedule::Cron(tmp),
+					Err(err) => {
+						println!("Invalid schedule for job '{}': {}, using the default", name, err);
+						default
+					}
+				}
+			}
+			None => default
+		};
+
+		self.jobs.push(Job { name, schedule, next_run: None, running: Arc::new(AtomicBool::new(false)), run: Arc::new(run) });
+	}
+
+	/// Run the jobs forever
+	pub async fn run(mut self) {
+		let now = Utc::now().naive_utc();
+
+		match JOB_STATUS.write() {
+			Ok(mut guard) => {
+				for job in self.jobs.iter_mut() {
+					job.next_run = job.schedule.first_run(now);
+					println!("Scheduled '{}' {}", job.name, job.schedule.describe());
+
+					guard.push(JobStatus {
+						name: job.name,
+						schedule: job.schedule.describe(),
+						running: false,
+						runs: 0,
+						last_run: None,
+						last_duration_ms: 0,
+						last_error: None,
+						next_run: job.next_run.map(|tmp| tmp.timestamp()),
+					});
+				}
+			}
+			_ => {}
+		}
+
+		loop {
+			let next_run = match self.jobs.iter().filter_map(|job| job.next_run).min() {
+				Some(tmp) => tmp,
+				None => return
+			};
+			time::delay_for((next_run - Utc::now().naive_utc()).to_std().unwrap_or(Duration::from_secs(0))).await;
+
+			let now = Utc::now().naive_utc();
+			for job in self.jobs.iter_mut() {
+				match job.next_run {
+					Some(tmp) if tmp <= now => {}
+					_ => { continue; }
+				}
+
+				job.next_run = job.schedule.next_after(now);
+				let next_run = job.next_run.map(|tmp| tmp.timestamp());
+				update_job_status(job.name, |status| status.next_run = next_run);
+
+				// Still busy with the last run
+				if job.running.swap(true, Ordering::SeqCst) { continue; }
+
+				let (name, run, running, on_failure) = (job.name, job.run.clone(), job.running.clone(), self.on_failure.clone());
+				task::spawn_blocking(move || {
+					update_job_status(name, |status| status.running = true);
+
+					let started = Utc::now().naive_utc();
+					// A panicking job must not stay running forever, it counts as failed
+					let result = match panic::catch_unwind(AssertUnwindSafe(|| run())) {
+						Ok(tmp) => tmp,
+						Err(payload) => {
+							let message = payload.downcast_ref::<&str>().map(|tmp| String::from(*tmp))
+								.or_else(|| payload.downcast_ref::<String>().cloned())
+								.unwrap_or_default();
+							Err(format!("the job panicked: {}", message))
+						}
+					};
+					let duration = Utc::now().naive_utc() - started;
+
+					match &result {
+						Err(err) => {
+							println!("Job '{}' failed: {}", name, err);
+							match &on_failure {
+								Some(callback) => callback(name, err),
+								_ => {}
+							}
+						}
+						_ => {}
+					}
+
+					update_job_status(name, |status| {
+						status.running = false;
+						status.runs += 1;
+						status.last_run = Some(started.timestamp());
+						status.last_duration_ms = duration.num_milliseconds().max(0) as u64;
+						status.last_error = result.err();
+					});
+					running.store(false, Ordering::SeqCst);
+				});
+			}
+		}
+	}
+}
+
+fn update_job_status<F: FnOnce(&mut JobStatus)>(name: &str, update: F) {
+	match JOB_STATUS.write() {
+		Ok(mut guard) => {
+			match guard.iter_mut().find(|status| status.name == name) {
+				Some(status) => update(status),
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+}
+
+/// The status of all scheduled jobs
+pub fn scheduler_get_jobs() -> Vec<JobStatus> {
+	match JOB_STATUS.read() {
+		Ok(guard) => guard.clone(),
+		_ => Vec::new()
+	}
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use regex::Regex;
+use tera::{Error, Result, Tera, Value};
+
+use crate::app::assets::asset_url;
+use crate::app::config::config_get_string;
+use crate::app::timezone::site_time_from_timestamp;
+use crate::blog::gallery::gallery_source_with_size;
+
+lazy_static! {
+	static ref HTML_TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+	static ref WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+	static ref SPACE_BEFORE_PUNCTUATION: Regex = Regex::new(r"\s+(?P<p>[,.;:!?])").unwrap();
+}
+
+/// Characters `excerpt` keeps unless told otherwise
+const EXCERPT_LENGTH: u64 = 200;
+
+
+// ------------------------------
+// ---------- TEMPLATES ---------
+// ------------------------------
+
+/// Make the filters and functions below available to every template
+///
+/// `gallery_size` is the same as `gallery_url`, for templates written before it
+pub fn register_template_functions(tera: &mut Tera) {
+	tera.register_filter("format_date", format_date_filter);
+	tera.register_filter("excerpt", excerpt_filter);
+	tera.register_filter("slugify", slugify_filter);
+	tera.register_filter("asset_url", asset_url_filter);
+	tera.register_function("asset", asset_function);
+	tera.register_filter("gallery_url", gallery_url_filter);
+	tera.register_filter("gallery_size", gallery_url_filter);
+}
+
+/// A unix timestamp as a date in the language and timezone of the blog, `locale` or the given one
+///
+/// `format` is `short`, `long` (the default), `month` or a chrono format string like `%Y`
+fn format_date_filter(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+	let timestamp = match value.as_i64() {
+		Some(tmp) => tmp,
+		_ => { return Err(Error::msg("format_date needs a unix timestamp")); }
+	};
+	let locale = match args.get("locale").and_then(|tmp| tmp.as_str()) {
+		Some(tmp) => String::from(tmp),
+		_ => config_get_string("locale")
+	};
+	let format = args.get("format").and_then(|tmp| tmp.as_str()).unwrap_or("long");
+
+	// In the timezone of the site unless another one is given, e.g. timezone="America/New_York"
+	let date = match args.get("timezone").and_then(|tmp| tmp.as_str()).map(|tmp| tmp.parse::<Tz>()) {
+		Some(Ok(tz)) => tz.from_utc_datetime(&NaiveDateTime::from_timestamp(timestamp, 0)).naive_local(),
+		Some(Err(_)) => { return Err(Error::msg("format_date got an unknown timezone")); }
+		_ => site_time_from_timestamp(timestamp as u64).naive_local()
+	};
+
+	Ok(Value::String(format_date(date, &locale, format)))
+}
+
+fn format_date(date: NaiveDateTime, locale: &str, format: &str) -> String {
+	let language = locale.split(['_', '-']).next().unwrap_or("").to_lowercase();
+	let (day, year) = (date.day(), date.year());
+	let month = month_name(&language, date.month0() as usize);
+
+	match (format, language.as_str()) {
+		("short", "de") => date.format("%d.%m.%Y").to_string(),
+		("short", "en") if locale.ends_with("US") => date.format("%m/%d/%Y").to_string(),
+		("short", _) => date.format("%d/%m/%Y").to_string(),
+		("month", "es") | ("month", "pt") => format!("{} de {}", month, year),
+		("month", _) => format!("{} {}", month, year),
+		("long", "de") => format!("{}. {} {}", day, month, year),
+		("long", "es") | ("long", "pt") => format!("{} de {} de {}", day, month, year),
+		("long", "fr") | ("long", "it") | ("long", "nl") => format!("{} {} {}", day, month, year),
+		("long", _) => format!("{} {}, {}", month, day, year),
+		_ => date.format(format).to_string()
+	}
+}
+
+/// English for languages we have no names for
+fn month_name(language: &str, month: usize) -> &'static str {
+	let names: [&str; 12] = match language {
+		"de" => ["Januar", "Februar", "März", "April", "Mai", "Juni", "Juli", "August", "September", "Oktober", "November", "Dezember"],
+		"es" => ["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "octubre", "noviembre", "diciembre"],
+		"fr" => ["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre"],
+		"it" => ["gennaio", "febbraio", "marzo", "aprile", "maggio", "giugno", "luglio", "agosto", "settembre", "ottobre", "novembre", "dicembre"],
+		"nl" => ["januari", "februari", "maart", "april", "mei", "juni", "juli", "augustus", "september", "oktober", "november", "december"],
+		"pt" => ["janeiro", "fevereiro", "março", "abril", "maio", "junho", "julho", "agosto", "setembro", "outubro", "novembro", "dezembro"],
+		_ => ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"]
+	};
+	names[month.min(11)]
+}
+
+/// Plain text from HTML, cut at a word boundary after at most `length` characters
+fn excerpt_filter(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+	let html = match value.as_str() {
+		Some(tmp) => tmp,
+		_ => { return Ok(value.clone()); }
+	};
+	let length = args.get("length").and_then(|tmp| tmp.as_u64()).unwrap_or(EXCERPT_LENGTH) as usize;
+
+	let text = HTML_TAG.replace_all(html, " ");
+	let text = WHITESPACE.replace_all(&text, " ");
+	let text = SPACE_BEFORE_PUNCTUATION.replace_all(&text, "$p");
+	let text = text.trim();
+	if text.chars().count() <= length { return Ok(Value::String(String::from(text))); }
+
+	let cut: String = text.chars().take(length).collect();
+	let cut = match cut.rfind(' ') {
+		Some(pos) if pos > 0 => &cut[..pos],
+		_ => &cut[..]
+	};
+	Ok(Value::String(format!("{}…", cut.trim_end_matches(|c: char| c.is_ascii_punctuation()))))
+}
+
+/// Lower case letters, digits and dashes, umlauts and accents are spelled out
+fn slugify_filter(value: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+	match value.as_str() {
+		Some(tmp) => Ok(Value::String(slugify(tmp))),
+		_ => Ok(value.clone())
+	}
+}
+
+/// Lower case letters, digits and dashes, also used to find posts by a differently spelled url
+pub fn slugify(text: &str) -> String {
+	let mut slug = String::new();
+	for c in text.to_lowercase().chars() {
+		let replacement = match c {
+			'ä' | 'æ' => "ae",
+			'ö' | 'ø' | 'œ' => "oe",
+			'ü' => "ue",
+			'ß' => "ss",
+			'à' | 'á' | 'â' | 'ã' | 'å' => "a",
+			'ç' => "c",
+			'è' | 'é' | 'ê' | 'ë' => "e",
+			'ì' | 'í' | 'î' | 'ï' => "i",
+			'ñ' => "n",
+			'ò' | 'ó' | 'ô' | 'õ' => "o",
+			'ù' | 'ú' | 'û' => "u",
+			'ý' | 'ÿ' => "y",
+			c if c.is_ascii_alphanumeric() => { slug.push(c); continue; }
+			_ => "-"
+		};
+		slug.push_str(replacement);
+	}
+
+	slug.split('-').filter(|tmp| tmp.len() > 0).collect::<Vec<&str>>().join("-")
+}
+
+/// The fingerprinted URL of a static file, see `asset_url`
+fn asset_url_filter(value: &Value, _: &HashMap<String, Value>) -> Result<Value> {
+	match value.as_str() {
+		Some(name) => Ok(Value::String(asset_url(name))),
+		_ => Err(Error::msg("asset_url needs the name of a static file"))
+	}
+}
+
+fn asset_function(args: &HashMap<String, Value>) -> Result<Value> {
+	match args.get("name").and_then(|tmp| tmp.as_str()) {
+		Some(name) => Ok(Value::String(asset_url(name))),
+		_ => Err(Error::msg("asset needs a name, e.g. name=\"css/site.css\""))
+	}
+}
+
+/// A gallery source in the given size, a bare guid needs the extension as well: `gallery_url(size="w400", ext="jpg")`
+///
+/// Missing sources stay missing, so the filter can be used on optional fields
+fn gallery_url_filter(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+	let size = match args.get("size").and_then(|tmp| tmp.as_str()) {
+		Some(tmp) => tmp,
+		_ => { return Err(Error::msg("gallery_url needs a size, e.g. size=\"w400\"")); }
+	};
+
+	match (value.as_str(), args.get("ext").and_then(|tmp| tmp.as_str())) {
+		(Some(guid), Some(ext)) if !guid.contains('/') => {
+			Ok(Value::String(gallery_source_with_size(&format!("/gallery/{}.{}", guid, ext), size)))
+		}
+		(Some(source), _) => Ok(Value::String(gallery_source_with_size(source, size))),
+		_ => Ok(value.clone())
+	}
+}
@@ -0,0 +1,56 @@
+use chrono::{DateTime, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::app::config::config_get_string;
+
+
+// ------------------------------
+// ---------- TIMEZONE ----------
+// ------------------------------
+
+/// The timezone of the blog from `timezone`, e.g. `Europe/Berlin`
+///
+/// Timestamps are stored in UTC, this is only applied when showing or grouping them. UTC if unset or unknown
+pub fn site_timezone() -> Tz {
+	config_get_string("timezone").parse::<Tz>().unwrap_or(Tz::UTC)
+}
+
+/// Check the configured timezone, so a typo does not go unnoticed
+pub fn timezone_check() -> Result<Tz, String> {
+	match config_get_string("timezone") {
+		tmp if tmp.len() == 0 => Ok(Tz::UTC),
+		tmp => tmp.parse::<Tz>().map_err(|_| format!("'{}' is not a known timezone, using UTC", tmp))
+	}
+}
+
+/// A UTC time in the timezone of the blog
+pub fn site_time(utc: NaiveDateTime) -> DateTime<Tz> {
+	site_timezone().from_utc_datetime(&utc)
+}
+
+/// A unix timestamp in the timezone of the blog
+pub fn site_time_from_timestamp(timestamp: u64) -> DateTime<Tz> {
+	site_time(NaiveDateTime::from_timestamp(timestamp as i64, 0))
+}
+
+/// A wall clock time of the blog in UTC
+///
+/// Times skipped when the clocks go forward do not exist, repeated ones are taken the first time
+pub fn site_time_to_utc(local: NaiveDateTime) -> Option<NaiveDateTime> {
+	site_timezone().from_local_datetime(&local).earliest().map(|tmp| tmp.naive_utc())
+}
+
+/// How far the blog is ahead of UTC right now, in seconds
+pub fn site_utc_offset_seconds() -> i64 {
+	site_time(Utc::now().naive_utc()).offset().fix().local_minus_utc() as i64
+}
+
+/// For RSS, e.g. `Tue, 5 Mar 2024 10:00:00 +0100`
+pub fn format_rfc2822(timestamp: u64) -> String {
+	site_time_from_timestamp(timestamp).to_rfc2822()
+}
+
+/// For site maps, e.g. `2024-03-05T10:00:00+01:00`
+pub fn format_w3c(timestamp: u64) -> String {
+	site_time_from_timestamp(timestamp).to_rfc3339()
+}
@@ -1,12 +1,14 @@
 use std::ffi::OsStr;
 use std::path::Path;
 use std::str;
+use std::time::Duration;
 
-use curl::easy::Easy;
+use curl::easy::{Easy, List};
 use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
 use rand::Rng;
 
-use crate::app::config::config_get_string;
+use crate::app::config::{config_get_string, config_get_string_vec};
 
 // ------------------------------
 // ---------- Helpers -----------
@@ -17,6 +19,11 @@ pub fn weak_random_base62_string(len: usize) -> String {
 	rand::thread_rng().sample_iter(&Alphanumeric).take(len).collect()
 }
 
+/// A random alphanumeric string from the OS generator, for anything security related
+pub fn secure_random_base62_string(len: usize) -> String {
+	OsRng.sample_iter(&Alphanumeric).take(len).collect()
+}
+
 /// Extract the extension from the given file name
 pub fn get_extension_from_filename(filename: &str) -> Option<&str> {
 	Path::new(filename).extension().and_then(OsStr::to_str)
@@ -60,45 +67,68 @@ fn curl_fetch(url: &str) -> Option<String> {
 	}
 
 	match String::from_utf8(dst) {
-		Ok(str) => { return Some(String::from(str)); }
+		Ok(str) => { return Some(str); }
 		_ => {}
 	}
 
 	None
 }
 
-//fn curl_post(url: &str) -> Option<String> {
-//    let mut data = "this is the body".as_bytes();
-//    let mut easy = Easy::new();
-//
-//    match easy.url(url) {
-//        Ok(()) => {}
-//        _ => { return None; }
-//    }
-//
-//    match easy.post(true) {
-//        Ok(()) => {}
-//        _ => { return None; }
-//    }
-//
-//    match easy.post_field_size(data.len() as u64) {
-//        Ok(()) => {}
-//        _ => { return None; }
-//    }
-//
-//    let mut transfer = easy.transfer();
-//
-////    transfer.read_function(|buf| {
-////        Ok(data.read(buf).unwrap_or(0))
-////    }).unwrap();
-//
-//    match transfer.perform() {
-//        Ok(()) => {}
-//        _ => { return None; }
-//    }
-//
-//    None
-//}
+/// POST a JSON body with some extra headers, gives up after `timeout_secs`
+///
+/// Returns the HTTP status code, any answer counts - the caller decides what a success is
+pub fn curl_post_json(url: &str, body: &str, headers: &[String], timeout_secs: u64) -> Result<u32, String> {
+	let mut easy = Easy::new();
+	let mut list = List::new();
+	list.append("Content-Type: application/json").map_err(|err| err.to_string())?;
+	for header in headers {
+		list.append(header).map_err(|err| err.to_string())?;
+	}
+
+	easy.url(url).map_err(|err| err.to_string())?;
+	easy.http_headers(list).map_err(|err| err.to_string())?;
+	easy.post(true).map_err(|err| err.to_string())?;
+	easy.post_fields_copy(body.as_bytes()).map_err(|err| err.to_string())?;
+	easy.timeout(Duration::from_secs(timeout_secs)).map_err(|err| err.to_string())?;
+
+	{
+		// The answer is not needed, but curl would print it otherwise
+		let mut transfer = easy.transfer();
+		transfer.write_function(|data| Ok(data.len())).map_err(|err| err.to_string())?;
+		transfer.perform().map_err(|err| err.to_string())?;
+	}
+
+	easy.response_code().map_err(|err| err.to_string())
+}
+
+/// Same as `curl_post_json`, but the answer is returned along with the HTTP status code
+pub fn curl_post_json_with_answer(url: &str, body: &str, headers: &[String], timeout_secs: u64) -> Result<(u32, String), String> {
+	let mut easy = Easy::new();
+	let mut list = List::new();
+	list.append("Content-Type: application/json").map_err(|err| err.to_string())?;
+	for header in headers {
+		list.append(header).map_err(|err| err.to_string())?;
+	}
+
+	easy.url(url).map_err(|err| err.to_string())?;
+	easy.http_headers(list).map_err(|err| err.to_string())?;
+	easy.post(true).map_err(|err| err.to_string())?;
+	easy.post_fields_copy(body.as_bytes()).map_err(|err| err.to_string())?;
+	easy.timeout(Duration::from_secs(timeout_secs)).map_err(|err| err.to_string())?;
+
+	let mut answer = Vec::new();
+	{
+		let mut transfer = easy.transfer();
+		transfer.write_function(|data| {
+			answer.extend_from_slice(data);
+			Ok(data.len())
+		}).map_err(|err| err.to_string())?;
+		transfer.perform().map_err(|err| err.to_string())?;
+	}
+
+	let code = easy.response_code().map_err(|err| err.to_string())?;
+	Ok((code, String::from_utf8_lossy(&answer).to_string()))
+}
 
 
 // ------------------------------
@@ -253,4 +283,30 @@ pub fn fetch_pinterest_feed() -> Option<Vec<PinterestPostCompact>> {
 	}
 
 	None
-}
\ No newline at end of file
+}
+
+
+// ------------------------------
+// ---------- SITEMAP -----------
+// ------------------------------
+
+/// Tell search engines about the sitemap
+///
+/// `sitemap_ping_urls` holds the ping URLs, `%SITEMAP%` is replaced with the sitemap URL
+pub fn ping_sitemap() -> Result<(), String> {
+	let sitemap = Easy::new().url_encode(format!("https://{}/sitemap.xml", config_get_string("fqdn")).as_bytes());
+	let mut failed = Vec::new();
+
+	for url in config_get_string_vec("sitemap_ping_urls") {
+		match curl_fetch(url.replace("%SITEMAP%", sitemap.as_str()).as_str()) {
+			Some(_) => {}
+			None => { failed.push(url); }
+		}
+	}
+
+	if failed.len() > 0 {
+		return Err(format!("Could not ping {}", failed.join(", ")));
+	}
+
+	Ok(())
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::app::utils::secure_random_base62_string;
+use crate::storage::{DbRow, Storage};
+
+/// What a token can be allowed to do, each scope unlocks a single route
+///
+/// `publish_post` is `/admin/set_post`, `reload_data` is `/admin/reload_data`, `export` is `/admin/export_post`
+pub const API_TOKEN_SCOPES: &[&str] = &["publish_post", "reload_data", "export"];
+
+/// Tokens start with this, so they are easy to spot in a leaked config
+const API_TOKEN_PREFIX: &str = "nd_";
+const API_TOKEN_LENGTH: usize = 40;
+
+
+/// A token for scripts and CI jobs, it acts as the admin who created it within its scopes
+#[derive(Serialize, Debug)]
+pub struct ApiToken {
+	pub id: u32,
+	pub name: String,
+	pub scopes: Vec<String>,
+	/// The admin who created the token
+	pub user_id: u32,
+	pub created_at: u64,
+	pub last_used_at: Option<u64>,
+	pub revoked: bool,
+}
+
+impl ApiToken {
+	fn from_sql(mut row: DbRow) -> Option<ApiToken> {
+		Some(ApiToken {
+			id: row.take("id")?,
+			name: row.take("name")?,
+			scopes: split_scopes(&row.take::<String>("scopes")?),
+			user_id: row.take("user_id")?,
+			created_at: row.take::<NaiveDateTime>("created_at")?.timestamp() as u64,
+			last_used_at: row.take::<Option<NaiveDateTime>>("last_used_at")?.map(|tmp| tmp.timestamp() as u64),
+			revoked: row.take("revoked")?,
+		})
+	}
+}
+
+
+/// Create a token with the given scopes
+///
+/// Returns the id and the token itself, only its hash is stored so it can not be shown again
+pub fn create_api_token(db: &Arc<dyn Storage>, user_id: u32, name: &str, scopes: &Vec<String>) -> Result<(u64, String), String> {
+	let name = name.trim();
+	if name.len() == 0 || name.chars().count() > 64 {
+		return Err(String::from("The name must have 1 to 64 characters"));
+	}
+	if scopes.len() == 0 {
+		return Err(String::from("The token needs at least one scope"));
+	}
+	match scopes.iter().find(|scope| !API_TOKEN_SCOPES.contains(&scope.as_str())) {
+		Some(scope) => { return Err(format!("'{}' is not one of {}", scope, API_TOKEN_SCOPES.join(", "))); }
+		_ => {}
+	}
+
+	let token = format!("{}{}", API_TOKEN_PREFIX, secure_random_base62_string(API_TOKEN_LENGTH));
+
+	let query = "INSERT INTO api_tokens (name, token_hash, scopes, user_id, created_at, revoked) VALUES (:name, :token_hash, :scopes, :user_id, :created_at, 0)";
+	match db.exec(query, db_params! {"name" => name, "token_hash" => hash_token(&token), "scopes" => scopes.join(","), "user_id" => user_id, "created_at" => Utc::now().naive_utc()}) {
+		Ok(res) => Ok((res.last_insert_id, token)),
+		Err(err) => {
+			println!("Error storing API token: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
+
+/// All tokens, revoked ones included, the newest first
+pub fn load_api_tokens(db: &Arc<dyn Storage>) -> Result<Vec<ApiToken>, String> {
+	let query = "SELECT id, name, scopes, user_id, created_at, last_used_at, revoked FROM api_tokens ORDER BY id DESC";
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(ApiToken::from_sql).collect())
+}
+
+/// A revoked token stays in the list, so it is clear what it was used for
+pub fn revoke_api_token(db: &Arc<dyn Storage>, id: u32) -> Result<(), String> {
+	match db.exec("UPDATE api_tokens SET revoked=1 WHERE id=:id", db_params! {"id" => id}) {
+		Ok(res) if res.affected_rows == 0 => Err(String::from("Token not found")),
+		Ok(_) => Ok(()),
+		Err(err) => Err(err.to_string())
+	}
+}
+
+/// The user a token acts as, if the token is valid and has the scope
+pub fn check_api_token(db: &Arc<dyn Storage>, token: &str, scope: &str) -> Option<u32> {
+	if !token.starts_with(API_TOKEN_PREFIX) { return None; }
+
+	let token_hash = hash_token(token);
+	let mut row = match db.query_first("SELECT id, scopes, user_id FROM api_tokens WHERE token_hash=:token_hash AND revoked=0", db_params! {"token_hash" => &token_hash}) {
+		Ok(Some(tmp)) => tmp,
+		_ => { return None; }
+	};
+
+	let id: u32 = row.take("id")?;
+	if !split_scopes(&row.take::<String>("scopes")?).iter().any(|tmp| tmp == scope) { return None; }
+
+	match db.exec("UPDATE api_tokens SET last_used_at=:now WHERE id=:id", db_params! {"now" => Utc::now().naive_utc(), "id" => id}) {
+		Err(err) => { println!("Error updating API token {}: {:?}", id, err); }
+		_ => {}
+	}
+
+	row.take("user_id")
+}
+
+fn split_scopes(scopes: &str) -> Vec<String> {
+	scopes.split(',').map(|tmp| tmp.trim()).filter(|tmp| tmp.len() > 0).map(String::from).collect()
+}
+
+/// Same as the reset tokens, a leaked table does not give access
+fn hash_token(token: &str) -> String {
+	format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::{Error, HttpMessage};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use chrono::Utc;
+use futures::future::{ok, Ready};
+
+use crate::storage::Storage;
+
+/// Write an entry to the audit log
+///
+/// Failures are only logged, an audit problem should not break the action itself
+pub fn log_audit_event(db: &Arc<dyn Storage>, user_id: u32, action: &str, detail: &str) {
+	let query = "INSERT INTO audit_log (user_id, action, detail, created_at) VALUES (:user_id, :action, :detail, :created_at)";
+
+	match db.exec(query, db_params! {"user_id" => user_id, "action" => action, "detail" => detail, "created_at" => Utc::now().naive_utc()}) {
+		Ok(_) => {}
+		Err(err) => { println!("Error writing audit log [{}] {}: {:?}", action, detail, err); }
+	}
+}
+
+
+/// Middleware that writes every change made while impersonating a user to the audit log, under the admin
+///
+/// Reading requests are left out, they do not change anything
+pub struct AuditImpersonation;
+
+impl<S, B> Transform<S> for AuditImpersonation
+	where
+		S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+		S::Future: 'static,
+		B: 'static,
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type InitError = ();
+	type Transform = AuditImpersonationMiddleware<S>;
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(AuditImpersonationMiddleware { service })
+	}
+}
+
+pub struct AuditImpersonationMiddleware<S> {
+	service: S,
+}
+
+impl<S, B> Service for AuditImpersonationMiddleware<S>
+	where
+		S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+		S::Future: 'static,
+		B: 'static,
+{
+	type Request = ServiceRequest;
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = S::Future;
+
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: ServiceRequest) -> Self::Future {
+		if req.method() != Method::GET && req.method() != Method::HEAD && req.method() != Method::OPTIONS {
+			match (req.cookie("nd_user"), req.app_data::<Arc<dyn Storage>>()) {
+				(Some(cookie), Some(db)) => {
+					match crate::auth::check_token(&db, cookie.value()) {
+						Some(token) => {
+							match token.impersonated_by {
+								Some(admin_id) => {
+									log_audit_event(&db, admin_id, "impersonated_request", &format!("admin {} as user {} ({}): {} {}", admin_id, token.sub, token.name, req.method(), req.path()));
+								}
+								_ => {}
+							}
+						}
+						_ => {}
+					}
+				}
+				_ => {}
+			}
+		}
+
+		self.service.call(req)
+	}
+}
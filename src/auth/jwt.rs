@@ -1,17 +1,28 @@
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use frank_jwt::{Algorithm, decode, encode, ValidationOptions};
 use serde_json::Error;
 
-use crate::app::config::config_get_string;
+use crate::app::config::{config_get_i64, config_get_string};
 use crate::auth::user::User;
+use crate::storage::Storage;
 
 // We will use the HMAC algo for now, as we are the only signing and verifying party
 const JWT_ALGO: Algorithm = Algorithm::HS256;
 
+/// Impersonation tokens expire after 15 minutes
+const IMPERSONATION_LIFETIME_SECS: u64 = 900;
+
+/// Visitors may edit their comments for 15 minutes, unless `comment_edit_minutes` says otherwise
+const COMMENT_EDIT_MINUTES: u64 = 15;
+
+/// Marks tokens that grant editing a comment, so they can not be mistaken for anything else
+const COMMENT_EDIT_PURPOSE: &str = "comment_edit";
+
 
 /// Authenticate the user and return a stringified `UserJWT` on success
-pub fn handle_auth_request(db: &mysql::Pool, login: &String, pass: &String) -> Option<(u32, String, String)> {
+pub fn handle_auth_request(db: &Arc<dyn Storage>, login: &String, pass: &String) -> Option<(u32, String, String)> {
 	// Fetch required data from the user database
 	let user = match User::get_user_from_db(db, login) {
 		Some(tmp) => { tmp }
@@ -21,32 +32,94 @@ pub fn handle_auth_request(db: &mysql::Pool, login: &String, pass: &String) -> O
 	// Verify the users authenticity
 	if !user.verify_password(pass) { return None; }
 
-	// Create the token
-	match UserJWT::create_token_for_user(&user).to_serde_value() {
+	// Move old scrypt hashes over to Argon2 while we have the plain password
+	if user.needs_rehash() {
+		match User::store_password(db, user.id, pass) {
+			Ok(()) => { println!("Upgraded the password hash of user {}", user.id); }
+			Err(err) => { println!("Could not upgrade the password hash of user {}: {}", user.id, err); }
+		}
+	}
+
+	create_jwt(user)
+}
+
+/// Create a new token for the user with the given id, without checking a password
+pub fn create_jwt_for_user_id(db: &Arc<dyn Storage>, user_id: u32) -> Option<(u32, String, String)> {
+	create_jwt(User::get_user_by_id(db, user_id)?)
+}
+
+/// Create and sign a token for the given user
+fn create_jwt(user: User) -> Option<(u32, String, String)> {
+	let jwt = sign_jwt(&UserJWT::create_token_for_user(&user))?;
+	Some((user.id, user.display_name, jwt))
+}
+
+/// Create a short lived token that lets an admin act as another user
+///
+/// Both the admin and the impersonated user can revoke it through their token serial
+pub fn create_impersonation_jwt(db: &Arc<dyn Storage>, admin_id: u32, admin_serial: u32, user_id: u32) -> Option<(u32, String, String)> {
+	let user = User::get_user_by_id(db, user_id)?;
+
+	let mut token = UserJWT::create_token_for_user(&user);
+	token.impersonated_by = Some(admin_id);
+	token.serial_admin = admin_serial;
+	token.exp = token.iat + IMPERSONATION_LIFETIME_SECS;
+
+	let jwt = sign_jwt(&token)?;
+	Some((user.id, user.display_name, jwt))
+}
+
+/// Sign the given token
+fn sign_jwt(token: &UserJWT) -> Option<String> {
+	match token.to_serde_value() {
 		Ok(payload) => {
 			let header = json!({});
 			let secret = config_get_string("jwt_hmac_secret");
 
-			match encode(header, &secret, &payload, JWT_ALGO) {
-				Ok(jwt) => Some((user.id, user.display_name, jwt)),
-				_ => None
-			}
+			encode(header, &secret, &payload, JWT_ALGO).ok()
 		}
 		_ => None
 	}
 }
 
+/// Create a token that lets a visitor edit or delete the comment they just wrote
+///
+/// Returns the token and the unix time it expires at
+pub fn create_comment_edit_token(comment_id: u32) -> Option<(String, u64)> {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+	let minutes = match config_get_i64("comment_edit_minutes") {
+		tmp if tmp > 0 => tmp as u64,
+		_ => COMMENT_EDIT_MINUTES
+	};
+
+	let token = CommentEditJWT { comment_id, purpose: String::from(COMMENT_EDIT_PURPOSE), exp: now + minutes * 60 };
+	let payload = serde_json::to_value(&token).ok()?;
+
+	match encode(json!({}), &config_get_string("jwt_hmac_secret"), &payload, JWT_ALGO) {
+		Ok(jwt) => Some((jwt, token.exp)),
+		_ => None
+	}
+}
+
+/// The id of the comment the token grants editing, if it is valid and did not expire
+pub fn decode_comment_edit_token(token: &str) -> Option<u32> {
+	let (_header, payload) = decode(&String::from(token), &config_get_string("jwt_hmac_secret"), JWT_ALGO, &ValidationOptions::dangerous()).ok()?;
+	let token: CommentEditJWT = serde_json::from_value(payload).ok()?;
+
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+	if token.purpose != COMMENT_EDIT_PURPOSE || token.exp < now { return None; }
+
+	Some(token.comment_id)
+}
+
 
 /// Attempt to decode and validate the stringified jwt given
 ///
 /// on success, returns a `UserJWT`
-pub fn jwt_decode(token: &String) -> Option<UserJWT> {
+pub fn jwt_decode(token: &str) -> Option<UserJWT> {
 	match decode(token, &config_get_string("jwt_hmac_secret"), JWT_ALGO, &ValidationOptions::dangerous()) {
 		Ok((_header, payload)) => {
-			match UserJWT::from_serde_value(payload) {
-				Ok(jwt) => Some(jwt),
-				_ => None
-			}
+			UserJWT::from_serde_value(payload).ok()
 		}
 		_ => { None }
 	}
@@ -64,6 +137,26 @@ pub struct UserJWT {
 	pub name: String,
 	/// things the user can do
 	pub permissions: Vec<String>,
+	/// the token serial of the user at the time the token was issued
+	#[serde(default)]
+	pub serial: u32,
+	/// expiry time, 0 for tokens that do not expire
+	#[serde(default)]
+	pub exp: u64,
+	/// the admin acting as this user
+	#[serde(default)]
+	pub impersonated_by: Option<u32>,
+	/// the token serial of the impersonating admin
+	#[serde(default)]
+	pub serial_admin: u32,
+}
+
+/// Grants the author of a comment editing it for a while
+#[derive(Serialize, Deserialize)]
+struct CommentEditJWT {
+	comment_id: u32,
+	purpose: String,
+	exp: u64,
 }
 
 impl UserJWT {
@@ -88,6 +181,28 @@ impl UserJWT {
 			},
 			name: user.display_name.clone(),
 			permissions: user.permissions.clone(),
+			serial: user.token_serial,
+			exp: 0,
+			impersonated_by: None,
+			serial_admin: 0,
 		}
 	}
+
+	/// The posts this user may see and edit in the admin panel: all for admins, only their own for authors
+	pub fn author_scope(&self) -> Option<u32> {
+		match self.permissions.contains(&String::from("admin")) {
+			true => None,
+			false => Some(self.sub)
+		}
+	}
+
+	/// True if the token has an expiry time that lies in the past
+	pub fn is_expired(&self) -> bool {
+		let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+			Ok(tmp) => tmp.as_secs(),
+			_ => 0
+		};
+
+		self.exp != 0 && self.exp < now
+	}
 }
\ No newline at end of file
@@ -3,8 +3,17 @@ use std::sync::Arc;
 
 use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse, web};
 use actix_web::cookie::Cookie;
+use actix_web::http::StatusCode;
 
+use crate::app::api::{api_error, api_ok, ApiResponse};
+use crate::app::config::config_get_string;
+use crate::storage::Storage;
+
+pub mod api_token;
+pub mod audit;
 pub mod jwt;
+pub mod reset;
+pub mod session;
 pub mod user;
 
 
@@ -18,22 +27,70 @@ pub struct AuthRequestUserData {
 	pass: String,
 }
 
+#[derive(Deserialize)]
+pub struct AuthRequestChangePassword {
+	old_pass: String,
+	new_pass: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuthRequestReset {
+	login: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuthRequestResetPassword {
+	token: String,
+	pass: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequestImpersonate {
+	user_id: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequestRevoke {
+	user_id: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequestCreateApiToken {
+	name: String,
+	scopes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequestRevokeApiToken {
+	id: u32,
+}
+
 
 // ------------------------------
 // ---------- Response ----------
 // ------------------------------
 
-#[derive(Serialize)]
-pub struct AuthResponseError {
-	error: String,
-}
-
 #[derive(Serialize)]
 pub struct AuthResponseDefault {
 	#[serde(rename = "displayName")]
 	display_name: String,
 	#[serde(rename = "userId")]
 	user_id: u32,
+	#[serde(rename = "impersonatedBy", skip_serializing_if = "Option::is_none")]
+	impersonated_by: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct AuthResponseRevoke {
+	user_id: u32,
+	serial: u32,
+}
+
+#[derive(Serialize)]
+pub struct AuthResponseApiToken {
+	id: u64,
+	/// Only shown this once
+	token: String,
 }
 
 // ------------------------------
@@ -41,7 +98,7 @@ pub struct AuthResponseDefault {
 // ------------------------------
 
 /// Create a cookie holding the jwt for the user
-pub fn create_cookie(value: &str) -> Cookie {
+pub fn create_cookie(value: &str) -> Cookie<'_> {
 	let tmp = Cow::Owned(String::from(value));
 
 	Cookie::build("nd_user", tmp)
@@ -64,19 +121,133 @@ pub fn is_authenticated(req: &HttpRequest) -> Option<jwt::UserJWT> {
 		_ => {}
 	}
 
+	let db = req.app_data::<web::Data<Arc<dyn Storage>>>()?;
+	check_token(db, &jwt)
+}
+
+/// Decode the JWT and make sure it was not revoked since
+fn check_token(db: &Arc<dyn Storage>, jwt: &str) -> Option<jwt::UserJWT> {
 	// Validate / decode token
-	jwt::jwt_decode(&jwt)
+	let token = jwt::jwt_decode(jwt)?;
+	if token.is_expired() { return None; }
+
+	match session::get_token_serial(db, token.sub) {
+		Some(serial) if serial == token.serial => {}
+		_ => { return None; }
+	}
+
+	// Impersonation ends once the sessions of the admin are revoked
+	match token.impersonated_by {
+		Some(admin_id) => {
+			if session::get_token_serial(db, admin_id)? != token.serial_admin { return None; }
+		}
+		_ => {}
+	}
+
+	Some(token)
 }
 
 /// Returns true if the user is an admin
 pub fn is_admin(req: &HttpRequest) -> bool {
+	match get_admin(req) {
+		Some(_jwt) => true,
+		_ => false
+	}
+}
+
+/// Returns the JWT if the user is an admin
+pub fn get_admin(req: &HttpRequest) -> Option<jwt::UserJWT> {
 	match is_authenticated(req) {
 		Some(jwt) => {
-			if jwt.permissions.contains(&String::from("admin")) { return true; }
+			if jwt.permissions.contains(&String::from("admin")) { return Some(jwt); }
 		}
 		_ => {}
 	}
-	false
+	None
+}
+
+/// Returns the JWT if the user may write posts, that is admins and authors
+///
+/// Authors only get to see and edit their own posts, see `UserJWT::author_scope`
+pub fn get_editor(req: &HttpRequest) -> Option<jwt::UserJWT> {
+	match is_authenticated(req) {
+		Some(jwt) => {
+			if jwt.permissions.contains(&String::from("admin")) || jwt.permissions.contains(&String::from("author")) { return Some(jwt); }
+		}
+		_ => {}
+	}
+	None
+}
+
+/// Like `is_admin`, an API token with the given scope works as well
+pub fn is_admin_or_token(req: &HttpRequest, scope: &str) -> bool {
+	if is_admin(req) { return true; }
+
+	match get_api_token_user(req, scope) {
+		Some(jwt) => jwt.permissions.contains(&String::from("admin")),
+		_ => false
+	}
+}
+
+/// Like `get_editor`, an API token with the given scope works as well
+pub fn get_editor_or_token(req: &HttpRequest, scope: &str) -> Option<jwt::UserJWT> {
+	match get_editor(req) {
+		Some(jwt) => { return Some(jwt); }
+		_ => {}
+	}
+
+	let jwt = get_api_token_user(req, scope)?;
+	if jwt.permissions.contains(&String::from("admin")) || jwt.permissions.contains(&String::from("author")) { return Some(jwt); }
+	None
+}
+
+/// The user behind the API token of the request, with the permissions they have now
+///
+/// Tokens are checked against the database on every request, revoking one takes effect right away
+fn get_api_token_user(req: &HttpRequest, scope: &str) -> Option<jwt::UserJWT> {
+	let token = bearer_token(req)?;
+	let db = req.app_data::<web::Data<Arc<dyn Storage>>>()?;
+
+	let user_id = api_token::check_api_token(db, token, scope)?;
+	let user = user::User::get_user_by_id(db, user_id)?;
+	Some(jwt::UserJWT::create_token_for_user(&user))
+}
+
+/// Returns true if the request carries the token of the reload hook, the hook is off without a token
+pub fn has_reload_hook_token(req: &HttpRequest) -> bool {
+	let expected = config_get_string("reload_hook_token");
+	if expected.len() == 0 { return false; }
+
+	match bearer_token(req) {
+		Some(token) => tokens_match(token, &expected),
+		_ => false
+	}
+}
+
+/// The token of an `Authorization: Bearer` header
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+	match req.headers().get("Authorization").and_then(|tmp| tmp.to_str().ok()) {
+		Some(tmp) if tmp.starts_with("Bearer ") => Some(&tmp[7..]),
+		_ => None
+	}
+}
+
+/// Returns true if the stats page may be shown, it is public unless `stats_token` is set
+///
+/// Admins can always see it, everyone else needs `?token=`
+pub fn has_stats_access(req: &HttpRequest, token: Option<&str>) -> bool {
+	let expected = config_get_string("stats_token");
+	if expected.len() == 0 || is_admin(req) { return true; }
+
+	match token {
+		Some(token) => tokens_match(token, &expected),
+		_ => false
+	}
+}
+
+/// Compare all bytes, so the time taken does not tell how much of the token was right
+fn tokens_match(token: &str, expected: &str) -> bool {
+	token.len() == expected.len() && token.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
 }
 
 
@@ -87,21 +258,21 @@ pub fn is_admin(req: &HttpRequest) -> bool {
 /// Client calls this to check whether it is logged in or not
 pub async fn auth_check(req: HttpRequest) -> Result<HttpResponse, Error> {
 	match is_authenticated(&req) {
-		Some(jwt) => { Ok(HttpResponse::Ok().json(AuthResponseDefault { display_name: jwt.name, user_id: jwt.sub })) }
-		_ => { Ok(HttpResponse::Unauthorized().json(AuthResponseError { error: String::from("token is invalid") })) }
+		Some(jwt) => { Ok(api_ok(AuthResponseDefault { display_name: jwt.name, user_id: jwt.sub, impersonated_by: jwt.impersonated_by })) }
+		_ => { Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid")) }
 	}
 }
 
 /// Authenticate and send the jwt cookie
-pub async fn auth_login(mysql: web::Data<Arc<mysql::Pool>>, user: web::Json<AuthRequestUserData>) -> Result<HttpResponse, Error> {
-	match jwt::handle_auth_request(&mysql, &user.login, &user.pass) {
+pub async fn auth_login(db: web::Data<Arc<dyn Storage>>, user: web::Json<AuthRequestUserData>) -> Result<HttpResponse, Error> {
+	match jwt::handle_auth_request(&db, &user.login, &user.pass) {
 		Some((user_id, display_name, jwt)) => {
 			let cookie = create_cookie(&jwt);
 
-			Ok(HttpResponse::Ok().cookie(cookie).json(AuthResponseDefault { display_name, user_id }))
+			Ok(HttpResponse::Ok().cookie(cookie).json(ApiResponse::success(AuthResponseDefault { display_name, user_id, impersonated_by: None })))
 		}
 		_ => {
-			Ok(HttpResponse::InternalServerError().json(AuthResponseError { error: String::from("invalid login") }))
+			Ok(api_error(StatusCode::UNAUTHORIZED, "invalid login"))
 		}
 	}
 }
@@ -110,5 +281,171 @@ pub async fn auth_login(mysql: web::Data<Arc<mysql::Pool>>, user: web::Json<Auth
 pub async fn auth_logout() -> Result<HttpResponse, Error> {
 	let cookie = create_cookie("");
 
-	Ok(HttpResponse::Ok().del_cookie(&cookie).json(AuthResponseDefault { display_name: String::from(""), user_id: 0 }))
-}
\ No newline at end of file
+	Ok(HttpResponse::Ok().del_cookie(&cookie).json(ApiResponse::success(AuthResponseDefault { display_name: String::from(""), user_id: 0, impersonated_by: None })))
+}
+
+/// Revoke every token of the current user and delete the jwt cookie
+pub async fn auth_logout_all(db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let jwt = match is_authenticated(&req) {
+		Some(tmp) => tmp,
+		_ => return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"))
+	};
+
+	match session::revoke_sessions(&db, jwt.sub) {
+		Ok(_) => {
+			let cookie = create_cookie("");
+			Ok(HttpResponse::Ok().del_cookie(&cookie).json(ApiResponse::success(AuthResponseDefault { display_name: String::from(""), user_id: 0, impersonated_by: None })))
+		}
+		Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+	}
+}
+
+/// Admin: revoke every token of the given user
+pub async fn auth_revoke_user(db: web::Data<Arc<dyn Storage>>, revoke: web::Json<AuthRequestRevoke>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !is_admin(&req) {
+		return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"));
+	}
+
+	match session::revoke_sessions(&db, revoke.user_id) {
+		Ok(serial) => { Ok(api_ok(AuthResponseRevoke { user_id: revoke.user_id, serial })) }
+		Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+	}
+}
+
+/// Change the password of the current user, other sessions are logged out
+pub async fn auth_change_password(db: web::Data<Arc<dyn Storage>>, data: web::Json<AuthRequestChangePassword>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let jwt = match is_authenticated(&req) {
+		Some(tmp) => tmp,
+		_ => return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"))
+	};
+
+	// The old password has to be confirmed
+	let user = match user::User::get_user_by_id(&db, jwt.sub) {
+		Some(tmp) => tmp,
+		_ => return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"))
+	};
+	if !user.verify_password(&data.old_pass) {
+		return Ok(api_error(StatusCode::FORBIDDEN, "the current password is wrong"));
+	}
+
+	match user::User::set_password(&db, user.id, &data.new_pass) {
+		Ok(()) => {}
+		Err(err) => return Ok(api_error(StatusCode::BAD_REQUEST, &err))
+	}
+
+	// Revoke all tokens and hand a fresh one to this session
+	match session::revoke_sessions(&db, user.id).ok().and_then(|_| jwt::create_jwt_for_user_id(&db, user.id)) {
+		Some((user_id, display_name, jwt)) => {
+			let cookie = create_cookie(&jwt);
+			Ok(HttpResponse::Ok().cookie(cookie).json(ApiResponse::success(AuthResponseDefault { display_name, user_id, impersonated_by: None })))
+		}
+		_ => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "password changed, please log in again")) }
+	}
+}
+
+/// Send a reset link to the mail address of the given login
+///
+/// Always succeeds, a failing mail for an existing user must look the same as an unknown login
+pub async fn auth_request_reset(db: web::Data<Arc<dyn Storage>>, data: web::Json<AuthRequestReset>) -> Result<HttpResponse, Error> {
+	match reset::request_password_reset(&db, &data.login) {
+		Ok(()) => {}
+		Err(err) => { println!("Error sending password reset link: {}", err); }
+	}
+	Ok(api_ok(()))
+}
+
+/// Set a new password using a reset token, all sessions of the user are logged out
+pub async fn auth_reset_password(db: web::Data<Arc<dyn Storage>>, data: web::Json<AuthRequestResetPassword>) -> Result<HttpResponse, Error> {
+	match reset::reset_password(&db, &data.token, &data.pass) {
+		Ok(user_id) => {
+			match session::revoke_sessions(&db, user_id) {
+				Ok(_) => {}
+				Err(err) => { println!("Error revoking sessions after reset: {}", err); }
+			}
+			Ok(api_ok(()))
+		}
+		Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+	}
+}
+
+/// Admin: act as an author for a short while, e.g. to see what an editor sees
+///
+/// The admin cookie is replaced, logging out ends the impersonation. Changes made meanwhile are
+/// audited under the admin, see `audit::AuditImpersonation`
+pub async fn auth_impersonate(db: web::Data<Arc<dyn Storage>>, data: web::Json<AuthRequestImpersonate>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let admin = match get_admin(&req) {
+		Some(tmp) => tmp,
+		_ => return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"))
+	};
+
+	// No chains of impersonation
+	if admin.impersonated_by.is_some() {
+		return Ok(api_error(StatusCode::FORBIDDEN, "already impersonating a user"));
+	}
+
+	// Only authors, an admin must not act with the rights of another admin
+	match user::User::get_user_by_id(&db, data.user_id) {
+		Some(target) if target.permissions.contains(&String::from("author")) && !target.permissions.contains(&String::from("admin")) => {}
+		Some(_) => { return Ok(api_error(StatusCode::FORBIDDEN, "only authors can be impersonated")); }
+		_ => { return Ok(api_error(StatusCode::NOT_FOUND, "user not found")); }
+	}
+
+	match jwt::create_impersonation_jwt(&db, admin.sub, admin.serial, data.user_id) {
+		Some((user_id, display_name, jwt)) => {
+			audit::log_audit_event(&db, admin.sub, "impersonate", &format!("admin {} ({}) impersonates user {} ({})", admin.sub, admin.name, user_id, display_name));
+
+			let cookie = create_cookie(&jwt);
+			Ok(HttpResponse::Ok().cookie(cookie).json(ApiResponse::success(AuthResponseDefault { display_name, user_id, impersonated_by: Some(admin.sub) })))
+		}
+		_ => { Ok(api_error(StatusCode::NOT_FOUND, "user not found")) }
+	}
+}
+
+/// Admin: list the API tokens, the tokens themselves are not stored
+pub async fn auth_get_api_tokens(db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !is_admin(&req) {
+		return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"));
+	}
+
+	match api_token::load_api_tokens(&db) {
+		Ok(tokens) => { Ok(api_ok(tokens)) }
+		Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+	}
+}
+
+/// Admin: create an API token for scripts and CI jobs, it acts as the admin creating it
+pub async fn auth_create_api_token(db: web::Data<Arc<dyn Storage>>, data: web::Json<AuthRequestCreateApiToken>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let admin = match get_admin(&req) {
+		Some(tmp) => tmp,
+		_ => return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"))
+	};
+
+	// The token would outlive the impersonation
+	if admin.impersonated_by.is_some() {
+		return Ok(api_error(StatusCode::FORBIDDEN, "not while impersonating a user"));
+	}
+
+	match api_token::create_api_token(&db, admin.sub, &data.name, &data.scopes) {
+		Ok((id, token)) => {
+			audit::log_audit_event(&db, admin.sub, "api_token_create", &format!("token {} '{}' with scopes {}", id, data.name.trim(), data.scopes.join(",")));
+			Ok(api_ok(AuthResponseApiToken { id, token }))
+		}
+		Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+	}
+}
+
+/// Admin: revoke an API token, it stops working right away
+pub async fn auth_revoke_api_token(db: web::Data<Arc<dyn Storage>>, data: web::Json<AuthRequestRevokeApiToken>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let admin = match get_admin(&req) {
+		Some(tmp) => tmp,
+		_ => return Ok(api_error(StatusCode::UNAUTHORIZED, "token is invalid"))
+	};
+
+	match api_token::revoke_api_token(&db, data.id) {
+		Ok(()) => {
+			audit::log_audit_event(&db, admin.sub, "api_token_revoke", &format!("token {}", data.id));
+			Ok(api_ok(()))
+		}
+		Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+	}
+}
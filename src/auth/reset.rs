@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::app::config::config_get_string;
+use crate::app::mail::send_mail;
+use crate::app::utils::secure_random_base62_string;
+use crate::auth::user::User;
+use crate::storage::Storage;
+
+/// Reset links stay valid for one hour
+const RESET_TOKEN_LIFETIME_MINUTES: i64 = 60;
+
+
+/// Create a reset token for the user with the given login and mail the link
+///
+/// Unknown logins are not reported, so the endpoint does not reveal which users exist
+pub fn request_password_reset(db: &Arc<dyn Storage>, login: &str) -> Result<(), String> {
+	let user = match User::get_user_from_db(db, login) {
+		Some(tmp) => tmp,
+		_ => return Ok(())
+	};
+
+	if user.email.is_empty() {
+		println!("Password reset requested for user {} without an email address", user.id);
+		return Ok(());
+	}
+
+	// Only the hash is stored, the token itself is only in the mail
+	let token = secure_random_base62_string(48);
+	let expires_at = (Utc::now() + Duration::minutes(RESET_TOKEN_LIFETIME_MINUTES)).naive_utc();
+
+	let query = "INSERT INTO password_resets (token_hash, user_id, expires_at, used) VALUES (:token_hash, :user_id, :expires_at, 0)";
+	match db.exec(query, db_params! {"token_hash" => hash_token(&token), "user_id" => user.id, "expires_at" => expires_at}) {
+		Ok(_) => {}
+		Err(err) => {
+			println!("Error storing password reset token: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	let link = format!("https://{}/ndadmin/reset?token={}", config_get_string("fqdn"), token);
+	let body = format!("Hi {},\n\nsomeone asked to reset your password. Open the following link within {} minutes to choose a new one:\n\n{}\n\nIf this was not you, simply ignore this mail.\n",
+		user.display_name, RESET_TOKEN_LIFETIME_MINUTES, link);
+
+	send_mail(&user.email, "Reset your password", &body)
+}
+
+/// Use a reset token to set a new password, every token works only once
+///
+/// Returns the id of the user the password was changed for
+pub fn reset_password(db: &Arc<dyn Storage>, token: &str, pass: &str) -> Result<u32, String> {
+	let token_hash = hash_token(token);
+
+	// A rejected password must not use up the link
+	User::check_password_policy(pass)?;
+
+	// Mark the token as used first, so it cannot be used twice at the same time
+	let query = "UPDATE password_resets SET used=1 WHERE token_hash=:token_hash AND used=0 AND expires_at > :now";
+	match db.exec(query, db_params! {"token_hash" => &token_hash, "now" => Utc::now().naive_utc()}) {
+		Ok(res) => {
+			if res.affected_rows == 0 { return Err(String::from("The reset link is invalid or has expired")); }
+		}
+		Err(err) => {
+			println!("Error using password reset token: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	let user_id = load_token_user(db, &token_hash).ok_or(String::from("The reset link is invalid or has expired"))?;
+	User::set_password(db, user_id, pass)?;
+
+	Ok(user_id)
+}
+
+/// Find the user a reset token was issued for
+fn load_token_user(db: &Arc<dyn Storage>, token_hash: &str) -> Option<u32> {
+	match db.query_first("SELECT user_id FROM password_resets WHERE token_hash=:token_hash", db_params! {"token_hash" => token_hash}) {
+		Ok(Some(mut row)) => { row.take("user_id") }
+		_ => { None }
+	}
+}
+
+/// Tokens are stored hashed, a leaked table does not allow resetting passwords
+fn hash_token(token: &str) -> String {
+	format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::storage::Storage;
+
+lazy_static! {
+	/// The current token serial per user, tokens carrying an older serial are revoked
+	static ref TOKEN_SERIALS: RwLock<HashMap<u32, u32>> = RwLock::new(HashMap::new());
+}
+
+
+/// Get the current token serial of a user, the database is only asked once per user
+pub fn get_token_serial(db: &Arc<dyn Storage>, user_id: u32) -> Option<u32> {
+	match TOKEN_SERIALS.read() {
+		Ok(guard) => {
+			match guard.get(&user_id) {
+				Some(serial) => { return Some(*serial); }
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	let serial = load_token_serial(db, user_id)?;
+
+	match TOKEN_SERIALS.write() {
+		Ok(mut guard) => { guard.insert(user_id, serial); }
+		_ => {}
+	}
+
+	Some(serial)
+}
+
+/// Invalidate all tokens issued to a user so far
+///
+/// Returns the new serial
+pub fn revoke_sessions(db: &Arc<dyn Storage>, user_id: u32) -> Result<u32, String> {
+	match db.exec("UPDATE users SET token_serial=token_serial+1 WHERE id=:id", db_params! {"id" => user_id}) {
+		Ok(res) => {
+			if res.affected_rows == 0 { return Err(String::from("User not found")); }
+		}
+		Err(err) => {
+			println!("Error revoking sessions: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	let serial = load_token_serial(db, user_id).ok_or(String::from("Could not load the token serial"))?;
+
+	match TOKEN_SERIALS.write() {
+		Ok(mut guard) => { guard.insert(user_id, serial); }
+		_ => {}
+	}
+
+	Ok(serial)
+}
+
+/// Read the token serial of a user from the database
+fn load_token_serial(db: &Arc<dyn Storage>, user_id: u32) -> Option<u32> {
+	match db.query_first("SELECT token_serial FROM users WHERE id=:id", db_params! {"id" => user_id}) {
+		Ok(Some(mut row)) => { row.take("token_serial") }
+		_ => { None }
+	}
+}
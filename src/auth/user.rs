@@ -1,10 +1,26 @@
-use scrypt::{scrypt_check, scrypt_simple, ScryptParams};
+use std::sync::Arc;
 
+use argon2::{Config, ThreadMode, Variant, Version};
+use scrypt::scrypt_check;
+
+use crate::app::utils::secure_random_base62_string;
+use crate::storage::{DbValue, Storage};
+
+/// Prefix of hashes in our current scheme, anything else gets re-hashed on login
+const ARGON2_PREFIX: &str = "$argon2id$";
+const ARGON2_MEM_COST: u32 = 65536;
+const ARGON2_TIME_COST: u32 = 3;
+
+// Parameters of the legacy scrypt hashes, still stored next to them
 const SCRYPT_N: u8 = 10;
 const SCRYPT_R: u32 = 8;
 const SCRYPT_P: u32 = 1;
 
+/// Passwords shorter than this are rejected
+const MIN_PASSWORD_LENGTH: usize = 10;
+
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct User {
 	pub id: u32,
 	pub login: String,
@@ -14,13 +30,22 @@ pub struct User {
 	sr: u32,
 	sp: u32,
 	pub display_name: String,
+	pub email: String,
 	pub home_post: u32,
 	pub permissions: Vec<String>,
+	/// Bumped to revoke all tokens of this user
+	pub token_serial: u32,
 }
 
 impl User {
 	/// Compare the provided password against the users password
+	///
+	/// The scheme is taken from the stored hash, so Argon2 and legacy scrypt hashes both verify
 	pub fn verify_password(&self, pass: &str) -> bool {
+		if self.pass.starts_with("$argon2") {
+			return argon2::verify_encoded(&self.pass, pass.as_bytes()).unwrap_or(false);
+		}
+
 		match scrypt_check(pass, &self.pass) {
 			Ok(_) => { return true; }
 			_ => {}
@@ -29,19 +54,19 @@ impl User {
 		false
 	}
 
+	/// True if the stored hash does not use the current scheme
+	pub fn needs_rehash(&self) -> bool {
+		!self.pass.starts_with(ARGON2_PREFIX)
+	}
+
 	/// Create a new user
+	#[allow(dead_code)]
 	pub fn create_user(login: &str, pass: &str) -> Option<User> {
 		// Make some salt
 		let salt = crate::app::utils::weak_random_base62_string(128);
 
-		// Setup scrypt params
-		let params = match ScryptParams::new(SCRYPT_N, SCRYPT_R, SCRYPT_P) {
-			Ok(tmp) => { tmp }
-			_ => { return None; }
-		};
-
 		// Hash the password
-		let hashed = match scrypt_simple(pass, &params) {
+		let hashed = match hash_password(pass) {
 			Ok(tmp) => { tmp }
 			_ => { return None; }
 		};
@@ -57,43 +82,100 @@ impl User {
 			sr: SCRYPT_R,
 			sp: SCRYPT_P,
 			display_name: String::from(login),
+			email: String::from(""),
 			home_post: 0,
 			permissions: vec![String::from("guest")],
+			token_serial: 0,
 		})
 	}
 
+	/// Store a new password for the user
+	pub fn set_password(db: &Arc<dyn Storage>, user_id: u32, pass: &str) -> Result<(), String> {
+		User::check_password_policy(pass)?;
+
+		User::store_password(db, user_id, pass)
+	}
+
+	/// Whether a new password is acceptable, checked before anything else is changed
+	pub fn check_password_policy(pass: &str) -> Result<(), String> {
+		if pass.chars().count() < MIN_PASSWORD_LENGTH {
+			return Err(format!("The password needs at least {} characters", MIN_PASSWORD_LENGTH));
+		}
+
+		Ok(())
+	}
+
+	/// Hash and store a password without any policy checks
+	///
+	/// Used to move a verified password over to the current scheme
+	pub fn store_password(db: &Arc<dyn Storage>, user_id: u32, pass: &str) -> Result<(), String> {
+		let hashed = hash_password(pass)?;
+
+		match db.exec("UPDATE users SET pass=:pass WHERE id=:id", db_params! {"pass" => hashed, "id" => user_id}) {
+			Ok(res) => {
+				if res.affected_rows == 0 { return Err(String::from("User not found")); }
+				Ok(())
+			}
+			Err(err) => {
+				println!("Error setting password: {:?}", err);
+				Err(err.to_string())
+			}
+		}
+	}
+
 	/// Fetch a user from the database
-	pub fn get_user_from_db(db: &mysql::Pool, login: &str) -> Option<User> {
-		let query = r"SELECT id,login,pass,salt,sn,sr,sp,display_name,home_post,permissions FROM users WHERE login = :a";
+	pub fn get_user_from_db(db: &Arc<dyn Storage>, login: &str) -> Option<User> {
+		User::load_user(db, "login", DbValue::from(login))
+	}
 
-		let query_result = match db.prep_exec(query, params! {"a" => login}) {
-			Ok(tmp) => { tmp }
+	/// Fetch a user from the database by id
+	pub fn get_user_by_id(db: &Arc<dyn Storage>, user_id: u32) -> Option<User> {
+		User::load_user(db, "id", DbValue::from(user_id))
+	}
+
+	/// Load a single user matching the given column
+	fn load_user(db: &Arc<dyn Storage>, column: &str, value: DbValue) -> Option<User> {
+		let query = format!("SELECT id,login,pass,salt,sn,sr,sp,display_name,email,home_post,permissions,token_serial FROM users WHERE {} = :a", column);
+
+		let mut row = match db.query_first(&query, db_params! {"a" => value}) {
+			Ok(Some(tmp)) => { tmp }
 			_ => { return None; }
 		};
 
-		for result_row in query_result {
-			let mut row = match result_row {
-				Ok(tmp) => { tmp }
-				_ => { continue; }
-			};
-
-			return Some(User {
-				id: row.take("id")?,
-				login: row.take("login")?,
-				pass: row.take("pass")?,
-				salt: row.take("salt")?,
-				sn: row.take("sn")?,
-				sr: row.take("sr")?,
-				sp: row.take("sp")?,
-				display_name: row.take("display_name")?,
-				home_post: row.take("home_post")?,
-				permissions: match serde_json::from_str(row.take::<String, _>("permissions")?.as_str()) {
-					Ok(tmp) => { Some(tmp)? }
-					_ => { vec![] }
-				},
-			});
-		}
-
-		None
+		Some(User {
+			id: row.take("id")?,
+			login: row.take("login")?,
+			pass: row.take("pass")?,
+			salt: row.take("salt")?,
+			sn: row.take("sn")?,
+			sr: row.take("sr")?,
+			sp: row.take("sp")?,
+			display_name: row.take("display_name")?,
+			email: row.take::<Option<String>>("email")?.unwrap_or_default(),
+			home_post: row.take("home_post")?,
+			permissions: match serde_json::from_str(row.take::<String>("permissions")?.as_str()) {
+				Ok(tmp) => { Some(tmp)? }
+				_ => { vec![] }
+			},
+			token_serial: row.take("token_serial")?,
+		})
 	}
-}
\ No newline at end of file
+}
+
+/// Hash a password with Argon2id, the result carries the scheme and parameters
+fn hash_password(pass: &str) -> Result<String, String> {
+	let config = Config {
+		variant: Variant::Argon2id,
+		version: Version::Version13,
+		mem_cost: ARGON2_MEM_COST,
+		time_cost: ARGON2_TIME_COST,
+		lanes: 1,
+		thread_mode: ThreadMode::Sequential,
+		secret: &[],
+		ad: &[],
+		hash_length: 32,
+	};
+
+	let salt = secure_random_base62_string(16);
+	argon2::hash_encoded(pass.as_bytes(), salt.as_bytes(), &config).map_err(|err| err.to_string())
+}
@@ -0,0 +1,92 @@
+use regex::Regex;
+
+lazy_static! {
+	static ref HTML_TAG: Regex = Regex::new(r"(?is)<html\b([^>]*)>").unwrap();
+	static ref LANG_ATTR: Regex = Regex::new(r#"(?i)\blang\s*=\s*["']?\s*[^"'\s>]"#).unwrap();
+	static ref IMG_TAG: Regex = Regex::new(r"(?is)<img\b([^>]*)>").unwrap();
+	static ref ALT_ATTR: Regex = Regex::new(r"(?i)\balt\s*=").unwrap();
+	static ref LINK_TAG: Regex = Regex::new(r"(?is)<a\b([^>]*)>(.*?)</a\s*>").unwrap();
+	static ref LABEL_ATTR: Regex = Regex::new(r#"(?i)\b(aria-label|aria-labelledby|title)\s*=\s*["']\s*[^"'\s]"#).unwrap();
+	static ref IMG_WITH_ALT: Regex = Regex::new(r#"(?is)<img\b[^>]*\balt\s*=\s*["']\s*[^"'\s]"#).unwrap();
+	static ref ANY_TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+	static ref HEADING_TAG: Regex = Regex::new(r"(?i)<h([1-6])\b[^>]*>").unwrap();
+}
+
+/// Longest piece of HTML quoted in an issue
+const SNIPPET_LENGTH: usize = 120;
+
+
+// ------------------------------
+// ------------ AUDIT -----------
+// ------------------------------
+
+/// The accessibility problems found in a rendered page
+#[derive(Serialize, Debug)]
+pub struct A11yReport {
+	pub post_id: u32,
+	pub template: String,
+	pub images: usize,
+	pub links: usize,
+	pub headings: usize,
+	pub issues: Vec<A11yIssue>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct A11yIssue {
+	/// One of `html_lang`, `img_alt`, `empty_link` and `heading_order`
+	pub rule: &'static str,
+	pub message: String,
+	/// The offending HTML, shortened
+	pub snippet: String,
+}
+
+/// Check rendered HTML for missing image alts, empty links, skipped heading levels and a missing lang attribute
+///
+/// This looks at the markup only, it is no replacement for trying the page with a screen reader
+pub fn a11y_audit_html(post_id: u32, template: &str, html: &str) -> A11yReport {
+	let mut report = A11yReport { post_id, template: String::from(template), images: 0, links: 0, headings: 0, issues: Vec::new() };
+
+	// The language of the document
+	match HTML_TAG.captures(html) {
+		Some(caps) if LANG_ATTR.is_match(&caps[1]) => {}
+		Some(caps) => { report.issue("html_lang", String::from("The html element has no lang attribute"), &caps[0]); }
+		None => { report.issue("html_lang", String::from("There is no html element to carry a lang attribute"), ""); }
+	}
+
+	// Images need an alt, decorative ones an empty one
+	for caps in IMG_TAG.captures_iter(html) {
+		report.images += 1;
+		if !ALT_ATTR.is_match(&caps[1]) {
+			report.issue("img_alt", String::from("The image has no alt attribute"), &caps[0]);
+		}
+	}
+
+	// Links need some text a screen reader can announce
+	for caps in LINK_TAG.captures_iter(html) {
+		report.links += 1;
+		let text = ANY_TAG.replace_all(&caps[2], "").replace("&nbsp;", " ");
+		if text.trim().len() == 0 && !LABEL_ATTR.is_match(&caps[1]) && !IMG_WITH_ALT.is_match(&caps[2]) {
+			report.issue("empty_link", String::from("The link has no text, label or image alt"), &caps[0]);
+		}
+	}
+
+	// Headings may go down only one level at a time
+	let mut previous = 0;
+	for caps in HEADING_TAG.captures_iter(html) {
+		report.headings += 1;
+		let level: u32 = caps[1].parse().unwrap_or(1);
+		if previous > 0 && level > previous + 1 {
+			report.issue("heading_order", format!("h{} follows h{}, skipping a level", level, previous), &caps[0]);
+		}
+		previous = level;
+	}
+
+	report
+}
+
+impl A11yReport {
+	fn issue(&mut self, rule: &'static str, message: String, html: &str) {
+		let snippet: String = html.chars().take(SNIPPET_LENGTH).collect();
+		self.issues.push(A11yIssue { rule, message, snippet });
+	}
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::app::config::{config_get_i64, config_get_string};
+use crate::app::utils::curl_post_json_with_answer;
+use crate::storage::{DbRow, Storage};
+
+/// Images sent to the captioning service per run, `alt_text_batch_size` overrides it
+const ALT_TEXT_BATCH_SIZE: i64 = 10;
+
+/// Captioning takes a while, but should not hold up a blocking thread forever
+const ALT_TEXT_TIMEOUT_SECONDS: u64 = 30;
+
+/// Longer suggestions are cut, the column holds 512 characters
+const ALT_TEXT_MAX_LENGTH: usize = 500;
+
+
+// ------------------------------
+// ---------- SERVICE -----------
+// ------------------------------
+
+/// An image the captioning service is asked about
+pub struct AltTextImage {
+	pub guid: String,
+	/// Public address of a resized copy, videos are described by their poster frame
+	pub url: String,
+	/// The original on disk, for services that want the file itself
+	#[allow(dead_code)]
+	pub path: String,
+}
+
+/// Something that describes images, picked by `alt_text_service`
+///
+/// Suggestions are only stored as drafts, an admin confirms or edits them before they are used
+pub trait AltTextService: Send + Sync {
+	fn name(&self) -> &str;
+
+	/// A short description of the image, suitable as alt text
+	fn suggest(&self, image: &AltTextImage) -> Result<String, String>;
+}
+
+/// Any HTTP endpoint taking `{"image_url": ..., "locale": ...}` and answering `{"alt_text": ...}`
+///
+/// `alt_text_url` is the endpoint, `alt_text_token` is sent as bearer token if set
+struct HttpAltTextService {
+	url: String,
+	token: String,
+}
+
+impl AltTextService for HttpAltTextService {
+	fn name(&self) -> &str {
+		"http"
+	}
+
+	fn suggest(&self, image: &AltTextImage) -> Result<String, String> {
+		let body = json!({ "image_url": image.url, "locale": config_get_string("locale") }).to_string();
+		let headers = match self.token.len() {
+			0 => vec![],
+			_ => vec![format!("Authorization: Bearer {}", self.token)]
+		};
+
+		let answer = match curl_post_json_with_answer(&self.url, &body, &headers, ALT_TEXT_TIMEOUT_SECONDS)? {
+			(200, answer) => answer,
+			(code, _) => { return Err(format!("The captioning service answered with HTTP status {}", code)); }
+		};
+
+		match serde_json::from_str::<serde_json::Value>(&answer) {
+			Ok(value) => match value["alt_text"].as_str() {
+				Some(tmp) => Ok(String::from(tmp)),
+				_ => Err(String::from("The captioning service sent no alt_text"))
+			},
+			Err(err) => Err(format!("The captioning service sent invalid JSON: {}", err))
+		}
+	}
+}
+
+/// The configured captioning service, None if suggestions are off
+pub fn alt_text_service() -> Option<Box<dyn AltTextService>> {
+	match config_get_string("alt_text_service").to_lowercase().as_str() {
+		"http" => Some(Box::new(HttpAltTextService { url: config_get_string("alt_text_url"), token: config_get_string("alt_text_token") })),
+		_ => None
+	}
+}
+
+
+// ------------------------------
+// -------- SUGGESTIONS ---------
+// ------------------------------
+
+/// An image waiting for an admin to look at the suggested alt text
+#[derive(Serialize, Debug)]
+pub struct AltTextSuggestion {
+	pub guid: String,
+	pub src: String,
+	pub suggestion: String,
+}
+
+impl AltTextSuggestion {
+	fn from_sql(mut row: DbRow) -> Option<AltTextSuggestion> {
+		let guid: String = row.take("guid")?;
+		let extension = match row.take::<String>("media_type")?.as_str() {
+			"video" => String::from("jpg"),
+			_ => row.take("extension")?
+		};
+
+		Some(AltTextSuggestion {
+			src: format!("/gallery/{}/w200/thumb.{}", guid, extension),
+			guid,
+			suggestion: row.take("alt_text_suggested")?,
+		})
+	}
+}
+
+/// Ask the captioning service about images that have neither alt text nor a suggestion, newest first
+///
+/// Every image is only asked about once, whatever the answer. Returns the number of suggestions stored
+pub fn suggest_alt_texts(db: &Arc<dyn Storage>) -> Result<u32, String> {
+	let service = match alt_text_service() {
+		Some(tmp) => tmp,
+		_ => { return Ok(0); }
+	};
+	let limit = match config_get_i64("alt_text_batch_size") {
+		tmp if tmp > 0 => tmp,
+		_ => ALT_TEXT_BATCH_SIZE
+	};
+
+	let query = r##"SELECT guid, extension, media_type FROM gallery
+        WHERE alt_text = '' AND alt_text_checked_at IS NULL
+        ORDER BY uploadedAt DESC LIMIT :limit"##;
+	let rows = db.query(query, db_params! {"limit" => limit}).map_err(|err| err.to_string())?;
+
+	let mut stored = 0;
+	let mut errors = Vec::new();
+	for mut row in rows {
+		let (guid, extension, media_type) = match (row.take::<String>("guid"), row.take::<String>("extension"), row.take::<String>("media_type")) {
+			(Some(a), Some(b), Some(c)) => (a, b, c),
+			_ => { continue; }
+		};
+		let extension = if media_type == "video" { String::from("jpg") } else { extension };
+		let image = AltTextImage {
+			url: format!("https://{}/gallery/{}/w800/image.{}", config_get_string("fqdn"), guid, extension),
+			path: format!("data/gallery/original/{}.{}", guid, extension),
+			guid,
+		};
+
+		let suggestion = match service.suggest(&image) {
+			Ok(tmp) => Some(tmp.trim().chars().take(ALT_TEXT_MAX_LENGTH).collect::<String>()).filter(|tmp| tmp.len() > 0),
+			Err(err) => {
+				errors.push(format!("{}: {}", image.guid, err));
+				None
+			}
+		};
+
+		let query = "UPDATE gallery SET alt_text_suggested=:suggestion, alt_text_checked_at=:now WHERE guid=:guid";
+		match db.exec(query, db_params! {"guid" => &image.guid, "suggestion" => &suggestion, "now" => Utc::now().naive_utc()}) {
+			Ok(_) if suggestion.is_some() => { stored += 1; }
+			Err(err) => { return Err(err.to_string()); }
+			_ => {}
+		}
+	}
+
+	match errors.len() {
+		0 => Ok(stored),
+		_ => Err(format!("{} could not describe {} images, e.g. {}", service.name(), errors.len(), errors[0]))
+	}
+}
+
+/// Images with a suggested alt text, newest first
+pub fn load_alt_text_suggestions(db: &Arc<dyn Storage>) -> Result<Vec<AltTextSuggestion>, String> {
+	let query = r##"SELECT guid, extension, media_type, alt_text_suggested FROM gallery
+        WHERE alt_text_suggested IS NOT NULL
+        ORDER BY uploadedAt DESC"##;
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(AltTextSuggestion::from_sql).collect())
+}
+
+/// Store the alt text of an image, which may be the suggestion as is or edited
+///
+/// The suggestion is removed either way, an empty alt text rejects it
+pub fn confirm_alt_text(db: &Arc<dyn Storage>, guid: &str, alt_text: &str) -> Result<(), String> {
+	let alt_text = alt_text.trim();
+	if alt_text.chars().count() > ALT_TEXT_MAX_LENGTH {
+		return Err(format!("The alt text must not be longer than {} characters", ALT_TEXT_MAX_LENGTH));
+	}
+
+	let query = "UPDATE gallery SET alt_text=:alt_text, alt_text_suggested=NULL WHERE guid=:guid";
+	match db.exec(query, db_params! {"guid" => guid, "alt_text" => alt_text}) {
+		Ok(res) if res.affected_rows == 0 => Err(String::from("Image not found")),
+		Ok(_) => Ok(()),
+		Err(err) => Err(err.to_string())
+	}
+}
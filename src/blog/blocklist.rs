@@ -0,0 +1,228 @@
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::app::client_ip::ip_in_network;
+use crate::storage::{DbRow, Storage, StorageError};
+
+lazy_static! {
+	static ref BLOCKLIST: RwLock<Vec<BlockEntry>> = RwLock::new(Vec::new());
+}
+
+
+// ------------------------------
+// ---------- BLOCKLIST ---------
+// ------------------------------
+
+/// What a blocklist entry is matched against
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+	/// A single address
+	Ip,
+	/// A range like `10.0.0.0/8`
+	Cidr,
+	/// The domain of an email address, including its subdomains
+	EmailDomain,
+}
+
+impl BlockKind {
+	fn as_str(&self) -> &'static str {
+		match self {
+			BlockKind::Ip => "ip",
+			BlockKind::Cidr => "cidr",
+			BlockKind::EmailDomain => "email_domain",
+		}
+	}
+
+	fn from_str(kind: &str) -> Option<BlockKind> {
+		match kind {
+			"ip" => Some(BlockKind::Ip),
+			"cidr" => Some(BlockKind::Cidr),
+			"email_domain" => Some(BlockKind::EmailDomain),
+			_ => None
+		}
+	}
+}
+
+
+/// Visitors matching an entry may not use the public forms
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockEntry {
+	#[serde(default)]
+	pub id: u32,
+	pub kind: BlockKind,
+	pub value: String,
+	#[serde(default)]
+	pub note: String,
+
+	/// How often the entry stopped someone, tells whether it is still needed
+	#[serde(default)]
+	pub hits: u64,
+	#[serde(default)]
+	pub last_hit: Option<u64>,
+	#[serde(default)]
+	pub created_at: u64,
+}
+
+impl BlockEntry {
+	fn from_sql(mut row: DbRow) -> Option<BlockEntry> {
+		Some(BlockEntry {
+			id: row.take("id")?,
+			kind: BlockKind::from_str(&row.take::<String>("kind")?)?,
+			value: row.take("value")?,
+			note: row.take("note")?,
+			hits: row.take("hits")?,
+			last_hit: row.take::<Option<NaiveDateTime>>("last_hit_at")?.map(|tmp| tmp.timestamp() as u64),
+			created_at: row.take::<NaiveDateTime>("created_at")?.timestamp() as u64,
+		})
+	}
+
+	/// Check and clean up an entry from the admin panel
+	pub fn normalized(mut self) -> Result<BlockEntry, String> {
+		self.value = self.value.trim().to_lowercase();
+		self.note = String::from(self.note.trim());
+
+		match self.kind {
+			BlockKind::Ip => {
+				self.value.parse::<IpAddr>().map_err(|_| format!("'{}' is not an IP address", self.value))?;
+			}
+			BlockKind::Cidr => {
+				let mut parts = self.value.splitn(2, '/');
+				let network = parts.next().and_then(|tmp| tmp.parse::<IpAddr>().ok());
+				let prefix = parts.next().and_then(|tmp| tmp.parse::<u32>().ok());
+				match (network, prefix) {
+					(Some(IpAddr::V4(_)), Some(prefix)) if prefix <= 32 => {}
+					(Some(IpAddr::V6(_)), Some(prefix)) if prefix <= 128 => {}
+					_ => { return Err(format!("'{}' is not a range like 10.0.0.0/8", self.value)); }
+				}
+			}
+			BlockKind::EmailDomain => {
+				self.value = String::from(self.value.trim_start_matches('@'));
+				if self.value.len() == 0 || !self.value.contains('.') || self.value.contains('@') {
+					return Err(format!("'{}' is not a domain like example.org", self.value));
+				}
+			}
+		}
+
+		Ok(self)
+	}
+
+	fn matches(&self, ip: &Option<IpAddr>, email_domain: &str) -> bool {
+		match self.kind {
+			BlockKind::Ip | BlockKind::Cidr => {
+				match ip {
+					Some(ip) => ip_in_network(ip, &self.value),
+					_ => false
+				}
+			}
+			BlockKind::EmailDomain => {
+				email_domain == self.value || email_domain.ends_with(&format!(".{}", self.value))
+			}
+		}
+	}
+}
+
+
+/// All entries with their hit counters
+pub fn blocklist_entries() -> Vec<BlockEntry> {
+	match BLOCKLIST.read() {
+		Ok(guard) => guard.clone(),
+		_ => Vec::new()
+	}
+}
+
+/// Read the blocklist from the database into memory
+///
+/// Returns the number of entries
+pub fn blocklist_load(db: &Arc<dyn Storage>) -> Result<usize, String> {
+	let query = "SELECT id, kind, value, note, hits, last_hit_at, created_at FROM blocklist ORDER BY id";
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+	let entries: Vec<BlockEntry> = rows.into_iter().filter_map(BlockEntry::from_sql).collect();
+	let count = entries.len();
+
+	match BLOCKLIST.write() {
+		Ok(mut guard) => { *guard = entries; }
+		_ => {}
+	}
+
+	Ok(count)
+}
+
+/// Add a new entry or update the note of an existing one, entries must be normalized
+///
+/// Returns the id of the entry
+pub fn blocklist_store_entry(db: &Arc<dyn Storage>, entry: &BlockEntry) -> Result<u32, String> {
+	let result = match entry.id {
+		0 => {
+			let query = "INSERT INTO blocklist (kind, value, note, hits, created_at) VALUES (:kind, :value, :note, 0, :created_at)";
+			db.exec(query, db_params! {"kind" => entry.kind.as_str(), "value" => &entry.value, "note" => &entry.note, "created_at" => Utc::now().naive_utc()})
+				.map(|res| res.last_insert_id as u32)
+		}
+		id => {
+			let query = "UPDATE blocklist SET kind=:kind, value=:value, note=:note WHERE id=:id";
+			db.exec(query, db_params! {"id" => id, "kind" => entry.kind.as_str(), "value" => &entry.value, "note" => &entry.note})
+				.map(|_| id)
+		}
+	};
+
+	let id = match result {
+		Ok(id) => id,
+		Err(StorageError::Duplicate(_)) => { return Err(format!("'{}' is already on the blocklist", entry.value)); }
+		Err(err) => {
+			println!("Error: {:?}", err);
+			return Err(err.to_string());
+		}
+	};
+
+	blocklist_load(db)?;
+	Ok(id)
+}
+
+/// Remove an entry
+pub fn blocklist_delete_entry(db: &Arc<dyn Storage>, id: u32) -> Result<(), String> {
+	match db.exec("DELETE FROM blocklist WHERE id=:id", db_params! {"id" => id}) {
+		Ok(_) => {}
+		Err(err) => {
+			println!("Error: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	blocklist_load(db)?;
+	Ok(())
+}
+
+/// Check a visitor of a public form, counts a hit on the first matching entry
+///
+/// Returns true if the visitor is blocked
+pub fn blocklist_check(db: &Arc<dyn Storage>, ip: &str, email: &str) -> bool {
+	let ip = ip.parse::<IpAddr>().ok();
+	let email_domain = match email.rfind('@') {
+		Some(pos) => email[pos + 1..].trim().to_lowercase(),
+		_ => String::from("")
+	};
+
+	let now = Utc::now().naive_utc();
+	let id = match BLOCKLIST.write() {
+		Ok(mut guard) => {
+			match guard.iter_mut().find(|entry| entry.matches(&ip, &email_domain)) {
+				Some(entry) => {
+					entry.hits += 1;
+					entry.last_hit = Some(now.timestamp() as u64);
+					entry.id
+				}
+				_ => { return false; }
+			}
+		}
+		_ => { return false; }
+	};
+
+	match db.exec("UPDATE blocklist SET hits=hits+1, last_hit_at=:now WHERE id=:id", db_params! {"id" => id, "now" => now}) {
+		Err(err) => { println!("Could not count the blocklist hit: {:?}", err); }
+		_ => {}
+	}
+
+	true
+}
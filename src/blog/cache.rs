@@ -1,25 +1,60 @@
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
-use crate::app::config::config_get_i64;
+use crate::app::config::{config_get_i64, config_get_string};
 use crate::app::utils::*;
 use crate::blog::Blog;
-use crate::blog::sitemap::SiteMap;
-use crate::blog::types::post::{fetch_latest_posts, fetch_most_viewed_posts, PostExcerpt};
+use crate::blog::minify::{is_html_document, minify_enabled, minify_html};
+use crate::blog::types::post::{fetch_most_viewed_posts, PostExcerpt};
+use crate::storage::Storage;
+
+/// Expired HTML is served for this many seconds while it is rendered again, `cache_stale_html` overrides it
+const HTML_MAX_STALE: u64 = 300;
+
+thread_local! {
+	/// Set while pages are rendered again in the background, lookups on that thread always miss
+	static BYPASS_HTML_CACHE: Cell<bool> = const { Cell::new(false) };
+}
 
 /// Cacheable items
 #[derive(Clone)]
 enum CacheItem {
 	PinterestPosts { decay_time: u64, data: Vec<PinterestPostCompact> },
 	InstagramPosts { decay_time: u64, data: Vec<InstagramPostCompact> },
-	FeaturedPosts { decay_time: u64, data: Vec<PostExcerpt> },
-	LatestPosts { decay_time: u64, data: Vec<PostExcerpt> },
-	CachedTag { decay_time: u64, data: Vec<PostExcerpt> },
-	SiteMap { data: SiteMap },
-	Html { cached_at: u64, decay_time: u64, data: String },
+	FeaturedPosts { decay_time: u64, data: Vec<Arc<PostExcerpt>> },
+	LatestPosts { decay_time: u64, data: Vec<Arc<PostExcerpt>> },
+	CachedTag { decay_time: u64, data: Vec<Arc<PostExcerpt>> },
+	/// `dated` is set when dated content decided the decay time, such HTML is never served stale
+	Html { cached_at: u64, decay_time: u64, dated: bool, data: String },
+}
+
+/// Counters for one class of cached HTML
+#[derive(Default)]
+struct HtmlCacheCounter {
+	hits: u64,
+	stale_hits: u64,
+	misses: u64,
+	renders: u64,
+	render_us_total: u64,
+	render_us_max: u64,
+}
+
+/// How well the HTML cache works for one class of pages
+#[derive(Debug, Serialize)]
+pub struct HtmlCacheStats {
+	class: &'static str,
+	hits: u64,
+	/// Hits on expired HTML, counted in `hits` as well
+	stale_hits: u64,
+	misses: u64,
+	hit_ratio: f32,
+	renders: u64,
+	render_ms_avg: f32,
+	render_ms_max: f32,
 }
 
 pub struct Cache {
@@ -28,48 +63,63 @@ pub struct Cache {
 
 	/// HTML cache may be reset by setting a minimum timestamp
 	html_cache_min_time: AtomicU64,
+
+	/// Hits, misses and render times by class of page
+	html_counters: Mutex<HashMap<&'static str, HtmlCacheCounter>>,
+
+	/// Keys of expired HTML that was served stale and waits to be rendered again
+	html_revalidate: Mutex<HashSet<String>>,
 }
 
 impl Cache {
 	pub fn new() -> Cache {
 		Cache {
 			cache: RwLock::new(HashMap::new()),
-			html_cache_min_time: AtomicU64::new(0)
+			html_cache_min_time: AtomicU64::new(0),
+			html_counters: Mutex::new(HashMap::new()),
+			html_revalidate: Mutex::new(HashSet::new()),
 		}
 	}
 
-	pub fn cache_sitemap(&self, sitemap: SiteMap) {
-		match self.cache.write() {
-			Ok(mut write_lock) => {
-				write_lock.insert(String::from("sitemap"), CacheItem::SiteMap { data: sitemap });
-			}
-			_ => {}
-		}
-	}
+	/// Cache some rendered html, `render_time` is what it took to build it
+	///
+	/// `expires_at` is when dated content in the page changes by itself, see `Blog::render_template`.
+	/// Pages are minified first if `html_minify` is set, the HTML as cached is returned to be served
+	pub fn cache_html(&self, key: String, html: String, expires_at: Option<u64>, render_time: Duration) -> String {
+		self.count_html_render(&key, render_time);
+
+		let html = if minify_enabled() && is_html_document(&html) { minify_html(&html) } else { html };
 
-	pub fn cache_html(&self, key: String, html: String) {
 		let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 		let life_time = config_get_i64("cache_expire_html") as u64;
 		//TODO: introduce cache jitter - add some random amount of seconds +(0-60 minutes)
 
+		// Dated content must not outlive its time
+		let (decay_time, dated) = match expires_at {
+			Some(tmp) if tmp < unix_time + life_time => (tmp, true),
+			_ => (unix_time + life_time, false)
+		};
+
 		let cache_key = format!("html_{}", key);
 
 		match self.cache.write() {
 			Ok(mut write_lock) => {
-				write_lock.insert(cache_key, CacheItem::Html { cached_at: unix_time, decay_time: (unix_time + life_time), data: html });
+				write_lock.insert(cache_key, CacheItem::Html { cached_at: unix_time, decay_time, dated, data: html.clone() });
 			}
 			_ => {}
 		}
+
+		html
 	}
 
-	/// Cache Pinterest posts
-	pub fn cache_pinterest_posts(&self) {
+	/// Cache Pinterest posts, fails if the feed is configured but could not be fetched
+	pub fn cache_pinterest_posts(&self) -> Result<(), String> {
 		// Current time - without time this system wouldn't work so we may as well crash
 		let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 		let life_time = config_get_i64("pinterest_lifetime") as u64;
 
 		// Return if still valid
-		if self.not_yet_expired(unix_time, "pinterest_posts") { return; }
+		if self.not_yet_expired(unix_time, "pinterest_posts") { return Ok(()); }
 
 		// Nothing in the cache so fetch the latest data from the Pinterest API
 		match fetch_pinterest_feed() {
@@ -82,18 +132,21 @@ impl Cache {
 					_ => {}
 				}
 			}
+			_ if config_get_string("pinterest_url").len() > 0 => { return Err(String::from("Could not fetch the Pinterest feed")); }
 			_ => {}
 		}
+
+		Ok(())
 	}
 
-	/// Cache Instagram posts
-	pub fn cache_instagram_posts(&self) {
+	/// Cache Instagram posts, fails if the feed is configured but could not be fetched
+	pub fn cache_instagram_posts(&self) -> Result<(), String> {
 		// Current time - without time this system wouldn't work so we may as well crash
 		let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 		let life_time = config_get_i64("instagram_lifetime") as u64;
 
 		// Return if still valid
-		if self.not_yet_expired(unix_time, "instagram_posts") { return; }
+		if self.not_yet_expired(unix_time, "instagram_posts") { return Ok(()); }
 
 		// Nothing in the cache so fetch the latest data from the Instagram API
 		match fetch_instagram_feed() {
@@ -105,12 +158,15 @@ impl Cache {
 					_ => {}
 				}
 			}
+			_ if config_get_string("instagram_url").len() > 0 => { return Err(String::from("Could not fetch the Instagram feed")); }
 			_ => {}
 		}
+
+		Ok(())
 	}
 
-	/// Cache excerpts for the latest posts
-	pub fn cache_latest_posts(&self, blog: &Blog, db: &mysql::Pool) {
+	/// Cache excerpts for the latest posts, pinned posts come first
+	pub fn cache_latest_posts(&self, blog: &Blog) {
 		// Current time - without time this system wouldn't work so we may as well crash
 		let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 		let life_time = config_get_i64("latest_posts_lifetime") as u64;
@@ -118,26 +174,21 @@ impl Cache {
 		// Return if still valid
 		if self.not_yet_expired(unix_time, "latest_posts") { return; }
 
-		// Nothing in the cache so fetch the latest data from the Instagram API
-		match fetch_latest_posts(db, 8) {
-			Ok(tmp) => {
-				let res = blog.get_post_excerpts(&tmp);
+		// Nothing in the cache so take the top of the index listing
+		let res = blog.get_post_excerpts(&blog.get_index_post_ids(8));
 
-				if res.len() > 0 {
-					match self.cache.write() {
-						Ok(mut write_lock) => {
-							write_lock.insert(String::from("latest_posts"), CacheItem::LatestPosts { decay_time: (unix_time + life_time), data: res });
-						}
-						_ => {}
-					}
+		if res.len() > 0 {
+			match self.cache.write() {
+				Ok(mut write_lock) => {
+					write_lock.insert(String::from("latest_posts"), CacheItem::LatestPosts { decay_time: (unix_time + life_time), data: res });
 				}
+				_ => {}
 			}
-			_ => {}
 		}
 	}
 
 	/// Cache excerpts of the posts with the most views
-	pub fn cache_featured_posts(&self, blog: &Blog, db: &mysql::Pool) {
+	pub fn cache_featured_posts(&self, blog: &Blog, db: &Arc<dyn Storage>) {
 		// Current time - without time this system wouldn't work so we may as well crash
 		let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 		let life_time = config_get_i64("featured_posts_lifetime") as u64;
@@ -202,7 +253,8 @@ impl Cache {
 					CacheItem::InstagramPosts { decay_time, data: _ } => { decay_time }
 					CacheItem::LatestPosts { decay_time, data: _ } => { decay_time }
 					CacheItem::FeaturedPosts { decay_time, data: _ } => { decay_time }
-					_ => { std::u64::MAX } // Default: does not expire
+					CacheItem::CachedTag { decay_time, data: _ } => { decay_time }
+					_ => { u64::MAX } // Default: does not expire
 				};
 
 				unix_time <= tmp
@@ -211,6 +263,14 @@ impl Cache {
 		}
 	}
 
+	/// Drop the cached HTML of a single page
+	pub fn remove_html(&self, key: &str) {
+		match self.cache.write() {
+			Ok(mut write_lock) => { write_lock.remove(&format!("html_{}", key)); }
+			_ => {}
+		}
+	}
+
 	/// Invalidate the entire HTML cache
 	pub fn reset_html_cache(&self) {
 		self.html_cache_min_time.store(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(), Ordering::Relaxed);
@@ -235,14 +295,6 @@ impl Cache {
 		}
 	}
 
-	/// Retrieve the site map from the cache
-	pub fn get_site_map(&self) -> Option<SiteMap> {
-		match self.get("sitemap")? {
-			CacheItem::SiteMap { data } => { Some(data) }
-			_ => { None }
-		}
-	}
-
 	/// Fetch the Pinterest posts from the cache
 	pub fn get_pinterest_posts(&self) -> Option<Vec<PinterestPostCompact>> {
 		match self.get("pinterest_posts")? {
@@ -260,7 +312,7 @@ impl Cache {
 	}
 
 	/// Fetch excerpts of the latest posts from the cache
-	pub fn get_latest_posts(&self) -> Option<Vec<PostExcerpt>> {
+	pub fn get_latest_posts(&self) -> Option<Vec<Arc<PostExcerpt>>> {
 		match self.get("latest_posts")? {
 			CacheItem::LatestPosts { decay_time: _, data } => { Some(data) }
 			_ => { None }
@@ -268,7 +320,7 @@ impl Cache {
 	}
 
 	/// Fetch excerpts from the featured (most viewed) posts from the cache
-	pub fn get_featured_posts(&self) -> Option<Vec<PostExcerpt>> {
+	pub fn get_featured_posts(&self) -> Option<Vec<Arc<PostExcerpt>>> {
 		match self.get("featured_posts")? {
 			CacheItem::FeaturedPosts { decay_time: _, data } => { Some(data) }
 			_ => { None }
@@ -276,7 +328,7 @@ impl Cache {
 	}
 
 	/// Fetch excerpts from posts with a given tag from the cache
-	pub fn get_posts_by_tag(&self, tag_key: u8) -> Option<Vec<PostExcerpt>> {
+	pub fn get_posts_by_tag(&self, tag_key: u8) -> Option<Vec<Arc<PostExcerpt>>> {
 		let key = format!("post_by_tag_{}", tag_key);
 		match self.get(&key)? {
 			CacheItem::CachedTag { decay_time: _, data } => { Some(data) }
@@ -285,20 +337,143 @@ impl Cache {
 	}
 
 	/// Retrieve some html from the cache
+	///
+	/// Expired HTML is still returned for a while, it is queued to be rendered again in the background
 	pub fn get_html(&self, key: &str) -> Option<String> {
+		if BYPASS_HTML_CACHE.with(|bypass| bypass.get()) { return None; }
+
+		let html = self.get_html_unless_expired(key);
+		match &html {
+			Some((_, true)) => {
+				match self.html_revalidate.lock() {
+					Ok(mut guard) => { guard.insert(String::from(key)); }
+					_ => {}
+				}
+			}
+			_ => {}
+		}
+
+		self.count_html_lookup(key, html.as_ref().map(|(_, stale)| *stale));
+		html.map(|(data, _)| data)
+	}
+
+	/// The cached HTML and whether it is stale
+	fn get_html_unless_expired(&self, key: &str) -> Option<(String, bool)> {
 		let cache_key = format!("html_{}", key);
 		match self.get(&cache_key)? {
-			CacheItem::Html { cached_at, decay_time, data } => {
+			CacheItem::Html { cached_at, decay_time, dated, data } => {
+				// Invalidated HTML is never served, the content changed
+				if cached_at < self.html_cache_min_time.load(Ordering::Relaxed) {
+					return None;
+				}
 
-				// Make sure this item did not yet expire
+				// Make sure this item did not yet expire, or only recently
 				let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-				if decay_time < unix_time || cached_at < self.html_cache_min_time.load(Ordering::Relaxed) {
-					return None;
+				if dated {
+					// The content changes at the decay time, the page is out of date from then on
+					return if unix_time < decay_time { Some((data, false)) } else { None };
+				}
+				if decay_time >= unix_time {
+					return Some((data, false));
+				}
+
+				let max_stale = match config_get_i64("cache_stale_html") {
+					tmp if tmp > 0 => tmp as u64,
+					_ => HTML_MAX_STALE
+				};
+				if decay_time + max_stale >= unix_time {
+					return Some((data, true));
 				}
 
-				Some(data)
+				None
 			}
 			_ => { None }
 		}
 	}
-}
\ No newline at end of file
+
+	/// The keys of HTML that was served stale since the last call
+	pub fn take_html_revalidations(&self) -> Vec<String> {
+		match self.html_revalidate.lock() {
+			Ok(mut guard) => guard.drain().collect(),
+			_ => Vec::new()
+		}
+	}
+
+	/// Run `render` without reading cached HTML, so pages are rendered again and cached fresh
+	pub fn bypassing_html<T, F: FnOnce() -> T>(&self, render: F) -> T {
+		BYPASS_HTML_CACHE.with(|bypass| bypass.set(true));
+		let result = render();
+		BYPASS_HTML_CACHE.with(|bypass| bypass.set(false));
+		result
+	}
+
+	// ------------------------------------------------------------------
+	// ------------------------ HTML STATISTICS -------------------------
+	// ------------------------------------------------------------------
+
+	/// `stale` is None for a miss
+	fn count_html_lookup(&self, key: &str, stale: Option<bool>) {
+		match self.html_counters.lock() {
+			Ok(mut guard) => {
+				let counter = guard.entry(html_key_class(key)).or_default();
+				match stale {
+					Some(true) => { counter.hits += 1; counter.stale_hits += 1; }
+					Some(false) => { counter.hits += 1; }
+					None => { counter.misses += 1; }
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Count a page render, also for pages that are never cached
+	pub fn count_html_render(&self, key: &str, render_time: Duration) {
+		let micros = render_time.as_micros() as u64;
+
+		match self.html_counters.lock() {
+			Ok(mut guard) => {
+				let counter = guard.entry(html_key_class(key)).or_default();
+				counter.renders += 1;
+				counter.render_us_total += micros;
+				counter.render_us_max = counter.render_us_max.max(micros);
+			}
+			_ => {}
+		}
+	}
+
+	/// Hit ratio and render times of the HTML cache by class of page
+	pub fn get_html_stats(&self) -> Vec<HtmlCacheStats> {
+		let mut stats = match self.html_counters.lock() {
+			Ok(guard) => {
+				guard.iter().map(|(class, counter)| HtmlCacheStats {
+					class,
+					hits: counter.hits,
+					stale_hits: counter.stale_hits,
+					misses: counter.misses,
+					hit_ratio: if counter.hits + counter.misses > 0 { counter.hits as f32 / (counter.hits + counter.misses) as f32 } else { 0.0 },
+					renders: counter.renders,
+					render_ms_avg: if counter.renders > 0 { counter.render_us_total as f32 / counter.renders as f32 / 1000.0 } else { 0.0 },
+					render_ms_max: counter.render_us_max as f32 / 1000.0,
+				}).collect::<Vec<_>>()
+			}
+			_ => Vec::new()
+		};
+
+		stats.sort_by(|a, b| a.class.cmp(b.class));
+		stats
+	}
+}
+
+/// The class of page a HTML cache key belongs to
+fn html_key_class(key: &str) -> &'static str {
+	if key.starts_with("post_") { "post" }
+	else if key.starts_with("lite_") { "lite" }
+	else if key.starts_with("print_") { "print" }
+	else if key.starts_with("tag_") { "tag" }
+	else if key.starts_with("author_") { "author" }
+	else if key.starts_with("base_") || key.starts_with("index_") { "base" }
+	else if key.starts_with("rss_feed") { "feed" }
+	else if key.starts_with("search") { "search" }
+	else if key == "site_map" { "sitemap" }
+	else { "other" }
+}
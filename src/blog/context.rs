@@ -1,10 +1,18 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use crate::blog::types::menu;
-use crate::blog::types::post::{PostExcerpt, Post};
+use crate::blog::types::post::{PostAuthor, PostExcerpt, Post};
 use crate::blog::types::comment::Comment;
+use crate::blog::mentions::Mention;
+use crate::blog::stats::PublicStats;
+use crate::blog::types::poll::PollResults;
 use crate::app::utils::{InstagramPostCompact, PinterestPostCompact};
 use crate::blog::types::tag::Tag;
 
 /// Context is required by the Tera template engine
+///
+/// Posts and excerpts are shared with the blog snapshots, serde serializes the `Arc`s transparently
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Context {
 	pub title: Option<String>,
@@ -15,41 +23,119 @@ pub struct Context {
 	pub canonical: Option<String>,
 	pub time: u64,
 
+	// -- navigation, the JSON-LD goes into a script tag as is --
+	pub breadcrumbs: Option<Vec<Breadcrumb>>,
+	pub breadcrumbs_json_ld: Option<String>,
+
 	// -- social --
 	pub facebook_app_id: Option<String>,
 	pub facebook_user: Option<String>,
 	pub instagram_user: Option<String>,
 	pub twitter_user: Option<String>,
 	pub youtube_channel: Option<String>,
+	/// Share card of the post, made from its title and featured image
+	pub og_image: Option<String>,
+
+	// -- free-form values from the [site_extras] config section --
+	pub site_extras: Option<HashMap<String, String>>,
+	/// Feature flags that are on for everyone, see `template_flags`
+	pub flags: Option<HashMap<String, bool>>,
 
 	// -- menus --
 	pub main_menu: Option<Vec<menu::MenuItem>>,
 
+	// -- tags & feeds --
+	pub tag_cloud: Option<Arc<Vec<TagCloudEntry>>>,
+	pub feed_links: Option<Vec<FeedLink>>,
+
 	// -- excerpts of posts with certain tags --
-	pub excerpts_tag_1: Option<Vec<PostExcerpt>>,
-	pub excerpts_tag_2: Option<Vec<PostExcerpt>>,
-	pub excerpts_tag_3: Option<Vec<PostExcerpt>>,
-	pub excerpts_tag_4: Option<Vec<PostExcerpt>>,
-	pub excerpts_tag_5: Option<Vec<PostExcerpt>>,
+	pub excerpts_tag_1: Option<Vec<Arc<PostExcerpt>>>,
+	pub excerpts_tag_2: Option<Vec<Arc<PostExcerpt>>>,
+	pub excerpts_tag_3: Option<Vec<Arc<PostExcerpt>>>,
+	pub excerpts_tag_4: Option<Vec<Arc<PostExcerpt>>>,
+	pub excerpts_tag_5: Option<Vec<Arc<PostExcerpt>>>,
 
 	// -- site: POST --
-	pub post: Option<Post>,
-	pub post_related: Option<Vec<PostExcerpt>>,
+	pub post: Option<Arc<Post>>,
+	pub post_related: Option<Vec<Arc<PostExcerpt>>>,
+	/// "You might also like", picked by tags and popularity, see `recommend_posts`
+	pub post_recommended: Option<Vec<Arc<PostExcerpt>>>,
 	pub post_comments: Option<Vec<Comment>>,
+	/// Approved pingbacks and trackbacks
+	pub post_mentions: Option<Vec<Mention>>,
+	pub post_polls: Option<Vec<Arc<PollResults>>>,
+	/// The tag page of the primary tag, for a link back to the category
+	pub post_category: Option<Breadcrumb>,
+	/// A post that is not shown in the country of the reader, only its excerpt is handed out
+	pub post_unavailable: Option<Arc<PostExcerpt>>,
+	pub reader_country: Option<String>,
 
 	// -- site: INDEX --
 	pub instagram_posts: Option<Vec<InstagramPostCompact>>,
 	pub pinterest_posts: Option<Vec<PinterestPostCompact>>,
-	pub latest_posts: Option<Vec<PostExcerpt>>,
-	pub featured_posts: Option<Vec<PostExcerpt>>,
+	pub latest_posts: Option<Vec<Arc<PostExcerpt>>>,
+	pub featured_posts: Option<Vec<Arc<PostExcerpt>>>,
+
+	// -- site: RSS feed --
+	pub feed_items: Option<Vec<FeedItem>>,
+	pub feed_full_content: bool,
+	pub feed_last_build_date: u64,
+	pub feed_last_build_date_rfc2822: Option<String>,
 
-	// -- site: SEARCH & TAG (category) --
+	// -- site: SEARCH & TAG (category) & AUTHOR --
 	pub tag: Option<Tag>,
 	pub tag_id: Option<String>,
+	pub author: Option<PostAuthor>,
 	pub search_string: Option<String>,
-	pub post_list: Option<Vec<PostExcerpt>>,
+	pub post_list: Option<Vec<Arc<PostExcerpt>>>,
 	pub page_current: u32,
 	pub page_total: u32,
+
+	// -- site: STATS --
+	pub stats: Option<PublicStats>,
+}
+
+/// A single item of the RSS feed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedItem {
+	pub post: Arc<PostExcerpt>,
+	/// When the post was published, in the timezone of the site
+	pub pub_date: String,
+	pub enclosure: Option<FeedEnclosure>,
+}
+
+/// Featured image attached to a feed item
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedEnclosure {
+	pub url: String,
+	pub mime_type: String,
+	pub length: u64,
+}
+
+/// One step from the start page to the current page, the last one is the page itself
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Breadcrumb {
+	pub title: String,
+	pub url: String,
+}
+
+/// A link to one of our feeds, used for feed discovery
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FeedLink {
+	pub title: String,
+	pub url: String,
+}
+
+/// A tag with the number of posts using it
+///
+/// The weight is a bucket from 1 (rarely used) to 5 (used the most)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TagCloudEntry {
+	pub tag: String,
+	pub title: String,
+	pub post_count: u32,
+	pub weight: u8,
+	pub feed_url: String,
 }
 
 
@@ -1,11 +1,28 @@
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use crate::app::timezone::{site_time, site_utc_offset_seconds};
+use crate::blog::Blog;
+use crate::blog::cache::HtmlCacheStats;
+use crate::blog::types::experiment::{load_experiment_results, ExperimentResults};
+use crate::blog::types::poll::PollResults;
+use crate::storage::{DbRow, Storage};
+
 #[derive(Debug, Serialize)]
 pub struct DashboardPerformance {
 	views_by_day: Vec<DashboardViewsByDay>,
 	views_by_post: Vec<DashboardViewsByPost>,
+	views_by_campaign: Vec<DashboardViewsByCampaign>,
+	views_by_country: Vec<DashboardViewsByCountry>,
+	engagement_by_post: Vec<DashboardEngagementByPost>,
 	comments_total: u32,
 	comments_new: u32,
 	posts_total: u32,
 	posts_unpublished: u32,
+	html_cache: Vec<HtmlCacheStats>,
+	polls: Vec<Arc<PollResults>>,
+	experiments: Vec<ExperimentResults>,
 }
 
 #[derive(Debug, Serialize)]
@@ -22,17 +39,46 @@ pub struct DashboardViewsByPost {
 	title: String,
 }
 
+/// Views that came with tracking parameters, e.g. from a newsletter
+#[derive(Debug, Serialize)]
+pub struct DashboardViewsByCampaign {
+	campaign: String,
+	count: u32,
+}
+
+/// Where readers come from, as told by the CDN, see `get_client_country`
+#[derive(Debug, Serialize)]
+pub struct DashboardViewsByCountry {
+	/// ISO code like `DE`
+	country: String,
+	count: u32,
+}
+
+/// How much of a post gets read, from the beacons sent when readers leave it
+#[derive(Debug, Serialize)]
+pub struct DashboardEngagementByPost {
+	post_id: u32,
+	title: String,
+	beacons: u32,
+	/// Average scroll depth in percent
+	avg_depth: u32,
+	/// Average time on the page in seconds
+	avg_time: u32,
+	/// Readers that got to the end, in percent
+	finished: u32,
+}
+
 impl DashboardViewsByDay {
-	pub fn from_sql(mut row: mysql::Row) -> Option<DashboardViewsByDay> {
+	pub fn from_sql(mut row: DbRow) -> Option<DashboardViewsByDay> {
 		Some(DashboardViewsByDay {
-			date: row.take("date")?,
+			date: row.take::<NaiveDateTime>("date")?.format("%d.%m.%Y").to_string(),
 			count: row.take("count")?,
 		})
 	}
 }
 
 impl DashboardViewsByPost {
-	pub fn from_sql(mut row: mysql::Row) -> Option<DashboardViewsByPost> {
+	pub fn from_sql(mut row: DbRow) -> Option<DashboardViewsByPost> {
 		Some(DashboardViewsByPost {
 			post_id: row.take("post_id")?,
 			last_14: row.take("last_14")?,
@@ -42,26 +88,61 @@ impl DashboardViewsByPost {
 	}
 }
 
+impl DashboardViewsByCampaign {
+	pub fn from_sql(mut row: DbRow) -> Option<DashboardViewsByCampaign> {
+		Some(DashboardViewsByCampaign {
+			campaign: row.take("campaign")?,
+			count: row.take("count")?,
+		})
+	}
+}
+
+impl DashboardViewsByCountry {
+	pub fn from_sql(mut row: DbRow) -> Option<DashboardViewsByCountry> {
+		Some(DashboardViewsByCountry {
+			country: row.take("country")?,
+			count: row.take("count")?,
+		})
+	}
+}
+
+impl DashboardEngagementByPost {
+	pub fn from_sql(mut row: DbRow) -> Option<DashboardEngagementByPost> {
+		let beacons: u32 = row.take("beacons")?;
+		let finished: u32 = row.take("finished")?;
+		Some(DashboardEngagementByPost {
+			post_id: row.take("post_id")?,
+			title: row.take("title")?,
+			beacons,
+			avg_depth: row.take::<f64>("avg_depth")?.round() as u32,
+			avg_time: row.take::<f64>("avg_time")?.round() as u32,
+			finished: (finished * 100).checked_div(beacons).unwrap_or(0),
+		})
+	}
+}
+
 
-/// Query some statistics from the database
-pub fn dashboard_get_statistics(db: &mysql::Pool) -> DashboardPerformance {
-	let query_a = r###"
-        SELECT DATE_FORMAT(viewed_at, '%d.%m.%Y') AS date, COUNT(id) AS count
+/// Query some statistics from the database, plus how well the HTML cache works
+pub fn dashboard_get_statistics(db: &Arc<dyn Storage>, blog: &Blog) -> DashboardPerformance {
+	// Whole days of the site's timezone, today included, the offset of today is used for all of them
+	let offset = site_utc_offset_seconds();
+	let today = site_time(Utc::now().naive_utc()).naive_local().date().and_hms(0, 0, 0) - Duration::seconds(offset);
+	let since_14 = today - Duration::days(13);
+	let since_7 = today - Duration::days(6);
+
+	let viewed_at = db.shift_seconds("viewed_at", offset);
+	let query_a = format!(r###"
+        SELECT DATE({}) AS date, COUNT(id) AS count
         FROM post_views
-        WHERE viewed_at >= DATE_ADD(NOW(), INTERVAL -13 DAY)
-        GROUP BY DATE_FORMAT(viewed_at, '%d.%m.%Y')
-    "###;
+        WHERE viewed_at >= :since_14
+        GROUP BY DATE({})
+    "###, viewed_at, viewed_at);
 
 	let mut views_by_day = Vec::new();
 
-	match db.prep_exec(&query_a, ()) {
-		Ok(query_result) => {
-			for result_row in query_result {
-				let row = match result_row {
-					Ok(tmp) => tmp,
-					_ => continue
-				};
-
+	match db.query(&query_a, db_params! {"since_14" => since_14}) {
+		Ok(rows) => {
+			for row in rows {
 				match DashboardViewsByDay::from_sql(row) {
 					Some(tmp) => views_by_day.push(tmp),
 					_ => {}
@@ -73,24 +154,19 @@ pub fn dashboard_get_statistics(db: &mysql::Pool) -> DashboardPerformance {
 
 
 	let query_b = r###"
-        SELECT post_id, COUNT(id) AS last_14, COUNT(IF(viewed_at>=DATE_ADD(NOW(), INTERVAL -6 DAY),1, NULL)) AS last_7,
-        LEFT((SELECT title FROM posts WHERE id = post_id), 30) AS title
+        SELECT post_id, COUNT(id) AS last_14, SUM(CASE WHEN viewed_at >= :since_7 THEN 1 ELSE 0 END) AS last_7,
+        SUBSTR((SELECT title FROM posts WHERE id = post_id), 1, 30) AS title
         FROM post_views
-        WHERE viewed_at >= DATE_ADD(NOW(), INTERVAL -13 DAY)
+        WHERE viewed_at >= :since_14
         GROUP BY post_id
         ORDER BY COUNT(id) DESC LIMIT 0,10
     "###;
 
 	let mut views_by_post = Vec::new();
 
-	match db.prep_exec(&query_b, ()) {
-		Ok(query_result) => {
-			for result_row in query_result {
-				let row = match result_row {
-					Ok(tmp) => tmp,
-					_ => continue
-				};
-
+	match db.query(query_b, db_params! {"since_14" => since_14, "since_7" => since_7}) {
+		Ok(rows) => {
+			for row in rows {
 				match DashboardViewsByPost::from_sql(row) {
 					Some(tmp) => views_by_post.push(tmp),
 					_ => {}
@@ -100,6 +176,49 @@ pub fn dashboard_get_statistics(db: &mysql::Pool) -> DashboardPerformance {
 		_ => {}
 	}
 
+	let query_c = r###"
+        SELECT campaign, COUNT(id) AS count
+        FROM post_views
+        WHERE viewed_at >= :since_14 AND campaign <> ''
+        GROUP BY campaign
+        ORDER BY COUNT(id) DESC LIMIT 0,10
+    "###;
+
+	let views_by_campaign = match db.query(query_c, db_params! {"since_14" => since_14}) {
+		Ok(rows) => rows.into_iter().filter_map(DashboardViewsByCampaign::from_sql).collect(),
+		_ => vec![]
+	};
+
+	// Scrolling 90% of the way counts as finished, comments and related posts follow the text
+	let query_d = r###"
+        SELECT post_id, COUNT(id) AS beacons, AVG(scroll_depth) AS avg_depth, AVG(read_time) AS avg_time,
+        SUM(CASE WHEN scroll_depth >= 90 THEN 1 ELSE 0 END) AS finished,
+        SUBSTR((SELECT title FROM posts WHERE id = post_id), 1, 30) AS title
+        FROM post_engagement
+        WHERE recorded_at >= :since_14
+        GROUP BY post_id
+        ORDER BY COUNT(id) DESC LIMIT 0,10
+    "###;
+
+	let engagement_by_post = match db.query(query_d, db_params! {"since_14" => since_14}) {
+		Ok(rows) => rows.into_iter().filter_map(DashboardEngagementByPost::from_sql).collect(),
+		_ => vec![]
+	};
+
+	// Views from unknown countries are left out
+	let query_e = r###"
+        SELECT country, COUNT(id) AS count
+        FROM post_views
+        WHERE viewed_at >= :since_14 AND country <> ''
+        GROUP BY country
+        ORDER BY COUNT(id) DESC LIMIT 0,20
+    "###;
+
+	let views_by_country = match db.query(query_e, db_params! {"since_14" => since_14}) {
+		Ok(rows) => rows.into_iter().filter_map(DashboardViewsByCountry::from_sql).collect(),
+		_ => vec![]
+	};
+
 	// The number of comments as well as the number of new (unapproved comments)
 	let (comments_total, comments_new) = get_comment_counts(db);
 
@@ -109,32 +228,33 @@ pub fn dashboard_get_statistics(db: &mysql::Pool) -> DashboardPerformance {
 	DashboardPerformance {
 		views_by_day,
 		views_by_post,
+		views_by_campaign,
+		views_by_country,
+		engagement_by_post,
 		comments_total,
 		comments_new,
 		posts_total,
 		posts_unpublished,
+		html_cache: blog.get_html_cache_stats(),
+		polls: blog.get_all_poll_results(),
+		experiments: load_experiment_results(db, 10).unwrap_or_default(),
 	}
 }
 
 /// This function will return the total number of comments as well as how many comments are not yet approved
-fn get_comment_counts(db: &mysql::Pool) -> (u32, u32) {
+fn get_comment_counts(db: &Arc<dyn Storage>) -> (u32, u32) {
 	let query = "SELECT COUNT(*) AS total, SUM(case when status='new' then 1 else 0 end) AS new FROM post_comments";
 	let mut comments_total = 0u32;
 	let mut comments_new = 0u32;
 
-	match db.prep_exec(&query, ()) {
-		Ok(query_result) => {
-			for result_row in query_result {
-				let row = match result_row {
-					Ok(tmp) => tmp,
-					_ => continue
-				};
-
-				comments_total = match row.get("total") {
+	match db.query(query, db_params!()) {
+		Ok(rows) => {
+			for mut row in rows {
+				comments_total = match row.take("total") {
 					Some(val) => val,
 					_ => 0
 				};
-				comments_new = match row.get("new") {
+				comments_new = match row.take("new") {
 					Some(val) => val,
 					_ => 0
 				};
@@ -147,24 +267,19 @@ fn get_comment_counts(db: &mysql::Pool) -> (u32, u32) {
 }
 
 /// This function will return the total numbr of posts as well as the number of unpublished posts
-fn get_post_counts(db: &mysql::Pool) -> (u32, u32) {
+fn get_post_counts(db: &Arc<dyn Storage>) -> (u32, u32) {
 	let query = "SELECT COUNT(*) AS total, SUM(case when state!='published' then 1 else 0 end) AS unpublished FROM posts";
 	let mut posts_total = 0u32;
 	let mut posts_unpublished = 0u32;
 
-	match db.prep_exec(&query, ()) {
-		Ok(query_result) => {
-			for result_row in query_result {
-				let row = match result_row {
-					Ok(tmp) => tmp,
-					_ => continue
-				};
-
-				posts_total = match row.get("total") {
+	match db.query(query, db_params!()) {
+		Ok(rows) => {
+			for mut row in rows {
+				posts_total = match row.take("total") {
 					Some(val) => val,
 					_ => 0
 				};
-				posts_unpublished = match row.get("unpublished") {
+				posts_unpublished = match row.take("unpublished") {
 					Some(val) => val,
 					_ => 0
 				};
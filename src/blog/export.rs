@@ -0,0 +1,135 @@
+use std::fs;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::{Captures, Regex};
+
+use crate::app::config::config_get_string;
+use crate::blog::gallery::{gallery_find_file, gallery_find_original};
+use crate::storage::Storage;
+
+lazy_static! {
+	static ref GALLERY_SOURCE: Regex = Regex::new(r#"(?i)(?P<attr>\s(?:src|poster)\s*=\s*)(?P<quote>["'])(?P<url>(?:https?://(?P<host>[^/"']+))?/gallery/(?P<path>[^"'?#]+))["']"#).unwrap();
+	static ref RESPONSIVE: Regex = Regex::new(r#"(?i)\s+(srcset|sizes)\s*=\s*("[^"]*"|'[^']*')"#).unwrap();
+}
+
+
+// ------------------------------
+// ------------ EXPORT ----------
+// ------------------------------
+
+/// Formats a post can be exported in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+	Html,
+	Pdf,
+}
+
+impl ExportFormat {
+	pub fn parse(format: &str) -> Option<ExportFormat> {
+		match format {
+			"html" => Some(ExportFormat::Html),
+			"pdf" => Some(ExportFormat::Pdf),
+			_ => None
+		}
+	}
+
+	pub fn extension(&self) -> &'static str {
+		match self {
+			ExportFormat::Html => "html",
+			ExportFormat::Pdf => "pdf",
+		}
+	}
+
+	pub fn mime_type(&self) -> &'static str {
+		match self {
+			ExportFormat::Html => "text/html; charset=utf-8",
+			ExportFormat::Pdf => "application/pdf",
+		}
+	}
+}
+
+/// Turn an exported post into a file that works without our server
+///
+/// Gallery images are inlined, `srcset` is dropped as it would point back to us. PDFs are made by
+/// `wkhtmltopdf`, see `pdf_export_enabled`
+pub fn export_document(db: &Arc<dyn Storage>, html: &str, format: ExportFormat) -> Result<Vec<u8>, String> {
+	let html = RESPONSIVE.replace_all(html, "");
+	let html = inline_gallery_images(db, &html);
+
+	match format {
+		ExportFormat::Html => Ok(html.into_bytes()),
+		ExportFormat::Pdf => html_to_pdf(&html),
+	}
+}
+
+/// PDF export is opt-in, `pdf_converter_path` has to point to a `wkhtmltopdf` binary
+pub fn pdf_export_enabled() -> bool {
+	config_get_string("pdf_converter_path").len() > 0
+}
+
+/// Replace the images from our gallery by data URIs
+fn inline_gallery_images(db: &Arc<dyn Storage>, html: &str) -> String {
+	let own_host = config_get_string("fqdn").to_lowercase();
+
+	GALLERY_SOURCE.replace_all(html, |caps: &Captures| {
+		// Images hosted elsewhere stay as they are
+		match caps.name("host") {
+			Some(host) if host.as_str().to_lowercase() != own_host => { return String::from(&caps[0]); }
+			_ => {}
+		}
+
+		// Resized variants are `guid/size/name.jpg`, everything else is an original
+		let parts: Vec<&str> = caps["path"].splitn(3, '/').collect();
+		let local_path = match parts.len() {
+			3 => gallery_find_file(db, parts[0], parts[1], parts[2]),
+			_ => gallery_find_original(&caps["path"])
+		};
+
+		match (image_mime_type(&local_path), fs::read(&local_path)) {
+			(Some(mime_type), Ok(data)) => format!("{}{}data:{};base64,{}{}", &caps["attr"], &caps["quote"], mime_type, base64::encode(&data), &caps["quote"]),
+			_ => String::from(&caps[0])
+		}
+	}).into_owned()
+}
+
+fn image_mime_type(path: &str) -> Option<&'static str> {
+	let extension = path.rsplit('.').next()?.to_lowercase();
+	match extension.as_str() {
+		"jpg" | "jpeg" => Some("image/jpeg"),
+		"png" => Some("image/png"),
+		"gif" => Some("image/gif"),
+		_ => None
+	}
+}
+
+/// Print the HTML to a PDF with wkhtmltopdf, through files in the temp directory
+fn html_to_pdf(html: &str) -> Result<Vec<u8>, String> {
+	let converter = match config_get_string("pdf_converter_path") {
+		path if path.len() > 0 => path,
+		_ => { return Err(String::from("PDF export is not set up")); }
+	};
+
+	let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|tmp| tmp.as_nanos()).unwrap_or(0);
+	let html_path = std::env::temp_dir().join(format!("export_{}.html", stamp));
+	let pdf_path = std::env::temp_dir().join(format!("export_{}.pdf", stamp));
+
+	fs::write(&html_path, html).map_err(|err| format!("Could not write the export: {}", err))?;
+	let result = Command::new(converter)
+		.arg("--quiet")
+		// The post is ours but comments and embeds are not, nothing may read files from this machine
+		.arg("--disable-local-file-access")
+		.arg(&html_path)
+		.arg(&pdf_path)
+		.status()
+		.map_err(|err| format!("Could not run wkhtmltopdf: {}", err))
+		.and_then(|status| match status.success() {
+			true => fs::read(&pdf_path).map_err(|err| format!("Could not read the PDF: {}", err)),
+			false => Err(String::from("Could not convert the post to a PDF"))
+		});
+
+	let _ = fs::remove_file(&html_path);
+	let _ = fs::remove_file(&pdf_path);
+	result
+}
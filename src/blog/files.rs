@@ -0,0 +1,207 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+
+use crate::app::utils::get_extension_from_filename;
+use crate::app::utils::get_stem_from_filename;
+use crate::app::utils::weak_random_base62_string;
+use crate::storage::{DbRow, Storage};
+
+const FILES_PATH: &str = "data/files";
+
+/// File types we accept as attachments: extension, content type and how the content starts
+const ALLOWED_TYPES: &[(&str, &str, &str)] = &[
+	("pdf", "application/pdf", "%PDF-"),
+	("gpx", "application/gpx+xml", "<?xml"),
+];
+
+#[derive(Debug, Serialize)]
+pub struct Attachment {
+	guid: String,
+	ext: String,
+	name: String,
+	size: u64,
+	mime_type: String,
+	url: String,
+	uploaded_at: u64,
+}
+
+/// An uploaded file that was written to disk but not yet stored in the database
+pub struct PendingAttachment {
+	pub local_path: String,
+	pub name: String,
+}
+
+
+/// Look up the content type of a whitelisted extension
+pub fn attachment_mime_type(extension: &str) -> Option<&'static str> {
+	ALLOWED_TYPES.iter()
+		.find(|(ext, _, _)| ext.eq_ignore_ascii_case(extension))
+		.map(|(_, mime_type, _)| *mime_type)
+}
+
+/// Generate a new file name for an attachment, only whitelisted types are accepted
+pub fn generate_attachment_file_name(uploaded_name: &str) -> Result<String, String> {
+	let extension = match get_extension_from_filename(uploaded_name) {
+		Some(ext) => ext.to_lowercase(),
+		_ => return Err(String::from("Could not get extension from filename")),
+	};
+
+	if attachment_mime_type(&extension).is_none() {
+		return Err(format!("Files of type '{}' are not allowed", extension));
+	}
+
+	// Make sure all the folders exist
+	match fs::create_dir_all(FILES_PATH) {
+		Ok(_tmp) => {}
+		_ => {}
+	}
+
+	for _ in 0..25 {
+		let path_local = format!("{}/{}.{}", FILES_PATH, weak_random_base62_string(15), extension);
+
+		if !Path::new(&path_local).exists() {
+			return Ok(path_local);
+		}
+	}
+
+	Err(String::from("All file names collide"))
+}
+
+/// Validate the uploaded files and store references in the database, invalid files are removed again
+pub fn finish_attachment_upload(uploads: &Vec<PendingAttachment>, db: &Arc<dyn Storage>) -> Vec<Attachment> {
+	let mut result = vec![];
+	for upload in uploads {
+		match uploaded_attachment_get_info(upload) {
+			Ok(attachment) => {
+				match add_attachment(&attachment, db) {
+					Ok(()) => { result.push(attachment); }
+					Err(err) => { println!("Error adding attachment: {}", err); }
+				}
+			}
+			Err(err) => {
+				println!("Rejected attachment {}: {}", upload.name, err);
+				match fs::remove_file(&upload.local_path) {
+					Ok(_) => {}
+					Err(err) => { println!("Error removing rejected attachment: {:?}", err); }
+				}
+			}
+		}
+	}
+
+	result
+}
+
+/// Check the content of an uploaded file against its extension
+fn uploaded_attachment_get_info(upload: &PendingAttachment) -> Result<Attachment, String> {
+	let extension = get_extension_from_filename(&upload.local_path).ok_or(String::from("Cannot get file extension"))?;
+	let stem = get_stem_from_filename(&upload.local_path).ok_or(String::from("Cannot get file stem"))?;
+
+	let (_, mime_type, magic) = ALLOWED_TYPES.iter()
+		.find(|(ext, _, _)| *ext == extension)
+		.ok_or(String::from("File type not allowed"))?;
+
+	// Compare the start of the file
+	let mut head = vec![0u8; magic.len()];
+	let mut file = File::open(&upload.local_path).map_err(|_| String::from("Uploaded file not found"))?;
+	file.read_exact(&mut head).map_err(|_| String::from("Uploaded file is too short"))?;
+	if head != magic.as_bytes() {
+		return Err(String::from("File content does not match its type"));
+	}
+
+	let size = file.metadata().map_err(|err| err.to_string())?.len();
+
+	Ok(Attachment {
+		guid: String::from(stem),
+		ext: String::from(extension),
+		name: sanitize_attachment_name(&upload.name, extension),
+		size,
+		mime_type: String::from(*mime_type),
+		url: String::from(""),
+		uploaded_at: 0,
+	}.with_url())
+}
+
+/// Keep the user facing name readable but safe for URLs and download headers
+fn sanitize_attachment_name(name: &str, extension: &str) -> String {
+	let stem: String = get_stem_from_filename(name).unwrap_or("download").chars()
+		.map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+		.collect();
+
+	format!("{}.{}", if stem.is_empty() { "download" } else { &stem }, extension)
+}
+
+/// Add a new attachment to the database
+fn add_attachment(attachment: &Attachment, db: &Arc<dyn Storage>) -> Result<(), String> {
+	let query = "INSERT INTO files (guid, extension, name, size) VALUES (:guid, :extension, :name, :size)";
+
+	match db.exec(query, db_params! {"guid" => &attachment.guid, "extension" => &attachment.ext, "name" => &attachment.name, "size" => attachment.size}) {
+		Ok(_) => Ok(()),
+		Err(err) => Err(err.to_string())
+	}
+}
+
+/// Load all attachments from the database
+pub fn load_attachments_from_sql(db: &Arc<dyn Storage>) -> Vec<Attachment> {
+	let rows = match db.query("SELECT guid, extension, name, size, uploadedAt FROM files ORDER BY uploadedAt DESC", db_params!()) {
+		Ok(tmp) => { tmp }
+		_ => { return vec![]; }
+	};
+
+	let mut attachments = Vec::new();
+
+	for row in rows {
+		match from_sql(row) {
+			Some(tmp) => attachments.push(tmp),
+			_ => {}
+		}
+	}
+
+	attachments
+}
+
+/// Load a single attachment
+pub fn load_attachment(db: &Arc<dyn Storage>, guid: &str) -> Option<Attachment> {
+	match db.query_first("SELECT guid, extension, name, size, uploadedAt FROM files WHERE guid=:guid", db_params! {"guid" => guid}) {
+		Ok(Some(row)) => { from_sql(row) }
+		_ => { None }
+	}
+}
+
+/// Turn a SQL row into an attachment
+fn from_sql(mut row: DbRow) -> Option<Attachment> {
+	let ext: String = row.take("extension")?;
+
+	Some(Attachment {
+		guid: row.take("guid")?,
+		mime_type: String::from(attachment_mime_type(&ext)?),
+		ext,
+		name: row.take("name")?,
+		size: row.take("size")?,
+		url: String::from(""),
+		uploaded_at: row.take::<NaiveDateTime>("uploadedAt")?.timestamp() as u64,
+	}.with_url())
+}
+
+impl Attachment {
+	/// The link that can be used in posts
+	fn with_url(mut self) -> Attachment {
+		self.url = format!("/files/{}/{}", self.guid, self.name);
+		self
+	}
+
+	pub fn local_path(&self) -> String {
+		format!("{}/{}.{}", FILES_PATH, self.guid, self.ext)
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn mime_type(&self) -> &str {
+		&self.mime_type
+	}
+}
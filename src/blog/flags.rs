@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::app::config::config_get_string_map;
+use crate::storage::{DbRow, Storage};
+
+lazy_static! {
+	/// The flags switched in the admin panel, they win over the config
+	static ref FLAGS: RwLock<HashMap<String, FeatureFlag>> = RwLock::new(HashMap::new());
+}
+
+/// Longest name of a flag
+const FLAG_NAME_MAX_LENGTH: usize = 64;
+
+
+// ------------------------------
+// ------------ FLAGS -----------
+// ------------------------------
+
+/// A feature that can be rolled out gradually or switched off without a release
+///
+/// Defaults come from the `[flags]` config section, e.g. `mentions = "on"` or `recommendations = "25"`
+/// for a quarter. Saving a flag in the admin panel overrides the config until it is deleted again
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FeatureFlag {
+	pub name: String,
+	pub enabled: bool,
+	/// Percent of visitors or items that get the feature while it is enabled
+	#[serde(default = "default_rollout")]
+	pub rollout: u8,
+	#[serde(default)]
+	pub note: String,
+	/// `config` or `admin`
+	#[serde(default)]
+	pub source: String,
+	#[serde(default)]
+	pub updated_at: u64,
+}
+
+fn default_rollout() -> u8 {
+	100
+}
+
+impl FeatureFlag {
+	fn from_sql(mut row: DbRow) -> Option<FeatureFlag> {
+		Some(FeatureFlag {
+			name: row.take("name")?,
+			enabled: row.take("enabled")?,
+			rollout: row.take("rollout")?,
+			note: row.take("note")?,
+			source: String::from("admin"),
+			updated_at: row.take::<NaiveDateTime>("updated_at")?.timestamp() as u64,
+		})
+	}
+
+	/// A default from the config, `on`, `off` or a rollout in percent
+	fn from_config(name: &str, value: &str) -> Option<FeatureFlag> {
+		let (enabled, rollout) = match value.trim().trim_end_matches('%').to_lowercase().as_str() {
+			"true" | "on" | "yes" => (true, 100),
+			"false" | "off" | "no" => (false, 100),
+			tmp => match tmp.parse::<u8>() {
+				Ok(percent) if percent <= 100 => (percent > 0, percent),
+				_ => { return None; }
+			}
+		};
+
+		Some(FeatureFlag { name: String::from(name), enabled, rollout, note: String::new(), source: String::from("config"), updated_at: 0 })
+	}
+
+	/// Check and clean up a flag from the admin panel
+	pub fn normalized(mut self) -> Result<FeatureFlag, String> {
+		self.name = self.name.trim().to_lowercase();
+		self.note = String::from(self.note.trim());
+		self.source = String::from("admin");
+
+		if self.name.len() == 0 || self.name.len() > FLAG_NAME_MAX_LENGTH || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+			return Err(format!("'{}' is not a flag name, use up to {} letters, digits and underscores", self.name, FLAG_NAME_MAX_LENGTH));
+		}
+		if self.rollout > 100 {
+			return Err(String::from("the rollout is a percentage from 0 to 100"));
+		}
+
+		Ok(self)
+	}
+
+	/// Whether the feature is on for everyone
+	fn is_on(&self) -> bool {
+		self.enabled && self.rollout >= 100
+	}
+
+	/// Whether the feature is on for a visitor or item, the same key always gets the same answer
+	///
+	/// FNV-1a like `ExperimentVariant::for_visitor`, salted with the name so each flag picks other keys
+	fn is_on_for(&self, key: &str) -> bool {
+		if !self.enabled { return false; }
+		if self.rollout >= 100 { return true; }
+
+		let mut hash: u64 = 0xcbf29ce484222325;
+		for byte in self.name.bytes().chain(key.bytes()) {
+			hash ^= byte as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+		hash % 100 < self.rollout as u64
+	}
+}
+
+
+/// Whether a feature is on for everyone, `default` if no flag of that name is set
+///
+/// Partial rollouts count as off, use `flag_enabled_for` where there is something to pick by
+pub fn flag_enabled(name: &str, default: bool) -> bool {
+	match find_flag(name) {
+		Some(flag) => flag.is_on(),
+		_ => default
+	}
+}
+
+/// Whether a feature is on for a visitor or item, e.g. the id of a post for pages from the HTML cache
+pub fn flag_enabled_for(name: &str, key: &str, default: bool) -> bool {
+	match find_flag(name) {
+		Some(flag) => flag.is_on_for(key),
+		_ => default
+	}
+}
+
+fn find_flag(name: &str) -> Option<FeatureFlag> {
+	let saved = match FLAGS.read() {
+		Ok(guard) => guard.get(name).cloned(),
+		_ => None
+	};
+
+	saved.or_else(|| {
+		config_get_string_map("flags").get(name).and_then(|value| FeatureFlag::from_config(name, value))
+	})
+}
+
+/// All flags from the config and the admin panel, by name
+pub fn feature_flags() -> Vec<FeatureFlag> {
+	let mut flags: HashMap<String, FeatureFlag> = config_get_string_map("flags").iter()
+		.filter_map(|(name, value)| FeatureFlag::from_config(name, value))
+		.map(|flag| (flag.name.clone(), flag))
+		.collect();
+
+	match FLAGS.read() {
+		Ok(guard) => {
+			for flag in guard.values() {
+				flags.insert(flag.name.clone(), flag.clone());
+			}
+		}
+		_ => {}
+	}
+
+	let mut flags: Vec<FeatureFlag> = flags.into_values().collect();
+	flags.sort_by(|a, b| a.name.cmp(&b.name));
+	flags
+}
+
+/// The flags for templates, `{% if flags.mentions %}`. Only flags that are on for everyone are true,
+/// the pages are cached for all visitors
+pub fn template_flags() -> HashMap<String, bool> {
+	feature_flags().into_iter().map(|flag| (flag.name.clone(), flag.is_on())).collect()
+}
+
+/// Read the flags saved in the admin panel into memory
+///
+/// Returns the number of flags
+pub fn flags_load(db: &Arc<dyn Storage>) -> Result<usize, String> {
+	let query = "SELECT name, enabled, rollout, note, updated_at FROM feature_flags";
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+	let flags: HashMap<String, FeatureFlag> = rows.into_iter()
+		.filter_map(FeatureFlag::from_sql)
+		.map(|flag| (flag.name.clone(), flag))
+		.collect();
+	let count = flags.len();
+
+	match FLAGS.write() {
+		Ok(mut guard) => { *guard = flags; }
+		_ => {}
+	}
+
+	Ok(count)
+}
+
+/// Save a flag and use it right away, flags must be normalized
+pub fn flag_store(db: &Arc<dyn Storage>, flag: &FeatureFlag) -> Result<(), String> {
+	let query = format!(r##"
+    INSERT INTO feature_flags (name, enabled, rollout, note, updated_at) VALUES
+    (:name, :enabled, :rollout, :note, :updated_at)
+    {}
+    "##, db.upsert("name", &["enabled", "rollout", "note", "updated_at"]));
+
+	match db.exec(&query, db_params! {"name" => &flag.name, "enabled" => flag.enabled, "rollout" => flag.rollout, "note" => &flag.note, "updated_at" => Utc::now().naive_utc()}) {
+		Ok(_) => {}
+		Err(err) => {
+			println!("Error: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	flags_load(db)?;
+	Ok(())
+}
+
+/// Remove a saved flag, the config decides again
+pub fn flag_delete(db: &Arc<dyn Storage>, name: &str) -> Result<(), String> {
+	match db.exec("DELETE FROM feature_flags WHERE name=:name", db_params! {"name" => name}) {
+		Ok(_) => {}
+		Err(err) => {
+			println!("Error: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	flags_load(db)?;
+	Ok(())
+}
@@ -1,26 +1,75 @@
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io;
-use md5::{Md5, Digest};
+use sha2::{Sha256, Digest};
 use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
 
+use chrono::{NaiveDateTime, Utc};
 use image::GenericImageView;
 use regex::Regex;
 
+use crate::app::config::{config_get_i64, config_get_string, config_get_string_vec};
 use crate::app::utils::get_extension_from_filename;
 use crate::app::utils::get_stem_from_filename;
 use crate::app::utils::weak_random_base62_string;
+use crate::blog::image_meta::{ImageMetadata, read_image_metadata, strip_jpeg_metadata};
+use crate::blog::optimize::{encode_image, optimize_original};
+use crate::blog::types::post::PostMedia;
+use crate::blog::validation::DataIssue;
+use crate::storage::{DbRow, Storage};
 
 const GALLERY_PATH: &str = "data/gallery";
 const DEFAULT_PICTURE_PATH: &str = "data/gallery/not_found.png";
 
+/// Video clips are stored next to a poster frame, which is used wherever an image is needed
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm"];
+
+/// Crop sizes made on request when `gallery_crop_sizes` is not set, every other size would be a file on disk
+const DEFAULT_CROP_SIZES: &[&str] = &["400x300", "800x600", "1200x630"];
+
+/// Default upper limit for uploaded clips, 64 MB
+const VIDEO_MAX_SIZE: u64 = 67108864;
+
 #[derive(Debug, Serialize)]
 pub struct UploadedImage {
 	guid: String,
 	ext: String,
+	/// Either `image` or `video`
+	media_type: String,
 	src: String,
 	hash: String,
 	x: u32,
 	y: u32,
+	focal_x: f32,
+	focal_y: f32,
+	metadata: ImageMetadata,
+}
+
+impl UploadedImage {
+	pub fn guid(&self) -> &str {
+		&self.guid
+	}
+
+	pub fn extension(&self) -> &str {
+		&self.ext
+	}
+}
+
+/// Images in the gallery sharing the same file hash
+#[derive(Debug, Serialize)]
+pub struct DuplicateImages {
+	hash: String,
+	guids: Vec<String>,
+}
+
+/// The point of interest of an image, in percent of its width and height
+#[derive(Debug, Deserialize)]
+pub struct FocalPoint {
+	pub guid: String,
+	pub x: f32,
+	pub y: f32,
 }
 
 
@@ -49,11 +98,30 @@ pub fn generate_upload_file_name(uploaded_name: &str) -> Result<String, String>
 }
 
 /// Once an upload finishes, we will take a list of all uploaded files and store references in the database
-pub fn finish_file_upload(local_files: &Vec<String>, db: &mysql::Pool) -> Vec<UploadedImage> {
+pub fn finish_file_upload(local_files: &Vec<String>, db: &Arc<dyn Storage>) -> Vec<UploadedImage> {
 	let mut result = vec![];
 	for path in local_files {
 		match uploaded_file_get_info(path) {
 			Ok(image_info) => {
+				// The same file was uploaded before, hand out the existing image instead
+				match find_image_by_hash(db, &image_info.hash) {
+					Some(existing) => {
+						// Clips take the poster frame made for them along
+						let mut duplicates = vec![path.clone()];
+						if image_info.media_type == "video" { duplicates.push(video_poster_path(image_info.guid())); }
+
+						for duplicate in duplicates {
+							match fs::remove_file(&duplicate) {
+								Ok(_) => {}
+								Err(err) => { println!("Error removing duplicate upload: {:?}", err); }
+							}
+						}
+						result.push(existing);
+						continue;
+					}
+					_ => {}
+				}
+
 				// Store this info in the database
 				add_image_to_gallery(&image_info, db);
 
@@ -80,56 +148,137 @@ fn uploaded_file_get_info(local_path: &str) -> Result<UploadedImage, String> {
 		_ => return Err(String::from("Cannot get image file stem")),
 	};
 
+	// Clips have to pass some checks before we decode anything
+	let is_video = VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str());
+	if is_video {
+		validate_video(local_path, extension)?;
+	}
+
+	// Read the EXIF data before it is stripped, the served file must not leak the location
+	let metadata = if is_video { ImageMetadata::default() } else { read_image_metadata(local_path) };
+	if extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg") {
+		match strip_jpeg_metadata(local_path) {
+			Ok(()) => {}
+			Err(err) => { println!("Could not strip metadata from {}: {}", local_path, err); }
+		}
+	}
+
+	// The stored hash has to match the file as it is kept
+	match optimize_original(local_path, extension) {
+		Ok(saved) if saved > 0 => { println!("Optimized {}, saved {} bytes", local_path, saved); }
+		Err(err) => { println!("Could not optimize {}: {}", local_path, err); }
+		_ => {}
+	}
+
 	// Hash the source file
-	let mut file = fs::File::open(local_path).map_err(|_| String::from("Image not found when trying to hash"))?;
-	let mut hasher = Md5::new();
-	let n = io::copy(&mut file, &mut hasher).map_err(|_| String::from("Image hashing error"))?;
-	let hash = hasher.finalize();
+	let hash = hash_file(local_path)?;
+
+	// Videos are measured by their poster frame
+	let image_path = if is_video { create_video_poster(local_path, stem)? } else { String::from(local_path) };
 
 	// Open the image
-	match image::open(local_path) {
+	match image::open(&image_path) {
 		Ok(img) => {
 			let (x, y) = img.dimensions();
 			Ok(UploadedImage {
 				guid: String::from(stem),
 				ext: String::from(extension),
-				src: format!("/gallery/{}/w200/thumb.{}", stem, extension),
-				hash: format!("{:x}", hash),
+				media_type: String::from(if is_video { "video" } else { "image" }),
+				src: format!("/gallery/{}/w200/thumb.{}", stem, if is_video { "jpg" } else { extension }),
+				hash,
 				x,
 				y,
+				focal_x: 50.0,
+				focal_y: 50.0,
+				metadata,
 			})
 		}
 		_ => { Err(String::from("Cannot open image")) }
 	}
 }
 
+/// Make sure an uploaded clip is small enough and really is the container its extension claims
+fn validate_video(local_path: &str, extension: &str) -> Result<(), String> {
+	let max_size = match config_get_i64("video_max_size") {
+		size if size > 0 => size as u64,
+		_ => VIDEO_MAX_SIZE
+	};
+
+	let size = fs::metadata(local_path).map_err(|_| String::from("Video not found"))?.len();
+	if size > max_size {
+		return Err(format!("Videos may not be larger than {} bytes", max_size));
+	}
+
+	let mut head = [0u8; 12];
+	let mut file = File::open(local_path).map_err(|_| String::from("Video not found"))?;
+	io::Read::read_exact(&mut file, &mut head).map_err(|_| String::from("Video is too short"))?;
+
+	// MP4 starts with an ftyp box, WebM with the EBML header
+	let valid = match extension.to_lowercase().as_str() {
+		"mp4" => { &head[4..8] == b"ftyp" }
+		"webm" => { head[0..4] == [0x1A, 0x45, 0xDF, 0xA3] }
+		_ => { false }
+	};
+
+	if valid { Ok(()) } else { Err(String::from("Video content does not match its type")) }
+}
+
+/// Extract the first frame of a clip with ffmpeg and store it as the poster image
+///
+/// The poster shares the guid of the video, so resized variants work like for any other jpg
+fn create_video_poster(local_path: &str, stem: &str) -> Result<String, String> {
+	let poster_path = video_poster_path(stem);
+
+	let ffmpeg = match config_get_string("ffmpeg_path") {
+		path if path.len() > 0 => path,
+		_ => String::from("ffmpeg")
+	};
+
+	let status = Command::new(ffmpeg)
+		.args(["-y", "-loglevel", "error", "-i", local_path, "-frames:v", "1", "-q:v", "2", &poster_path])
+		.status()
+		.map_err(|err| format!("Could not run ffmpeg: {}", err))?;
+
+	if !status.success() || !Path::new(&poster_path).exists() {
+		return Err(String::from("Could not extract a poster frame"));
+	}
+
+	Ok(poster_path)
+}
+
+/// Where the poster frame of a clip is kept, next to the clip in the originals
+fn video_poster_path(stem: &str) -> String {
+	format!("{}/original/{}.jpg", GALLERY_PATH, stem)
+}
+
 /// Add a new image to the gallery database
-fn add_image_to_gallery(image_info: &UploadedImage, db: &mysql::Pool) {
+fn add_image_to_gallery(image_info: &UploadedImage, db: &Arc<dyn Storage>) {
 	// INSERT INTO gallery (guid, extension, sizeX, sizeY) VALUES ()
-	let query = "INSERT IGNORE INTO gallery (guid, hash, extension, sizeX, sizeY) VALUES (:guid, :hash, :extension, :x, :y)";
+	let query = format!(r##"{} INTO gallery (guid, hash, extension, media_type, sizeX, sizeY, captured_at, camera, lat, lng)
+        VALUES (:guid, :hash, :extension, :media_type, :x, :y, :captured_at, :camera, :lat, :lng)"##, db.insert_ignore());
+
+	let captured_at = image_info.metadata.captured_at.map(|tmp| NaiveDateTime::from_timestamp(tmp as i64, 0));
 
 	// Execute
-	match db.prep_exec(query, params! {"guid" => &image_info.guid, "hash" => &image_info.hash, "extension" => &image_info.ext, "x" => image_info.x, "y" => image_info.y}) {
+	match db.exec(&query, db_params! {
+		"guid" => &image_info.guid, "hash" => &image_info.hash, "extension" => &image_info.ext, "media_type" => &image_info.media_type, "x" => image_info.x, "y" => image_info.y,
+		"captured_at" => captured_at, "camera" => &image_info.metadata.camera, "lat" => image_info.metadata.lat, "lng" => image_info.metadata.lng
+	}) {
 		Ok(_) => {}
 		Err(err) => { println!("Error adding image to gallery: {:?}", err); }
 	}
 }
 
 /// Load all the gallery images from the database
-pub fn load_gallery_from_sql(db: &mysql::Pool) -> Vec<UploadedImage> {
-	let query_result = match db.prep_exec("SELECT guid, extension, sizeX, sizeY FROM gallery ORDER BY uploadedAt DESC", ()) {
+pub fn load_gallery_from_sql(db: &Arc<dyn Storage>) -> Vec<UploadedImage> {
+	let rows = match db.query("SELECT guid, extension, media_type, sizeX, sizeY, focal_x, focal_y, captured_at, camera, lat, lng FROM gallery ORDER BY uploadedAt DESC", db_params!()) {
 		Ok(tmp) => { tmp }
 		_ => { return vec![]; }
 	};
 
 	let mut images = Vec::new();
 
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => tmp,
-			_ => continue
-		};
-
+	for row in rows {
 		match from_sql(row) {
 			Some(tmp) => images.push(tmp),
 			_ => {}
@@ -139,21 +288,470 @@ pub fn load_gallery_from_sql(db: &mysql::Pool) -> Vec<UploadedImage> {
 	images
 }
 
+/// Find an image that was already uploaded with the given hash
+fn find_image_by_hash(db: &Arc<dyn Storage>, hash: &str) -> Option<UploadedImage> {
+	let query = r##"SELECT guid, hash, extension, media_type, sizeX, sizeY, focal_x, focal_y, captured_at, camera, lat, lng
+        FROM gallery WHERE hash=:hash ORDER BY uploadedAt ASC LIMIT 1"##;
+
+	let row = match db.query_first(query, db_params! {"hash" => hash}) {
+		Ok(Some(tmp)) => { tmp }
+		_ => { return None; }
+	};
+
+	from_sql(row).map(|mut image| {
+		let thumb_ext = if image.media_type == "video" { String::from("jpg") } else { image.ext.clone() };
+		image.src = format!("/gallery/{}/w200/thumb.{}", image.guid, thumb_ext);
+		image
+	})
+}
+
+/// List all groups of images that share the same hash, oldest upload first
+pub fn find_duplicate_images(db: &Arc<dyn Storage>) -> Vec<DuplicateImages> {
+	let query = r##"SELECT g.hash, g.guid FROM gallery g
+        JOIN (SELECT hash FROM gallery WHERE hash <> '' GROUP BY hash HAVING COUNT(*) > 1) d ON d.hash = g.hash
+        ORDER BY g.hash, g.uploadedAt ASC"##;
+
+	let rows = match db.query(query, db_params!()) {
+		Ok(tmp) => { tmp }
+		_ => { return vec![]; }
+	};
+
+	let mut duplicates: Vec<DuplicateImages> = Vec::new();
+
+	for mut row in rows {
+		let (hash, guid): (String, String) = match (row.take("hash"), row.take("guid")) {
+			(Some(hash), Some(guid)) => (hash, guid),
+			_ => continue
+		};
+
+		// Rows are ordered by hash, so a group continues as long as the hash matches
+		match duplicates.last_mut() {
+			Some(group) if group.hash == hash => { group.guids.push(guid); }
+			_ => { duplicates.push(DuplicateImages { hash, guids: vec![guid] }); }
+		}
+	}
+
+	duplicates
+}
+
+/// A post showing a gallery image, in its content or its media
+#[derive(Debug, Serialize)]
+pub struct ImageUsage {
+	post_id: u32,
+	title: String,
+	state: String,
+	in_content: bool,
+	/// The classes of the media entries using the image, e.g. `featured`
+	in_media: Vec<String>,
+}
+
+/// Find every post, drafts included, that references the given image
+pub fn find_image_usage(db: &Arc<dyn Storage>, guid: &str) -> Result<Vec<ImageUsage>, String> {
+	// Guids are base62, anything else can not be in the gallery
+	if guid.len() == 0 || !guid.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return Err(String::from("invalid guid"));
+	}
+
+	// Resized variants, `/gallery/guid/w400/name.jpg`, and originals, `/gallery/guid.jpg` or `/gallery/original/guid.jpg`
+	let reference = Regex::new(&format!(r"/gallery/(original/)?{}[/.]", guid)).map_err(|err| err.to_string())?;
+
+	let query = r##"SELECT id, title, state, content, media FROM posts
+        WHERE content LIKE :pattern OR media LIKE :pattern
+        ORDER BY id DESC"##;
+	let rows = db.query(query, db_params! {"pattern" => format!("%{}%", guid)}).map_err(|err| err.to_string())?;
+
+	let mut usage = Vec::new();
+	for mut row in rows {
+		let (post_id, title, state, content, media) = match (row.take::<u32>("id"), row.take::<String>("title"), row.take::<String>("state"),
+			row.take::<String>("content"), row.take::<String>("media")) {
+			(Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+			_ => { continue; }
+		};
+
+		let media: Vec<PostMedia> = match serde_json::from_str(&media) {
+			Ok(tmp) => { tmp }
+			_ => { vec![] }
+		};
+		let in_media: Vec<String> = media.into_iter()
+			.filter(|item| reference.is_match(&item.source)
+				|| item.poster.as_ref().is_some_and(|poster| reference.is_match(poster))
+				|| item.source_dark.as_ref().is_some_and(|dark| reference.is_match(dark)))
+			.map(|item| item.class)
+			.collect();
+		let in_content = reference.is_match(&content);
+
+		// The guid may just appear in some text
+		if in_content || in_media.len() > 0 {
+			usage.push(ImageUsage { post_id, title, state, in_content, in_media });
+		}
+	}
+
+	Ok(usage)
+}
+
 /// Turn a SQL row into an image struct
-pub fn from_sql(mut row: mysql::Row) -> Option<UploadedImage> {
+pub fn from_sql(mut row: DbRow) -> Option<UploadedImage> {
 	Some(UploadedImage {
 		guid: row.take("guid")?,
 		ext: row.take("extension")?,
+		media_type: row.take("media_type")?,
 		src: String::from(""),
-		hash: String::from(""),
+		hash: row.take::<String>("hash").unwrap_or_default(),
 		x: row.take("sizeX")?,
 		y: row.take("sizeY")?,
+		focal_x: row.take("focal_x")?,
+		focal_y: row.take("focal_y")?,
+		metadata: ImageMetadata {
+			captured_at: row.take::<Option<NaiveDateTime>>("captured_at")?.map(|tmp| tmp.timestamp() as u64),
+			camera: row.take("camera")?,
+			lat: row.take("lat")?,
+			lng: row.take("lng")?,
+		},
 	})
 }
 
+/// Load the EXIF data stored for a single image
+pub fn load_image_metadata(db: &Arc<dyn Storage>, guid: &str) -> Option<ImageMetadata> {
+	let mut row = match db.query_first("SELECT captured_at, camera, lat, lng FROM gallery WHERE guid=:guid", db_params! {"guid" => guid}) {
+		Ok(Some(tmp)) => { tmp }
+		_ => { return None; }
+	};
+
+	Some(ImageMetadata {
+		captured_at: row.take::<Option<NaiveDateTime>>("captured_at")?.map(|tmp| tmp.timestamp() as u64),
+		camera: row.take("camera")?,
+		lat: row.take("lat")?,
+		lng: row.take("lng")?,
+	})
+}
+
+/// Store the focal point of an image, cropped variants are removed so they get created again
+pub fn set_focal_point(db: &Arc<dyn Storage>, focal: &FocalPoint) -> Result<(), String> {
+	// Percentages only
+	if focal.x < 0.0 || focal.x > 100.0 || focal.y < 0.0 || focal.y > 100.0 {
+		return Err(String::from("The focal point must be within 0 and 100 percent"));
+	}
+
+	match db.exec("UPDATE gallery SET focal_x=:x, focal_y=:y WHERE guid=:guid", db_params! {"x" => focal.x, "y" => focal.y, "guid" => &focal.guid}) {
+		Ok(res) => {
+			if res.affected_rows == 0 && load_focal_point(db, &focal.guid).is_none() {
+				return Err(String::from("Image not found"));
+			}
+		}
+		Err(err) => {
+			println!("Error setting focal point: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	purge_cropped_variants(db, &focal.guid);
+
+	Ok(())
+}
+
+/// Load the focal point of an image as fractions of its width and height
+fn load_focal_point(db: &Arc<dyn Storage>, guid: &str) -> Option<(f32, f32)> {
+	let mut row = match db.query_first("SELECT focal_x, focal_y FROM gallery WHERE guid=:guid", db_params! {"guid" => guid}) {
+		Ok(Some(tmp)) => { tmp }
+		_ => { return None; }
+	};
+
+	Some((row.take::<f32>("focal_x")? / 100.0, row.take::<f32>("focal_y")? / 100.0))
+}
+
+/// Remove every cropped variant of an image
+fn purge_cropped_variants(db: &Arc<dyn Storage>, guid: &str) {
+	match db.exec("DELETE FROM gallery_variants WHERE guid=:guid AND size LIKE 'c%'", db_params! {"guid" => guid}) {
+		Ok(_) => {}
+		Err(err) => { println!("Error removing cropped variant hashes: {:?}", err); }
+	}
+
+	let dirs = match fs::read_dir(GALLERY_PATH) {
+		Ok(tmp) => tmp,
+		_ => return
+	};
+
+	for dir in dirs.filter_map(|entry| entry.ok()) {
+		// Crop folders are named like c400x300
+		if !dir.file_name().to_string_lossy().starts_with('c') { continue; }
+
+		let files = match fs::read_dir(dir.path()) {
+			Ok(tmp) => tmp,
+			_ => continue
+		};
+
+		for file in files.filter_map(|entry| entry.ok()) {
+			if get_stem_from_filename(&file.file_name().to_string_lossy()) == Some(guid) {
+				match fs::remove_file(file.path()) {
+					Ok(_) => {}
+					Err(err) => { println!("Error removing cropped image: {:?}", err); }
+				}
+			}
+		}
+	}
+}
+
+/// Hash a file on disk with SHA-256, the file is streamed so large images are fine
+fn hash_file(path: &str) -> Result<String, String> {
+	let mut file = File::open(path).map_err(|_| String::from("Image not found when trying to hash"))?;
+	let mut hasher = Sha256::new();
+	io::copy(&mut file, &mut hasher).map_err(|_| String::from("Image hashing error"))?;
+
+	Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Remember the hash of a resized or cropped variant, so it can be verified later
+fn store_variant_hash(db: &Arc<dyn Storage>, guid: &str, size: &str, path: &str) {
+	let hash = match hash_file(path) {
+		Ok(tmp) => tmp,
+		Err(err) => {
+			println!("Error hashing image variant {}: {}", path, err);
+			return;
+		}
+	};
+
+	match db.exec("REPLACE INTO gallery_variants (guid, size, hash) VALUES (:guid, :size, :hash)", db_params! {"guid" => guid, "size" => size, "hash" => hash}) {
+		Ok(_) => {}
+		Err(err) => { println!("Error storing image variant hash: {:?}", err); }
+	}
+}
+
+/// Compare the gallery files on disk with the hashes stored in the database
+///
+/// Nothing is repaired, except that hashes still in the old MD5 format get replaced
+pub fn check_gallery_integrity(db: &Arc<dyn Storage>) -> Vec<DataIssue> {
+	let mut issues = Vec::new();
+	let mut known_originals = HashSet::new();
+
+	// Originals
+	let originals: Vec<(String, String, String, String)> = match db.query("SELECT guid, extension, media_type, hash FROM gallery ORDER BY guid", db_params!()) {
+		Ok(rows) => {
+			rows.into_iter()
+				.filter_map(|mut row| Some((row.take("guid")?, row.take("extension")?, row.take("media_type")?, row.take::<Option<String>>("hash")?.unwrap_or_default())))
+				.collect()
+		}
+		Err(err) => {
+			println!("Error loading gallery for integrity check: {:?}", err);
+			return issues;
+		}
+	};
+
+	for (guid, extension, media_type, stored_hash) in originals {
+		let path = format!("{}/original/{}.{}", GALLERY_PATH, guid, extension);
+		known_originals.insert(format!("{}.{}", guid, extension));
+
+		// Videos come with a poster frame
+		if media_type == "video" {
+			known_originals.insert(format!("{}.jpg", guid));
+			if !Path::new(&format!("{}/original/{}.jpg", GALLERY_PATH, guid)).exists() {
+				issues.push(DataIssue::new("gallery_missing_file", 0, format!("poster frame of video '{}' is missing", guid)));
+			}
+		}
+
+		let hash = match hash_file(&path) {
+			Ok(tmp) => tmp,
+			_ => {
+				issues.push(DataIssue::new("gallery_missing_file", 0, format!("original of image '{}' is missing at {}", guid, path)));
+				continue;
+			}
+		};
+
+		// Old rows still carry an MD5 hash, upgrade them instead of reporting
+		if stored_hash.len() != hash.len() {
+			match db.exec("UPDATE gallery SET hash=:hash WHERE guid=:guid", db_params! {"hash" => &hash, "guid" => &guid}) {
+				Ok(_) => {}
+				Err(err) => { println!("Error upgrading gallery hash: {:?}", err); }
+			}
+		} else if stored_hash != hash {
+			issues.push(DataIssue::new("gallery_corrupted_file", 0, format!("original of image '{}' does not match its hash", guid)));
+		}
+	}
+
+	// Files without a database row
+	match fs::read_dir(format!("{}/original", GALLERY_PATH)) {
+		Ok(files) => {
+			for file in files.filter_map(|entry| entry.ok()) {
+				let name = file.file_name().to_string_lossy().to_string();
+				if !known_originals.contains(&name) {
+					issues.push(DataIssue::new("gallery_orphaned_file", 0, format!("{}/original/{} has no gallery entry", GALLERY_PATH, name)));
+				}
+			}
+		}
+		_ => {}
+	}
+
+	// Resized and cropped variants
+	let query = r##"SELECT v.guid, v.size, v.hash, g.extension FROM gallery_variants v
+        JOIN gallery g ON g.guid = v.guid ORDER BY v.guid, v.size"##;
+
+	let variants: Vec<(String, String, String, String)> = match db.query(query, db_params!()) {
+		Ok(rows) => {
+			rows.into_iter()
+				.filter_map(|mut row| Some((row.take("guid")?, row.take("size")?, row.take("hash")?, row.take("extension")?)))
+				.collect()
+		}
+		Err(err) => {
+			println!("Error loading gallery variants for integrity check: {:?}", err);
+			vec![]
+		}
+	};
+
+	for (guid, size, stored_hash, extension) in variants {
+		let path = format!("{}/{}/{}.{}", GALLERY_PATH, size, guid, extension);
+
+		match hash_file(&path) {
+			Ok(hash) => {
+				if hash != stored_hash {
+					issues.push(DataIssue::new("gallery_corrupted_file", 0, format!("variant {} of image '{}' does not match its hash", size, guid)));
+				}
+			}
+			_ => {
+				issues.push(DataIssue::new("gallery_missing_file", 0, format!("variant {} of image '{}' is missing at {}", size, guid, path)));
+			}
+		}
+	}
+
+	issues
+}
+
+/// Gallery files and rows nothing needs anymore, see `find_gallery_orphans`
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct GalleryOrphans {
+	/// Originals on disk without a gallery row
+	pub files_without_row: Vec<String>,
+	/// Gallery rows whose original is missing
+	pub rows_without_file: Vec<GalleryMissingOriginal>,
+	/// Resized and cropped files of originals that were deleted
+	pub variants_without_original: Vec<String>,
+	/// Guids of images no post references, they are only reported
+	pub unused_images: Vec<String>,
+	pub checked_at: u64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct GalleryMissingOriginal {
+	pub guid: String,
+	/// A post still shows the image, the row is kept so the post can be fixed
+	pub referenced: bool,
+}
+
+/// What purging the orphans removed
+#[derive(Debug, Serialize)]
+pub struct GalleryPurgeResult {
+	pub files_removed: u32,
+	pub rows_removed: u64,
+}
+
+/// Compare the files on disk with the gallery rows and what the posts reference
+pub fn find_gallery_orphans(db: &Arc<dyn Storage>) -> Result<GalleryOrphans, String> {
+	let rows = db.query("SELECT guid, extension, media_type FROM gallery ORDER BY guid", db_params!()).map_err(|err| err.to_string())?;
+	let images: Vec<(String, String, String)> = rows.into_iter()
+		.filter_map(|mut row| Some((row.take("guid")?, row.take("extension")?, row.take("media_type")?)))
+		.collect();
+	let guids: HashSet<&str> = images.iter().map(|(guid, _, _)| guid.as_str()).collect();
+	let referenced = find_referenced_guids(db)?;
+
+	let mut orphans = GalleryOrphans::default();
+
+	// Rows and their originals, videos come with a poster frame
+	let mut known_originals = HashSet::new();
+	for (guid, extension, media_type) in images.iter() {
+		known_originals.insert(format!("{}.{}", guid, extension));
+		if media_type == "video" { known_originals.insert(format!("{}.jpg", guid)); }
+
+		if !Path::new(&format!("{}/original/{}.{}", GALLERY_PATH, guid, extension)).exists() {
+			orphans.rows_without_file.push(GalleryMissingOriginal { guid: guid.clone(), referenced: referenced.contains(guid) });
+		} else if !referenced.contains(guid) {
+			orphans.unused_images.push(guid.clone());
+		}
+	}
+
+	// Every folder next to the originals holds one size, e.g. w400 or c400x300
+	let size = Regex::new(r"^([hw][0-9]+|c[0-9]+x[0-9]+)$").map_err(|err| err.to_string())?;
+	let dirs = fs::read_dir(GALLERY_PATH).map_err(|err| format!("Could not read {}: {}", GALLERY_PATH, err))?;
+	for dir in dirs.filter_map(|entry| entry.ok()) {
+		let dir_name = dir.file_name().to_string_lossy().to_string();
+		let is_original = dir_name == "original";
+		if !is_original && !size.is_match(&dir_name) { continue; }
+
+		let files = match fs::read_dir(dir.path()) {
+			Ok(tmp) => tmp,
+			_ => continue
+		};
+		for file in files.filter_map(|entry| entry.ok()) {
+			let name = file.file_name().to_string_lossy().to_string();
+			let path = format!("{}/{}/{}", GALLERY_PATH, dir_name, name);
+
+			if is_original {
+				if !known_originals.contains(&name) { orphans.files_without_row.push(path); }
+			} else {
+				match get_stem_from_filename(&name) {
+					Some(guid) if guids.contains(guid) => {}
+					_ => { orphans.variants_without_original.push(path); }
+				}
+			}
+		}
+	}
+
+	orphans.files_without_row.sort();
+	orphans.variants_without_original.sort();
+	orphans.checked_at = Utc::now().timestamp() as u64;
+	Ok(orphans)
+}
+
+/// Delete what `find_gallery_orphans` finds right now
+///
+/// Rows of missing originals are kept while a post references them, unused images are kept as well
+pub fn purge_gallery_orphans(db: &Arc<dyn Storage>) -> Result<GalleryPurgeResult, String> {
+	let orphans = find_gallery_orphans(db)?;
+	let mut result = GalleryPurgeResult { files_removed: 0, rows_removed: 0 };
+
+	for path in orphans.files_without_row.iter().chain(orphans.variants_without_original.iter()) {
+		match fs::remove_file(path) {
+			Ok(_) => { result.files_removed += 1; }
+			Err(err) => { println!("Error removing orphaned gallery file {}: {:?}", path, err); }
+		}
+	}
+
+	for missing in orphans.rows_without_file.iter().filter(|tmp| !tmp.referenced) {
+		match db.exec("DELETE FROM gallery WHERE guid=:guid", db_params! {"guid" => &missing.guid}) {
+			Ok(res) => { result.rows_removed += res.affected_rows; }
+			Err(err) => { println!("Error removing gallery row {}: {:?}", missing.guid, err); }
+		}
+	}
+
+	// Hashes of variants whose image is gone
+	match db.exec("DELETE FROM gallery_variants WHERE guid NOT IN (SELECT guid FROM gallery)", db_params!()) {
+		Ok(res) => { result.rows_removed += res.affected_rows; }
+		Err(err) => { println!("Error removing orphaned variant hashes: {:?}", err); }
+	}
+
+	Ok(result)
+}
+
+/// The guids of all images the posts use, drafts included
+fn find_referenced_guids(db: &Arc<dyn Storage>) -> Result<HashSet<String>, String> {
+	// Same forms as in `find_image_usage`
+	let reference = Regex::new(r"/gallery/(?:original/)?(?P<guid>[A-Za-z0-9]+)[/.]").map_err(|err| err.to_string())?;
+
+	let rows = db.query("SELECT content, media FROM posts", db_params!()).map_err(|err| err.to_string())?;
+
+	let mut guids = HashSet::new();
+	for mut row in rows {
+		for text in vec![row.take::<String>("content"), row.take::<String>("media")].into_iter().flatten() {
+			for caps in reference.captures_iter(&text) {
+				guids.insert(String::from(&caps["guid"]));
+			}
+		}
+	}
+
+	Ok(guids)
+}
+
 /// Find the file system path for the given original
 pub fn gallery_find_original(path: &str) -> String {
-	// Validate input
+	// Validate input, nothing outside of the gallery is served, e.g. the quarantined submissions
+	if path.contains("..") { return String::from(DEFAULT_PICTURE_PATH); }
 	match Regex::new(r"[A-z0-9.]+") {
 		Ok(regex) => {
 			if !regex.is_match(path) { return String::from(DEFAULT_PICTURE_PATH); }
@@ -177,8 +775,83 @@ pub fn gallery_find_original(path: &str) -> String {
 	String::from(DEFAULT_PICTURE_PATH)
 }
 
+/// Check whether a media source pointing into our own gallery has a file on disk
+///
+/// Sources hosted elsewhere are assumed to exist
+pub fn gallery_source_exists(source: &str) -> bool {
+	match gallery_source_to_local_path(source) {
+		Some(path) => { path != DEFAULT_PICTURE_PATH }
+		_ => { true }
+	}
+}
+
+/// Map a media source to the local file backing it
+///
+/// Resized variants map to their original as they are created on demand.
+/// Returns None for sources that are not hosted in our gallery
+pub fn gallery_source_to_local_path(source: &str) -> Option<String> {
+	// Strip our own host, anything else with a host is external
+	let own_host = format!("https://{}", config_get_string("fqdn"));
+	let path = if source.starts_with(&own_host) { &source[own_host.len()..] } else { source };
+	if !path.starts_with("/gallery/") { return None; }
+
+	let tail = &path["/gallery/".len()..];
+	let parts: Vec<&str> = tail.splitn(3, '/').collect();
+
+	if parts.len() == 3 {
+		let extension = match get_extension_from_filename(parts[2]) {
+			Some(tmp) => tmp,
+			_ => return Some(String::from(DEFAULT_PICTURE_PATH)),
+		};
+		let path_original = format!("{}/original/{}.{}", GALLERY_PATH, parts[0], extension);
+		if Path::new(&path_original).exists() { return Some(path_original); }
+		return Some(String::from(DEFAULT_PICTURE_PATH));
+	}
+
+	Some(gallery_find_original(tail))
+}
+
+/// Point a gallery source at the given size, e.g. `w400` or `c400x300`
+///
+/// Works for resized variants and originals, so a media entry and its dark variant can be
+/// requested in the same sizes. Anything else is returned as is
+pub fn gallery_source_with_size(source: &str, size: &str) -> String {
+	let resized = Regex::new(r"^(?P<prefix>.*/gallery/)(?P<guid>[A-Za-z0-9]+)/(?:[hw][0-9]+|c[0-9]+x[0-9]+)/(?P<name>[^/]+)$");
+	let original = Regex::new(r"^(?P<prefix>.*/gallery/)(?:original/)?(?P<guid>[A-Za-z0-9]+)\.(?P<ext>jpg|jpeg|gif|png)$");
+	let valid_size = Regex::new(r"^([hw][0-9]+|c[0-9]+x[0-9]+)$");
+
+	match (resized, original, valid_size) {
+		(Ok(resized), Ok(original), Ok(valid_size)) if valid_size.is_match(size) => {
+			if let Some(caps) = resized.captures(source) {
+				return format!("{}{}/{}/{}", &caps["prefix"], &caps["guid"], size, &caps["name"]);
+			}
+			if let Some(caps) = original.captures(source) {
+				return format!("{}{}/{}/image.{}", &caps["prefix"], &caps["guid"], size, &caps["ext"]);
+			}
+			String::from(source)
+		}
+		_ => { String::from(source) }
+	}
+}
+
+/// Whether a crop size like `c400x300` may be made, only those in `gallery_crop_sizes` are
+///
+/// Scaled sizes like `w400` are always allowed
+pub fn gallery_crop_size_allowed(size: &str) -> bool {
+	if !size.starts_with('c') { return true; }
+
+	let configured = config_get_string_vec("gallery_crop_sizes");
+	match configured.len() {
+		0 => DEFAULT_CROP_SIZES.contains(&&size[1..]),
+		_ => configured.iter().any(|tmp| tmp.trim_start_matches('c') == &size[1..])
+	}
+}
+
 /// Return the file system path for the requested resource
-pub fn gallery_find_file(guid: &str, size: &str, tail: &str) -> String {
+///
+/// Sizes are either `w400`/`h400` to scale by one side or `c400x300` to crop around the focal point,
+/// see `gallery_crop_size_allowed`
+pub fn gallery_find_file(db: &Arc<dyn Storage>, guid: &str, size: &str, tail: &str) -> String {
 	// Find the extension of the requested file
 	let mut extension = String::from("");
 	match Regex::new(r".(?P<ext>jpg|jpeg|gif|png)$") {
@@ -191,9 +864,9 @@ pub fn gallery_find_file(guid: &str, size: &str, tail: &str) -> String {
 	}
 
 	// Validate size input
-	match Regex::new(r"[hw][0-9]+") {
+	match Regex::new(r"^([hw][0-9]+|c[0-9]+x[0-9]+)$") {
 		Ok(regex) => {
-			if !regex.is_match(size) { return String::from(DEFAULT_PICTURE_PATH); }
+			if !regex.is_match(size) || !gallery_crop_size_allowed(size) { return String::from(DEFAULT_PICTURE_PATH); }
 		}
 		_ => { return String::from(DEFAULT_PICTURE_PATH); }
 	}
@@ -221,9 +894,16 @@ pub fn gallery_find_file(guid: &str, size: &str, tail: &str) -> String {
 
 	// Can we find the original file?
 	if Path::new(&path_original).exists() {
-		// Try to resize it as required
-		if gallery_resize_image(&path_original, &path_resized, size, &extension) {
+		// Try to resize or crop it as required
+		let created = if size.starts_with('c') {
+			let focal = load_focal_point(db, guid).unwrap_or((0.5, 0.5));
+			gallery_crop_image(&path_original, &path_resized, size, &extension, focal)
+		} else {
+			gallery_resize_image(&path_original, &path_resized, size, &extension)
+		};
 
+		if created {
+			store_variant_hash(db, guid, size, &path_resized);
 			return path_resized;
 		} else {
 			return path_original;
@@ -274,31 +954,70 @@ pub fn gallery_resize_image(path_original: &str, path_resized: &str, size: &str,
 			// Resize it
 			let scaled = img.resize_exact(new_width, new_height, image::imageops::FilterType::Lanczos3);
 
-			// What is the format ?
-			let format = match extension {
-				"bmp" => { image::ImageFormat::Bmp }
-				"gif" => { image::ImageFormat::Gif }
-				"png" => { image::ImageFormat::Png }
-				_ => { image::ImageFormat::Jpeg }
-			};
+			gallery_write_image(&scaled, path_resized, size, extension)
+		}
+		_ => { return false; }
+	}
+}
 
-			// Make sure all the folders exist
-			match fs::create_dir_all(format!("{}/{}", GALLERY_PATH, size)) {
-				Ok(_tmp) => {}
-				_ => {}
-			}
+/// Crop the given image to the exact size, keeping the focal point as close to the center as possible
+///
+/// The focal point is given as fractions of the width and height
+pub fn gallery_crop_image(path_original: &str, path_cropped: &str, size: &str, extension: &str, focal: (f32, f32)) -> bool {
+	// Parse the requested size, e.g. c400x300
+	let dimensions: Vec<u32> = size[1..].split('x').filter_map(|tmp| tmp.parse::<u32>().ok()).collect();
+	if dimensions.len() != 2 { return false; }
+	let (width, height) = (dimensions[0], dimensions[1]);
 
-			// Store it in the given path
-			match File::create(path_resized) {
-				Ok(mut output) => {
-					match scaled.write_to(&mut output, format) {
-						Ok(_tmp) => { return true; }
-						_ => { return false; }
-					}
+	// Some size wise constraints
+	if width <= 25 || width > 2000 || height <= 25 || height > 2000 { return false; }
+
+	// Load the original
+	let img = match image::open(path_original) {
+		Ok(tmp) => tmp,
+		_ => return false
+	};
+
+	// The scale that lets the target fill the image on both sides, we do not upscale
+	let scale = f64::max(width as f64 / img.width() as f64, height as f64 / img.height() as f64);
+	if scale > 1.0 { return false; }
+
+	// The window in the original we are going to keep
+	let window_width = ((width as f64 / scale).round() as u32).min(img.width());
+	let window_height = ((height as f64 / scale).round() as u32).min(img.height());
+
+	// Center the window on the focal point, but keep it inside the image
+	let center_x = (focal.0 as f64 * img.width() as f64).round() as i64;
+	let center_y = (focal.1 as f64 * img.height() as f64).round() as i64;
+	let left = (center_x - window_width as i64 / 2).max(0).min((img.width() - window_width) as i64) as u32;
+	let top = (center_y - window_height as i64 / 2).max(0).min((img.height() - window_height) as i64) as u32;
+
+	// Crop and scale down to the requested size
+	let cropped = img.crop_imm(left, top, window_width, window_height)
+		.resize_exact(width, height, image::imageops::FilterType::Lanczos3);
+
+	gallery_write_image(&cropped, path_cropped, size, extension)
+}
+
+/// Write a resized or cropped image into the folder of its size, optimized if `image_optimize` is set
+fn gallery_write_image(img: &image::DynamicImage, path: &str, size: &str, extension: &str) -> bool {
+	// Make sure all the folders exist
+	match fs::create_dir_all(format!("{}/{}", GALLERY_PATH, size)) {
+		Ok(_tmp) => {}
+		_ => {}
+	}
+
+	// Store it in the given path
+	match File::create(path) {
+		Ok(mut output) => {
+			match encode_image(img, extension, &mut output) {
+				Ok(()) => { true }
+				Err(err) => {
+					println!("Error writing image {}: {}", path, err);
+					false
 				}
-				_ => { return false; }
 			}
 		}
-		_ => { return false; }
+		_ => { false }
 	}
 }
\ No newline at end of file
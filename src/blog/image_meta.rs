@@ -0,0 +1,207 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use chrono::NaiveDate;
+use exif::{In, Reader, Tag, Value};
+
+use crate::blog::types::post::PostLocation;
+
+/// Data taken from the EXIF block of an uploaded image
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct ImageMetadata {
+	pub captured_at: Option<u64>,
+	pub camera: Option<String>,
+	pub lat: Option<f64>,
+	pub lng: Option<f64>,
+}
+
+impl ImageMetadata {
+	/// A location the post editor can use to prefill `PostLocation`
+	pub fn suggested_location(&self) -> Option<PostLocation> {
+		Some(PostLocation {
+			title: String::from(""),
+			desc: String::from(""),
+			lat: self.lat? as f32,
+			lng: self.lng? as f32,
+			typ: String::from("photo"),
+		})
+	}
+}
+
+
+/// Read capture date, camera model and GPS position from the image at the given path
+///
+/// Images without EXIF data give an empty result
+pub fn read_image_metadata(path: &str) -> ImageMetadata {
+	let mut meta = ImageMetadata::default();
+
+	let file = match File::open(path) {
+		Ok(tmp) => tmp,
+		_ => return meta
+	};
+
+	let exif = match Reader::new().read_from_container(&mut BufReader::new(file)) {
+		Ok(tmp) => tmp,
+		_ => return meta
+	};
+
+	// Capture date, the camera does not know about time zones so this is local time
+	match exif.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
+		Some(field) => {
+			match &field.value {
+				Value::Ascii(vec) if !vec.is_empty() => {
+					match exif::DateTime::from_ascii(&vec[0]) {
+						Ok(dt) => {
+							meta.captured_at = NaiveDate::from_ymd_opt(dt.year as i32, dt.month as u32, dt.day as u32)
+								.and_then(|date| date.and_hms_opt(dt.hour as u32, dt.minute as u32, dt.second as u32))
+								.map(|date_time| date_time.timestamp() as u64);
+						}
+						_ => {}
+					}
+				}
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	// Camera model
+	match exif.get_field(Tag::Model, In::PRIMARY) {
+		Some(field) => {
+			match &field.value {
+				Value::Ascii(vec) if !vec.is_empty() => {
+					let model = String::from_utf8_lossy(&vec[0]).trim().to_string();
+					if model.len() > 0 { meta.camera = Some(model); }
+				}
+				_ => {}
+			}
+		}
+		_ => {}
+	}
+
+	// GPS position
+	meta.lat = read_gps_coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S");
+	meta.lng = read_gps_coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W");
+
+	meta
+}
+
+/// Convert a degrees, minutes, seconds coordinate into decimal degrees
+fn read_gps_coordinate(exif: &exif::Exif, tag: Tag, tag_ref: Tag, negative_ref: &str) -> Option<f64> {
+	let dms = match &exif.get_field(tag, In::PRIMARY)?.value {
+		Value::Rational(vec) if vec.len() >= 3 => { (vec[0].to_f64(), vec[1].to_f64(), vec[2].to_f64()) }
+		_ => { return None; }
+	};
+
+	let mut degrees = dms.0 + dms.1 / 60.0 + dms.2 / 3600.0;
+
+	// South and west are negative
+	match exif.get_field(tag_ref, In::PRIMARY) {
+		Some(field) => {
+			if field.display_value().to_string().trim() == negative_ref { degrees = -degrees; }
+		}
+		_ => {}
+	}
+
+	Some(degrees)
+}
+
+/// Remove metadata segments (EXIF, XMP, IPTC) from a JPEG file in place
+///
+/// This works on the segment level so the image data is not re-encoded. The EXIF orientation is
+/// written back on its own, otherwise photos taken upright on a phone would show up rotated
+pub fn strip_jpeg_metadata(path: &str) -> Result<(), String> {
+	let data = fs::read(path).map_err(|err| err.to_string())?;
+
+	// Must start with SOI
+	if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+		return Err(String::from("Not a JPEG file"));
+	}
+
+	let mut stripped = Vec::with_capacity(data.len());
+	stripped.extend_from_slice(&data[0..2]);
+
+	let mut pos = 2;
+	while pos + 4 <= data.len() {
+		if data[pos] != 0xFF { return Err(String::from("Broken JPEG segment")); }
+		let marker = data[pos + 1];
+
+		// Start of scan: everything after this is image data
+		if marker == 0xDA || marker == 0xD9 {
+			stripped.extend_from_slice(&data[pos..]);
+			break;
+		}
+
+		let length = ((data[pos + 2] as usize) << 8) | data[pos + 3] as usize;
+		let end = pos + 2 + length;
+		if length < 2 || end > data.len() { return Err(String::from("Broken JPEG segment")); }
+
+		// APP1 holds EXIF and XMP, APP13 holds IPTC - everything else stays
+		if marker != 0xE1 && marker != 0xED {
+			stripped.extend_from_slice(&data[pos..end]);
+		} else if marker == 0xE1 {
+			match read_exif_orientation(&data[pos + 4..end]) {
+				Some(orientation) => { stripped.extend_from_slice(&orientation_segment(orientation)); }
+				_ => {}
+			}
+		}
+
+		pos = end;
+	}
+
+	fs::write(path, stripped).map_err(|err| err.to_string())
+}
+
+/// The orientation tag from the payload of an APP1 segment, None if it is missing or the default
+fn read_exif_orientation(payload: &[u8]) -> Option<u16> {
+	if payload.len() < 14 || &payload[0..6] != b"Exif\0\0" { return None; }
+	let tiff = &payload[6..];
+
+	let big_endian = match &tiff[0..2] {
+		b"MM" => true,
+		b"II" => false,
+		_ => { return None; }
+	};
+	let read_u16 = |at: usize| -> Option<u16> {
+		let bytes = tiff.get(at..at + 2)?;
+		Some(if big_endian { u16::from_be_bytes([bytes[0], bytes[1]]) } else { u16::from_le_bytes([bytes[0], bytes[1]]) })
+	};
+	let read_u32 = |at: usize| -> Option<u32> {
+		let bytes = tiff.get(at..at + 4)?;
+		let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+		Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+	};
+
+	// Walk the entries of IFD0, the orientation is a single SHORT stored in the entry itself
+	let ifd = read_u32(4)? as usize;
+	let count = read_u16(ifd)? as usize;
+	for i in 0..count {
+		let entry = ifd + 2 + i * 12;
+		if read_u16(entry)? == 0x0112 && read_u16(entry + 2)? == 3 {
+			let orientation = read_u16(entry + 8)?;
+			return if (2..=8).contains(&orientation) { Some(orientation) } else { None };
+		}
+	}
+
+	None
+}
+
+/// An APP1 segment with an EXIF block that holds nothing but the orientation
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+	let mut payload: Vec<u8> = Vec::with_capacity(32);
+	payload.extend_from_slice(b"Exif\0\0");
+	// Big endian TIFF header, IFD0 follows right after it
+	payload.extend_from_slice(&[b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08]);
+	// One entry: tag, type SHORT, count 1, value padded to four bytes
+	payload.extend_from_slice(&1u16.to_be_bytes());
+	payload.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+	payload.extend_from_slice(&orientation.to_be_bytes());
+	payload.extend_from_slice(&[0x00, 0x00]);
+	// No next IFD
+	payload.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+
+	let mut segment = vec![0xFF, 0xE1];
+	segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+	segment.extend_from_slice(&payload);
+	segment
+}
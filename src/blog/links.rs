@@ -0,0 +1,155 @@
+use regex::Regex;
+
+use crate::app::config::{config_get_bool, config_get_string, config_get_string_vec};
+
+lazy_static! {
+	static ref ANCHOR: Regex = Regex::new(r"(?i)<a\s[^>]*>").unwrap();
+	static ref ATTRIBUTE: Regex = Regex::new(r#"(?i)\s(?P<name>href|rel|target)\s*=\s*(?:"(?P<dq>[^"]*)"|'(?P<sq>[^']*)')"#).unwrap();
+}
+
+
+// ------------------------------
+// ------------ LINKS -----------
+// ------------------------------
+
+/// How outbound links in post and tag content are rewritten
+///
+/// Domains match themselves and their subdomains, e.g. `amazon.de` matches `www.amazon.de`
+pub struct LinkRules {
+	own_host: String,
+	/// Get `rel="nofollow sponsored"`, from `links_affiliate_domains`
+	affiliate: Vec<String>,
+	/// Go through `/fwd?url=` so clicks show up in the access log, from `links_tracked_domains`
+	tracked: Vec<String>,
+	/// Open outbound links in a new tab, from `links_new_tab`
+	new_tab: bool,
+}
+
+impl LinkRules {
+	pub fn from_config() -> LinkRules {
+		let domains = |key: &str| -> Vec<String> {
+			config_get_string_vec(key).iter().map(|tmp| tmp.trim().trim_start_matches('.').to_lowercase()).filter(|tmp| tmp.len() > 0).collect()
+		};
+
+		LinkRules {
+			own_host: config_get_string("fqdn").to_lowercase(),
+			affiliate: domains("links_affiliate_domains"),
+			tracked: domains("links_tracked_domains"),
+			new_tab: config_get_bool("links_new_tab"),
+		}
+	}
+
+	/// Nothing to do without any rules, the content stays as it is
+	pub fn is_empty(&self) -> bool {
+		self.affiliate.len() == 0 && self.tracked.len() == 0 && !self.new_tab
+	}
+
+	/// Whether `/fwd` may send visitors to this URL
+	pub fn is_tracked(&self, url: &str) -> bool {
+		match outbound_host(url) {
+			Some(host) => domain_matches(&host, &self.tracked),
+			_ => false
+		}
+	}
+
+	/// Rewrite the outbound links in some HTML
+	pub fn rewrite(&self, content: &str) -> String {
+		if self.is_empty() { return String::from(content); }
+
+		ANCHOR.replace_all(content, |caps: &regex::Captures| self.rewrite_anchor(&caps[0])).into_owned()
+	}
+
+	fn rewrite_anchor(&self, tag: &str) -> String {
+		let mut href = None;
+		let mut rel: Vec<String> = Vec::new();
+		let mut target = None;
+
+		for cap in ATTRIBUTE.captures_iter(tag) {
+			let value = cap.name("dq").or(cap.name("sq")).map(|tmp| tmp.as_str()).unwrap_or("");
+			match cap["name"].to_lowercase().as_str() {
+				"href" => { href = Some(String::from(value)); }
+				"rel" => { rel = value.split_whitespace().map(String::from).collect(); }
+				"target" => { target = Some(String::from(value)); }
+				_ => {}
+			}
+		}
+
+		// Links to our own pages, anchors and mail addresses stay as they are
+		let (href, host) = match href.and_then(|href| outbound_host(&href).map(|host| (href, host))) {
+			Some(tmp) => tmp,
+			_ => { return String::from(tag); }
+		};
+		if host == self.own_host || host.ends_with(&format!(".{}", self.own_host)) {
+			return String::from(tag);
+		}
+
+		let mut add_rel = |token: &str| {
+			if !rel.iter().any(|tmp| tmp.eq_ignore_ascii_case(token)) { rel.push(String::from(token)); }
+		};
+		if domain_matches(&host, &self.affiliate) {
+			add_rel("nofollow");
+			add_rel("sponsored");
+		}
+		if self.new_tab {
+			target = Some(String::from("_blank"));
+			add_rel("noopener");
+		}
+
+		let href = match domain_matches(&host, &self.tracked) {
+			true => format!("/fwd?url={}", percent_encode(&href.replace("&amp;", "&"))),
+			false => href
+		};
+
+		// Drop the attributes we manage, then add them again at the end
+		let stripped = ATTRIBUTE.replace_all(tag, "");
+		let mut result = String::from(stripped.trim_end_matches('>').trim_end_matches('/').trim_end());
+		result.push_str(&format!(" href=\"{}\"", href.replace('"', "&quot;")));
+		if rel.len() > 0 {
+			result.push_str(&format!(" rel=\"{}\"", rel.join(" ").replace('"', "&quot;")));
+		}
+		match target {
+			Some(target) => { result.push_str(&format!(" target=\"{}\"", target.replace('"', "&quot;"))); }
+			_ => {}
+		}
+		result.push('>');
+
+		result
+	}
+}
+
+/// The lowercase host of an absolute http or https URL
+fn outbound_host(url: &str) -> Option<String> {
+	let lower = url.trim().to_lowercase();
+	let rest = if lower.starts_with("https://") {
+		&lower[8..]
+	} else if lower.starts_with("http://") {
+		&lower[7..]
+	} else if lower.starts_with("//") {
+		&lower[2..]
+	} else {
+		return None;
+	};
+
+	// Cut off the path, query, fragment, credentials and port
+	let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+	let host = authority.rsplit('@').next().unwrap_or("").split(':').next().unwrap_or("");
+	if host.len() == 0 { return None; }
+
+	Some(String::from(host))
+}
+
+fn domain_matches(host: &str, domains: &Vec<String>) -> bool {
+	domains.iter().any(|domain| host == domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Encode a URL to be used as a query parameter
+fn percent_encode(text: &str) -> String {
+	let mut result = String::with_capacity(text.len() * 3 / 2);
+	for byte in text.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => { result.push(byte as char); }
+			_ => { result.push_str(&format!("%{:02X}", byte)); }
+		}
+	}
+	result
+}
@@ -0,0 +1,48 @@
+use regex::{Captures, Regex};
+
+use crate::app::config::config_get_i64;
+
+/// Width of gallery images in the lite version, `lite_image_width` overrides it
+const LITE_IMAGE_WIDTH: i64 = 480;
+
+lazy_static! {
+	static ref SCRIPT: Regex = Regex::new(r"(?is)<(script|style|noscript|video|audio|object)\b.*?</(script|style|noscript|video|audio|object)\s*>").unwrap();
+	static ref IFRAME: Regex = Regex::new(r"(?is)<iframe\b[^>]*?\ssrc\s*=\s*[\x22'](?P<src>[^\x22']*)[\x22'][^>]*>.*?</iframe\s*>").unwrap();
+	static ref EVENT_HANDLER: Regex = Regex::new(r#"(?i)\s+on[a-z]+\s*=\s*("[^"]*"|'[^']*')"#).unwrap();
+	static ref RESPONSIVE: Regex = Regex::new(r#"(?i)\s+(srcset|sizes)\s*=\s*("[^"]*"|'[^']*')"#).unwrap();
+	static ref GALLERY_RESIZED: Regex = Regex::new(r#"/gallery/(?P<guid>[A-Za-z0-9]+)/(?:[hw][0-9]+|c[0-9]+x[0-9]+)/"#).unwrap();
+	static ref GALLERY_ORIGINAL: Regex = Regex::new(r#"/gallery/(?:original/)?(?P<guid>[A-Za-z0-9]+)\.(?P<ext>jpg|jpeg|gif|png)\b"#).unwrap();
+	static ref IMG: Regex = Regex::new(r"(?i)<img\b").unwrap();
+}
+
+
+// ------------------------------
+// ------------- LITE -----------
+// ------------------------------
+
+/// Strip post content for readers on slow connections
+///
+/// Scripts, styles and players are removed, embeds become plain links and gallery images are
+/// requested in a small size, see `lite_image_width`
+pub fn lite_html(content: &str) -> String {
+	let width = match config_get_i64("lite_image_width") {
+		tmp if tmp > 0 => tmp,
+		_ => LITE_IMAGE_WIDTH
+	};
+	let size = format!("w{}", width);
+
+	let content = SCRIPT.replace_all(content, "");
+	let content = IFRAME.replace_all(&content, |caps: &Captures| {
+		format!("<p><a href=\"{}\">{}</a></p>", &caps["src"], &caps["src"])
+	});
+	let content = EVENT_HANDLER.replace_all(&content, "");
+	let content = RESPONSIVE.replace_all(&content, "");
+
+	// Originals are served through the resizer as well, the name at the end only needs the extension
+	let content = GALLERY_RESIZED.replace_all(&content, |caps: &Captures| format!("/gallery/{}/{}/", &caps["guid"], size));
+	let content = GALLERY_ORIGINAL.replace_all(&content, |caps: &Captures| {
+		format!("/gallery/{}/{}/image.{}", &caps["guid"], size, &caps["ext"])
+	});
+
+	IMG.replace_all(&content, "<img loading=\"lazy\"").into_owned()
+}
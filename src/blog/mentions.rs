@@ -0,0 +1,478 @@
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use curl::easy::{Easy, List};
+use regex::Regex;
+
+use crate::app::config::{config_get_bool, config_get_i64, config_get_string};
+use crate::blog::flags::flag_enabled;
+use crate::storage::{DbRow, Storage};
+
+lazy_static! {
+	static ref XMLRPC_METHOD: Regex = Regex::new(r"(?s)<methodName>\s*([^<]*?)\s*</methodName>").unwrap();
+	static ref XMLRPC_PARAM: Regex = Regex::new(r"(?s)<param>\s*<value>\s*(?:<string>([^<]*)</string>|([^<]*))\s*</value>\s*</param>").unwrap();
+	static ref HTML_TITLE: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+	static ref HTML_LINK: Regex = Regex::new(r#"(?is)<a\s[^>]*?href\s*=\s*(?:"([^"]*)"|'([^']*)')[^>]*>"#).unwrap();
+	static ref HTML_NOISE: Regex = Regex::new(r"(?is)<head[^>]*>.*?</head>|<script[^>]*>.*?</script>|<style[^>]*>.*?</style>|<!--.*?-->").unwrap();
+	static ref HTML_TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+	static ref WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+}
+
+/// Time to fetch the page that claims to link to us
+const SOURCE_TIMEOUT_SECS: u64 = 10;
+
+/// Only the start of larger pages is looked at
+const SOURCE_MAX_SIZE: usize = 1048576;
+
+/// Redirects followed to the linking page, every hop is checked like the first
+const SOURCE_MAX_REDIRECTS: u32 = 3;
+
+/// Default number of mentions per address and day
+const MENTIONS_PER_DAY: i64 = 20;
+
+/// Characters of the linking page kept around the link
+const EXCERPT_LENGTH: usize = 300;
+
+/// Bytes of HTML looked at on either side of the link for the excerpt
+const EXCERPT_WINDOW: usize = 800;
+
+
+// ------------------------------
+// ----------- MENTION ----------
+// ------------------------------
+
+/// Another site that links to a post and told us so, by pingback or trackback
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mention {
+	pub id: u32,
+	pub post_id: u32,
+	/// `pingback` or `trackback`
+	pub kind: String,
+	/// `pending`, `approved` or `rejected`
+	pub status: String,
+	pub source_url: String,
+	pub title: String,
+	pub excerpt: String,
+	pub blog_name: String,
+	pub remote_ip: String,
+	pub received_at: u64,
+}
+
+impl Mention {
+	fn from_sql(mut row: DbRow) -> Option<Mention> {
+		Some(Mention {
+			id: row.take("id")?,
+			post_id: row.take("post_id")?,
+			kind: row.take("kind")?,
+			status: row.take("status")?,
+			source_url: row.take("source_url")?,
+			title: row.take("title")?,
+			excerpt: row.take("excerpt")?,
+			blog_name: row.take("blog_name")?,
+			remote_ip: row.take("remote_ip")?,
+			received_at: row.take::<NaiveDateTime>("received_at")?.timestamp() as u64,
+		})
+	}
+}
+
+/// Why a mention was not taken, each maps to a pingback fault code
+#[derive(Debug)]
+pub enum MentionError {
+	/// The request is no pingback we understand
+	Invalid,
+	/// Some other XML-RPC method
+	UnknownMethod,
+	/// The source could not be fetched
+	SourceMissing,
+	/// The source does not link to the post
+	NoLink,
+	/// The target is no post of ours
+	TargetMissing,
+	AlreadyRegistered,
+	/// Blocked or too many mentions from the address
+	Denied,
+	/// Something on our side
+	Internal(String),
+}
+
+impl MentionError {
+	/// The fault code of the pingback specification, or of XML-RPC itself
+	pub fn fault_code(&self) -> i32 {
+		match self {
+			MentionError::Invalid => -32600,
+			MentionError::UnknownMethod => -32601,
+			MentionError::SourceMissing => 16,
+			MentionError::NoLink => 17,
+			MentionError::TargetMissing => 33,
+			MentionError::AlreadyRegistered => 48,
+			MentionError::Denied => 49,
+			MentionError::Internal(_) => 0,
+		}
+	}
+
+	pub fn message(&self) -> &str {
+		match self {
+			MentionError::Invalid => "The request is not a valid pingback.",
+			MentionError::UnknownMethod => "Only pingback.ping is supported.",
+			MentionError::SourceMissing => "The source page could not be fetched.",
+			MentionError::NoLink => "The source page does not link to the target.",
+			MentionError::TargetMissing => "The target is not a post on this site.",
+			MentionError::AlreadyRegistered => "The mention was already registered.",
+			MentionError::Denied => "Access denied.",
+			MentionError::Internal(_) => "The mention could not be stored.",
+		}
+	}
+}
+
+
+/// Pingbacks and trackbacks are only accepted with `mentions_enabled`, the `mentions` flag can switch them off
+pub fn mentions_enabled() -> bool {
+	flag_enabled("mentions", config_get_bool("mentions_enabled"))
+}
+
+/// Where pingback clients send their requests, announced with the `X-Pingback` header of every post
+pub fn pingback_url() -> String {
+	format!("https://{}/xmlrpc", config_get_string("fqdn"))
+}
+
+/// Check whether the address sent too many mentions today, `mentions_per_day` overrides the limit
+pub fn mention_rate_limited(db: &Arc<dyn Storage>, remote_ip: &str) -> bool {
+	let limit = match config_get_i64("mentions_per_day") {
+		tmp if tmp > 0 => tmp,
+		_ => MENTIONS_PER_DAY
+	};
+
+	let since = Utc::now().naive_utc() - ChronoDuration::days(1);
+	let query = "SELECT COUNT(*) AS count FROM post_mentions WHERE remote_ip=:remote_ip AND received_at >= :since";
+	match db.query_first(query, db_params! {"remote_ip" => remote_ip, "since" => since}) {
+		Ok(Some(mut row)) => row.take::<i64>("count").unwrap_or(0) >= limit,
+		// Better to turn someone away than to be flooded
+		_ => true
+	}
+}
+
+/// Fetch the source, make sure it links to one of `targets` and queue the mention for moderation
+///
+/// `targets` are the URLs the post is known by. Title and excerpt are taken from the source unless the
+/// trackback brought its own. Blocks while the source is fetched, returns the id of the mention
+pub fn receive_mention(db: &Arc<dyn Storage>, post_id: u32, targets: &[String], kind: &str, source: &str, title: &str, excerpt: &str, blog_name: &str, remote_ip: &str) -> Result<u64, MentionError> {
+	let source = source.trim();
+	if source.len() == 0 || source.len() > 512 {
+		return Err(MentionError::SourceMissing);
+	}
+
+	let html = fetch_source(source)?;
+	let link_at = find_link(&html, targets).ok_or(MentionError::NoLink)?;
+
+	let title = match title.trim() {
+		tmp if tmp.len() > 0 => String::from(tmp),
+		_ => HTML_TITLE.captures(&html).map(|tmp| plain_text(&tmp[1])).unwrap_or_default()
+	};
+	let excerpt = match excerpt.trim() {
+		tmp if tmp.len() > 0 => plain_text(tmp),
+		_ => excerpt_around(&html, link_at)
+	};
+	let blog_name = match blog_name.trim() {
+		tmp if tmp.len() > 0 => String::from(tmp),
+		_ => source_host(source).map(|(host, _)| host).unwrap_or_default()
+	};
+
+	let query = format!(r##"{} INTO post_mentions (post_id, kind, status, source_url, title, excerpt, blog_name, remote_ip, received_at)
+        VALUES (:post_id, :kind, :status, :source_url, :title, :excerpt, :blog_name, :remote_ip, :received_at)"##, db.insert_ignore());
+
+	match db.exec(&query, db_params! {
+		"post_id" => post_id, "kind" => kind, "status" => "pending", "source_url" => source, "title" => truncate(&title, 255),
+		"excerpt" => truncate(&excerpt, EXCERPT_LENGTH + 2), "blog_name" => truncate(&blog_name, 255), "remote_ip" => remote_ip,
+		"received_at" => Utc::now().naive_utc()
+	}) {
+		Ok(res) if res.affected_rows == 0 => { Err(MentionError::AlreadyRegistered) }
+		Ok(res) => { Ok(res.last_insert_id) }
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(MentionError::Internal(err.to_string()))
+		}
+	}
+}
+
+
+// ------------------------------
+// ---------- PROTOCOLS ---------
+// ------------------------------
+
+/// The source and target of an XML-RPC `pingback.ping` call
+pub fn parse_pingback(body: &str) -> Result<(String, String), MentionError> {
+	let method = XMLRPC_METHOD.captures(body).ok_or(MentionError::Invalid)?;
+	if &method[1] != "pingback.ping" {
+		return Err(MentionError::UnknownMethod);
+	}
+
+	let params: Vec<String> = XMLRPC_PARAM.captures_iter(body)
+		.map(|tmp| unescape_xml(tmp.get(1).or(tmp.get(2)).map_or("", |tmp| tmp.as_str()).trim()))
+		.collect();
+	match params.as_slice() {
+		[source, target] if source.len() > 0 && target.len() > 0 => Ok((source.clone(), target.clone())),
+		_ => Err(MentionError::Invalid)
+	}
+}
+
+/// The XML-RPC answer to a pingback, a fault for anything but success
+pub fn pingback_response(result: &Result<u64, MentionError>) -> String {
+	match result {
+		Ok(_) => String::from(r##"<?xml version="1.0" encoding="UTF-8"?>
+<methodResponse><params><param><value><string>Thanks for the ping, it will show once it is approved.</string></value></param></params></methodResponse>"##),
+		Err(err) => format!(r##"<?xml version="1.0" encoding="UTF-8"?>
+<methodResponse><fault><value><struct>
+<member><name>faultCode</name><value><int>{}</int></value></member>
+<member><name>faultString</name><value><string>{}</string></value></member>
+</struct></value></fault></methodResponse>"##, err.fault_code(), escape_xml(err.message()))
+	}
+}
+
+/// The answer to a trackback, `<error>` is 0 for success
+pub fn trackback_response(result: &Result<u64, MentionError>) -> String {
+	match result {
+		Ok(_) => String::from(r##"<?xml version="1.0" encoding="utf-8"?>
+<response><error>0</error></response>"##),
+		Err(err) => format!(r##"<?xml version="1.0" encoding="utf-8"?>
+<response><error>1</error><message>{}</message></response>"##, escape_xml(err.message()))
+	}
+}
+
+fn escape_xml(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+/// The entities XML and most HTML use, `&amp;` last so nothing is unescaped twice
+fn unescape_xml(text: &str) -> String {
+	text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'")
+		.replace("&#39;", "'").replace("&nbsp;", " ").replace("&amp;", "&")
+}
+
+
+// ------------------------------
+// ----------- SOURCE -----------
+// ------------------------------
+
+/// Fetch the page at an http(s) URL, at most `SOURCE_MAX_SIZE` bytes of it
+///
+/// Every address the host resolves to has to be public and curl is pinned to the one that was
+/// checked, so a mention can not make us request something on our own network
+fn fetch_source(url: &str) -> Result<String, MentionError> {
+	let mut url = String::from(url);
+
+	for _ in 0..=SOURCE_MAX_REDIRECTS {
+		let (host, port) = source_host(&url).ok_or(MentionError::SourceMissing)?;
+		let address = public_address(&host, port)?;
+
+		let mut resolve = List::new();
+		let pinned = match address {
+			IpAddr::V4(ip) => format!("{}:{}:{}", host, port, ip),
+			IpAddr::V6(ip) => format!("{}:{}:[{}]", host, port, ip),
+		};
+		resolve.append(&pinned).map_err(|_| MentionError::SourceMissing)?;
+
+		let mut easy = Easy::new();
+		let mut html = Vec::new();
+		let setup = easy.url(&url)
+			.and_then(|_| easy.resolve(resolve))
+			.and_then(|_| easy.useragent(&format!("Mentions/1.0 (+https://{})", config_get_string("fqdn"))))
+			.and_then(|_| easy.timeout(Duration::from_secs(SOURCE_TIMEOUT_SECS)))
+			.and_then(|_| easy.accept_encoding(""));
+		match setup {
+			Err(err) => {
+				println!("Could not fetch the mention source {}: {:?}", url, err);
+				return Err(MentionError::SourceMissing);
+			}
+			_ => {}
+		}
+
+		let performed = {
+			let mut transfer = easy.transfer();
+			// Stops the transfer once there is enough to look at
+			transfer.write_function(|data| {
+				if html.len() >= SOURCE_MAX_SIZE { return Ok(0); }
+				html.extend_from_slice(data);
+				Ok(data.len())
+			}).and_then(|_| transfer.perform())
+		};
+		match performed {
+			Err(err) if !(err.is_write_error() && html.len() >= SOURCE_MAX_SIZE) => {
+				println!("Could not fetch the mention source {}: {:?}", url, err);
+				return Err(MentionError::SourceMissing);
+			}
+			_ => {}
+		}
+
+		match (easy.response_code(), easy.redirect_url()) {
+			(Ok(code), _) if (200..300).contains(&code) => { return Ok(String::from_utf8_lossy(&html).to_string()); }
+			(Ok(code), Ok(Some(location))) if (300..400).contains(&code) => { url = String::from(location); }
+			_ => { return Err(MentionError::SourceMissing); }
+		}
+	}
+
+	Err(MentionError::SourceMissing)
+}
+
+/// Host and port of an http(s) URL, URLs with credentials are refused
+fn source_host(url: &str) -> Option<(String, u16)> {
+	let (rest, default_port) = if url.len() > 8 && url[..8].eq_ignore_ascii_case("https://") {
+		(&url[8..], 443)
+	} else if url.len() > 7 && url[..7].eq_ignore_ascii_case("http://") {
+		(&url[7..], 80)
+	} else {
+		return None;
+	};
+
+	let authority = rest.split(['/', '?', '#']).next()?;
+	if authority.len() == 0 || authority.contains('@') { return None; }
+
+	// `[::1]:8080`, `example.com:8080` or just the host
+	let (host, port) = match authority.rfind(':') {
+		Some(pos) if !authority[pos..].contains(']') => (&authority[..pos], authority[pos + 1..].parse::<u16>().ok()?),
+		_ => (authority, default_port)
+	};
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	if host.len() == 0 { return None; }
+
+	Some((host.to_lowercase(), port))
+}
+
+/// The address to connect to, only if everything the host resolves to is public
+fn public_address(host: &str, port: u16) -> Result<IpAddr, MentionError> {
+	let addresses: Vec<IpAddr> = match (host, port).to_socket_addrs() {
+		Ok(tmp) => tmp.map(|tmp| tmp.ip()).collect(),
+		_ => { return Err(MentionError::SourceMissing); }
+	};
+
+	if addresses.iter().any(|ip| !is_public_ip(ip)) {
+		return Err(MentionError::Denied);
+	}
+	addresses.into_iter().next().ok_or(MentionError::SourceMissing)
+}
+
+fn is_public_ip(ip: &IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => {
+			let octets = ip.octets();
+			!(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_broadcast() || ip.is_documentation()
+				|| ip.is_unspecified() || octets[0] == 0
+				// Shared address space of carrier-grade NATs, 100.64.0.0/10
+				|| (octets[0] == 100 && octets[1] & 0xc0 == 64))
+		}
+		IpAddr::V6(ip) => {
+			let first = ip.segments()[0];
+			match ip.to_ipv4() {
+				// Mapped and compatible addresses end up at the IPv4 one
+				Some(v4) if ip.segments()[..5].iter().all(|tmp| *tmp == 0) => is_public_ip(&IpAddr::V4(v4)),
+				_ => !(ip.is_loopback() || ip.is_unspecified()
+					// Unique local fc00::/7 and link local fe80::/10
+					|| first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+			}
+		}
+	}
+}
+
+/// Where the first link to one of `targets` starts in the page
+///
+/// Links in scripts, styles, comments and the head do not count
+fn find_link(html: &str, targets: &[String]) -> Option<usize> {
+	let targets: Vec<String> = targets.iter().map(|tmp| normalize_link(tmp)).collect();
+	let hidden: Vec<(usize, usize)> = HTML_NOISE.find_iter(html).map(|tmp| (tmp.start(), tmp.end())).collect();
+	HTML_LINK.captures_iter(html)
+		.find(|tmp| {
+			let start = tmp.get(0).map_or(0, |tmp| tmp.start());
+			let href = tmp.get(1).or(tmp.get(2)).map_or("", |tmp| tmp.as_str());
+			!hidden.iter().any(|(from, to)| start >= *from && start < *to) && targets.contains(&normalize_link(&unescape_xml(href)))
+		})
+		.and_then(|tmp| tmp.get(0))
+		.map(|tmp| tmp.start())
+}
+
+/// A link without scheme, `www.`, query, fragment and trailing slash, lower case
+fn normalize_link(link: &str) -> String {
+	let link = link.trim().to_lowercase();
+	let link = link.split(['?', '#']).next().unwrap_or("");
+	let link = link.trim_start_matches("https:").trim_start_matches("http:").trim_start_matches("//");
+	let link = link.trim_start_matches("www.");
+	String::from(link.trim_end_matches('/'))
+}
+
+/// The text around the link, with an ellipsis where it was cut
+fn excerpt_around(html: &str, link_at: usize) -> String {
+	let mut start = link_at.saturating_sub(EXCERPT_WINDOW);
+	while !html.is_char_boundary(start) { start -= 1; }
+	let mut end = (link_at + EXCERPT_WINDOW).min(html.len());
+	while !html.is_char_boundary(end) { end += 1; }
+
+	// Half of the excerpt before the link, the rest after it
+	let before = plain_text(&html[start..link_at]);
+	let after = plain_text(&html[link_at..end]);
+	let before: String = before.chars().rev().take(EXCERPT_LENGTH / 2).collect::<Vec<char>>().into_iter().rev().collect();
+	let after: String = after.chars().take(EXCERPT_LENGTH - before.chars().count()).collect();
+
+	format!("…{} {}…", before.trim(), after.trim())
+}
+
+/// HTML as plain text on a single line
+fn plain_text(html: &str) -> String {
+	let text = HTML_NOISE.replace_all(html, " ");
+	let text = HTML_TAG.replace_all(&text, " ");
+	let text = unescape_xml(&text);
+	String::from(WHITESPACE.replace_all(&text, " ").trim())
+}
+
+fn truncate(text: &str, length: usize) -> String {
+	text.chars().take(length).collect()
+}
+
+
+// ------------------------------
+// --------- MODERATION ---------
+// ------------------------------
+
+/// All mentions with the given status, newest first
+pub fn load_mentions(db: &Arc<dyn Storage>, status: &str) -> Vec<Mention> {
+	let query = r##"SELECT id, post_id, kind, status, source_url, title, excerpt, blog_name, remote_ip, received_at
+        FROM post_mentions WHERE status=:status ORDER BY id DESC"##;
+
+	match db.query(query, db_params! {"status" => status}) {
+		Ok(rows) => rows.into_iter().filter_map(Mention::from_sql).collect(),
+		_ => vec![]
+	}
+}
+
+/// The approved mentions of all posts, oldest first like comments
+pub fn load_approved_mentions(db: &Arc<dyn Storage>) -> Result<Vec<Mention>, String> {
+	let query = r##"SELECT id, post_id, kind, status, source_url, title, excerpt, blog_name, remote_ip, received_at
+        FROM post_mentions WHERE status=:status ORDER BY id ASC"##;
+
+	let rows = db.query(query, db_params! {"status" => "approved"}).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(Mention::from_sql).collect())
+}
+
+/// Approve or reject a mention, approved ones can be taken back
+///
+/// Returns whether the mention was or now is public
+pub fn moderate_mention(db: &Arc<dyn Storage>, id: u32, status: &str) -> Result<bool, String> {
+	if status != "approved" && status != "rejected" {
+		return Err(String::from("the status must be approved or rejected"));
+	}
+
+	let query = r##"SELECT id, post_id, kind, status, source_url, title, excerpt, blog_name, remote_ip, received_at
+        FROM post_mentions WHERE id=:id"##;
+	let mention = match db.query_first(query, db_params! {"id" => id}) {
+		Ok(Some(row)) => Mention::from_sql(row).ok_or_else(|| String::from("the mention could not be read"))?,
+		_ => return Err(String::from("the mention does not exist")),
+	};
+
+	match db.exec("UPDATE post_mentions SET status=:status WHERE id=:id", db_params! {"id" => id, "status" => status}) {
+		Ok(_) => Ok(mention.status == "approved" || status == "approved"),
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
@@ -0,0 +1,110 @@
+use crate::app::config::config_get_bool;
+
+/// Content of these elements is kept as it is, whitespace matters there
+const VERBATIM_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+
+// ------------------------------
+// ----------- MINIFY -----------
+// ------------------------------
+
+/// Whether rendered pages are minified before they are cached, off unless `html_minify` is set
+pub fn minify_enabled() -> bool {
+	config_get_bool("html_minify")
+}
+
+/// Only whole HTML documents are minified, feeds and site maps are XML
+pub fn is_html_document(content: &str) -> bool {
+	let start: String = content.trim_start().chars().take(14).collect::<String>().to_lowercase();
+	start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+/// Collapse whitespace and drop comments, without changing how the page looks
+///
+/// Whitespace with a line break becomes a line break, any other becomes a single space. Tags, the
+/// content of `VERBATIM_ELEMENTS` and conditional comments like `<!--[if IE]>` are left alone, so are
+/// comments starting with `<!--!` that are meant to stay
+pub fn minify_html(html: &str) -> String {
+	let mut minified = String::with_capacity(html.len());
+	let mut rest = html;
+
+	while let Some(start) = rest.find('<') {
+		collapse_whitespace(&rest[..start], &mut minified);
+		rest = &rest[start..];
+
+		// Comments
+		if rest.starts_with("<!--") {
+			let end = match rest.find("-->") {
+				Some(tmp) => tmp + 3,
+				_ => rest.len()
+			};
+			let comment = &rest[..end];
+			rest = &rest[end..];
+			if comment.starts_with("<!--[") || comment.starts_with("<!--<![") || comment.starts_with("<!--!") {
+				minified.push_str(comment);
+			} else if minified.ends_with(|c: char| c.is_ascii_whitespace()) {
+				// The whitespace around the comment is already there once
+				rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+			}
+			continue;
+		}
+
+		// The tag itself, attribute values may hold whitespace that matters
+		let tag_end = match rest.find('>') {
+			Some(tmp) => tmp + 1,
+			_ => rest.len()
+		};
+		let tag = &rest[..tag_end];
+		minified.push_str(tag);
+		rest = &rest[tag_end..];
+
+		match verbatim_element(tag) {
+			Some(name) => {
+				let end = find_closing_tag(rest, name).unwrap_or(rest.len());
+				minified.push_str(&rest[..end]);
+				rest = &rest[end..];
+			}
+			_ => {}
+		}
+	}
+	collapse_whitespace(rest, &mut minified);
+
+	minified
+}
+
+/// The name of the element an opening tag starts, if its content has to stay as it is
+fn verbatim_element(tag: &str) -> Option<&'static str> {
+	let name: String = tag[1..].chars()
+		.take_while(|c| c.is_ascii_alphanumeric())
+		.collect::<String>()
+		.to_lowercase();
+	VERBATIM_ELEMENTS.iter().find(|element| **element == name).cloned()
+}
+
+/// Where `</name` starts, case insensitive
+fn find_closing_tag(html: &str, name: &str) -> Option<usize> {
+	let closing = format!("</{}", name);
+	html.as_bytes().windows(closing.len()).position(|window| window.eq_ignore_ascii_case(closing.as_bytes()))
+}
+
+fn collapse_whitespace(text: &str, minified: &mut String) {
+	let mut whitespace: Option<char> = None;
+	for c in text.chars() {
+		if c.is_ascii_whitespace() {
+			whitespace = match (whitespace, c) {
+				(Some('\n'), _) | (_, '\n') => Some('\n'),
+				_ => Some(' ')
+			};
+			continue;
+		}
+		match whitespace.take() {
+			Some(tmp) => { minified.push(tmp); }
+			_ => {}
+		}
+		minified.push(c);
+	}
+	match whitespace {
+		Some(tmp) => { minified.push(tmp); }
+		_ => {}
+	}
+}
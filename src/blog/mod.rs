@@ -1,67 +1,207 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Mutex, RwLock, Arc};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
-use regex::Regex;
+use arc_swap::ArcSwap;
+use tokio::task;
 
-use crate::app::config::{config_get_i64, config_get_string};
+use crate::app::cdn;
+use crate::app::config::{config_get_bool, config_get_i64, config_get_string, config_get_string_map};
+use crate::app::timezone::{format_rfc2822, format_w3c};
 use crate::blog::cache::Cache;
-use crate::blog::context::Context;
+use crate::blog::context::{Breadcrumb, Context, FeedEnclosure, FeedItem, FeedLink, TagCloudEntry};
+use crate::blog::flags::{flag_enabled_for, template_flags};
+use crate::blog::links::LinkRules;
+use crate::blog::mentions::Mention;
+use crate::blog::resolver::{PostMatch, PostResolver};
+use crate::blog::shortcodes::{evaluate_conditions, mark_conditions, replace_shortcodes, shortcode_keys};
 use crate::blog::sitemap::*;
-use crate::blog::types::{comment, menu, post, redirect, snippet, tag};
+use crate::blog::types::{comment, draft, experiment, lock, menu, poll, post, redirect, revision, snippet, tag};
 use crate::blog::types::comment::Comment;
+use crate::blog::types::experiment::{Experiment, ExperimentVariant, is_internal_referer};
+use crate::blog::types::poll::{Poll, PollResults};
+use crate::blog::types::redirect::Redirect;
+use crate::blog::types::snippet::Snippet;
 use crate::blog::types::post::{Post, PostExcerpt};
 use crate::blog::types::tag::Tag;
+use crate::blog::validation::{DataIssue, validate_blog_data};
+use crate::storage::Storage;
 use actix_web::{error, web};
 
+pub mod a11y;
+pub mod alt_text;
+pub mod blocklist;
 pub mod cache;
 pub mod context;
 pub mod types;
 pub mod dashboard;
+pub mod export;
+pub mod files;
+pub mod flags;
 pub mod gallery;
+pub mod image_meta;
+pub mod links;
+pub mod lite;
+pub mod mentions;
+pub mod minify;
+pub mod notifications;
+pub mod og_image;
+pub mod optimize;
+pub mod print;
+pub mod profile;
+pub mod recommendations;
+pub mod replace;
+pub mod resolver;
 pub mod routes;
 pub mod routes_admin;
+pub mod search;
+pub mod shortcodes;
 pub mod sitemap;
+pub mod spam;
+pub mod stats;
+pub mod submissions;
+pub mod validation;
+pub mod webhooks;
 
 
 /// Internal messages the blog can send
 pub enum BlogMessage {
-	PostView { post_id: u32, viewed_at: u64, remote_ip: String, user_agent: String, referer: String }
+	PostView { post_id: u32, viewed_at: u64, remote_ip: String, user_agent: String, referer: String, campaign: String, country: String },
+	PollVote { poll_id: u32, option: u32, voted_at: u64, remote_ip: String },
+	PostEngagement { post_id: u32, recorded_at: u64, scroll_depth: u32, read_time: u32, remote_ip: String },
+	ExperimentEvent { experiment_id: u32, variant: ExperimentVariant, kind: &'static str, recorded_at: u64 },
 }
 
+/// Served when not even `error_500.html` can be rendered
+const ERROR_PAGE_FALLBACK: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Error</title></head><body><h1>Something went wrong</h1><p>Please try again in a moment.</p></body></html>";
+
+/// Queue length used when `message_queue_max` is not configured
+const MESSAGE_QUEUE_MAX: usize = 10000;
+
+/// Recommendations count the views of this many days
+const POPULARITY_DAYS: i64 = 30;
+
+/// Seconds the views are kept when `recommendations_lifetime` is not configured
+const POPULARITY_LIFETIME: u64 = 6 * 3600;
+
+/// Longer read times are capped, the tab was most likely left open
+const ENGAGEMENT_MAX_READ_TIME: u32 = 4 * 60 * 60;
+
+/// Numbers on the message queue for the admin panel
+#[derive(Debug, Serialize)]
+pub struct MessageQueueStats {
+	queued: usize,
+	capacity: usize,
+	dropped: u64,
+	flushed: u64,
+}
+
+
+/// A post with its snippets expanded, built when it is first shown
+struct ExpandedPost {
+	/// What it was built from, it is built again once the post or the snippets were reloaded
+	source: Arc<Post>,
+	snippets: Arc<Vec<Snippet>>,
+	post: Arc<Post>,
+	excerpt: Arc<PostExcerpt>,
+}
+
+/// The posts and everything derived from them, a reload publishes all of it at once
+///
+/// Readers that load one snapshot never see new posts with an old index or site map
+struct PostSnapshot {
+	posts: HashMap<u32, Arc<Post>>,
+	tag_2_posts: HashMap<String, Vec<u32>>,
+	author_2_posts: HashMap<u32, Vec<u32>>,
+	posts_by_date: Vec<u32>,
+	posts_index: Vec<u32>,
+	post_2_polls: HashMap<u32, Vec<u32>>,
+	sitemap: SiteMap,
+}
 
 /// Main blog data structure
+///
+/// All maps are immutable snapshots: readers load the current `Arc` without locking,
+/// reloads build a fresh map and swap it in once it is complete
 pub struct Blog {
-	posts: RwLock<HashMap<u32, Post>>,
-	post_excerpts: RwLock<HashMap<u32, PostExcerpt>>,
-	seo_urls: RwLock<HashMap<String, u32>>,
-	seo_urls_historic: RwLock<HashMap<String, u32>>,
-	comments: RwLock<HashMap<u32, Vec<Comment>>>,
-	tags: RwLock<HashMap<String, Tag>>,
-	tag_2_posts: RwLock<HashMap<String, Vec<u32>>>,
-	menus: RwLock<HashMap<String, Vec<menu::MenuItem>>>,
-	redirects: RwLock<HashMap<String, String>>,
+	snapshot: ArcSwap<PostSnapshot>,
+	expanded_posts: RwLock<HashMap<u32, Arc<ExpandedPost>>>,
+	/// Canonical and historic SEO urls of the posts
+	resolver: PostResolver,
+	comments: ArcSwap<HashMap<u32, Vec<Comment>>>,
+	/// Approved pingbacks and trackbacks by post
+	mentions: ArcSwap<HashMap<u32, Vec<Mention>>>,
+	/// Lowercase addresses of everyone with an approved comment
+	approved_commenters: ArcSwap<HashSet<String>>,
+	tags: ArcSwap<HashMap<String, Tag>>,
+	tag_cloud: ArcSwap<Vec<TagCloudEntry>>,
+	menus: ArcSwap<HashMap<String, Vec<menu::MenuItem>>>,
+	redirects: ArcSwap<HashMap<String, Redirect>>,
+	/// Redirects of paths, in the order they are tried, see `match_redirect_rule`
+	redirect_rules: ArcSwap<Vec<Redirect>>,
+	polls: ArcSwap<HashMap<u32, Arc<Poll>>>,
+	snippets: ArcSwap<Vec<Snippet>>,
+	poll_results: ArcSwap<HashMap<u32, Arc<PollResults>>>,
+	/// The running experiments by post
+	experiments: ArcSwap<HashMap<u32, Arc<Experiment>>>,
+	/// Views of the last `POPULARITY_DAYS` by post
+	post_popularity: ArcSwap<HashMap<u32, u32>>,
+	post_popularity_at: AtomicU64,
+	/// Recommended posts by post, built on first use, see `recommend_posts`
+	recommendations: RwLock<HashMap<u32, Vec<u32>>>,
+	data_issues: ArcSwap<Vec<DataIssue>>,
+	gallery_issues: ArcSwap<Vec<DataIssue>>,
+	gallery_orphans: ArcSwap<gallery::GalleryOrphans>,
 	cache: Cache,
-	messages: Mutex<Vec<BlogMessage>>,
+	messages: Mutex<VecDeque<BlogMessage>>,
+	messages_dropped: AtomicU64,
+	/// Dropped messages the admins were already notified about
+	messages_dropped_notified: AtomicU64,
+	messages_flushed: AtomicU64,
 }
 
 impl Blog {
 	/// Constructor
 	pub fn new() -> Blog {
 		Blog {
-			posts: RwLock::new(HashMap::new()),
-			post_excerpts: RwLock::new(HashMap::new()),
-			seo_urls: RwLock::new(HashMap::new()),
-			seo_urls_historic: RwLock::new(HashMap::new()),
-			comments: RwLock::new(HashMap::new()),
-			tags: RwLock::new(HashMap::new()),
-			tag_2_posts: RwLock::new(HashMap::new()),
-			menus: RwLock::new(HashMap::new()),
-			redirects: RwLock::new(HashMap::new()),
+			snapshot: ArcSwap::from_pointee(PostSnapshot {
+				posts: HashMap::new(),
+				tag_2_posts: HashMap::new(),
+				author_2_posts: HashMap::new(),
+				posts_by_date: Vec::new(),
+				posts_index: Vec::new(),
+				post_2_polls: HashMap::new(),
+				sitemap: SiteMap { content: None },
+			}),
+			expanded_posts: RwLock::new(HashMap::new()),
+			resolver: PostResolver::new(),
+			comments: ArcSwap::from_pointee(HashMap::new()),
+			mentions: ArcSwap::from_pointee(HashMap::new()),
+			approved_commenters: ArcSwap::from_pointee(HashSet::new()),
+			tags: ArcSwap::from_pointee(HashMap::new()),
+			tag_cloud: ArcSwap::from_pointee(Vec::new()),
+			menus: ArcSwap::from_pointee(HashMap::new()),
+			redirects: ArcSwap::from_pointee(HashMap::new()),
+			redirect_rules: ArcSwap::from_pointee(Vec::new()),
+			polls: ArcSwap::from_pointee(HashMap::new()),
+			snippets: ArcSwap::from_pointee(Vec::new()),
+			poll_results: ArcSwap::from_pointee(HashMap::new()),
+			experiments: ArcSwap::from_pointee(HashMap::new()),
+			post_popularity: ArcSwap::from_pointee(HashMap::new()),
+			post_popularity_at: AtomicU64::new(0),
+			recommendations: RwLock::new(HashMap::new()),
+			data_issues: ArcSwap::from_pointee(Vec::new()),
+			gallery_issues: ArcSwap::from_pointee(Vec::new()),
+			gallery_orphans: ArcSwap::from_pointee(gallery::GalleryOrphans::default()),
 			cache: Cache::new(),
-			messages: Mutex::new(Vec::new()),
+			messages: Mutex::new(VecDeque::new()),
+			messages_dropped: AtomicU64::new(0),
+			messages_dropped_notified: AtomicU64::new(0),
+			messages_flushed: AtomicU64::new(0),
 		}
 	}
 
@@ -74,7 +214,11 @@ impl Blog {
 	///     Latest Posts
 	///
 	/// Returns the number of blog posts that were loaded
-	pub fn startup(&self, db: &mysql::Pool) -> Result<usize, io::Error> {
+	pub fn startup(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
+		// Reload polls and snippets, posts embed them
+		let poll_count = self.reload_polls(db)?;
+		let snippet_count = self.reload_snippets(db)?;
+
 		// Reload blog post data
 		let post_count = self.reload_posts(db)?;
 
@@ -90,22 +234,24 @@ impl Blog {
 		// Reload blog comments
 		let comment_count = self.reload_comments(db)?;
 
+		// Reload the approved pingbacks and trackbacks
+		let mention_count = self.reload_mentions(db)?;
+
+		// Reload the running experiments
+		let experiment_count = self.reload_experiments(db)?;
+
 		// Drop a note on how much of what we have loaded
-		println!("Startup found {} posts, {} tags, {} comments, {} menus, {} redirects", post_count, tag_count, comment_count, menu_count, redirect_count);
+		println!("Startup found {} posts, {} tags, {} comments, {} mentions, {} menus, {} redirects, {} polls, {} snippets, {} experiments", post_count, tag_count, comment_count, mention_count, menu_count, redirect_count, poll_count, snippet_count, experiment_count);
 
-		// Cache Pinterest, Instagram, featured and latest posts
-		self.cache.cache_pinterest_posts();
-		self.cache.cache_instagram_posts();
-		self.cache.cache_latest_posts(&self, db);
-		self.cache.cache_featured_posts(&self, db);
+		// Make sure the data we have loaded is consistent
+		self.check_data_consistency();
 
-		// We want certain tags available on the start page
-		// These tags can be changed in the config
-		self.cache.cache_posts_by_tag(&self, 1, config_get_string("cached_tag_1").as_str());
-		self.cache.cache_posts_by_tag(&self, 2, config_get_string("cached_tag_2").as_str());
-		self.cache.cache_posts_by_tag(&self, 3, config_get_string("cached_tag_3").as_str());
-		self.cache.cache_posts_by_tag(&self, 4, config_get_string("cached_tag_4").as_str());
-		self.cache.cache_posts_by_tag(&self, 5, config_get_string("cached_tag_5").as_str());
+		// Cache Pinterest, Instagram, featured and latest posts
+		match self.refresh_social_feeds() {
+			Err(err) => { println!("{}", err); }
+			_ => {}
+		}
+		self.refresh_post_caches(db);
 
 		Ok(post_count)
 	}
@@ -116,96 +262,146 @@ impl Blog {
 
 	/// Load the blog post data from SQL
 	///
-	/// This function will `lock` (write)
-	fn reload_posts(&self, db: &mysql::Pool) -> Result<usize, io::Error> {
+	/// The new snapshot is built aside and swapped in once complete
+	fn reload_posts(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
 		// Load all blog posts
-		let blog_posts = post::load_posts_from_sql(db)?;
+		let blog_posts = post::load_posts_from_sql(db).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
 		let post_count = blog_posts.len();
 
-		// Use the post data to build the sitemap
-		self.reload_sitemap(&blog_posts);
-
-		// Fetch all snippets - we will need these to do some replacing in the posts
-		let snippets = match snippet::load_snippets_from_sql(db) {
-			Some(tmp) => { tmp }
-			_ => { vec![] }
-		};
+		// Use the post data to build the sitemap and the tag lookup
+		let (sitemap, tag_2_posts) = self.build_sitemap(&blog_posts);
+
+		// Polls are replaced right away, snippets once a post is first shown, see `get_expanded_post`
+		let polls = self.polls.load_full();
+
+		// Build fresh collections, readers keep using the old snapshot until we swap
+		let mut posts = HashMap::with_capacity(post_count);
+		let mut seo_urls = HashMap::with_capacity(post_count);
+		let mut seo_urls_historic = HashMap::new();
+		let mut posts_by_date: Vec<(u64, u32)> = Vec::with_capacity(post_count);
+		let mut posts_pinned: Vec<u32> = Vec::new();
+		let mut author_2_posts: HashMap<u32, Vec<u32>> = HashMap::new();
+		let mut post_2_polls: HashMap<u32, Vec<u32>> = HashMap::new();
+
+		for mut post in blog_posts {
+			// Every author, including co-authors, lists the post on their archive page
+			for author in &post.authors {
+				author_2_posts.entry(author.id).or_default().push(post.id);
+			}
 
-		// Create a regular expression to find snippets
-		let regex = Regex::new(r"\[(?P<key>[^\s^\]]+)[\s]*(?P<tail>[^]]*)\]").unwrap();
+			// Remember the publishing date for the chronological listing
+			posts_by_date.push((post.date_posted, post.id));
+			if post.pinned { posts_pinned.push(post.id); }
 
-		// CRITICAL SECTION: Load blog posts, map SEO urls
-		{
-			// DEADLOCK RISK!
-			// However, as of right now there are no other write locks
-			let mut guard_posts = self.posts.write().unwrap();
-			let mut guard_post_excerpts = self.post_excerpts.write().unwrap();
-			let mut guard_seo_urls = self.seo_urls.write().unwrap();
-			let mut guard_seo_urls_historic = self.seo_urls_historic.write().unwrap();
-
-			// Make sure the collections are empty
-			guard_posts.clear();
-			guard_post_excerpts.clear();
-			guard_seo_urls.clear();
-			guard_seo_urls_historic.clear();
-
-			for mut post in blog_posts {
-				// This is the main seo url for this post
-				guard_seo_urls.insert(post.url_canonical.to_lowercase(), post.id);
-
-				// Every post can have a number of historic seo urls
-				for post_seo_url in post.url_historic.as_slice() {
-					guard_seo_urls_historic.insert(post_seo_url.to_lowercase(), post.id);
-				}
+			// This is the main seo url for this post
+			seo_urls.insert(post.url_canonical.to_lowercase(), post.id);
 
-				// We will overwrite the content after we have replaced all snippets that we can find
-				let mut modified_content = post.content.clone();
+			// Every post can have a number of historic seo urls
+			for post_seo_url in post.url_historic.as_slice() {
+				seo_urls_historic.insert(post_seo_url.to_lowercase(), post.id);
+			}
 
-				// Replace any snippets inside the posts content
-				for cap in regex.captures_iter(&post.content) {
-					//println!("Matched key {:?}, tail: {:?}", &cap["key"], &cap["tail"]);
+			// Posts made of blocks are rendered from them
+			post.content = post.rendered_content();
 
-					// Do we have a snippet with that name?
-					// Could make this into a hash map...
-					for snippet in &snippets {
-						if snippet.name == &cap["key"] {
-							let replacement = snippet.get_replacement(&cap["tail"]);
+			// Replace any polls inside the posts content
+			let (content, poll_ids) = replace_shortcodes(&post.content, Some(&post), &Vec::new(), &polls);
+			post.content = content;
+			if poll_ids.len() > 0 {
+				post_2_polls.insert(post.id, poll_ids);
+			}
 
-							// Replace the occurrence in the posts content with the provided string
-							modified_content = modified_content.replace(&cap[0], &replacement);
-						}
-					}
-				}
+			// Push to posts map
+			posts.insert(post.id, Arc::new(post));
+		}
 
-				// Overwrite content
-				post.content = modified_content;
+		// Newest first, ties keep the newer id on top
+		posts_by_date.sort_by(|a, b| b.cmp(a));
+		let posts_by_date: Vec<u32> = posts_by_date.into_iter().map(|(_date, id)| id).collect();
+
+		// The index listing is the same, except that pinned posts come first
+		let mut posts_index = posts_by_date.clone();
+		posts_index.sort_by_key(|id| !posts_pinned.contains(id));
+
+		// Publish the new snapshot, the tag cloud counts the posts of its tags
+		self.resolver.store(seo_urls, seo_urls_historic);
+		self.snapshot.store(Arc::new(PostSnapshot {
+			posts,
+			tag_2_posts,
+			author_2_posts,
+			posts_by_date,
+			posts_index,
+			post_2_polls,
+			sitemap,
+		}));
+		self.rebuild_tag_cloud();
+
+		// The posts are expanded again when they are shown, recommendations are made again as well
+		match self.expanded_posts.write() {
+			Ok(mut guard) => { guard.clear(); }
+			_ => {}
+		}
+		match self.recommendations.write() {
+			Ok(mut guard) => { guard.clear(); }
+			_ => {}
+		}
 
-				// Push excerpt to post_excerpt map
-				guard_post_excerpts.insert(post.id, post.get_excerpt());
+		Ok(post_count)
+	}
 
-				// Push to posts map
-				guard_posts.insert(post.id, post);
-			}
+	/// Load all snippets from SQL
+	///
+	/// Only the pages of posts using a snippet that changed are dropped from the cache
+	fn reload_snippets(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
+		let snippets = snippet::load_snippets_from_sql(db).ok_or_else(|| io::Error::new(io::ErrorKind::Other, "could not load snippets"))?;
+		let snippet_count = snippets.len();
+
+		// Added, removed and modified snippets, by name
+		let old_snippets = self.snippets.load_full();
+		let mut changed: Vec<&String> = Vec::new();
+		for snippet in snippets.iter() {
+			if !old_snippets.iter().any(|old| old == snippet) { changed.push(&snippet.name); }
+		}
+		for old in old_snippets.iter() {
+			if !snippets.iter().any(|snippet| snippet.name == old.name) { changed.push(&old.name); }
 		}
 
-		Ok(post_count)
+		let keys: Vec<String> = match changed.len() {
+			0 => Vec::new(),
+			_ => self.snapshot.load().posts.values()
+				.filter(|post| shortcode_keys(&post.content).iter().any(|key| changed.contains(&key)))
+				.map(|post| format!("post_{}", post.id))
+				.collect()
+		};
+		self.snippets.store(Arc::new(snippets));
+		self.invalidate_html(&keys);
+
+		Ok(snippet_count)
 	}
 
-	/// This function will create the sitemap for our blog
-	fn reload_sitemap(&self, posts: &Vec<Post>) {
+	/// Create the sitemap for our blog, along with the lookup of the posts by tag
+	fn build_sitemap(&self, posts: &Vec<Post>) -> (SiteMap, HashMap<String, Vec<u32>>) {
 		let base_url = format!("https://{}/", config_get_string("fqdn"));
 		let mut locs = Vec::new();
-		let mut guard_tag_2_posts = self.tag_2_posts.write().unwrap();
+		let mut tag_2_posts: HashMap<String, Vec<u32>> = HashMap::new();
+		let mut posts_pinned: Vec<u32> = Vec::new();
+		let mut newest_modified = 0u64;
 
-		// Clear out data
-		guard_tag_2_posts.clear();
+		// How often each kind of page changes and how it ranks, see `sitemap_settings`
+		let (post_changefreq, post_priority) = sitemap_settings("posts", "0.9");
+		let (page_changefreq, page_priority) = sitemap_settings("pages", "1.0");
+		let (tag_changefreq, tag_priority) = sitemap_settings("tags", "0.5");
+		let (archive_changefreq, archive_priority) = sitemap_settings("archives", "0.5");
 
 		// Gather all post locations
 		for post in posts {
+			newest_modified = newest_modified.max(post.date_modified);
+			if post.pinned { posts_pinned.push(post.id); }
+
 			// Gather pictures for this post
 			let mut img_locs = Vec::new();
 			for image in &post.media {
-				if !image.source.contains("nomadicdays.org") { continue; }
+				if !image.source.contains("nomadicdays.org") || image.class == "video" { continue; }
 				img_locs.push({
 					SiteMapImage {
 						loc: image.source.clone(),
@@ -219,27 +415,30 @@ impl Blog {
 				});
 			}
 
-			// Create the post location including all it's images
-			locs.push(SiteMapUrl {
-				loc: format!("{}{}", base_url, post.url_canonical),
-				lastmod: post.date_modified,
-				changefreq: None,
-				priority: Some(String::from("0.9")),
-				images: {
-					if img_locs.len() > 0 { Some(img_locs) } else { None }
-				},
-			});
+			// Create the post location including all it's images, syndicated posts leave that to the original
+			if !post.is_syndicated() {
+				locs.push(SiteMapUrl {
+					loc: format!("{}{}", base_url, post.url_canonical),
+					lastmod: post.date_modified,
+					lastmod_w3c: format_w3c(post.date_modified),
+					changefreq: post_changefreq.clone(),
+					priority: post_priority.clone(),
+					images: {
+						if img_locs.len() > 0 { Some(img_locs) } else { None }
+					},
+				});
+			}
 
 			// For every tag this post has, store the post_id in a lookup map
 			for tag in &post.tags {
 				// Since this might be shared as an URL somewhere, it is better to make sure there are no spaces in those tags
 				let tag_encoded = tag.replace(" ", "-");
 
-				if let Some(vec) = guard_tag_2_posts.get_mut(&tag_encoded) {
+				if let Some(vec) = tag_2_posts.get_mut(&tag_encoded) {
 					vec.push(post.id);
 					continue;
 				}
-				guard_tag_2_posts.insert(tag_encoded, vec![post.id]);
+				tag_2_posts.insert(tag_encoded, vec![post.id]);
 			}
 		}
 
@@ -249,9 +448,29 @@ impl Blog {
 			_ => 0
 		};
 
-		// Compile all tags into the sitemap
+		// The start page and the further pages of the index change with the newest post
 		let per_page = config_get_i64("posts_per_page") as u32;
-		for (tag, posts) in guard_tag_2_posts.iter_mut() {
+		locs.push(SiteMapUrl {
+			loc: base_url.clone(),
+			lastmod: newest_modified,
+			lastmod_w3c: format_w3c(newest_modified),
+			changefreq: page_changefreq,
+			priority: page_priority,
+			images: None,
+		});
+		for page in 2..=(posts.len() as f32 / per_page as f32).ceil() as u32 {
+			locs.push(SiteMapUrl {
+				loc: format!("{}page/{}", base_url, page),
+				lastmod: newest_modified,
+				lastmod_w3c: format_w3c(newest_modified),
+				changefreq: archive_changefreq.clone(),
+				priority: archive_priority.clone(),
+				images: None,
+			});
+		}
+
+		// Compile all tags into the sitemap, their first page is the tag page, the rest is archive
+		for (tag, posts) in tag_2_posts.iter() {
 			let pages = (posts.len() as f32 / per_page as f32).ceil() as u32;
 			let mut page = 0u32;
 
@@ -263,132 +482,321 @@ impl Blog {
 						if page == 1 { format!("{}tag/{}", base_url, tag.clone()) } else { format!("{}tag/{}?p={}", base_url, tag.clone(), page) }
 					},
 					lastmod: time,
-					changefreq: None,
-					priority: Some(String::from("0.5")),
+					lastmod_w3c: format_w3c(time),
+					changefreq: if page == 1 { tag_changefreq.clone() } else { archive_changefreq.clone() },
+					priority: if page == 1 { tag_priority.clone() } else { archive_priority.clone() },
 					images: None,
 				});
 			}
 		}
 
-		// Compile the sitemap and cache it
-		self.cache.cache_sitemap(SiteMap { content: Some(locs) });
+		// Pinned posts go on top of their tag pages, the sort is stable so the rest keeps its order
+		for (_tag, posts) in tag_2_posts.iter_mut() {
+			posts.sort_by_key(|id| !posts_pinned.contains(id));
+		}
+
+		(SiteMap { content: Some(locs) }, tag_2_posts)
 	}
 
 	/// Load all menus from SQL
-	fn reload_menus(&self, db: &mysql::Pool) -> Result<usize, io::Error> {
+	fn reload_menus(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
 		let menus = match menu::load_menus_from_sql(db) {
 			Some(tmp) => { tmp }
 			_ => { return Ok(0); }
 		};
 		let menu_count = menus.len();
 
-		// CRITICAL SECTION: Load blog menus
-		{
-			let mut guard_menus = self.menus.write().unwrap();
-
-			// Make sure the collections are empty
-			guard_menus.clear();
-
-			for menu in menus {
-				guard_menus.insert(menu.name, menu.items);
-			}
+		// Build the new lookup and swap it in
+		let mut menu_map = HashMap::with_capacity(menu_count);
+		for menu in menus {
+			menu_map.insert(menu.name, menu.items);
 		}
+		self.menus.store(Arc::new(menu_map));
 
 		Ok(menu_count)
 	}
 
 	/// Load all menus from SQL
-	fn reload_redirects(&self, db: &mysql::Pool) -> Result<usize, io::Error> {
+	fn reload_redirects(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
 		let redirects = match redirect::load_redirects_from_sql(db) {
 			Some(tmp) => { tmp }
 			_ => { return Ok(0); }
 		};
 		let redirect_count = redirects.len();
 
-		// CRITICAL SECTION: Load blog redirects
-		{
-			let mut guard_redirects = self.redirects.write().unwrap();
-
-			// Make sure the collections are empty
-			guard_redirects.clear();
-
-			for redirect in redirects {
-				guard_redirects.insert(redirect.name, redirect.target);
+		// Build the new lookups and swap them in
+		let mut redirect_map = HashMap::with_capacity(redirect_count);
+		let mut redirect_rules = Vec::new();
+		for redirect in redirects {
+			match redirect.kind.as_str() {
+				"name" => { redirect_map.insert(redirect.name.clone(), redirect); }
+				_ => { redirect_rules.push(redirect); }
 			}
 		}
 
+		// Exact paths first, then the longest prefix, wildcards last in the order they were made
+		let rank = |redirect: &Redirect| match redirect.kind.as_str() {
+			"path" => 0,
+			"prefix" => 1,
+			_ => 2
+		};
+		redirect_rules.sort_by(|a, b| rank(a).cmp(&rank(b))
+			.then_with(|| match a.kind.as_str() {
+				"prefix" => b.name.len().cmp(&a.name.len()),
+				_ => a.id.cmp(&b.id)
+			}));
+
+		self.redirects.store(Arc::new(redirect_map));
+		self.redirect_rules.store(Arc::new(redirect_rules));
+
 		Ok(redirect_count)
 	}
 
 	/// Load all tags from SQL
-	fn reload_tags(&self, db: &mysql::Pool) -> Result<usize, io::Error> {
+	fn reload_tags(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
 		let tags = match tag::load_tags_from_sql(db) {
 			Ok(tmp) => { tmp }
 			_ => { return Ok(0); }
 		};
 		let tag_count = tags.len();
 
-		// CRITICAL SECTION: Load blog tags
-		{
-			let mut guard_tags = self.tags.write().unwrap();
+		// Tag pages may introduce their posts with snippets and polls, just like posts
+		let snippets = self.snippets.load_full();
+		let polls = self.polls.load_full();
+		let link_rules = LinkRules::from_config();
+
+		// Build the new lookup and swap it in
+		let mut tag_map = HashMap::with_capacity(tag_count);
+		for mut tag in tags {
+			tag.content = mark_conditions(&link_rules.rewrite(&replace_shortcodes(&tag.content, None, &snippets, &polls).0));
+			tag_map.insert(tag.id.clone(), tag);
+		}
+		self.tags.store(Arc::new(tag_map));
+
+		// Tag titles are part of the tag cloud
+		self.rebuild_tag_cloud();
+
+		Ok(tag_count)
+	}
+
+	/// Compute the tag cloud from the tag lookup and the tag titles
+	fn rebuild_tag_cloud(&self) {
+		let snapshot = self.snapshot.load();
+		let tag_2_posts = &snapshot.tag_2_posts;
+		let tags = self.tags.load();
+
+		// The range of post counts decides the weight buckets
+		let min_count = tag_2_posts.values().map(|posts| posts.len()).min().unwrap_or(0);
+		let max_count = tag_2_posts.values().map(|posts| posts.len()).max().unwrap_or(0);
+
+		let mut cloud = Vec::with_capacity(tag_2_posts.len());
+		for (tag_id, posts) in tag_2_posts.iter() {
+			let weight = if max_count > min_count {
+				1 + ((posts.len() - min_count) * 4 / (max_count - min_count)) as u8
+			} else {
+				3
+			};
+
+			cloud.push(TagCloudEntry {
+				tag: tag_id.clone(),
+				title: match tags.get(tag_id) {
+					Some(tag) if tag.title.len() > 0 => { tag.title.clone() }
+					_ => { tag_id.replace("-", " ") }
+				},
+				post_count: posts.len() as u32,
+				weight,
+				feed_url: format!("https://{}/feed/tag/{}", config_get_string("fqdn"), tag_id),
+			});
+		}
+
+		// Alphabetical, as clouds usually are
+		cloud.sort_by(|a, b| a.tag.cmp(&b.tag));
+
+		self.tag_cloud.store(Arc::new(cloud));
+	}
+
+	/// Load the polls and count their votes
+	fn reload_polls(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
+		let polls = poll::load_polls_from_sql(db).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+		let poll_count = polls.len();
+
+		self.polls.store(Arc::new(polls.into_iter().map(|tmp| (tmp.id, Arc::new(tmp))).collect()));
+		self.refresh_poll_results(db);
+
+		Ok(poll_count)
+	}
 
-			// Make sure the collections are empty
-			guard_tags.clear();
+	/// Count the votes again, pages showing a poll whose results changed are dropped from the cache
+	fn refresh_poll_results(&self, db: &Arc<dyn Storage>) {
+		let votes = match poll::load_poll_votes_from_sql(db) {
+			Ok(tmp) => tmp,
+			Err(err) => {
+				println!("Could not count the poll votes: {}", err);
+				return;
+			}
+		};
 
-			for tag in tags {
-				guard_tags.insert(tag.id.clone(), tag);
+		let old_results = self.poll_results.load_full();
+		let mut results = HashMap::new();
+		let mut changed = Vec::new();
+
+		for (id, poll) in self.polls.load().iter() {
+			let result = poll.results(votes.get(id));
+			match old_results.get(id) {
+				Some(old) if old.total == result.total && old.options.len() == result.options.len() => {}
+				_ => { changed.push(*id); }
 			}
+			results.insert(*id, Arc::new(result));
 		}
+		self.poll_results.store(Arc::new(results));
 
-		Ok(tag_count)
+		let keys: Vec<String> = self.snapshot.load().post_2_polls.iter()
+			.filter(|(_, poll_ids)| poll_ids.iter().any(|id| changed.contains(id)))
+			.map(|(post_id, _)| format!("post_{}", post_id))
+			.collect();
+		self.invalidate_html(&keys);
+	}
+
+	/// Load the running experiments from SQL
+	///
+	/// List pages that show them are not dropped from the cache here, see `invalidate_html_cache`
+	pub fn reload_experiments(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
+		let experiments = experiment::load_experiments_from_sql(db).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+		let mut running = HashMap::new();
+		for experiment in experiments.into_iter().filter(|tmp| tmp.running) {
+			running.insert(experiment.post_id, Arc::new(experiment));
+		}
+		let experiment_count = running.len();
+		self.experiments.store(Arc::new(running));
+
+		Ok(experiment_count)
 	}
 
 	/// Load all comments from SQL
-	fn reload_comments(&self, db: &mysql::Pool) -> Result<usize, io::Error> {
+	fn reload_comments(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
 		let comments = match comment::load_comments_from_sql(db) {
 			Ok(tmp) => { tmp }
 			_ => { return Ok(0); }
 		};
 		let comment_count = comments.len();
 
-		// CRITICAL SECTION: Load blog comments
-		{
-			let mut guard_comments = self.comments.write().unwrap();
-
-			// Make sure the collections are empty
-			guard_comments.clear();
-
-			for comment in comments {
-				// Check if that post already has comments
-				match guard_comments.get_mut(&comment.post_id) {
-					Some(vec) => {
-						vec.push(comment);
-					}
-					_ => {
-						guard_comments.insert(comment.post_id, vec![comment]);
-					}
+		// Build the new lookups and swap them in
+		let mut comment_map: HashMap<u32, Vec<Comment>> = HashMap::new();
+		let mut commenters = HashSet::new();
+		for comment in comments {
+			let email = comment.author_email.trim().to_lowercase();
+			if email.len() > 0 { commenters.insert(email); }
+
+			// Check if that post already has comments
+			match comment_map.get_mut(&comment.post_id) {
+				Some(vec) => {
+					vec.push(comment);
+				}
+				_ => {
+					comment_map.insert(comment.post_id, vec![comment]);
 				}
 			}
 		}
+		self.comments.store(Arc::new(comment_map));
+		self.approved_commenters.store(Arc::new(commenters));
+
+		Ok(comment_count)
+	}
+
+	/// Load the comments again after one of them changed, only the pages of its post are dropped from the cache
+	fn refresh_post_comments(&self, db: &Arc<dyn Storage>, post_id: u32) -> Result<usize, io::Error> {
+		let comment_count = self.reload_comments(db)?;
+		self.invalidate_html(&vec![format!("post_{}", post_id)]);
 
 		Ok(comment_count)
 	}
 
+	/// Load the approved mentions from SQL
+	fn reload_mentions(&self, db: &Arc<dyn Storage>) -> Result<usize, io::Error> {
+		let mentions = match mentions::load_approved_mentions(db) {
+			Ok(tmp) => { tmp }
+			_ => { return Ok(0); }
+		};
+		let mention_count = mentions.len();
+
+		let mut mention_map: HashMap<u32, Vec<Mention>> = HashMap::new();
+		for mention in mentions {
+			mention_map.entry(mention.post_id).or_default().push(mention);
+		}
+		self.mentions.store(Arc::new(mention_map));
+
+		Ok(mention_count)
+	}
+
+	/// Whether a new comment goes online right away, for addresses with an approved comment if
+	/// `comments_approve_known` is set
+	///
+	/// Addresses are not verified, spam checks still apply
+	pub fn may_auto_approve_comment(&self, email: &str) -> bool {
+		config_get_bool("comments_approve_known") && self.approved_commenters.load().contains(&email.trim().to_lowercase())
+	}
+
+	/// Validate the currently loaded data and store the resulting report
+	///
+	/// Returns the number of issues found
+	fn check_data_consistency(&self) -> usize {
+		let snapshot = self.snapshot.load();
+		let issues = validate_blog_data(&snapshot.posts, &self.tags.load(), &snapshot.tag_2_posts);
+
+		// Log the report
+		if issues.len() > 0 {
+			println!("Data validation found {} issues:", issues.len());
+			for issue in &issues {
+				println!("  [{}] post {}: {}", issue.kind, issue.post_id, issue.detail);
+			}
+		}
+
+		let issue_count = issues.len();
+		self.data_issues.store(Arc::new(issues));
+
+		issue_count
+	}
+
+	/// Verify the gallery files against their stored hashes and keep the report
+	pub fn check_gallery_integrity(&self, db: &Arc<dyn Storage>) {
+		let issues = gallery::check_gallery_integrity(db);
+
+		// Log the report
+		if issues.len() > 0 {
+			println!("Gallery integrity check found {} issues:", issues.len());
+			for issue in &issues {
+				println!("  [{}] {}", issue.kind, issue.detail);
+			}
+
+			let missing = issues.iter().filter(|issue| issue.kind == "gallery_missing_file").count();
+			notifications::notify(db, "broken_link", "Gallery integrity check found issues",
+				&format!("{} issues, {} of them missing files", issues.len(), missing), "gallery_issues");
+		}
+
+		self.gallery_issues.store(Arc::new(issues));
+	}
+
+	/// Look for gallery files and rows nothing needs anymore and keep the report
+	pub fn find_gallery_orphans(&self, db: &Arc<dyn Storage>) -> Result<(), String> {
+		let orphans = gallery::find_gallery_orphans(db)?;
+
+		let count = orphans.files_without_row.len() + orphans.rows_without_file.len() + orphans.variants_without_original.len();
+		if count > 0 {
+			println!("Gallery has {} orphaned files and rows, {} images are unused", count, orphans.unused_images.len());
+		}
+
+		self.gallery_orphans.store(Arc::new(orphans));
+		Ok(())
+	}
+
 	// ------------------------------------------------------------------
 	// ------------------------ GETTER FUNCTIONS ------------------------
 	// ------------------------------------------------------------------
 
 	/// Retrieve a menu by its key
-	///
-	/// This function will `lock` (read)
 	fn get_menu(&self, key: &str) -> Option<Vec<menu::MenuItem>> {
-		// Assume we have no menus
-		let guard = match self.menus.read() {
-			Ok(tmp) => { tmp }
-			_ => { return None; }
-		};
-
-		match guard.get(key) {
+		match self.menus.load().get(key) {
 			Some(menu) => { Some((*menu).clone()) }
 			_ => { None }
 		}
@@ -396,26 +804,19 @@ impl Blog {
 
 	/// Retrieve a post by its key
 	///
-	/// This function will `lock` (read)
-	fn get_post(&self, key: u32) -> Option<Post> {
-		// Crash is intentional as we cannot operate a blog without access to posts
-		let guard = self.posts.read().unwrap();
-
-		match guard.get(&key) {
+	/// Only the reference is cloned, the post itself is shared with the snapshot
+	fn get_post(&self, key: u32) -> Option<Arc<Post>> {
+		match self.snapshot.load().posts.get(&key) {
 			Some(post) => { Some(post.clone()) }
 			_ => { None }
 		}
 	}
 
 	/// Retrieve post excerpts for a given tag
-	///
-	/// This function will `lock` (read)
-	fn get_post_excerpts_by_tag(&self, tag_id: &str, limit: u32) -> Vec<PostExcerpt> {
-		let guard_tag_2_posts = self.tag_2_posts.read().unwrap();
-
-		match guard_tag_2_posts.get(tag_id) {
+	fn get_post_excerpts_by_tag(&self, tag_id: &str, limit: u32) -> Vec<Arc<PostExcerpt>> {
+		match self.snapshot.load().tag_2_posts.get(tag_id) {
 			Some(tmp) => {
-				return self.get_post_excerpts(&self.get_pagination_slice(&tmp, 0, limit));
+				return self.get_post_excerpts(&self.get_pagination_slice(tmp, 0, limit));
 			}
 			_ => {}
 		}
@@ -424,19 +825,17 @@ impl Blog {
 	}
 
 	/// Retrieve post excerpts by their keys
-	///
-	/// This function will `lock` (read)
-	fn get_post_excerpts(&self, keys: &Vec<u32>) -> Vec<PostExcerpt> {
+	fn get_post_excerpts(&self, keys: &Vec<u32>) -> Vec<Arc<PostExcerpt>> {
 		// Create an empty vectors to hold requested excerpts
-		let mut excerpts = Vec::<PostExcerpt>::with_capacity(keys.len());
+		let mut excerpts = Vec::<Arc<PostExcerpt>>::with_capacity(keys.len());
 
-		// Crash is intentional as we cannot operate a blog without access to posts
-		let guard = self.post_excerpts.read().unwrap();
+		let snapshot = self.snapshot.load();
+		let posts = &snapshot.posts;
 
 		for key in keys {
-			match guard.get(&key) {
-				Some(post_excerpt) => {
-					excerpts.push(post_excerpt.clone());
+			match posts.get(key) {
+				Some(post) => {
+					excerpts.push(self.get_expanded_post(post).excerpt.clone());
 				}
 				_ => {}
 			}
@@ -445,66 +844,190 @@ impl Blog {
 		excerpts
 	}
 
-	/// Do a lookup to check if we have the blog post key for a given seo url string.
+	/// Retrieve post excerpts by their keys, as the experiment variant shows them
+	fn get_post_excerpts_in_variant(&self, keys: &Vec<u32>, variant: ExperimentVariant) -> Vec<Arc<PostExcerpt>> {
+		let experiments = self.experiments.load();
+
+		self.get_post_excerpts(keys).iter().map(|excerpt| match experiments.get(&excerpt.id) {
+			Some(experiment) => experiment.apply(excerpt, variant),
+			_ => excerpt.clone()
+		}).collect()
+	}
+
+	/// The variant a list page with these posts is shown in, plus the key it is cached under
 	///
-	/// This function will `lock` (read, read)
+	/// Pages without a running experiment are the same for everyone. Without a visitor, e.g. when
+	/// rendering again in the background, it is variant A
+	fn list_variant(&self, keys: &Vec<u32>, visitor: Option<ExperimentVariant>, cache_key: String) -> (ExperimentVariant, String) {
+		let experiments = self.experiments.load();
+		if !keys.iter().any(|key| experiments.contains_key(key)) {
+			return (ExperimentVariant::A, cache_key);
+		}
+
+		match visitor {
+			Some(ExperimentVariant::B) => (ExperimentVariant::B, format!("{}~b", cache_key)),
+			_ => (ExperimentVariant::A, cache_key)
+		}
+	}
+
+	/// The experiment running for a post, if any
+	pub fn get_running_experiment(&self, post_id: u32) -> Option<Arc<Experiment>> {
+		self.experiments.load().get(&post_id).cloned()
+	}
+
+	/// Whether any experiment is running, list pages must not be cached by the CDN then
+	pub fn has_running_experiments(&self) -> bool {
+		self.experiments.load().len() > 0
+	}
+
+	/// The post with its snippets expanded and the link rules applied, as it is shown
 	///
-	/// Should we find a key for the given url we will return the matching post using `get_post()`
-	fn get_post_by_seo_url(&self, seo_url: &str) -> u32 {
-		let mut post_key = 0;
+	/// Built on first use and kept until the post or the snippets are reloaded
+	fn get_expanded_post(&self, post: &Arc<Post>) -> Arc<ExpandedPost> {
+		let snippets = self.snippets.load_full();
 
-		// CRITICAL SECTION: Lookup the canonical seo url table
-		{
-			let seo_url_lower = seo_url.to_lowercase();
-			let guard_seo_urls = self.seo_urls.read().unwrap();
-			match guard_seo_urls.get(seo_url_lower.as_str()) {
-				Some(val) => { post_key = *val; }
+		match self.expanded_posts.read() {
+			Ok(guard) => match guard.get(&post.id) {
+				Some(tmp) if Arc::ptr_eq(&tmp.source, post) && Arc::ptr_eq(&tmp.snippets, &snippets) => { return tmp.clone(); }
 				_ => {}
-			}
+			},
+			_ => {}
 		}
 
-		// CRITICAL SECTION: Lookup the historical seo url table
-		if post_key == 0
-		{
-			let guard_seo_urls_historic = self.seo_urls_historic.read().unwrap();
-			match guard_seo_urls_historic.get(seo_url) {
-				Some(val) => { post_key = *val; }
+		// Polls were replaced when the posts were loaded
+		let mut expanded = (**post).clone();
+		let (content, _) = replace_shortcodes(&post.content, Some(post), &snippets, &HashMap::new());
+		expanded.content = mark_conditions(&LinkRules::from_config().rewrite(&content));
+
+		let expanded = Arc::new(ExpandedPost {
+			source: post.clone(),
+			snippets,
+			excerpt: Arc::new(expanded.get_excerpt()),
+			post: Arc::new(expanded),
+		});
+		match self.expanded_posts.write() {
+			Ok(mut guard) => { guard.insert(post.id, expanded.clone()); }
+			_ => {}
+		}
+
+		expanded
+	}
+
+	/// The posts recommended to readers of a post, see `recommend_posts`
+	///
+	/// Made on first use and kept until the posts or their views are reloaded
+	fn get_recommended_post_ids(&self, post: &Arc<Post>) -> Vec<u32> {
+		// Rolled out by post, the pages are cached for every reader
+		if !flag_enabled_for("recommendations", &post.id.to_string(), true) { return Vec::new(); }
+
+		match self.recommendations.read() {
+			Ok(guard) => match guard.get(&post.id) {
+				Some(tmp) => { return tmp.clone(); }
 				_ => {}
-			}
+			},
+			_ => {}
+		}
+
+		let limit = match config_get_i64("recommended_posts_count") {
+			tmp if tmp > 0 => tmp as usize,
+			_ => recommendations::RECOMMENDED_POSTS
+		};
+		let ids = recommendations::recommend_posts(post, &self.snapshot.load().posts, &self.post_popularity.load(), limit);
+		match self.recommendations.write() {
+			Ok(mut guard) => { guard.insert(post.id, ids.clone()); }
+			_ => {}
 		}
 
-		post_key
+		ids
 	}
 
-	/// Retrieve a `Tag` by its name
+	/// Do a lookup to check if we have the blog post key for a given seo url string.
 	///
-	/// This function will `lock` (read)
-	fn get_tag(&self, tag_id: &str) -> Option<Tag> {
-		// Crash is intentional as we cannot operate a blog without access to tags
-		let guard = self.tags.read().unwrap();
+	/// Should we find a key for the given url we will return the matching post using `get_post()`
+	fn get_post_by_seo_url(&self, seo_url: &str) -> u32 {
+		match self.resolver.resolve(seo_url) {
+			Some(tmp) => tmp.id(),
+			_ => 0
+		}
+	}
+
+	/// The post behind a SEO url and whether that is its current url, see `PostResolver`
+	pub fn resolve_post(&self, seo_url: &str) -> Option<PostMatch> {
+		self.resolver.resolve(seo_url)
+	}
 
-		match guard.get(tag_id) {
+	/// Retrieve a `Tag` by its name
+	fn get_tag(&self, tag_id: &str) -> Option<Tag> {
+		match self.tags.load().get(tag_id) {
 			Some(tag) => { Some(tag.clone()) }
 			_ => { None }
 		}
 	}
 
+	/// The breadcrumb of a tag page, tags without a title are named after their id
+	fn get_tag_breadcrumb(&self, tag_id: &str) -> Breadcrumb {
+		Breadcrumb {
+			title: match self.tags.load().get(tag_id) {
+				Some(tag) if tag.title.len() > 0 => { tag.title.clone() }
+				_ => { tag_id.replace("-", " ") }
+			},
+			url: format!("https://{}/tag/{}", config_get_string("fqdn"), tag_id),
+		}
+	}
+
+	/// Put the start page in front of the trail and add it to the context, also as JSON-LD for search engines
+	fn set_breadcrumbs(&self, context: &mut Context, trail: Vec<Breadcrumb>) {
+		let mut breadcrumbs = vec![Breadcrumb { title: config_get_string("title"), url: format!("https://{}/", config_get_string("fqdn")) }];
+		breadcrumbs.extend(trail);
+
+		let items: Vec<serde_json::Value> = breadcrumbs.iter().enumerate().map(|(index, crumb)| json!({
+			"@type": "ListItem",
+			"position": index + 1,
+			"name": &crumb.title,
+			"item": &crumb.url,
+		})).collect();
+		let json_ld = json!({
+			"@context": "https://schema.org",
+			"@type": "BreadcrumbList",
+			"itemListElement": items,
+		});
+
+		// Titles must not be able to close the script tag
+		context.breadcrumbs_json_ld = Some(json_ld.to_string().replace("</", "<\\/"));
+		context.breadcrumbs = Some(breadcrumbs);
+	}
+
 	/// Returns a list of all tags currently in use
 	pub fn get_all_in_use_tags(&self) -> Vec<String> {
-		let guard = self.tag_2_posts.read().unwrap();
-
 		let mut tmp = vec![];
-		for (tag, _posts) in guard.iter() {
+		for (tag, _posts) in self.snapshot.load().tag_2_posts.iter() {
 			tmp.push(tag.clone());
 		}
 
 		tmp
 	}
 
-	fn get_post_comments(&self, post_id: u32) -> Option<Vec<Comment>> {
-		let guard = self.comments.read().unwrap();
+	/// The results of all polls embedded in a post
+	fn get_post_polls(&self, post_id: u32) -> Option<Vec<Arc<PollResults>>> {
+		let poll_ids = self.snapshot.load().post_2_polls.get(&post_id)?.clone();
+		let results = self.poll_results.load();
+		Some(poll_ids.iter().filter_map(|id| results.get(id).cloned()).collect())
+	}
+
+	/// The current results of a poll
+	pub fn get_poll_results(&self, poll_id: u32) -> Option<Arc<PollResults>> {
+		self.poll_results.load().get(&poll_id).cloned()
+	}
+
+	/// The current results of all polls, newest first
+	pub fn get_all_poll_results(&self) -> Vec<Arc<PollResults>> {
+		let mut results: Vec<Arc<PollResults>> = self.poll_results.load().values().cloned().collect();
+		results.sort_by(|a, b| b.poll_id.cmp(&a.poll_id));
+		results
+	}
 
-		match guard.get(&post_id) {
+	fn get_post_comments(&self, post_id: u32) -> Option<Vec<Comment>> {
+		match self.comments.load().get(&post_id) {
 			Some(comments) => {
 				Some(comments.clone())
 			}
@@ -512,19 +1035,65 @@ impl Blog {
 		}
 	}
 
-	/// Do a lookup in our redirect table and find the correct target url
-	pub fn lookup_redirect(&self, name: &str) -> String {
-		match self.redirects.read() {
-			Ok(guard) => {
-				match guard.get(name) {
-					Some(val) => { return val.clone(); }
-					_ => {}
-				}
-			}
+	fn get_post_mentions(&self, post_id: u32) -> Option<Vec<Mention>> {
+		self.mentions.load().get(&post_id).cloned()
+	}
+
+	/// The post a full URL on our own domain points to, along with every URL it is known by
+	///
+	/// For pingbacks and trackbacks, the linking page may use an older URL of the post
+	pub fn get_post_by_url(&self, url: &str) -> Option<(u32, Vec<String>)> {
+		let fqdn = config_get_string("fqdn").to_lowercase();
+		let url = url.trim();
+		let rest = match url.find("://") {
+			Some(pos) if url[..pos].eq_ignore_ascii_case("https") || url[..pos].eq_ignore_ascii_case("http") => &url[pos + 3..],
+			_ => { return None; }
+		};
+
+		let (host, path) = match rest.find('/') {
+			Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+			_ => (rest, "")
+		};
+		let host = host.to_lowercase();
+		if host != fqdn && host.trim_start_matches("www.") != fqdn.trim_start_matches("www.") { return None; }
+
+		let seo_url = path.split(['?', '#']).next().unwrap_or("").trim_end_matches('/');
+		let post_id = self.get_post_by_seo_url(seo_url);
+		let post = self.snapshot.load().posts.get(&post_id).cloned()?;
+
+		let mut targets = vec![format!("https://{}/{}", fqdn, post.url_canonical)];
+		if !post.url_canonical.eq_ignore_ascii_case(seo_url) {
+			targets.push(format!("https://{}/{}", fqdn, seo_url));
+		}
+		Some((post_id, targets))
+	}
+
+	/// Returns the issues found by the last data validation
+	pub fn get_data_issues(&self) -> Vec<DataIssue> {
+		let mut issues = self.data_issues.load().to_vec();
+		issues.extend(self.gallery_issues.load().iter().cloned());
+		issues
+	}
+
+	/// The last report of orphaned gallery files
+	pub fn get_gallery_orphans(&self) -> Arc<gallery::GalleryOrphans> {
+		self.gallery_orphans.load_full()
+	}
+
+	/// Do a lookup in our redirect table and find the status and target url, unknown names go home
+	pub fn lookup_redirect(&self, name: &str) -> (u16, String) {
+		match self.redirects.load().get(name) {
+			Some(val) => { return (val.status, val.target.clone()); }
 			_ => {}
 		}
 
-		format!("https://{}", config_get_string("fqdn"))
+		(302, format!("https://{}", config_get_string("fqdn")))
+	}
+
+	/// The status and target of the first path redirect that matches, for paths that are no post or page
+	pub fn match_redirect_rule(&self, path: &str) -> Option<(u16, String)> {
+		self.redirect_rules.load().iter()
+			.find_map(|redirect| redirect.target_for(path).map(|target| (redirect.status, target)))
 	}
 
 	// ------------------------------------------------------------------
@@ -543,16 +1112,32 @@ impl Blog {
 			canonical: Some(format!("https://{}/", config_get_string("fqdn"))),
 			time: self.get_time_in_secs(),
 
+			// -- navigation --
+			breadcrumbs: None,
+			breadcrumbs_json_ld: None,
+
 			// -- social --
 			facebook_app_id: Some(config_get_string("facebook_app_id")),
 			facebook_user: Some(config_get_string("facebook_user")),
 			instagram_user: Some(config_get_string("instagram_user")),
 			twitter_user: Some(config_get_string("twitter_user")),
 			youtube_channel: Some(config_get_string("youtube_channel")),
+			og_image: None,
+
+			// -- template values that need no code changes (analytics ids, ad slots, ...) --
+			site_extras: Some(config_get_string_map("site_extras")),
+			flags: Some(template_flags()),
 
 			// -- menus --
 			main_menu: self.get_menu("main"),
 
+			// -- tags & feeds --
+			tag_cloud: Some(self.tag_cloud.load_full()),
+			feed_links: Some(vec![FeedLink {
+				title: config_get_string("title"),
+				url: format!("https://{}/feed", config_get_string("fqdn")),
+			}]),
+
 			// -- excerpts of posts with certain tags --
 			excerpts_tag_1: None,
 			excerpts_tag_2: None,
@@ -563,7 +1148,13 @@ impl Blog {
 			// -- site: POST --
 			post: None,
 			post_related: None,
+			post_recommended: None,
 			post_comments: None,
+			post_mentions: None,
+			post_polls: None,
+			post_category: None,
+			post_unavailable: None,
+			reader_country: None,
 
 			// -- site: INDEX --
 			instagram_posts: None,
@@ -571,13 +1162,23 @@ impl Blog {
 			latest_posts: None,
 			featured_posts: None,
 
-			// -- site: SEARCH & TAG (category) --
+			// -- site: RSS feed --
+			feed_items: None,
+			feed_full_content: false,
+			feed_last_build_date: 0,
+			feed_last_build_date_rfc2822: None,
+
+			// -- site: SEARCH & TAG (category) & AUTHOR --
 			tag: None,
 			tag_id: None,
+			author: None,
 			search_string: None,
 			post_list: None,
 			page_current: 0,
 			page_total: 0,
+
+			// -- site: STATS --
+			stats: None,
 		}
 	}
 
@@ -596,40 +1197,102 @@ impl Blog {
 			Some(html) => return Ok(html),
 			_ => {}
 		}
+		let started = Instant::now();
 
-		let mut context = self.create_base_context();
-
-		// Instagram posts
-		context.instagram_posts = self.cache.get_instagram_posts();
-
-		// Pinterest posts
-		context.pinterest_posts = self.cache.get_pinterest_posts();
-
-		// Latest & Featured posts
-		context.latest_posts = self.cache.get_latest_posts();
-		context.featured_posts = self.cache.get_featured_posts();
-
-		// Excerpts for up to 5 configurable tags
-		context.excerpts_tag_1 = self.cache.get_posts_by_tag(1);
-		context.excerpts_tag_2 = self.cache.get_posts_by_tag(2);
-		context.excerpts_tag_3 = self.cache.get_posts_by_tag(3);
-		context.excerpts_tag_4 = self.cache.get_posts_by_tag(4);
-		context.excerpts_tag_5 = self.cache.get_posts_by_tag(5);
+		let context = self.create_index_context();
 
 		// Render the template
 		match self.render_template(tera, template, &context) {
-			Ok(html) => {
+			Ok((html, expires_at)) => {
 				// Cache the HTML output
-				self.cache.cache_html(cache_key, html.clone());
-
-				Ok(html)
+				Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed()))
 			},
 			Err(err) => Err(err)
 		}
 	}
 
-	/// Get the HTML for a post. The HTML may be fetched from the cache.
-	pub fn get_html_post(&self, url: &str, remote_ip: String, user_agent: String, referer: String, tera: &web::Data<Arc<tera::Tera>>) -> Option<String> {
+	/// Get the HTML for a page of the chronological index. The HTML may be fetched from the cache.
+	///
+	/// `visitor` is the experiment variant of the reader, None when rendering in the background
+	pub fn get_html_index(&self, tera: &web::Data<Arc<tera::Tera>>, page: u32, visitor: Option<ExperimentVariant>) -> Result<String, String> {
+		// Slice the chronological post list
+		let per_page = config_get_i64("posts_per_page") as u32;
+		let keys = self.get_pagination_slice(&self.snapshot.load().posts_index, page, per_page);
+		self.message_experiments_shown(&keys, visitor);
+
+		// The identifier we will use to check for a cached version
+		let (variant, cache_key) = self.list_variant(&keys, visitor, format!("index_{}", page));
+
+		// Check if the HTML for this page is cached
+		match self.cache.get_html(&cache_key) {
+			Some(html) => return Ok(html),
+			_ => {}
+		}
+		let started = Instant::now();
+
+		let context = self.create_index_page_context(page, &keys, variant);
+
+		// Render the template
+		match self.render_template(tera, "index.html", &context) {
+			Ok((html, expires_at)) => {
+				// Cache the HTML output
+				Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed()))
+			},
+			Err(err) => Err(err)
+		}
+	}
+
+	/// The number of pages the chronological index has
+	pub fn get_index_page_total(&self) -> u32 {
+		let per_page = config_get_i64("posts_per_page") as u32;
+		(self.snapshot.load().posts_index.len() as f32 / per_page as f32).ceil() as u32
+	}
+
+	/// Create the context for a page of the chronological index, `keys` are the posts on it
+	fn create_index_page_context(&self, page: u32, keys: &Vec<u32>, variant: ExperimentVariant) -> Context {
+		let mut context = self.create_index_context();
+
+		context.page_current = page;
+		context.page_total = self.get_index_page_total();
+		context.post_list = Some(self.get_post_excerpts_in_variant(keys, variant));
+
+		// The first page is the start page
+		if page > 0 {
+			context.canonical = Some(format!("https://{}/page/{}", config_get_string("fqdn"), page + 1));
+		}
+
+		context
+	}
+
+	/// Create the context shared by the start page and other base pages
+	fn create_index_context(&self) -> Context {
+		let mut context = self.create_base_context();
+
+		// Instagram posts
+		context.instagram_posts = self.cache.get_instagram_posts();
+
+		// Pinterest posts
+		context.pinterest_posts = self.cache.get_pinterest_posts();
+
+		// Latest & Featured posts
+		context.latest_posts = self.cache.get_latest_posts();
+		context.featured_posts = self.cache.get_featured_posts();
+
+		// Excerpts for up to 5 configurable tags
+		context.excerpts_tag_1 = self.cache.get_posts_by_tag(1);
+		context.excerpts_tag_2 = self.cache.get_posts_by_tag(2);
+		context.excerpts_tag_3 = self.cache.get_posts_by_tag(3);
+		context.excerpts_tag_4 = self.cache.get_posts_by_tag(4);
+		context.excerpts_tag_5 = self.cache.get_posts_by_tag(5);
+
+		context
+	}
+
+	/// Get the HTML for a post. The HTML may be fetched from the cache.
+	///
+	/// The campaign holds the tracking parameters stripped from the URL, see `NormalizePath`, the country
+	/// of the reader comes from `get_client_country` and is empty if unknown
+	pub fn get_html_post(&self, url: &str, remote_ip: String, user_agent: String, referer: String, campaign: String, country: String, tera: &web::Data<Arc<tera::Tera>>) -> Option<Result<String, String>> {
 
 		// Lookup the SEO url
 		let post_key = self.get_post_by_seo_url(url);
@@ -640,59 +1303,258 @@ impl Blog {
 		// Check if the HTML for this post is cached
 		match self.cache.get_html(&cache_key) {
 			Some(html) => {
-				self.message_post_viewed(post_key, self.get_time_in_secs(), remote_ip, user_agent, referer);
-				return Some(html)
+				self.message_post_viewed(post_key, self.get_time_in_secs(), remote_ip, user_agent, referer, campaign, country);
+				return Some(Ok(html))
 			}
 			_ => {}
 		}
-
-		// Create context for template rendering
-		let mut context = self.create_base_context();
+		let started = Instant::now();
 
 		// Did we match a blog post for the SEO url?
-		if post_key > 0 {
-			context.post = self.get_post(post_key);
+		let post = match self.get_post(post_key) {
+			Some(tmp) if post_key > 0 => tmp,
+			_ => { return None; }
+		};
+
+		// Log the post view by sending a post view message over the queue
+		self.message_post_viewed(post.id, self.get_time_in_secs(), remote_ip, user_agent, referer, campaign, country);
+
+		// Render the template, special posts may use their own
+		let context = self.create_post_context(&post);
+		match self.render_template(tera, post.template_name(), &context) {
+			Ok((html, expires_at)) => {
+				// Cache the HTML output
+				Some(Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed())))
+			},
+			Err(err) => Some(Err(err))
 		}
+	}
 
-		// Set the canonical url and fetch related posts
-		match &context.post {
-			Some(tmp) => {
-				// Log the post view by sending a post view message over the queue
-				self.message_post_viewed(tmp.id, context.time, remote_ip, user_agent, referer);
+	/// Render a post by its id, bypassing the cache and without counting a view
+	///
+	/// Returns the template used and the HTML with its expiry, see `render_template`
+	pub fn render_post_uncached(&self, post_id: u32, tera: &web::Data<Arc<tera::Tera>>) -> Option<(String, Result<(String, Option<u64>), String>)> {
+		let post = self.get_post(post_id)?;
+		let context = self.create_post_context(&post);
+		Some((String::from(post.template_name()), self.render_template(tera, post.template_name(), &context)))
+	}
 
-				// Canonical URL
-				context.canonical = Some(format!("https://{}/{}", config_get_string("fqdn"), tmp.url_canonical));
+	/// Get the HTML for the lite version of a post, for slow connections. The HTML may be fetched from the cache.
+	///
+	/// Views of the lite version count like views of the post
+	pub fn get_html_post_lite(&self, url: &str, remote_ip: String, user_agent: String, referer: String, campaign: String, country: String, tera: &web::Data<Arc<tera::Tera>>) -> Option<Result<String, String>> {
+		let post_key = self.get_post_by_seo_url(url);
+		if post_key == 0 { return None; }
 
-				// Copy over meta title & meta description
-				context.meta_title = Some(tmp.meta_title.clone());
-				context.meta_description = Some(tmp.meta_description.clone());
+		// Cached apart from the full version
+		let cache_key = format!("lite_{}", post_key);
 
-				// Check if we have got related posts
-				if tmp.related_posts.len() > 0
-				{
-					context.post_related = Some(self.get_post_excerpts(&tmp.related_posts));
-				}
+		match self.cache.get_html(&cache_key) {
+			Some(html) => {
+				self.message_post_viewed(post_key, self.get_time_in_secs(), remote_ip, user_agent, referer, campaign, country);
+				return Some(Ok(html));
+			}
+			_ => {}
+		}
+		let started = Instant::now();
+
+		let result = self.render_post_lite_uncached(post_key, tera)?;
+		self.message_post_viewed(post_key, self.get_time_in_secs(), remote_ip, user_agent, referer, campaign, country);
 
-				// Check if we have got comments for this post
-				context.post_comments = self.get_post_comments(tmp.id);
+		Some(result.map(|(html, expires_at)| self.cache.cache_html(cache_key, html, expires_at, started.elapsed())))
+	}
+
+	/// Render the lite version of a post, from the same content with scripts, embeds and large images stripped
+	fn render_post_lite_uncached(&self, post_id: u32, tera: &web::Data<Arc<tera::Tera>>) -> Option<Result<(String, Option<u64>), String>> {
+		let post = self.get_post(post_id)?;
+		let context = self.create_post_lite_context(&post);
+
+		Some(self.render_template(tera, "post_lite.html", &context))
+	}
+
+	/// Create the context for the lite version of a post
+	fn create_post_lite_context(&self, post: &Arc<Post>) -> Context {
+		let mut context = self.create_post_context(post);
+
+		// The context holds the post with its snippets expanded
+		let mut lite = match &context.post {
+			Some(tmp) => (**tmp).clone(),
+			_ => (**post).clone()
+		};
+		lite.content = lite::lite_html(&lite.content);
+		context.post = Some(Arc::new(lite));
+
+		context
+	}
+
+	/// Get the HTML for the printer-friendly version of a post. The HTML may be fetched from the cache.
+	///
+	/// Views of the print version count like views of the post
+	pub fn get_html_post_print(&self, url: &str, remote_ip: String, user_agent: String, referer: String, campaign: String, country: String, tera: &web::Data<Arc<tera::Tera>>) -> Option<Result<String, String>> {
+		let post_key = self.get_post_by_seo_url(url);
+		if post_key == 0 { return None; }
+
+		// Cached apart from the full version
+		let cache_key = format!("print_{}", post_key);
+
+		match self.cache.get_html(&cache_key) {
+			Some(html) => {
+				self.message_post_viewed(post_key, self.get_time_in_secs(), remote_ip, user_agent, referer, campaign, country);
+				return Some(Ok(html));
 			}
-			_ => { return None; }
+			_ => {}
+		}
+		let started = Instant::now();
+
+		let result = self.render_post_print_uncached(post_key, tera)?;
+		self.message_post_viewed(post_key, self.get_time_in_secs(), remote_ip, user_agent, referer, campaign, country);
+
+		Some(result.map(|(html, expires_at)| self.cache.cache_html(cache_key, html, expires_at, started.elapsed())))
+	}
+
+	/// Render the print version of a post with `post_print.html`, without menus, with full size images and links as footnotes
+	fn render_post_print_uncached(&self, post_id: u32, tera: &web::Data<Arc<tera::Tera>>) -> Option<Result<(String, Option<u64>), String>> {
+		let post = self.get_post(post_id)?;
+		let context = self.create_post_print_context(&post);
+
+		Some(self.render_template(tera, "post_print.html", &context))
+	}
+
+	/// Create the context for the print version of a post
+	fn create_post_print_context(&self, post: &Arc<Post>) -> Context {
+		let mut context = self.create_post_context(post);
+		context.main_menu = None;
+
+		// The context holds the post with its snippets expanded
+		let mut print = match &context.post {
+			Some(tmp) => (**tmp).clone(),
+			_ => (**post).clone()
+		};
+		print.content = print::print_html(&print.content);
+		context.post = Some(Arc::new(print));
+
+		context
+	}
+
+	/// Render a post for export with `post_export.html`, it is never cached
+	///
+	/// The post comes from the database, drafts are not loaded into the blog
+	pub fn render_post_export(&self, mut post: Post, tera: &web::Data<Arc<tera::Tera>>) -> Result<String, String> {
+		// Expanded like `get_expanded_post`, drafts have their polls in place as well
+		post.content = post.rendered_content();
+		let (content, _) = replace_shortcodes(&post.content, Some(&post), &self.snippets.load(), &self.polls.load());
+		post.content = mark_conditions(&LinkRules::from_config().rewrite(&content));
+
+		let mut context = self.create_base_context();
+		context.canonical = Some(format!("https://{}/{}", config_get_string("fqdn"), post.url_canonical));
+		context.meta_title = Some(post.meta_title.clone());
+		context.meta_description = Some(post.meta_description.clone());
+		context.post = Some(Arc::new(post));
+
+		self.render_template(tera, "post_export.html", &context).map(|(html, _)| html)
+	}
+
+	/// Whether the post behind a SEO url is only shown in some countries
+	pub fn is_post_region_restricted(&self, url: &str) -> bool {
+		match self.get_post(self.get_post_by_seo_url(url)) {
+			Some(post) => post.is_region_restricted(),
+			_ => false
+		}
+	}
+
+	/// Get the HTML explaining that a post is not shown in the country of the reader
+	///
+	/// Returns None if the post can be shown. Only a few readers see this, so it is not cached
+	pub fn get_html_post_unavailable(&self, url: &str, country: Option<&str>, tera: &web::Data<Arc<tera::Tera>>) -> Option<Result<String, String>> {
+		let post = self.get_post(self.get_post_by_seo_url(url))?;
+		if post.is_available_in(country) { return None; }
+
+		let mut context = self.create_base_context();
+		context.post_unavailable = Some(self.get_expanded_post(&post).excerpt.clone());
+		context.reader_country = country.map(String::from);
+		context.canonical = Some(post.canonical_url());
+
+		Some(self.render_template(tera, "region_unavailable.html", &context).map(|(html, _)| html))
+	}
+
+	/// The context a single post is rendered with
+	fn create_post_context(&self, post: &Arc<Post>) -> Context {
+		let mut context = self.create_base_context();
+		context.post = Some(self.get_expanded_post(post).post.clone());
+
+		// Canonical URL, the original for syndicated posts
+		context.canonical = Some(post.canonical_url());
+
+		// Home > Tag > Post, through the primary tag of the post
+		context.post_category = post.primary_tag().map(|tag_id| self.get_tag_breadcrumb(tag_id));
+		let mut trail = Vec::new();
+		match &context.post_category {
+			Some(category) => { trail.push(category.clone()); }
+			_ => {}
+		}
+		trail.push(Breadcrumb { title: post.title.clone(), url: format!("https://{}/{}", config_get_string("fqdn"), post.url_canonical) });
+		self.set_breadcrumbs(&mut context, trail);
+
+		// Copy over meta title & meta description
+		context.meta_title = Some(post.meta_title.clone());
+		context.meta_description = Some(post.meta_description.clone());
+
+		// The share card, it is only made once a social network asks for it
+		context.og_image = og_image::og_image_url(post);
+
+		// Check if we have got related posts
+		if post.related_posts.len() > 0
+		{
+			context.post_related = Some(self.get_post_excerpts(&post.related_posts));
+		}
+
+		// Picked by tags and popularity, in addition to the curated ones
+		let recommended = self.get_recommended_post_ids(post);
+		if recommended.len() > 0 {
+			context.post_recommended = Some(self.get_post_excerpts(&recommended));
+		}
+
+		// Check if we have got comments for this post
+		context.post_comments = self.get_post_comments(post.id);
+
+		// Other sites that link to this post
+		context.post_mentions = self.get_post_mentions(post.id);
+
+		// The current results of the polls in this post
+		context.post_polls = self.get_post_polls(post.id);
+
+		context
+	}
+
+	/// Get the HTML for the public stats page. The HTML may be fetched from the cache.
+	pub fn get_html_stats(&self, db: &Arc<dyn Storage>, tera: &web::Data<Arc<tera::Tera>>) -> Result<String, String> {
+		// The identifier we will use to check for a cached version
+		let cache_key = String::from("stats");
+
+		// Check if the HTML for the stats is cached, the numbers may be as old as the cache
+		match self.cache.get_html(&cache_key) {
+			Some(html) => return Ok(html),
+			_ => {}
 		}
+		let started = Instant::now();
+
+		let mut context = self.create_base_context();
+		context.stats = Some(stats::stats_get_public(db, self));
+		context.canonical = Some(format!("https://{}/stats", config_get_string("fqdn")));
 
 		// Render the template
-		match self.render_template(tera, "post.html", &context) {
-			Ok(html) => {
+		match self.render_template(tera, "stats.html", &context) {
+			Ok((html, expires_at)) => {
 				// Cache the HTML output
-				self.cache.cache_html(cache_key, html.clone());
-
-				Some(html)
+				Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed()))
 			},
-			Err(err) => Some(err)
+			Err(err) => Err(err)
 		}
 	}
 
 	/// Get the HTML for a search. This is not yet cached.
-	pub fn get_html_search(&self, db: &mysql::Pool, tera: &web::Data<Arc<tera::Tera>>, search_string: String, page: u32) -> Result<String, String> {
+	pub fn get_html_search(&self, db: &Arc<dyn Storage>, tera: &web::Data<Arc<tera::Tera>>, search_string: String, page: u32) -> Result<String, String> {
+		let started = Instant::now();
 		let mut context = self.create_base_context();
 
 		match crate::blog::post::fetch_posts_by_search_string(db, &search_string) {
@@ -710,31 +1572,40 @@ impl Blog {
 		//TODO: may need URL encode for search string?? Tera template may do something to it
 
 		// Render the template
-		self.render_template(tera, "post_list.html", &context)
+		let html = self.render_template(tera, "post_list.html", &context).map(|(html, _)| html);
+		self.cache.count_html_render("search", started.elapsed());
+		html
 	}
 
 	/// Get the HTML for a tag page. The HTML may be fetched from the cache.
-	pub fn get_html_tag(&self, _db: &mysql::Pool, tera: &web::Data<Arc<tera::Tera>>, tag_id: String, page: u32) -> Result<String, String> {
+	///
+	/// `visitor` is the experiment variant of the reader, None when rendering in the background
+	pub fn get_html_tag(&self, _db: &Arc<dyn Storage>, tera: &web::Data<Arc<tera::Tera>>, tag_id: String, page: u32, visitor: Option<ExperimentVariant>) -> Result<String, String> {
+		let per_page = config_get_i64("posts_per_page") as u32;
+		let snapshot = self.snapshot.load();
+		let keys = match snapshot.tag_2_posts.get(&tag_id) {
+			Some(tmp) => self.get_pagination_slice(tmp, page, per_page),
+			_ => Vec::new()
+		};
+		self.message_experiments_shown(&keys, visitor);
 
 		// The identifier we will use to check for a cached version
-		let cache_key = format!("tag_{}_{}", tag_id, page);
+		let (variant, cache_key) = self.list_variant(&keys, visitor, format!("tag_{}_{}", tag_id, page));
 
 		// Check if the HTML for this tag is cached
 		match self.cache.get_html(&cache_key) {
 			Some(html) => return Ok(html),
 			_ => {}
 		}
+		let started = Instant::now();
 
 		let mut context = self.create_base_context();
 
-		let guard_tag_2_posts = self.tag_2_posts.read().unwrap();
-
-		match guard_tag_2_posts.get(&tag_id) {
+		match snapshot.tag_2_posts.get(&tag_id) {
 			Some(tmp) => {
-				let per_page = config_get_i64("posts_per_page") as u32;
 				context.page_current = page;
 				context.page_total = (tmp.len() as f32 / per_page as f32).ceil() as u32;
-				context.post_list = Some(self.get_post_excerpts(&self.get_pagination_slice(&tmp, page, per_page)));
+				context.post_list = Some(self.get_post_excerpts_in_variant(&keys, variant));
 			}
 			_ => {}
 		}
@@ -743,6 +1614,20 @@ impl Blog {
 		let page_param = if page > 0 { format!("?p={}", page + 1) } else { String::from("") };
 		context.canonical = Some(format!("https://{}/tag/{}{}", config_get_string("fqdn"), tag_id, page_param));
 
+		// Home > Tag
+		self.set_breadcrumbs(&mut context, vec![self.get_tag_breadcrumb(&tag_id)]);
+
+		// Let feed readers discover the feed for this tag
+		match &mut context.feed_links {
+			Some(links) => {
+				links.push(FeedLink {
+					title: format!("{} - {}", config_get_string("title"), tag_id.replace("-", " ")),
+					url: format!("https://{}/feed/tag/{}", config_get_string("fqdn"), tag_id),
+				});
+			}
+			_ => {}
+		}
+
 		// If we have got some more data for this tag, use it to set custom meta title and description
 		match &context.tag {
 			Some(tag) => {
@@ -758,33 +1643,74 @@ impl Blog {
 
 		// Render the template
 		match self.render_template(tera, "post_list.html", &context) {
-			Ok(html) => {
+			Ok((html, expires_at)) => {
 				// Cache the HTML output
-				self.cache.cache_html(cache_key, html.clone());
-
-				Ok(html)
+				Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed()))
 			},
 			Err(err) => Err(err)
 		}
 	}
 
+	/// Get the HTML for the archive of an author, co-authored posts included. The HTML may be fetched from the cache.
+	///
+	/// `visitor` is the experiment variant of the reader, None when rendering in the background
+	pub fn get_html_author(&self, tera: &web::Data<Arc<tera::Tera>>, author_id: u32, page: u32, visitor: Option<ExperimentVariant>) -> Option<Result<String, String>> {
+		let snapshot = self.snapshot.load();
+		let post_ids = snapshot.author_2_posts.get(&author_id)?;
+
+		let per_page = config_get_i64("posts_per_page") as u32;
+		let keys = self.get_pagination_slice(post_ids, page, per_page);
+		self.message_experiments_shown(&keys, visitor);
+
+		// The identifier we will use to check for a cached version
+		let (variant, cache_key) = self.list_variant(&keys, visitor, format!("author_{}_{}", author_id, page));
+
+		// Check if the HTML for this author is cached
+		match self.cache.get_html(&cache_key) {
+			Some(html) => return Some(Ok(html)),
+			_ => {}
+		}
+		let started = Instant::now();
+
+		let mut context = self.create_base_context();
+
+		context.page_current = page;
+		context.page_total = (post_ids.len() as f32 / per_page as f32).ceil() as u32;
+		context.post_list = Some(self.get_post_excerpts_in_variant(&keys, variant));
+
+		// Take the author details from any of the posts
+		context.author = self.get_post(post_ids[0]).and_then(|post| post.authors.iter().find(|author| author.id == author_id).cloned());
+		let page_param = if page > 0 { format!("?p={}", page + 1) } else { String::from("") };
+		context.canonical = Some(format!("https://{}/author/{}{}", config_get_string("fqdn"), author_id, page_param));
+
+		// Render the template
+		match self.render_template(tera, "post_list.html", &context) {
+			Ok((html, expires_at)) => {
+				// Cache the HTML output
+				Some(Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed())))
+			},
+			Err(err) => Some(Err(err))
+		}
+	}
+
 	/// Get the HTML for the site map. The HTML may be fetched from the cache.
 	pub fn get_html_site_map(&self, tera: &web::Data<Arc<tera::Tera>>) -> Result<String, String> {
 
 		// The identifier we will use to check for a cached version
-		let cache_key = format!("site_map");
+		let cache_key = "site_map".to_string();
 
 		// Check if the HTML for this tag is cached
 		match self.cache.get_html(&cache_key) {
 			Some(html) => return Ok(html),
 			_ => {}
 		}
+		let started = Instant::now();
 
 		// Serialize context for tera
-		let tera_context = match tera::Context::from_serialize(self.cache.get_site_map()).map_err(|_| error::ErrorInternalServerError("Template context error")) {
+		let tera_context = match tera::Context::from_serialize(&self.snapshot.load().sitemap).map_err(|_| error::ErrorInternalServerError("Template context error")) {
 			Ok(tmp) => tmp,
 			Err(err) => {
-				return Err(format!("Template context error: {}", err.to_string()));
+				return Err(format!("Template context error: {}", err));
 			}
 		};
 
@@ -792,42 +1718,160 @@ impl Blog {
 		match tera.render("sitemap.xml", &tera_context) {
 			Ok(html) => {
 				// Cache the HTML output
-				self.cache.cache_html(cache_key, html.clone());
-
-				Ok(html)
+				Ok(self.cache.cache_html(cache_key, html, None, started.elapsed()))
 			},
-			Err(err) => Err(format!("Template render error: {}", err.to_string()))
+			Err(err) => Err(format!("Template render error: {}", err))
 		}
 	}
 
+	/// Get the site map for one country, without the posts that are not shown there. This is not cached.
+	pub fn get_html_site_map_region(&self, tera: &web::Data<Arc<tera::Tera>>, country: &str) -> Result<String, String> {
+		let base_url = format!("https://{}/", config_get_string("fqdn"));
+		let snapshot = self.snapshot.load();
+		let hidden: Vec<String> = snapshot.posts.values()
+			.filter(|post| !post.is_available_in(Some(country)))
+			.map(|post| format!("{}{}", base_url, post.url_canonical))
+			.collect();
+
+		let mut site_map = snapshot.sitemap.clone();
+		match &mut site_map.content {
+			Some(locs) => { locs.retain(|loc| !hidden.contains(&loc.loc)); }
+			_ => {}
+		}
+
+		// Serialize context for tera
+		let tera_context = match tera::Context::from_serialize(site_map).map_err(|_| error::ErrorInternalServerError("Template context error")) {
+			Ok(tmp) => tmp,
+			Err(err) => {
+				return Err(format!("Template context error: {}", err));
+			}
+		};
+
+		tera.render("sitemap.xml", &tera_context).map_err(|err| format!("Template render error: {}", err))
+	}
+
+	/// Get the news site map of the posts published within `NEWS_MAX_AGE`, None if it is not configured. This is not cached.
+	pub fn get_news_site_map(&self) -> Option<String> {
+		if !news_site_map_enabled() { return None; }
+
+		let base_url = format!("https://{}/", config_get_string("fqdn"));
+		let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+			Ok(tmp) => tmp.as_secs(),
+			_ => 0
+		};
+
+		// Newest first, scheduled posts are not news yet
+		let snapshot = self.snapshot.load();
+		let urls: Vec<NewsSiteMapUrl> = snapshot.posts_by_date.iter()
+			.filter_map(|id| snapshot.posts.get(id))
+			.filter(|post| post.date_posted <= now)
+			.take_while(|post| post.date_posted + NEWS_MAX_AGE >= now)
+			.filter(|post| !post.is_syndicated())
+			.map(|post| NewsSiteMapUrl {
+				loc: format!("{}{}", base_url, post.url_canonical),
+				title: post.title.clone(),
+				date_posted: post.date_posted,
+			})
+			.collect();
+
+		Some(news_site_map(&urls))
+	}
+
 	/// Get the HTML for the rss feed. The HTML may be fetched from the cache.
 	pub fn get_html_rss_feed(&self, tera: &web::Data<Arc<tera::Tera>>) -> Result<String, String> {
 
 		// The identifier we will use to check for a cached version
-		let cache_key = format!("rss_feed");
+		let cache_key = "rss_feed".to_string();
 
 		// Check if the HTML for this tag is cached
 		match self.cache.get_html(&cache_key) {
 			Some(html) => return Ok(html),
 			_ => {}
 		}
+		let started = Instant::now();
 
 		// Setup context for the RSS feed
 		let mut context = self.create_base_context();
 		context.latest_posts = self.cache.get_latest_posts();
 
+		let excerpts = self.get_post_excerpts(&self.get_latest_post_ids(self.get_feed_length()));
+
+		self.render_rss_feed(tera, cache_key, started, context, excerpts)
+	}
+
+	/// Get the HTML for the rss feed of a single tag. The HTML may be fetched from the cache.
+	pub fn get_html_rss_feed_tag(&self, tera: &web::Data<Arc<tera::Tera>>, tag_id: String) -> Result<String, String> {
+
+		// The identifier we will use to check for a cached version
+		let cache_key = format!("rss_feed_tag_{}", tag_id);
+
+		// Check if the HTML for this tag is cached
+		match self.cache.get_html(&cache_key) {
+			Some(html) => return Ok(html),
+			_ => {}
+		}
+		let started = Instant::now();
+
+		// Setup context for the RSS feed
+		let mut context = self.create_base_context();
+		context.tag = self.get_tag(&tag_id);
+		context.tag_id = Some(tag_id.clone());
+		context.canonical = Some(format!("https://{}/tag/{}", config_get_string("fqdn"), tag_id));
+
+		// The tag listing puts pinned posts on top, a feed is strictly chronological
+		let mut excerpts = match self.snapshot.load().tag_2_posts.get(&tag_id) {
+			Some(tmp) => { self.get_post_excerpts(tmp) }
+			_ => { vec![] }
+		};
+		excerpts.sort_by(|a, b| b.date_posted.cmp(&a.date_posted));
+		excerpts.truncate(self.get_feed_length() as usize);
+
+		self.render_rss_feed(tera, cache_key, started, context, excerpts)
+	}
+
+	/// Render the given posts as a feed and cache the result
+	fn render_rss_feed(&self, tera: &web::Data<Arc<tera::Tera>>, cache_key: String, started: Instant, mut context: Context, excerpts: Vec<Arc<PostExcerpt>>) -> Result<String, String> {
+		// How much of each post goes into the feed
+		context.feed_full_content = config_get_bool("feed_full_content");
+
+		// Attach the featured image as enclosure
+		let mut feed_items = Vec::new();
+		for excerpt in excerpts {
+			// The feed was last built when its most recently modified item changed
+			if excerpt.date_modified > context.feed_last_build_date {
+				context.feed_last_build_date = excerpt.date_modified;
+			}
+
+			feed_items.push(FeedItem {
+				enclosure: self.get_feed_enclosure(&excerpt.thumbnail),
+				pub_date: format_rfc2822(excerpt.date_posted),
+				post: excerpt,
+			});
+		}
+		if context.feed_last_build_date == 0 {
+			context.feed_last_build_date = context.time;
+		}
+		context.feed_last_build_date_rfc2822 = Some(format_rfc2822(context.feed_last_build_date));
+		context.feed_items = Some(feed_items);
+
 		// Render the template
 		match self.render_template(tera, "feed.rss", &context) {
-			Ok(html) => {
+			Ok((html, expires_at)) => {
 				// Cache the HTML output
-				self.cache.cache_html(cache_key, html.clone());
-
-				Ok(html)
+				Ok(self.cache.cache_html(cache_key, html, expires_at, started.elapsed()))
 			},
 			Err(err) => Err(err)
 		}
 	}
 
+	/// The number of posts that go into a feed
+	fn get_feed_length(&self) -> u32 {
+		match config_get_i64("feed_post_count") {
+			n if n > 0 => n as u32,
+			_ => 8
+		}
+	}
+
 	// ------------------------------------------------------------------
 	// ----------------------- UTILITY FUNCTIONS ------------------------
 	// ------------------------------------------------------------------
@@ -841,15 +1885,195 @@ impl Blog {
 	}
 
 	/// This message will create a post view
-	fn message_post_viewed(&self, post_id: u32, viewed_at: u64, remote_ip: String, user_agent: String, referer: String) {
+	///
+	/// Coming from another page of ours counts as a click for a running experiment of the post
+	fn message_post_viewed(&self, post_id: u32, viewed_at: u64, remote_ip: String, user_agent: String, referer: String, campaign: String, country: String) {
+		match self.experiments.load().get(&post_id) {
+			Some(experiment) if is_internal_referer(&referer) => {
+				let variant = ExperimentVariant::for_visitor(&remote_ip, &user_agent);
+				self.queue_message(BlogMessage::ExperimentEvent { experiment_id: experiment.id, variant, kind: "click", recorded_at: viewed_at });
+			}
+			_ => {}
+		}
+
+		self.queue_message(BlogMessage::PostView { post_id, viewed_at, remote_ip, user_agent, referer, campaign, country });
+	}
+
+	/// These messages count the exposures of the experiments on a list page
+	fn message_experiments_shown(&self, keys: &Vec<u32>, visitor: Option<ExperimentVariant>) {
+		let variant = match visitor {
+			Some(tmp) => tmp,
+			_ => { return; }
+		};
+
+		let experiments = self.experiments.load();
+		for key in keys {
+			match experiments.get(key) {
+				Some(experiment) => {
+					self.queue_message(BlogMessage::ExperimentEvent { experiment_id: experiment.id, variant, kind: "exposure", recorded_at: self.get_time_in_secs() });
+				}
+				_ => {}
+			}
+		}
+	}
+
+	/// Queue a vote, only the first vote of every address counts once it is stored
+	pub fn vote_poll(&self, poll_id: u32, option: u32, remote_ip: String) -> Result<(), String> {
+		match self.polls.load().get(&poll_id) {
+			Some(poll) if !poll.open => { return Err(String::from("The poll is closed.")); }
+			Some(poll) if option as usize >= poll.options.len() => { return Err(String::from("Please pick one of the options.")); }
+			Some(_) => {}
+			_ => { return Err(String::from("The poll could not be found.")); }
+		}
+
+		self.queue_message(BlogMessage::PollVote { poll_id, option, voted_at: self.get_time_in_secs(), remote_ip });
+		Ok(())
+	}
+
+	/// Queue how far a reader scrolled into a post, in percent, and how long they read it, in seconds
+	pub fn record_engagement(&self, post_id: u32, scroll_depth: u32, read_time: u32, remote_ip: String) -> Result<(), String> {
+		match self.get_post(post_id) {
+			Some(_) => {}
+			_ => { return Err(String::from("The post could not be found.")); }
+		}
+
+		self.queue_message(BlogMessage::PostEngagement {
+			post_id,
+			recorded_at: self.get_time_in_secs(),
+			scroll_depth: scroll_depth.min(100),
+			read_time: read_time.min(ENGAGEMENT_MAX_READ_TIME),
+			remote_ip,
+		});
+		Ok(())
+	}
+
+	/// Add a message to the queue, the oldest ones are dropped once it is full
+	fn queue_message(&self, message: BlogMessage) {
+		let capacity = self.get_message_queue_capacity();
+
 		match self.messages.lock() {
 			Ok(mut guard) => {
-				guard.push(BlogMessage::PostView { post_id, viewed_at, remote_ip, user_agent, referer });
+				while guard.len() >= capacity {
+					guard.pop_front();
+					if self.messages_dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+						println!("Message queue is full ({} messages), dropping the oldest ones", capacity);
+					}
+				}
+				guard.push_back(message);
 			}
 			_ => { println!("Message guard cannot be locked!"); }
 		}
 	}
 
+	/// The maximum number of queued messages
+	fn get_message_queue_capacity(&self) -> usize {
+		match config_get_i64("message_queue_max") {
+			tmp if tmp > 0 => tmp as usize,
+			_ => MESSAGE_QUEUE_MAX
+		}
+	}
+
+	/// Statistics on the message queue
+	pub fn get_message_queue_stats(&self) -> MessageQueueStats {
+		MessageQueueStats {
+			queued: match self.messages.lock() {
+				Ok(guard) => guard.len(),
+				_ => 0
+			},
+			capacity: self.get_message_queue_capacity(),
+			dropped: self.messages_dropped.load(Ordering::Relaxed),
+			flushed: self.messages_flushed.load(Ordering::Relaxed),
+		}
+	}
+
+	/// Hit ratio and render times of the HTML cache
+	pub fn get_html_cache_stats(&self) -> Vec<cache::HtmlCacheStats> {
+		self.cache.get_html_stats()
+	}
+
+	/// Render a page `runs` times past the cache, timing the context, its serialization and Tera apart
+	///
+	/// `target` is `post`, `lite` or `print` with the id of a post, `index` with the page, or the name of
+	/// a template that is rendered like `get_html_base`. Nothing is cached and no views are counted
+	pub fn profile_render(&self, tera: &web::Data<Arc<tera::Tera>>, target: &str, id: u32, runs: u32) -> Result<profile::RenderProfile, String> {
+		let post = match target {
+			"post" | "lite" | "print" => match self.get_post(id) {
+				Some(tmp) => Some(tmp),
+				_ => { return Err(format!("there is no post {}", id)); }
+			},
+			_ => None
+		};
+		let template = match (target, &post) {
+			("post", Some(post)) => String::from(post.template_name()),
+			("lite", _) => String::from("post_lite.html"),
+			("print", _) => String::from("post_print.html"),
+			("index", _) => String::from("index.html"),
+			(tmp, _) => String::from(tmp)
+		};
+		let keys = match target {
+			"index" => self.get_pagination_slice(&self.snapshot.load().posts_index, id, config_get_i64("posts_per_page") as u32),
+			_ => Vec::new()
+		};
+
+		let mut samples = profile::RenderSamples::new();
+		// One more for the warm-up
+		for _ in 0..=runs {
+			let started = Instant::now();
+			let context = match (target, &post) {
+				("post", Some(post)) => self.create_post_context(post),
+				("lite", Some(post)) => self.create_post_lite_context(post),
+				("print", Some(post)) => self.create_post_print_context(post),
+				("index", _) => self.create_index_page_context(id, &keys, ExperimentVariant::A),
+				_ => self.create_index_context()
+			};
+			let built = Instant::now();
+
+			let tera_context = tera::Context::from_serialize(&context).map_err(|err| format!("Template context error: {}", err))?;
+			let serialized = Instant::now();
+
+			// Like `render_template`, with the phases timed on their own
+			let html = match panic::catch_unwind(AssertUnwindSafe(|| tera.render(&template, &tera_context))) {
+				Ok(Ok(tmp)) => evaluate_conditions(&tmp, self.get_time_in_secs()).0,
+				Ok(Err(err)) => { return Err(format!("Template render error: {}", err)); }
+				Err(_) => { return Err(format!("Template render panicked: {}", template)); }
+			};
+			let rendered = Instant::now();
+
+			samples.add(built - started, serialized - built, rendered - serialized, html.len());
+		}
+
+		Ok(samples.into_profile(target, &template, id))
+	}
+
+	/// Ids of the newest posts, ordered by their publishing date
+	fn get_latest_post_ids(&self, limit: u32) -> Vec<u32> {
+		self.get_pagination_slice(&self.snapshot.load().posts_by_date, 0, limit)
+	}
+
+	/// Ids from the top of the index listing, pinned posts first
+	fn get_index_post_ids(&self, limit: u32) -> Vec<u32> {
+		self.get_pagination_slice(&self.snapshot.load().posts_index, 0, limit)
+	}
+
+	/// Create the enclosure for an image that is hosted in our gallery
+	fn get_feed_enclosure(&self, source: &str) -> Option<FeedEnclosure> {
+		let local_path = crate::blog::gallery::gallery_source_to_local_path(source)?;
+		let length = std::fs::metadata(&local_path).ok()?.len();
+
+		let mime_type = match crate::app::utils::get_extension_from_filename(&local_path) {
+			Some("png") => "image/png",
+			Some("gif") => "image/gif",
+			Some("mp4") => "video/mp4",
+			Some("webm") => "video/webm",
+			_ => "image/jpeg"
+		};
+
+		// Relative gallery links need our host
+		let url = if source.starts_with("/") { format!("https://{}{}", config_get_string("fqdn"), source) } else { String::from(source) };
+
+		Some(FeedEnclosure { url, mime_type: String::from(mime_type), length })
+	}
+
 	/// Try to find a slice in a vector
 	#[inline(always)]
 	fn get_pagination_slice(&self, source: &Vec<u32>, page: u32, per_page: u32) -> Vec<u32> {
@@ -871,64 +2095,303 @@ impl Blog {
 
 	pub fn invalidate_html_cache(&self) -> Result<usize, io::Error> {
 		self.cache.reset_html_cache();
+
+		// The CDN would keep serving the old pages
+		if cdn::cdn_enabled() {
+			task::spawn_blocking(|| match cdn::cdn_purge_all() {
+				Err(err) => { println!("Could not purge the CDN: {}", err); }
+				_ => {}
+			});
+		}
+
 		Ok(1)
 	}
 
+	/// Render the pages that were served stale again, runs in the background
+	///
+	/// Returns the number of pages rendered
+	pub fn revalidate_html(&self, db: &Arc<dyn Storage>, tera: &web::Data<Arc<tera::Tera>>) -> usize {
+		let keys = self.cache.take_html_revalidations();
+
+		for key in keys.iter() {
+			let started = Instant::now();
+			let result = self.cache.bypassing_html(|| -> Result<(), String> {
+				// The feed keys share a prefix, the longer one goes first
+				if key.starts_with("rss_feed_tag_") {
+					self.get_html_rss_feed_tag(tera, String::from(&key[13..])).map(|_| ())
+				} else if key == "rss_feed" {
+					self.get_html_rss_feed(tera).map(|_| ())
+				} else if key == "site_map" {
+					self.get_html_site_map(tera).map(|_| ())
+				} else if key == "stats" {
+					self.get_html_stats(db, tera).map(|_| ())
+				} else if key.starts_with("base_") {
+					self.get_html_base(tera, &key[5..]).map(|_| ())
+				} else if key.ends_with("~b") {
+					// Variant B of a page is rendered again once a reader of that variant asks for it
+					self.cache.remove_html(key);
+					Ok(())
+				} else if key.starts_with("index_") {
+					let page = key[6..].parse::<u32>().map_err(|err| err.to_string())?;
+					self.get_html_index(tera, page, None).map(|_| ())
+				} else if key.starts_with("lite_") {
+					let post_id = key[5..].parse::<u32>().map_err(|err| err.to_string())?;
+					match self.render_post_lite_uncached(post_id, tera) {
+						Some(Ok((html, expires_at))) => {
+							self.cache.cache_html(key.clone(), html, expires_at, started.elapsed());
+							Ok(())
+						}
+						Some(Err(err)) => Err(err),
+						_ => Err(String::from("the post is gone"))
+					}
+				} else if key.starts_with("print_") {
+					let post_id = key[6..].parse::<u32>().map_err(|err| err.to_string())?;
+					match self.render_post_print_uncached(post_id, tera) {
+						Some(Ok((html, expires_at))) => {
+							self.cache.cache_html(key.clone(), html, expires_at, started.elapsed());
+							Ok(())
+						}
+						Some(Err(err)) => Err(err),
+						_ => Err(String::from("the post is gone"))
+					}
+				} else if key.starts_with("post_") {
+					// Not through `get_html_post`, that would count a view
+					let post_id = key[5..].parse::<u32>().map_err(|err| err.to_string())?;
+					match self.render_post_uncached(post_id, tera) {
+						Some((_, Ok((html, expires_at)))) => {
+							self.cache.cache_html(key.clone(), html, expires_at, started.elapsed());
+							Ok(())
+						}
+						Some((_, Err(err))) => Err(err),
+						_ => Err(String::from("the post is gone"))
+					}
+				} else if key.starts_with("tag_") || key.starts_with("author_") {
+					// These keys end with the page, tags may contain underscores themselves
+					let (prefix, tail) = key.split_at(key.find('_').unwrap_or(0) + 1);
+					let mut parts = tail.rsplitn(2, '_');
+					let page = parts.next().and_then(|tmp| tmp.parse::<u32>().ok()).ok_or_else(|| String::from("no page"))?;
+					let id = parts.next().ok_or_else(|| String::from("no id"))?;
+
+					match prefix {
+						"tag_" => self.get_html_tag(db, tera, String::from(id), page, None).map(|_| ()),
+						_ => {
+							let author_id = id.parse::<u32>().map_err(|err| err.to_string())?;
+							self.get_html_author(tera, author_id, page, None).unwrap_or(Err(String::from("the author is gone"))).map(|_| ())
+						}
+					}
+				} else {
+					Err(String::from("unknown page"))
+				}
+			});
+
+			match result {
+				Err(err) => { println!("Could not render '{}' again: {}", key, err); }
+				_ => {}
+			}
+		}
+
+		keys.len()
+	}
+
+	/// Drop the cached HTML of some post and tag pages, here and at the CDN
+	fn invalidate_html(&self, keys: &Vec<String>) {
+		if keys.len() == 0 { return; }
+
+		let mut paths = Vec::new();
+		for key in keys {
+			self.cache.remove_html(key);
+
+			if key.starts_with("post_") {
+				// The lite and print versions show the same content
+				self.cache.remove_html(&format!("lite_{}", &key[5..]));
+				self.cache.remove_html(&format!("print_{}", &key[5..]));
+
+				match key[5..].parse::<u32>().ok().and_then(|id| self.get_post(id)) {
+					Some(post) => {
+						paths.push(format!("/{}", post.url_canonical));
+						paths.push(format!("/lite/{}", post.url_canonical));
+						paths.push(format!("/print/{}", post.url_canonical));
+					}
+					_ => {}
+				}
+			} else if key.starts_with("tag_") {
+				self.cache.remove_html(&format!("{}~b", key));
+
+				// Tag keys end with the page, tags may contain underscores themselves
+				let mut parts = key[4..].rsplitn(2, '_');
+				match (parts.next().and_then(|tmp| tmp.parse::<u32>().ok()), parts.next()) {
+					(Some(0), Some(tag_id)) => { paths.push(format!("/tag/{}", tag_id)); }
+					(Some(page), Some(tag_id)) => { paths.push(format!("/tag/{}?p={}", tag_id, page + 1)); }
+					_ => {}
+				}
+			}
+		}
+
+		if paths.len() > 0 && cdn::cdn_enabled() {
+			task::spawn_blocking(move || match cdn::cdn_purge_paths(&paths) {
+				Err(err) => { println!("Could not purge the CDN: {}", err); }
+				_ => {}
+			});
+		}
+	}
+
 	/// Render a template using the provided context
-	fn render_template(&self, tera: &web::Data<Arc<tera::Tera>>, template_name: &str, context: &Context) -> Result<String, String> {
+	///
+	/// Returns the HTML and when dated content in it changes by itself, the page must not be cached past that
+	fn render_template(&self, tera: &web::Data<Arc<tera::Tera>>, template_name: &str, context: &Context) -> Result<(String, Option<u64>), String> {
 		// Serialize context for tera
 		let tera_context = match tera::Context::from_serialize(context).map_err(|_| error::ErrorInternalServerError("Template context error")) {
 			Ok(tmp) => tmp,
 			Err(err) => {
-				return Err(format!("Template context error: {}", err.to_string()));
+				return Err(format!("Template context error: {}", err));
 			}
 		};
 
-		// Render the template
-		match tera.render(template_name, &tera_context) {
-			Ok(tmp) => Ok(tmp),
-			Err(err) => Err(format!("Template render error: {}", err.to_string()))
+		// Render the template, a panicking filter or function must not take the worker down
+		match panic::catch_unwind(AssertUnwindSafe(|| tera.render(template_name, &tera_context))) {
+			Ok(Ok(tmp)) => {
+				// Dated content like `[if before="2025-06-01"]` is decided now, the page is cached until it changes
+				Ok(evaluate_conditions(&tmp, self.get_time_in_secs()))
+			}
+			Ok(Err(err)) => Err(format!("Template render error: {}", err)),
+			Err(_) => Err(format!("Template render panicked: {}", template_name))
 		}
 	}
 
-	/// This function will check the cached items
+	/// Log a render error and get the generic error page instead, visitors never see template internals
 	///
-	/// Once a cache item's life time expires, it will be reloaded
-	pub fn maintenance_task(&self, db: &mysql::Pool) {
-
-		// Check cache Pinterest, Instagram, featured and latest posts
-		self.cache.cache_pinterest_posts();
-		self.cache.cache_instagram_posts();
-		self.cache.cache_latest_posts(&self, db);
-		self.cache.cache_featured_posts(&self, db);
-		self.cache.cache_posts_by_tag(&self, 1, config_get_string("cached_tag_1").as_str());
-		self.cache.cache_posts_by_tag(&self, 2, config_get_string("cached_tag_2").as_str());
-		self.cache.cache_posts_by_tag(&self, 3, config_get_string("cached_tag_3").as_str());
-		self.cache.cache_posts_by_tag(&self, 4, config_get_string("cached_tag_4").as_str());
-		self.cache.cache_posts_by_tag(&self, 5, config_get_string("cached_tag_5").as_str());
-
-		// Process messages handled by the queue
-		{
-			let mut views = Vec::<(u32, u64, String, String, String)>::new();
-
-			match self.messages.lock() {
-				Ok(mut guard) => {
-					for msg in guard.iter() {
-						match msg {
-							BlogMessage::PostView { post_id, viewed_at, remote_ip, user_agent, referer } => {
-								views.push((*post_id, *viewed_at, remote_ip.clone(), user_agent.clone(), referer.clone()));
-							}
+	/// The error page is cached like any other page, if it fails as well a built-in page is used
+	pub fn get_html_error(&self, tera: &web::Data<Arc<tera::Tera>>, err: &str) -> String {
+		println!("Render error: {}", err);
+
+		match self.get_html_base(tera, "error_500.html") {
+			Ok(html) => html,
+			Err(err) => {
+				println!("Could not render the error page: {}", err);
+				String::from(ERROR_PAGE_FALLBACK)
+			}
+		}
+	}
+
+	/// Reload the Pinterest and Instagram posts once their life time expired
+	///
+	/// Both are tried, the errors are joined
+	pub fn refresh_social_feeds(&self) -> Result<(), String> {
+		match (self.cache.cache_pinterest_posts(), self.cache.cache_instagram_posts()) {
+			(Err(a), Err(b)) => Err(format!("{}, {}", a, b)),
+			(Err(err), _) | (_, Err(err)) => Err(err),
+			_ => Ok(())
+		}
+	}
+
+	/// Reload the latest, featured and tagged posts once their life time expired
+	pub fn refresh_post_caches(&self, db: &Arc<dyn Storage>) {
+		self.cache.cache_latest_posts(self);
+		self.cache.cache_featured_posts(self, db);
+		self.refresh_post_popularity(db);
+
+		// We want certain tags available on the start page
+		// These tags can be changed in the config
+		self.cache.cache_posts_by_tag(self, 1, config_get_string("cached_tag_1").as_str());
+		self.cache.cache_posts_by_tag(self, 2, config_get_string("cached_tag_2").as_str());
+		self.cache.cache_posts_by_tag(self, 3, config_get_string("cached_tag_3").as_str());
+		self.cache.cache_posts_by_tag(self, 4, config_get_string("cached_tag_4").as_str());
+		self.cache.cache_posts_by_tag(self, 5, config_get_string("cached_tag_5").as_str());
+	}
+
+	/// Count the recent views of every post again once `recommendations_lifetime` seconds passed
+	///
+	/// Pages that are cached keep their recommendations until they expire
+	fn refresh_post_popularity(&self, db: &Arc<dyn Storage>) {
+		let now = self.get_time_in_secs();
+		let life_time = match config_get_i64("recommendations_lifetime") {
+			tmp if tmp > 0 => tmp as u64,
+			_ => POPULARITY_LIFETIME
+		};
+		if self.post_popularity_at.load(Ordering::Relaxed) + life_time > now { return; }
+
+		match post::fetch_post_view_counts(db, POPULARITY_DAYS) {
+			Ok(views) => {
+				self.post_popularity.store(Arc::new(views));
+				self.post_popularity_at.store(now, Ordering::Relaxed);
+				match self.recommendations.write() {
+					Ok(mut guard) => { guard.clear(); }
+					_ => {}
+				}
+			}
+			Err(err) => { println!("Could not count the post views for the recommendations: {}", err); }
+		}
+	}
+
+	/// Write the queued messages to the database
+	///
+	/// Runs on its own, shorter interval than the cache refreshes
+	pub fn flush_messages(&self, db: &Arc<dyn Storage>) {
+		let mut views = Vec::<(u32, u64, String, String, String, String, String)>::new();
+		let mut votes = Vec::<(u32, u32, u64, String)>::new();
+		let mut engagement = Vec::<(u32, u64, u32, u32, String)>::new();
+		let mut experiment_events = Vec::<(u32, ExperimentVariant, &'static str, u64)>::new();
+
+		match self.messages.lock() {
+			Ok(mut guard) => {
+				for msg in guard.drain(..) {
+					match msg {
+						BlogMessage::PostView { post_id, viewed_at, remote_ip, user_agent, referer, campaign, country } => {
+							views.push((post_id, viewed_at, remote_ip, user_agent, referer, campaign, country));
+						}
+						BlogMessage::PollVote { poll_id, option, voted_at, remote_ip } => {
+							votes.push((poll_id, option, voted_at, remote_ip));
+						}
+						BlogMessage::PostEngagement { post_id, recorded_at, scroll_depth, read_time, remote_ip } => {
+							engagement.push((post_id, recorded_at, scroll_depth, read_time, remote_ip));
+						}
+						BlogMessage::ExperimentEvent { experiment_id, variant, kind, recorded_at } => {
+							experiment_events.push((experiment_id, variant, kind, recorded_at));
 						}
 					}
-					// There is nothing but view messages atm so we can clear it
-					guard.clear();
 				}
-				_ => {}
 			}
+			_ => {}
+		}
 
-			if views.len() > 0 {
-				crate::blog::post::log_post_views(db, &views)
-			}
+		// A popular post can queue thousands of views, keep the inserts off the async threads
+		if views.len() > 0 {
+			self.messages_flushed.fetch_add(views.len() as u64, Ordering::Relaxed);
+
+			let db = db.clone();
+			task::spawn_blocking(move || crate::blog::post::log_post_views(&db, &views));
+		}
+
+		// Every page that is left sends one, same as the views
+		if engagement.len() > 0 {
+			self.messages_flushed.fetch_add(engagement.len() as u64, Ordering::Relaxed);
+
+			let db = db.clone();
+			task::spawn_blocking(move || crate::blog::post::log_post_engagement(&db, &engagement));
+		}
+
+		// List pages queue one exposure per experiment they show
+		if experiment_events.len() > 0 {
+			self.messages_flushed.fetch_add(experiment_events.len() as u64, Ordering::Relaxed);
+
+			let db = db.clone();
+			task::spawn_blocking(move || experiment::log_experiment_events(&db, &experiment_events));
+		}
+
+		// Votes are few and should show up right away, this runs on the scheduler's blocking thread anyway
+		if votes.len() > 0 {
+			self.messages_flushed.fetch_add(votes.len() as u64, Ordering::Relaxed);
+
+			poll::store_poll_votes(db, &votes);
+			self.refresh_poll_results(db);
+		}
+
+		// A full queue means more traffic than the database keeps up with
+		let dropped = self.messages_dropped.load(Ordering::Relaxed);
+		let notified = self.messages_dropped_notified.swap(dropped, Ordering::Relaxed);
+		if dropped > notified {
+			let detail = format!("{} views and other messages were dropped since the last flush, consider raising message_queue_max", dropped - notified);
+			notifications::notify(db, "traffic", "Message queue overflowed", &detail, "");
 		}
 	}
 }
\ No newline at end of file
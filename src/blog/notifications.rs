@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::storage::{DbParams, DbRow, DbValue, Storage};
+
+/// What a notification is about, the panel picks an icon by it
+pub const NOTIFICATION_KINDS: &[&str] = &["comment", "mention", "submission", "broken_link", "traffic", "social", "job"];
+
+/// Notifications returned at most, older ones are only counted
+const NOTIFICATION_LIMIT: u32 = 100;
+
+
+// ------------------------------
+// -------- NOTIFICATION --------
+// ------------------------------
+
+/// Something the admins should look at, shown behind the bell in the panel
+#[derive(Serialize, Debug)]
+pub struct Notification {
+	pub id: u64,
+	/// One of `NOTIFICATION_KINDS`
+	pub kind: String,
+	pub title: String,
+	pub detail: String,
+	/// Where in the panel to look, may be empty
+	pub link: String,
+	/// How often it happened since it was last read
+	pub count: u32,
+	pub created_at: u64,
+	pub updated_at: u64,
+	pub read: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NotificationList {
+	pub unread: u32,
+	pub items: Vec<Notification>,
+}
+
+impl Notification {
+	fn from_sql(mut row: DbRow) -> Option<Notification> {
+		Some(Notification {
+			id: row.take("id")?,
+			kind: row.take("kind")?,
+			title: row.take("title")?,
+			detail: row.take("detail")?,
+			link: row.take("link")?,
+			count: row.take("count")?,
+			created_at: row.take::<NaiveDateTime>("created_at")?.timestamp() as u64,
+			updated_at: row.take::<NaiveDateTime>("updated_at")?.timestamp() as u64,
+			read: row.take::<Option<NaiveDateTime>>("read_at")?.is_some(),
+		})
+	}
+}
+
+
+/// Tell the admins about something
+///
+/// An unread notification with the same kind and title is counted up instead, so a job failing
+/// every few seconds does not bury everything else. Failures are only logged
+pub fn notify(db: &Arc<dyn Storage>, kind: &str, title: &str, detail: &str, link: &str) {
+	debug_assert!(NOTIFICATION_KINDS.contains(&kind), "unknown notification kind '{}'", kind);
+	let now = Utc::now().naive_utc();
+
+	let query = "UPDATE notifications SET count=count+1, detail=:detail, link=:link, updated_at=:now WHERE kind=:kind AND title=:title AND read_at IS NULL";
+	match db.exec(query, db_params! {"kind" => kind, "title" => title, "detail" => detail, "link" => link, "now" => now}) {
+		Ok(res) if res.affected_rows > 0 => { return; }
+		Err(err) => {
+			println!("Could not store a notification: {}", err);
+			return;
+		}
+		_ => {}
+	}
+
+	let query = "INSERT INTO notifications (kind, title, detail, link, count, created_at, updated_at) VALUES (:kind, :title, :detail, :link, 1, :now, :now)";
+	match db.exec(query, db_params! {"kind" => kind, "title" => title, "detail" => detail, "link" => link, "now" => now}) {
+		Err(err) => { println!("Could not store a notification: {}", err); }
+		_ => {}
+	}
+}
+
+/// The latest notifications, newest first, plus the number of unread ones
+pub fn load_notifications(db: &Arc<dyn Storage>, unread_only: bool) -> Result<NotificationList, String> {
+	let unread = match db.query_first("SELECT COUNT(*) AS unread FROM notifications WHERE read_at IS NULL", db_params!()) {
+		Ok(Some(mut row)) => row.take("unread").unwrap_or(0),
+		Ok(None) => 0,
+		Err(err) => { return Err(err.to_string()); }
+	};
+
+	let query = format!(r###"
+        SELECT id, kind, title, detail, link, count, created_at, updated_at, read_at
+        FROM notifications
+        {}
+        ORDER BY updated_at DESC LIMIT {}
+    "###, if unread_only { "WHERE read_at IS NULL" } else { "" }, NOTIFICATION_LIMIT);
+	let rows = db.query(&query, db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(NotificationList { unread, items: rows.into_iter().filter_map(Notification::from_sql).collect() })
+}
+
+/// Mark some notifications as read, all of them if `ids` is None
+///
+/// Returns how many were marked
+pub fn mark_notifications_read(db: &Arc<dyn Storage>, ids: Option<&Vec<u64>>) -> Result<u64, String> {
+	let now = DbValue::from(Utc::now().naive_utc());
+
+	let (query, params) = match ids {
+		Some(ids) if ids.len() == 0 => { return Ok(0); }
+		Some(ids) => {
+			let placeholders = vec!["?"; ids.len()].join(", ");
+			let mut params = vec![now];
+			params.extend(ids.iter().map(|id| DbValue::from(*id)));
+			(format!("UPDATE notifications SET read_at=? WHERE read_at IS NULL AND id IN ({})", placeholders), params)
+		}
+		_ => (String::from("UPDATE notifications SET read_at=? WHERE read_at IS NULL"), vec![now])
+	};
+
+	db.exec(&query, DbParams::Positional(params)).map(|res| res.affected_rows).map_err(|err| err.to_string())
+}
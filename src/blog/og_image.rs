@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::Path;
+
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::{DynamicImage, Rgb, RgbImage};
+use image::imageops::FilterType;
+
+use crate::app::config::config_get_string;
+use crate::blog::gallery::gallery_source_to_local_path;
+use crate::blog::optimize::encode_image;
+use crate::blog::types::post::Post;
+
+/// Share images are kept next to the gallery, one per post
+const OG_IMAGE_PATH: &str = "data/gallery/og";
+
+/// The size Facebook, Twitter and friends ask for
+const OG_IMAGE_WIDTH: u32 = 1200;
+const OG_IMAGE_HEIGHT: u32 = 630;
+
+/// Space around the title
+const OG_IMAGE_MARGIN: u32 = 64;
+
+/// The title starts at this size and shrinks until it fits in `OG_IMAGE_MAX_LINES`
+const OG_TITLE_SIZE: f32 = 72.0;
+const OG_TITLE_MIN_SIZE: f32 = 40.0;
+const OG_IMAGE_MAX_LINES: usize = 3;
+
+/// Posts without a featured image get a plain background
+const OG_BACKGROUND: Rgb<u8> = Rgb([34, 40, 49]);
+
+
+// ------------------------------
+// ---------- OG IMAGE ----------
+// ------------------------------
+
+/// Share images are made if `og_image_font` points to a TrueType or OpenType font
+pub fn og_image_enabled() -> bool {
+	config_get_string("og_image_font").len() > 0
+}
+
+/// The address of the share image of a post, for the Open Graph tags
+///
+/// The name changes with the title and the featured image, so the social networks fetch it again
+pub fn og_image_url(post: &Post) -> Option<String> {
+	if !og_image_enabled() { return None; }
+	Some(format!("https://{}/og/{}", config_get_string("fqdn"), og_image_name(post)))
+}
+
+/// The local path of the share image of a post, it is made if it does not exist yet
+///
+/// Share images of older titles are removed
+pub fn og_image_file(post: &Post) -> Result<String, String> {
+	if !og_image_enabled() { return Err(String::from("Share images are disabled")); }
+
+	let name = og_image_name(post);
+	let path = format!("{}/{}", OG_IMAGE_PATH, name);
+	if Path::new(&path).exists() { return Ok(path); }
+
+	let img = render_og_image(post)?;
+	fs::create_dir_all(OG_IMAGE_PATH).map_err(|err| err.to_string())?;
+	let mut output = fs::File::create(&path).map_err(|err| err.to_string())?;
+	encode_image(&DynamicImage::ImageRgb8(img), "jpg", &mut output)?;
+
+	// The older versions of this post
+	let prefix = format!("{}-", post.id);
+	match fs::read_dir(OG_IMAGE_PATH) {
+		Ok(files) => {
+			for file in files.filter_map(|entry| entry.ok()) {
+				let file_name = file.file_name().to_string_lossy().to_string();
+				if file_name.starts_with(&prefix) && file_name != name {
+					let _ = fs::remove_file(file.path());
+				}
+			}
+		}
+		_ => {}
+	}
+
+	Ok(path)
+}
+
+/// `<post id>-<hash of title and featured image>.jpg`
+fn og_image_name(post: &Post) -> String {
+	// FNV-1a, it stays the same across releases
+	let mut hash: u32 = 0x811c9dc5;
+	for byte in post.title.bytes().chain(featured_source(post).unwrap_or("").bytes()) {
+		hash ^= byte as u32;
+		hash = hash.wrapping_mul(0x01000193);
+	}
+
+	format!("{}-{:08x}.jpg", post.id, hash)
+}
+
+fn featured_source(post: &Post) -> Option<&str> {
+	post.media.iter().find(|item| item.class == "featured").map(|item| item.source.as_str())
+}
+
+/// The featured image filling the card, darkened towards the bottom, with the title on top
+fn render_og_image(post: &Post) -> Result<RgbImage, String> {
+	let font_data = fs::read(config_get_string("og_image_font")).map_err(|err| format!("Could not read the font: {}", err))?;
+	let font = FontVec::try_from_vec(font_data).map_err(|_| String::from("The font is invalid"))?;
+
+	// Only images from our own gallery, anything else would have to be downloaded first
+	let background = featured_source(post)
+		.and_then(gallery_source_to_local_path)
+		.and_then(|path| image::open(path).ok());
+	let mut img = match background {
+		Some(tmp) => tmp.resize_to_fill(OG_IMAGE_WIDTH, OG_IMAGE_HEIGHT, FilterType::Lanczos3).to_rgb8(),
+		_ => RgbImage::from_pixel(OG_IMAGE_WIDTH, OG_IMAGE_HEIGHT, OG_BACKGROUND)
+	};
+
+	// Keep the text readable on bright photos
+	for (_, y, pixel) in img.enumerate_pixels_mut() {
+		let shade = 0.25 + 0.55 * (y as f32 / OG_IMAGE_HEIGHT as f32);
+		for channel in pixel.0.iter_mut() {
+			*channel = (*channel as f32 * (1.0 - shade)) as u8;
+		}
+	}
+
+	// The largest size the title fits at
+	let max_width = (OG_IMAGE_WIDTH - 2 * OG_IMAGE_MARGIN) as f32;
+	let mut size = OG_TITLE_SIZE;
+	let mut lines = wrap_text(&font, size, &post.title, max_width);
+	while lines.len() > OG_IMAGE_MAX_LINES && size > OG_TITLE_MIN_SIZE {
+		size -= 4.0;
+		lines = wrap_text(&font, size, &post.title, max_width);
+	}
+	lines.truncate(OG_IMAGE_MAX_LINES);
+
+	// The title sits at the bottom, the name of the site above it
+	let line_height = size * 1.2;
+	let mut baseline = OG_IMAGE_HEIGHT as f32 - OG_IMAGE_MARGIN as f32 - line_height * (lines.len() as f32 - 1.0);
+	draw_text(&mut img, &font, size * 0.45, OG_IMAGE_MARGIN as f32, baseline - line_height, &config_get_string("title"), [200, 200, 200]);
+	for line in lines.iter() {
+		draw_text(&mut img, &font, size, OG_IMAGE_MARGIN as f32, baseline, line, [255, 255, 255]);
+		baseline += line_height;
+	}
+
+	Ok(img)
+}
+
+/// Break the text into lines no wider than `max_width`, words longer than a line are not broken
+fn wrap_text(font: &FontVec, size: f32, text: &str, max_width: f32) -> Vec<String> {
+	let mut lines: Vec<String> = Vec::new();
+	let mut current = String::new();
+
+	for word in text.split_whitespace() {
+		let candidate = if current.len() == 0 { String::from(word) } else { format!("{} {}", current, word) };
+		if current.len() > 0 && text_width(font, size, &candidate) > max_width {
+			lines.push(current);
+			current = String::from(word);
+		} else {
+			current = candidate;
+		}
+	}
+	if current.len() > 0 { lines.push(current); }
+
+	lines
+}
+
+fn text_width(font: &FontVec, size: f32, text: &str) -> f32 {
+	let font = font.as_scaled(PxScale::from(size));
+
+	let mut width = 0.0;
+	let mut previous = None;
+	for c in text.chars() {
+		let glyph = font.glyph_id(c);
+		if let Some(tmp) = previous { width += font.kern(tmp, glyph); }
+		width += font.h_advance(glyph);
+		previous = Some(glyph);
+	}
+	width
+}
+
+/// Draw a single line of text, `y` is the baseline
+fn draw_text(img: &mut RgbImage, font: &FontVec, size: f32, x: f32, y: f32, text: &str, color: [u8; 3]) {
+	let scaled = font.as_scaled(PxScale::from(size));
+
+	let mut caret = x;
+	let mut previous = None;
+	for c in text.chars() {
+		let glyph_id = scaled.glyph_id(c);
+		if let Some(tmp) = previous { caret += scaled.kern(tmp, glyph_id); }
+		let glyph = glyph_id.with_scale_and_position(size, ab_glyph::point(caret, y));
+		caret += scaled.h_advance(glyph_id);
+		previous = Some(glyph_id);
+
+		let outline = match font.outline_glyph(glyph) {
+			Some(tmp) => tmp,
+			_ => continue
+		};
+		let bounds = outline.px_bounds();
+		outline.draw(|gx, gy, coverage| {
+			let px = bounds.min.x as i32 + gx as i32;
+			let py = bounds.min.y as i32 + gy as i32;
+			if px < 0 || py < 0 || px >= img.width() as i32 || py >= img.height() as i32 { return; }
+
+			let pixel = img.get_pixel_mut(px as u32, py as u32);
+			for (channel, target) in pixel.0.iter_mut().zip(color.iter()) {
+				*channel = (*channel as f32 * (1.0 - coverage) + *target as f32 * coverage) as u8;
+			}
+		});
+	}
+}
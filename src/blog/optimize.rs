@@ -0,0 +1,97 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, Write};
+
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageOutputFormat};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+
+use crate::app::config::{config_get_bool, config_get_i64, config_get_string};
+
+/// JPEG quality of resized images unless `image_quality_jpeg` says otherwise
+const JPEG_QUALITY: u8 = 82;
+
+/// Without optimization images are written with the defaults of the image crate
+const JPEG_QUALITY_DEFAULT: u8 = 75;
+
+
+// ------------------------------
+// ---------- OPTIMIZE ----------
+// ------------------------------
+
+/// Whether images are recompressed after uploading and resizing, off unless `image_optimize` is set
+pub fn optimize_enabled() -> bool {
+	config_get_bool("image_optimize")
+}
+
+/// Encode an image in the format of the extension, with the configured quality if optimization is on
+///
+/// `image_quality_jpeg` takes 1 to 100, `image_png_compression` is `fast`, `default` or `best`
+pub fn encode_image<W: Write + Seek>(img: &DynamicImage, extension: &str, output: &mut W) -> Result<(), String> {
+	let optimize = optimize_enabled();
+
+	match extension.to_lowercase().as_str() {
+		"png" if optimize => {
+			let (width, height) = img.dimensions();
+			PngEncoder::new_with_quality(output, png_compression(), FilterType::Paeth)
+				.write_image(img.as_bytes(), width, height, img.color())
+				.map_err(|err| err.to_string())
+		}
+		"png" => img.write_to(output, ImageOutputFormat::Png).map_err(|err| err.to_string()),
+		"gif" => img.write_to(output, ImageOutputFormat::Gif).map_err(|err| err.to_string()),
+		"bmp" => img.write_to(output, ImageOutputFormat::Bmp).map_err(|err| err.to_string()),
+		_ => {
+			let quality = if optimize { jpeg_quality() } else { JPEG_QUALITY_DEFAULT };
+			img.write_to(output, ImageOutputFormat::Jpeg(quality)).map_err(|err| err.to_string())
+		}
+	}
+}
+
+/// Recompress an uploaded original without losing anything, the file is only replaced if it got smaller
+///
+/// Only PNGs are touched, every variant is made from the original and a JPEG would lose quality
+/// each time it is encoded again. Returns the number of bytes saved
+pub fn optimize_original(path: &str, extension: &str) -> Result<u64, String> {
+	if !optimize_enabled() || !extension.eq_ignore_ascii_case("png") { return Ok(0); }
+
+	let before = fs::metadata(path).map_err(|err| err.to_string())?.len();
+	let img = image::open(path).map_err(|err| err.to_string())?;
+
+	let path_tmp = format!("{}.optimized", path);
+	let result = File::create(&path_tmp)
+		.map_err(|err| err.to_string())
+		.and_then(|file| {
+			let mut output = BufWriter::new(file);
+			encode_image(&img, extension, &mut output)?;
+			output.flush().map_err(|err| err.to_string())
+		})
+		.and_then(|_| fs::metadata(&path_tmp).map(|tmp| tmp.len()).map_err(|err| err.to_string()));
+
+	match result {
+		Ok(after) if after < before => {
+			fs::rename(&path_tmp, path).map_err(|err| err.to_string())?;
+			Ok(before - after)
+		}
+		Ok(_) => {
+			let _ = fs::remove_file(&path_tmp);
+			Ok(0)
+		}
+		Err(err) => {
+			let _ = fs::remove_file(&path_tmp);
+			Err(err)
+		}
+	}
+}
+
+fn jpeg_quality() -> u8 {
+	match config_get_i64("image_quality_jpeg") {
+		tmp if tmp > 0 && tmp <= 100 => tmp as u8,
+		_ => JPEG_QUALITY
+	}
+}
+
+fn png_compression() -> CompressionType {
+	match config_get_string("image_png_compression").as_str() {
+		"fast" => CompressionType::Fast,
+		"default" => CompressionType::Default,
+		_ => CompressionType::Best
+	}
+}
@@ -0,0 +1,73 @@
+use regex::{Captures, Regex};
+
+use crate::app::config::config_get_string;
+
+lazy_static! {
+	static ref SCRIPT: Regex = Regex::new(r"(?is)<(script|noscript|iframe|video|audio|object)\b.*?</(script|noscript|iframe|video|audio|object)\s*>").unwrap();
+	static ref RESPONSIVE: Regex = Regex::new(r#"(?i)\s+(srcset|sizes|loading)\s*=\s*("[^"]*"|'[^']*')"#).unwrap();
+	static ref GALLERY_RESIZED: Regex = Regex::new(r#"/gallery/(?P<guid>[A-Za-z0-9]+)/(?:[hw][0-9]+|c[0-9]+x[0-9]+)/[^"'\s?#]*?\.(?P<ext>jpg|jpeg|gif|png)\b"#).unwrap();
+	static ref DETAILS: Regex = Regex::new(r"(?i)<details\b").unwrap();
+	static ref LINK: Regex = Regex::new(r#"(?is)<a\b[^>]*?\shref\s*=\s*["'](?P<href>[^"']+)["'][^>]*>.*?</a\s*>"#).unwrap();
+}
+
+
+// ------------------------------
+// ------------ PRINT -----------
+// ------------------------------
+
+/// Prepare post content for printing
+///
+/// Players and scripts are removed, gallery images are shown in full size and collapsed sections
+/// are opened. Links become numbered footnotes, listed at the end with their full address
+pub fn print_html(content: &str) -> String {
+	let content = SCRIPT.replace_all(content, "");
+	let content = RESPONSIVE.replace_all(&content, "");
+
+	// The original is `guid.ext` next to the resized variants
+	let content = GALLERY_RESIZED.replace_all(&content, |caps: &Captures| format!("/gallery/{}.{}", &caps["guid"], &caps["ext"]));
+	let content = DETAILS.replace_all(&content, "<details open");
+
+	let mut notes: Vec<String> = Vec::new();
+	let content = LINK.replace_all(&content, |caps: &Captures| {
+		let target = match link_target(&caps["href"]) {
+			Some(tmp) => tmp,
+			_ => { return String::from(&caps[0]); }
+		};
+
+		// The same address is only listed once
+		let number = match notes.iter().position(|note| *note == target) {
+			Some(index) => index + 1,
+			_ => {
+				notes.push(target);
+				notes.len()
+			}
+		};
+		format!("{}<sup class=\"print-note\">[{}]</sup>", &caps[0], number)
+	}).into_owned();
+
+	if notes.len() == 0 { return content; }
+
+	let mut html = content;
+	html.push_str("<ol class=\"print-notes\">");
+	for note in &notes {
+		html.push_str(&format!("<li>{}</li>", note));
+	}
+	html.push_str("</ol>");
+	html
+}
+
+/// The full address of a link, None for anchors within the page and for things like `mailto:`
+///
+/// The address is taken as it is in the HTML, it is already escaped there
+fn link_target(href: &str) -> Option<String> {
+	let href = href.trim();
+	if href.starts_with("http://") || href.starts_with("https://") {
+		Some(String::from(href))
+	} else if href.starts_with("//") {
+		Some(format!("https:{}", href))
+	} else if href.starts_with('/') {
+		Some(format!("https://{}{}", config_get_string("fqdn"), href))
+	} else {
+		None
+	}
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+/// Runs when `profile_render` is called without `runs`
+pub const PROFILE_RUNS: u32 = 20;
+
+/// Upper limit for `runs`, each one renders the page from scratch
+pub const PROFILE_RUNS_MAX: u32 = 500;
+
+
+// ------------------------------
+// ----------- PROFILE ----------
+// ------------------------------
+
+/// Timings of one phase of rendering a page, in milliseconds
+#[derive(Debug, Serialize)]
+pub struct PhaseTimings {
+	min_ms: f32,
+	avg_ms: f32,
+	p95_ms: f32,
+	max_ms: f32,
+}
+
+impl PhaseTimings {
+	fn from_samples(mut samples: Vec<Duration>) -> PhaseTimings {
+		if samples.len() == 0 {
+			return PhaseTimings { min_ms: 0.0, avg_ms: 0.0, p95_ms: 0.0, max_ms: 0.0 };
+		}
+		samples.sort();
+
+		let ms = |duration: &Duration| duration.as_micros() as f32 / 1000.0;
+		let total: Duration = samples.iter().sum();
+		// Nearest rank, with 20 runs that is the 19th
+		let p95 = ((samples.len() as f32 * 0.95).ceil() as usize).max(1) - 1;
+
+		PhaseTimings {
+			min_ms: ms(&samples[0]),
+			avg_ms: ms(&total) / samples.len() as f32,
+			p95_ms: ms(&samples[p95]),
+			max_ms: ms(&samples[samples.len() - 1]),
+		}
+	}
+}
+
+/// Where the time goes when a page is rendered without the cache
+///
+/// `context` is building the `Context` from the blog data, `serialize` is turning it into a Tera context
+/// and `render` is Tera itself plus dated conditions. The first run warms up e.g. expanded posts and is
+/// only reported as `first_run_ms`
+#[derive(Debug, Serialize)]
+pub struct RenderProfile {
+	target: String,
+	template: String,
+	id: u32,
+	runs: u32,
+	html_bytes: usize,
+	first_run_ms: f32,
+	context: PhaseTimings,
+	serialize: PhaseTimings,
+	render: PhaseTimings,
+	total: PhaseTimings,
+	/// Part of the average total spent outside of Tera, building and serializing the context
+	context_share: f32,
+}
+
+/// Collects the timings of each run for a `RenderProfile`
+pub struct RenderSamples {
+	first_run: Option<Duration>,
+	context: Vec<Duration>,
+	serialize: Vec<Duration>,
+	render: Vec<Duration>,
+	html_bytes: usize,
+}
+
+impl RenderSamples {
+	pub fn new() -> RenderSamples {
+		RenderSamples { first_run: None, context: Vec::new(), serialize: Vec::new(), render: Vec::new(), html_bytes: 0 }
+	}
+
+	/// Add the timings of a run, the first one is the warm-up
+	pub fn add(&mut self, context: Duration, serialize: Duration, render: Duration, html_bytes: usize) {
+		self.html_bytes = html_bytes;
+		if self.first_run.is_none() {
+			self.first_run = Some(context + serialize + render);
+			return;
+		}
+
+		self.context.push(context);
+		self.serialize.push(serialize);
+		self.render.push(render);
+	}
+
+	pub fn into_profile(self, target: &str, template: &str, id: u32) -> RenderProfile {
+		let total: Vec<Duration> = self.context.iter().zip(&self.serialize).zip(&self.render)
+			.map(|((context, serialize), render)| *context + *serialize + *render)
+			.collect();
+		let total_sum: Duration = total.iter().sum();
+		let outside_sum: Duration = self.context.iter().chain(&self.serialize).sum();
+
+		RenderProfile {
+			target: String::from(target),
+			template: String::from(template),
+			id,
+			runs: self.render.len() as u32,
+			html_bytes: self.html_bytes,
+			first_run_ms: self.first_run.map(|tmp| tmp.as_micros() as f32 / 1000.0).unwrap_or(0.0),
+			context: PhaseTimings::from_samples(self.context),
+			serialize: PhaseTimings::from_samples(self.serialize),
+			render: PhaseTimings::from_samples(self.render),
+			total: PhaseTimings::from_samples(total),
+			context_share: if total_sum.as_micros() > 0 { outside_sum.as_micros() as f32 / total_sum.as_micros() as f32 } else { 0.0 },
+		}
+	}
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::blog::types::post::Post;
+
+/// Posts recommended when `recommended_posts_count` is not configured
+pub const RECOMMENDED_POSTS: usize = 4;
+
+/// Each tag a candidate shares with the post, the primary tag counts twice
+const SHARED_TAG_SCORE: f64 = 1.0;
+
+/// Added for the most viewed post, less popular ones get a share of it on a log scale
+const POPULARITY_SCORE: f64 = 0.75;
+
+
+// ------------------------------
+// ------- RECOMMENDATIONS ------
+// ------------------------------
+
+/// Posts a reader of `post` might also like, best first
+///
+/// Candidates score for the tags they share with the post and for their recent views, so a popular
+/// post from another category can still make it. The same for every reader, no cookies involved.
+/// The post itself, its curated `related_posts` and posts with regional restrictions are left out
+pub fn recommend_posts(post: &Post, posts: &HashMap<u32, Arc<Post>>, views: &HashMap<u32, u32>, limit: usize) -> Vec<u32> {
+	let max_views = views.values().cloned().max().unwrap_or(0);
+	let primary_tag = post.primary_tag();
+
+	let mut scored: Vec<(f64, u64, u32)> = Vec::new();
+	for candidate in posts.values() {
+		if candidate.id == post.id || post.related_posts.contains(&candidate.id) || candidate.is_region_restricted() { continue; }
+
+		let mut score = 0.0;
+		for tag in &candidate.tags {
+			if !post.tags.contains(tag) { continue; }
+			score += if Some(tag) == primary_tag { 2.0 * SHARED_TAG_SCORE } else { SHARED_TAG_SCORE };
+		}
+
+		match views.get(&candidate.id) {
+			Some(count) if max_views > 0 => { score += POPULARITY_SCORE * (1.0 + *count as f64).ln() / (1.0 + max_views as f64).ln(); }
+			_ => {}
+		}
+
+		if score > 0.0 { scored.push((score, candidate.date_posted, candidate.id)); }
+	}
+
+	// Ties go to the newer post
+	scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal).then(b.1.cmp(&a.1)));
+	scored.into_iter().take(limit).map(|(_, _, id)| id).collect()
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use regex::{Captures, Regex};
+
+use crate::blog::types::revision::store_post_revision;
+use crate::storage::Storage;
+
+/// Matches shown per post in the preview, all of them are replaced
+const REPLACE_DIFF_MAX: usize = 20;
+
+/// Characters of content shown around a match
+const REPLACE_DIFF_CONTEXT: usize = 40;
+
+
+// ------------------------------
+// ----------- REPLACE ----------
+// ------------------------------
+
+/// Find and replace across the content of all posts, e.g. `http://` to `https://`
+#[derive(Deserialize, Debug)]
+pub struct ReplaceRequest {
+	pub search: String,
+	#[serde(default)]
+	pub replace: String,
+
+	/// Treat `search` as a regular expression, `replace` may use groups like `$1`
+	#[serde(default)]
+	pub regex: bool,
+
+	/// Posts that are left alone
+	#[serde(default)]
+	pub exclude: Vec<u32>,
+
+	/// Only a preview unless this is set
+	#[serde(default)]
+	pub apply: bool,
+}
+
+/// One match in a post, with some content around it
+#[derive(Serialize, Debug)]
+pub struct ReplaceDiff {
+	pub before: String,
+	pub after: String,
+}
+
+/// A post the search matched
+#[derive(Serialize, Debug)]
+pub struct ReplacePost {
+	pub post_id: u32,
+	pub title: String,
+	pub state: String,
+	pub matches: usize,
+	pub diff: Vec<ReplaceDiff>,
+	/// Why the post was not changed, e.g. `excluded`
+	pub skipped: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplaceResult {
+	pub applied: bool,
+	pub changed: usize,
+	pub posts: Vec<ReplacePost>,
+}
+
+/// Preview or apply a replacement in every post, drafts included
+///
+/// The content before the change is kept as a revision of the post
+pub fn replace_in_posts(db: &Arc<dyn Storage>, user_id: u32, request: &ReplaceRequest) -> Result<ReplaceResult, String> {
+	if request.search.len() == 0 {
+		return Err(String::from("nothing to search for"));
+	}
+
+	let pattern = match request.regex {
+		true => request.search.clone(),
+		false => regex::escape(&request.search)
+	};
+	let search = Regex::new(&pattern).map_err(|err| format!("invalid regular expression: {}", err))?;
+
+	let rows = db.query("SELECT id, title, state, content, blocks, version FROM posts ORDER BY id DESC", db_params!()).map_err(|err| err.to_string())?;
+
+	let reason = format!("replace '{}' with '{}'", request.search, request.replace);
+	let mut posts = Vec::new();
+	let mut changed = 0;
+
+	for mut row in rows {
+		let (post_id, title, state, content, blocks, version) = match (row.take::<u32>("id"), row.take::<String>("title"), row.take::<String>("state"),
+			row.take::<String>("content"), row.take::<Option<String>>("blocks"), row.take::<u32>("version")) {
+			(Some(a), Some(b), Some(c), Some(d), Some(e), Some(f)) => (a, b, c, d, e, f),
+			_ => { continue; }
+		};
+
+		let (new_content, diff, matches) = replace_content(&search, &content, &request.replace, request.regex);
+		if matches == 0 || new_content == content { continue; }
+
+		// The content of posts made of blocks is rendered from them on every save
+		let skipped = if request.exclude.contains(&post_id) {
+			Some(String::from("excluded"))
+		} else if blocks.is_some_and(|tmp| tmp.len() > 0 && tmp != "[]") {
+			Some(String::from("the post is made of blocks"))
+		} else if request.apply {
+			match store_replaced_content(db, post_id, version, user_id, &reason, &content, &new_content) {
+				Ok(()) => { changed += 1; None }
+				Err(err) => { Some(err) }
+			}
+		} else {
+			None
+		};
+
+		posts.push(ReplacePost { post_id, title, state, matches, diff, skipped });
+	}
+
+	Ok(ReplaceResult { applied: request.apply, changed, posts })
+}
+
+/// Replace every match, returns the new content, the first few changes and the number of matches
+fn replace_content(search: &Regex, content: &str, replace: &str, expand: bool) -> (String, Vec<ReplaceDiff>, usize) {
+	let mut result = String::with_capacity(content.len());
+	let mut diff = Vec::new();
+	let mut matches = 0;
+	let mut last = 0;
+
+	for caps in search.captures_iter(content) {
+		let found = match caps.get(0) {
+			Some(tmp) => tmp,
+			_ => { continue; }
+		};
+
+		let replacement = replacement_for(&caps, replace, expand);
+		result.push_str(&content[last..found.start()]);
+		result.push_str(&replacement);
+		last = found.end();
+		matches += 1;
+
+		if diff.len() < REPLACE_DIFF_MAX {
+			let before = tail_chars(&content[..found.start()], REPLACE_DIFF_CONTEXT);
+			let after = head_chars(&content[found.end()..], REPLACE_DIFF_CONTEXT);
+			diff.push(ReplaceDiff {
+				before: format!("{}{}{}", before, found.as_str(), after),
+				after: format!("{}{}{}", before, replacement, after),
+			});
+		}
+	}
+	result.push_str(&content[last..]);
+
+	(result, diff, matches)
+}
+
+/// Literal replacements are taken as they are, regular expressions may refer to groups
+fn replacement_for(caps: &Captures, replace: &str, expand: bool) -> String {
+	if !expand { return String::from(replace); }
+
+	let mut tmp = String::new();
+	caps.expand(replace, &mut tmp);
+	tmp
+}
+
+fn head_chars(text: &str, count: usize) -> &str {
+	match text.char_indices().nth(count) {
+		Some((index, _)) => &text[..index],
+		_ => text
+	}
+}
+
+fn tail_chars(text: &str, count: usize) -> &str {
+	match text.char_indices().rev().take(count).last() {
+		Some((index, _)) => &text[index..],
+		_ => ""
+	}
+}
+
+/// Keep a revision, then write the new content unless the post was changed in the meantime
+///
+/// The modification date stays, a bulk edit like a domain move is no news for readers
+fn store_replaced_content(db: &Arc<dyn Storage>, post_id: u32, version: u32, user_id: u32, reason: &str, content: &str, new_content: &str) -> Result<(), String> {
+	store_post_revision(db, post_id, user_id, reason, content)?;
+
+	let query = "UPDATE posts SET content=:content, version=version+1 WHERE id=:id AND version=:version";
+	match db.exec(query, db_params! {"id" => post_id, "version" => version, "content" => new_content}) {
+		Ok(res) if res.affected_rows == 0 => { Err(String::from("the post was changed in the meantime")) }
+		Ok(_) => { Ok(()) }
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
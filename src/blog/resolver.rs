@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::app::config::config_get_bool;
+use crate::app::templates::slugify;
+
+/// Characters escaped in the path of a SEO url, besides anything that is not ASCII
+const PATH_ESCAPE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}').add(b'%');
+
+
+// ------------------------------
+// ---------- RESOLVER ----------
+// ------------------------------
+
+/// How a SEO url leads to a post
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostMatch {
+	/// The current url of the post, served by the post route
+	Canonical(u32),
+	/// An url the post had before or another spelling of it, the fallback route moves it to the canonical one
+	Historic(u32),
+}
+
+impl PostMatch {
+	pub fn id(&self) -> u32 {
+		match self {
+			PostMatch::Canonical(id) | PostMatch::Historic(id) => *id
+		}
+	}
+}
+
+/// Looks up posts by their SEO urls, see `seo_key` for how urls are compared
+///
+/// Used by the route guard before any handler runs, so it only holds the maps and never locks
+pub struct PostResolver {
+	canonical: ArcSwap<HashMap<String, u32>>,
+	historic: ArcSwap<HashMap<String, u32>>,
+	/// Every url transliterated with `slugify`, for links like `/über-uns` to `/ueber-uns`
+	transliterated: ArcSwap<HashMap<String, u32>>,
+}
+
+impl PostResolver {
+	pub fn new() -> PostResolver {
+		PostResolver {
+			canonical: ArcSwap::from_pointee(HashMap::new()),
+			historic: ArcSwap::from_pointee(HashMap::new()),
+			transliterated: ArcSwap::from_pointee(HashMap::new()),
+		}
+	}
+
+	/// Swap in the urls of freshly loaded posts
+	pub fn store(&self, canonical: HashMap<String, u32>, historic: HashMap<String, u32>) {
+		let canonical: HashMap<String, u32> = canonical.into_iter().map(|(url, id)| (seo_key(&url), id)).collect();
+		let historic: HashMap<String, u32> = historic.into_iter().map(|(url, id)| (seo_key(&url), id)).collect();
+
+		// Canonical urls first, they win if another url comes out the same
+		let mut transliterated = HashMap::new();
+		for (url, id) in canonical.iter().chain(historic.iter()) {
+			transliterated.entry(transliterate(url)).or_insert(*id);
+		}
+
+		self.canonical.store(Arc::new(canonical));
+		self.historic.store(Arc::new(historic));
+		self.transliterated.store(Arc::new(transliterated));
+	}
+
+	/// The post behind a SEO url, the canonical url wins if another post had it before
+	///
+	/// Urls that only match once transliterated count as historic, if `slug_transliterate` is set
+	pub fn resolve(&self, seo_url: &str) -> Option<PostMatch> {
+		let key = seo_key(seo_url);
+
+		match self.canonical.load().get(key.as_str()) {
+			Some(id) => { return Some(PostMatch::Canonical(*id)); }
+			_ => {}
+		}
+
+		match self.historic.load().get(key.as_str()) {
+			Some(id) => { return Some(PostMatch::Historic(*id)); }
+			_ => {}
+		}
+
+		if !transliterate_enabled() || key.len() == 0 { return None; }
+		self.transliterated.load().get(transliterate(&key).as_str()).map(|id| PostMatch::Historic(*id))
+	}
+}
+
+/// Whether urls that only match once transliterated still find their post, `slug_transliterate`
+fn transliterate_enabled() -> bool {
+	config_get_bool("slug_transliterate")
+}
+
+/// How urls are compared: percent-decoded, in Unicode NFC, lowercase and without the leading slash
+///
+/// `caf%C3%A9`, `café` with a combining accent and `Café` are all the same url
+pub fn seo_key(seo_url: &str) -> String {
+	let decoded = percent_decode_str(seo_url.trim_start_matches('/')).decode_utf8_lossy();
+	decoded.nfc().collect::<String>().to_lowercase()
+}
+
+/// Each part of the path with umlauts and accents spelled out, see `slugify`
+fn transliterate(seo_key: &str) -> String {
+	seo_key.split('/').map(slugify).collect::<Vec<String>>().join("/")
+}
+
+/// A SEO url as it goes into a `Location` header, `/café` becomes `/caf%C3%A9`
+pub fn seo_path(seo_url: &str) -> String {
+	format!("/{}", utf8_percent_encode(seo_url.trim_start_matches('/'), PATH_ESCAPE))
+}
@@ -1,9 +1,28 @@
+use std::collections::HashMap;
+use std::io::Write;
 use std::sync::Arc;
 
 use actix_files;
-use actix_web::{Error, http, HttpRequest, HttpResponse, web};
+use actix_multipart::{Field, Multipart};
+use actix_web::{error, Error, http, HttpRequest, HttpResponse, web};
+use actix_web::http::StatusCode;
+use futures::StreamExt;
 
+use crate::app::client_ip::get_client_ip;
+use crate::app::geo::get_client_country;
+use crate::app::normalize::Campaign;
+use crate::app::config::{config_get_bool, config_get_string};
 use crate::blog::Blog;
+use crate::blog::blocklist::blocklist_check;
+use crate::blog::links::LinkRules;
+use crate::blog::mentions::{MentionError, mention_rate_limited, mentions_enabled, parse_pingback, pingback_response, pingback_url, receive_mention, trackback_response};
+use crate::blog::notifications::notify;
+use crate::blog::resolver::{PostMatch, seo_path};
+use crate::blog::spam::email_score;
+use crate::blog::webhooks::webhook_fire;
+use crate::blog::types::experiment::ExperimentVariant;
+use crate::blog::submissions::{generate_quarantine_file_name, store_photo_submission, submission_max_size, submission_rate_limited};
+use crate::storage::{ReadStorage, Storage};
 
 // ------------------------------
 // -------- FORMS & STUFF -------
@@ -20,6 +39,16 @@ pub struct QueryPage {
 	p: Option<u32>,
 }
 
+#[derive(Deserialize)]
+pub struct QueryStats {
+	token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ForwardRequest {
+	url: String,
+}
+
 #[derive(Deserialize)]
 pub struct GalleryRequest {
 	guid: String,
@@ -41,6 +70,69 @@ pub struct Comment {
 struct CommentResult {
 	id: u64,
 	error: String,
+	edit_token: String,
+	edit_until: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CommentEdit {
+	token: String,
+	text: String,
+	nd: String,
+}
+
+#[derive(Deserialize)]
+pub struct CommentDelete {
+	token: String,
+}
+
+#[derive(Deserialize)]
+pub struct PollVote {
+	poll: u32,
+	option: u32,
+}
+
+/// Sent with `navigator.sendBeacon` when a post is left, depth in percent and time in seconds
+#[derive(Deserialize)]
+pub struct EngagementBeacon {
+	post: u32,
+	depth: u32,
+	time: u32,
+}
+
+#[derive(Deserialize)]
+pub struct QueryPoll {
+	id: u32,
+}
+
+#[derive(Serialize)]
+struct PollVoteResult {
+	error: String,
+	results: Option<Arc<super::poll::PollResults>>,
+}
+
+#[derive(Serialize)]
+struct PhotoSubmitResult {
+	id: u64,
+	error: String,
+}
+
+/// Longest text field accepted along with a submitted photo
+const FORM_FIELD_MAX_SIZE: usize = 4096;
+
+#[derive(Serialize)]
+struct CommentChangeResult {
+	id: u32,
+	error: String,
+}
+
+/// A trackback as other blogs send it, only the URL is required
+#[derive(Deserialize)]
+pub struct Trackback {
+	url: Option<String>,
+	title: Option<String>,
+	excerpt: Option<String>,
+	blog_name: Option<String>,
 }
 
 // ------------------------------
@@ -48,77 +140,244 @@ struct CommentResult {
 // ------------------------------
 
 
-/// Route: index & seo fallback
-pub async fn index(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
-	let mut seo_url = path.into_inner();
+/// Route: index, the start page and older pages through `?p=`
+pub async fn index(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
+	let page = match page.p {
+		Some(tmp) => {
+			if tmp > 0 { tmp - 1 } else { 0 }
+		}
+		_ => 0
+	};
+
+	// Pages past the end are a 404
+	if page > 0 && page >= blog.get_index_page_total() {
+		return Ok(not_found_page(&blog, &tera));
+	}
 
-	// Remove trailing '/'
-	match seo_url.chars().last() {
-		Some(chr) => { if chr == '/' { seo_url.pop(); } }
+	match blog.get_html_index(&tera, page, Some(visitor_variant(&req))) {
+		Ok(html) => { Ok(list_page_response(&blog, html)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
+	}
+}
+
+/// Route: a post by its canonical SEO url, only matched for known urls, see `PostResolver`
+pub async fn post(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
+	// The path is already normalized, see `NormalizePath`
+	Ok(post_response(&req, &blog, &tera, &path.into_inner()))
+}
+
+/// Route: catch all, posts moved from historic urls and redirect rules, otherwise the 404 page
+pub async fn fallback(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
+	let seo_url = path.into_inner();
+
+	match historic_redirect(&blog, &seo_url, "") {
+		Some(response) => { return Ok(response); }
 		_ => {}
 	}
 
-	//DEBUG: println!("Catch all: {}", seo_url);
+	// Historic urls end up here if they are rendered, see `historic_redirect`
+	if blog.resolve_post(&seo_url).is_some() {
+		return Ok(post_response(&req, &blog, &tera, &seo_url));
+	}
 
-	// Need some additional info for statistics
-	let referer = match req.headers().get("referer") {
-		Some(header_val) => {
-			match header_val.to_str() {
-				Ok(tmp) => String::from(tmp),
-				_ => String::from("")
-			}
+	// Content that moved or was removed on purpose
+	if seo_url.len() > 0 {
+		match blog.match_redirect_rule(&seo_url) {
+			Some((410, _)) => { return Ok(gone_page(&blog, &tera)); }
+			Some((status, target)) => { return Ok(redirect_response(status, &target)); }
+			_ => {}
 		}
-		_ => String::from("")
-	};
-	let user_agent = match req.headers().get("user-agent") {
-		Some(header_val) => {
-			match header_val.to_str() {
-				Ok(tmp) => String::from(tmp),
-				_ => String::from("")
+	}
+
+	Ok(not_found_page(&blog, &tera))
+}
+
+/// Older urls of a post are moved for good to the canonical one, so search engines settle on one url
+///
+/// `prefix` is put before the canonical url, e.g. `/lite`. Setting `historic_urls_render` renders the
+/// post under its old urls again
+fn historic_redirect(blog: &Blog, seo_url: &str, prefix: &str) -> Option<HttpResponse> {
+	if config_get_bool("historic_urls_render") { return None; }
+
+	match blog.resolve_post(seo_url) {
+		Some(PostMatch::Historic(id)) => {
+			let post = blog.get_post(id)?;
+			Some(redirect_response(301, &format!("{}{}", prefix, seo_path(&post.url_canonical))))
+		}
+		_ => None
+	}
+}
+
+/// The HTML of a post along with the statistics of the visit
+fn post_response(req: &HttpRequest, blog: &Blog, tera: &web::Data<Arc<tera::Tera>>, seo_url: &str) -> HttpResponse {
+	// Posts with regional restrictions explain why they are not shown, CDNs must not share the answer between countries
+	let region_restricted = blog.is_post_region_restricted(seo_url);
+	if region_restricted {
+		match region_unavailable_response(req, blog, tera, seo_url) {
+			Some(response) => { return response; }
+			_ => {}
+		}
+	}
+
+	let (remote_ip, user_agent, referer, campaign, country) = visit_details(req);
+	match blog.get_html_post(seo_url, remote_ip, user_agent, referer, campaign, country, tera) {
+		Some(Ok(html)) => {
+			let mut response = HttpResponse::Ok();
+			if region_restricted {
+				response.header(http::header::CACHE_CONTROL, "private, no-store");
+			}
+			// Where pingback clients find our endpoint
+			if mentions_enabled() {
+				response.header("X-Pingback", pingback_url());
 			}
+			response.content_type("text/html").body(html)
 		}
+		Some(Err(err)) => { error_page(blog, tera, &err) }
+		// Removed while the request was on its way
+		None => { not_found_page(blog, tera) }
+	}
+}
+
+/// What the statistics keep about a visit: the client ip, user agent, referer, campaign and country
+fn visit_details(req: &HttpRequest) -> (String, String, String, String, String) {
+	let referer = req.headers().get("referer").and_then(|tmp| tmp.to_str().ok()).map(String::from).unwrap_or_default();
+	let user_agent = req.headers().get("user-agent").and_then(|tmp| tmp.to_str().ok()).map(String::from).unwrap_or_default();
+	let campaign = match req.extensions().get::<Campaign>() {
+		Some(tmp) => tmp.0.clone(),
 		_ => String::from("")
 	};
-	let remote_ip = match req.connection_info().remote() {
-		Some(tmp) => String::from(tmp),
-		_ => String::from("")
-	};
-//    println!("Remote: {}, Agent: {}, Referer: {}", &remote_ip, &user_agent, &referer);
 
-	let mut content = String::from("");
+	(get_client_ip(req), user_agent, referer, campaign, get_client_country(req).unwrap_or_default())
+}
+
+/// Route: lite version of a post, for slow connections
+pub async fn post_lite(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
+	let seo_url = path.into_inner();
 
-	// Some path was specified - check our SEO urls
-	if seo_url.len() > 0 {
-		match blog.get_html_post(seo_url.as_str(), remote_ip, user_agent, referer, &tera) {
-			Some(html) => { content = html; }
+	match historic_redirect(&blog, &seo_url, "/lite") {
+		Some(response) => { return Ok(response); }
+		_ => {}
+	}
+
+	let region_restricted = blog.is_post_region_restricted(&seo_url);
+	if region_restricted {
+		match region_unavailable_response(&req, &blog, &tera, &seo_url) {
+			Some(response) => { return Ok(response); }
 			_ => {}
 		}
 	}
-	// If empty, this is the index route
-	else {
-		match blog.get_html_base(&tera, "index.html") {
-			Ok(html) => { content = html; }
-			Err(err) => { content = err; }
-		}
+
+	let (remote_ip, user_agent, referer, campaign, country) = visit_details(&req);
+	match blog.get_html_post_lite(&seo_url, remote_ip, user_agent, referer, campaign, country, &tera) {
+		Some(Ok(html)) if region_restricted => { Ok(HttpResponse::Ok().header(http::header::CACHE_CONTROL, "private, no-store").content_type("text/html").body(html)) }
+		Some(Ok(html)) => { Ok(HttpResponse::Ok().content_type("text/html").body(html)) }
+		Some(Err(err)) => { Ok(error_page(&blog, &tera, &err)) }
+		None => { Ok(not_found_page(&blog, &tera)) }
+	}
+}
+
+/// Route: printer-friendly version of a post
+pub async fn post_print(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
+	let seo_url = path.into_inner();
+
+	match historic_redirect(&blog, &seo_url, "/print") {
+		Some(response) => { return Ok(response); }
+		_ => {}
 	}
 
-	// That's a 404 fall through
-	if content == "" {
-		match blog.get_html_base(&tera, "error_404.html") {
-			Ok(html) => { content = html; }
-			Err(err) => { content = err; }
+	let region_restricted = blog.is_post_region_restricted(&seo_url);
+	if region_restricted {
+		match region_unavailable_response(&req, &blog, &tera, &seo_url) {
+			Some(response) => { return Ok(response); }
+			_ => {}
 		}
 	}
 
-	if content != "" {
-		Ok(HttpResponse::Ok().content_type("text/html").body(content))
-	} else {
-		Ok(HttpResponse::InternalServerError().content_type("text/html").body(format!("Internal Server Error")))
+	let (remote_ip, user_agent, referer, campaign, country) = visit_details(&req);
+	match blog.get_html_post_print(&seo_url, remote_ip, user_agent, referer, campaign, country, &tera) {
+		Some(Ok(html)) if region_restricted => { Ok(HttpResponse::Ok().header(http::header::CACHE_CONTROL, "private, no-store").content_type("text/html").body(html)) }
+		Some(Ok(html)) => { Ok(HttpResponse::Ok().content_type("text/html").body(html)) }
+		Some(Err(err)) => { Ok(error_page(&blog, &tera, &err)) }
+		None => { Ok(not_found_page(&blog, &tera)) }
+	}
+}
+
+/// The 404 page with status 404
+fn not_found_page(blog: &Blog, tera: &web::Data<Arc<tera::Tera>>) -> HttpResponse {
+	match blog.get_html_base(tera, "error_404.html") {
+		Ok(html) => { HttpResponse::NotFound().content_type("text/html").body(html) }
+		Err(err) => { error_page(blog, tera, &err) }
+	}
+}
+
+/// The generic error page with status 500, the error itself is only logged
+fn error_page(blog: &Blog, tera: &web::Data<Arc<tera::Tera>>, err: &str) -> HttpResponse {
+	HttpResponse::InternalServerError().content_type("text/html").body(blog.get_html_error(tera, err))
+}
+
+/// The page for content that was removed on purpose, `error_410.html` if the theme has one, else the 404 page
+fn gone_page(blog: &Blog, tera: &web::Data<Arc<tera::Tera>>) -> HttpResponse {
+	let template = match tera.get_template_names().any(|name| name == "error_410.html") {
+		true => "error_410.html",
+		false => "error_404.html"
+	};
+	match blog.get_html_base(tera, template) {
+		Ok(html) => HttpResponse::Gone().content_type("text/html").body(html),
+		Err(err) => error_page(blog, tera, &err)
+	}
+}
+
+/// Send the reader on with the status of the redirect, 410 has nowhere to send them
+fn redirect_response(status: u16, target: &str) -> HttpResponse {
+	match StatusCode::from_u16(status) {
+		Ok(StatusCode::GONE) => HttpResponse::Gone().finish(),
+		Ok(status) if status.is_redirection() => HttpResponse::build(status).header(http::header::LOCATION, target).finish(),
+		_ => HttpResponse::Found().header(http::header::LOCATION, target).finish()
+	}
+}
+
+/// The explanation for readers in a country a post is not shown in, None if they may read it
+fn region_unavailable_response(req: &HttpRequest, blog: &Blog, tera: &web::Data<Arc<tera::Tera>>, seo_url: &str) -> Option<HttpResponse> {
+	let country = get_client_country(req);
+	match blog.get_html_post_unavailable(seo_url, country.as_deref(), tera)? {
+		Ok(html) => Some(HttpResponse::build(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)
+			.header(http::header::CACHE_CONTROL, "private, no-store")
+			.content_type("text/html").body(html)),
+		Err(err) => Some(error_page(blog, tera, &err))
+	}
+}
+
+/// The experiment variant of the reader, see `ExperimentVariant::for_visitor`
+fn visitor_variant(req: &HttpRequest) -> ExperimentVariant {
+	let user_agent = req.headers().get("user-agent").and_then(|tmp| tmp.to_str().ok()).unwrap_or_default();
+	ExperimentVariant::for_visitor(&get_client_ip(req), user_agent)
+}
+
+/// A list page, readers get different versions of it while experiments run so CDNs must not share it
+fn list_page_response(blog: &Blog, html: String) -> HttpResponse {
+	match blog.has_running_experiments() {
+		true => HttpResponse::Ok().header(http::header::CACHE_CONTROL, "private, no-store").content_type("text/html").body(html),
+		false => HttpResponse::Ok().content_type("text/html").body(html)
+	}
+}
+
+/// Route: index - older posts in chronological order
+pub async fn index_page(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, page: web::Path<u32>) -> Result<HttpResponse, Error> {
+	let page = if *page > 0 { *page - 1 } else { 0 };
+
+	// Pages past the end are a 404
+	if page > 0 && page >= blog.get_index_page_total() {
+		return Ok(not_found_page(&blog, &tera));
+	}
+
+	match blog.get_html_index(&tera, page, Some(visitor_variant(&req))) {
+		Ok(html) => { Ok(list_page_response(&blog, html)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
 	}
 }
 
 /// Route: tag / category
-pub async fn list_by_tag(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, mysql: web::Data<Arc<mysql::Pool>>, path: web::Path<String>, page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
+pub async fn list_by_tag(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, db: web::Data<Arc<dyn Storage>>, path: web::Path<String>, page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
 	let page = match page.p {
 		Some(tmp) => {
 			if tmp > 0 { tmp - 1 } else { 0 }
@@ -126,14 +385,30 @@ pub async fn list_by_tag(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::T
 		_ => 0
 	};
 
-	match blog.get_html_tag(&mysql, &tera, path.replace("/", ""), page) {
-		Ok(html) => { Ok(HttpResponse::Ok().content_type("text/html").body(html)) }
-		Err(err) => { Ok(HttpResponse::InternalServerError().content_type("text/html").body(err)) }
+	match blog.get_html_tag(&db, &tera, path.replace("/", ""), page, Some(visitor_variant(&req))) {
+		Ok(html) => { Ok(list_page_response(&blog, html)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
+	}
+}
+
+/// Route: archive of a single author
+pub async fn list_by_author(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<u32>, page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
+	let page = match page.p {
+		Some(tmp) => {
+			if tmp > 0 { tmp - 1 } else { 0 }
+		}
+		_ => 0
+	};
+
+	match blog.get_html_author(&tera, *path, page, Some(visitor_variant(&req))) {
+		Some(Ok(html)) => { Ok(list_page_response(&blog, html)) }
+		Some(Err(err)) => { Ok(error_page(&blog, &tera, &err)) }
+		None => { Ok(not_found_page(&blog, &tera)) }
 	}
 }
 
 /// Route: search
-pub async fn list_by_search(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, mysql: web::Data<Arc<mysql::Pool>>, search: web::Query<QuerySearch>) -> Result<HttpResponse, Error> {
+pub async fn list_by_search(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, replica: web::Data<ReadStorage>, search: web::Query<QuerySearch>) -> Result<HttpResponse, Error> {
 	let page = match search.p {
 		Some(tmp) => {
 			if tmp > 0 { tmp - 1 } else { 0 }
@@ -141,9 +416,21 @@ pub async fn list_by_search(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera
 		_ => 0
 	};
 
-	match blog.get_html_search(&mysql, &tera,search.q.clone(), page) {
+	match blog.get_html_search(&replica, &tera,search.q.clone(), page) {
 		Ok(html) => { Ok(HttpResponse::Ok().content_type("text/html").body(html)) }
-		Err(err) => { Ok(HttpResponse::InternalServerError().content_type("text/html").body(err)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
+	}
+}
+
+/// Route: public stats, aggregated views without anything about single visitors
+pub async fn stats(req: HttpRequest, blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, replica: web::Data<ReadStorage>, query: web::Query<QueryStats>) -> Result<HttpResponse, Error> {
+	if !crate::auth::has_stats_access(&req, query.token.as_deref()) {
+		return Ok(HttpResponse::Forbidden().content_type("text/html").body("forbidden"));
+	}
+
+	match blog.get_html_stats(&replica, &tera) {
+		Ok(html) => { Ok(HttpResponse::Ok().content_type("text/html").body(html)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
 	}
 }
 
@@ -151,44 +438,445 @@ pub async fn list_by_search(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera
 pub async fn sitemap(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>) -> Result<HttpResponse, Error> {
 	match blog.get_html_site_map(&tera) {
 		Ok(html) => { Ok(HttpResponse::Ok().content_type("application/xml").body(html)) }
-		Err(err) => { Ok(HttpResponse::InternalServerError().content_type("text/html").body(err)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
 	}
 }
 
+/// Route: site map for one country, e.g. `/sitemap/de.xml`
+pub async fn sitemap_region(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
+	let country = path.trim_end_matches(".xml").to_uppercase();
+	if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+		return Ok(HttpResponse::NotFound().content_type("text/html").body("unknown region"));
+	}
+
+	match blog.get_html_site_map_region(&tera, &country) {
+		Ok(html) => { Ok(HttpResponse::Ok().content_type("application/xml").body(html)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
+	}
+}
+
+/// Route: news site map, the posts of the last two days
+pub async fn sitemap_news(blog: web::Data<Arc<Blog>>) -> Result<HttpResponse, Error> {
+	match blog.get_news_site_map() {
+		Some(xml) => { Ok(HttpResponse::Ok().content_type("application/xml").body(xml)) }
+		_ => { Ok(HttpResponse::NotFound().content_type("text/html").body("no news site map")) }
+	}
+}
+
+/// Route: site map index
+pub async fn sitemap_index() -> Result<HttpResponse, Error> {
+	Ok(HttpResponse::Ok().content_type("application/xml").body(crate::blog::sitemap::site_map_index()))
+}
+
 /// Route: feed.rss
 pub async fn feed(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>) -> Result<HttpResponse, Error> {
 	match blog.get_html_rss_feed(&tera) {
 		Ok(html) => { Ok(HttpResponse::Ok().content_type("application/xml").body(html)) }
-		Err(err) => { Ok(HttpResponse::InternalServerError().content_type("text/html").body(err)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
+	}
+}
+
+/// Route: feed.rss for a single tag
+pub async fn feed_tag(blog: web::Data<Arc<Blog>>, tera: web::Data<Arc<tera::Tera>>, path: web::Path<String>) -> Result<HttpResponse, Error> {
+	match blog.get_html_rss_feed_tag(&tera, path.replace("/", "")) {
+		Ok(html) => { Ok(HttpResponse::Ok().content_type("application/xml").body(html)) }
+		Err(err) => { Ok(error_page(&blog, &tera, &err)) }
 	}
 }
 
 /// Route: gallery - image of specific size
-pub async fn gallery(path: web::Path<GalleryRequest>) -> Result<actix_files::NamedFile, Error> {
+pub async fn gallery(db: web::Data<Arc<dyn Storage>>, path: web::Path<GalleryRequest>) -> Result<actix_files::NamedFile, Error> {
+	// Unknown crop sizes would each be rendered to disk
+	if !super::gallery::gallery_crop_size_allowed(&path.size) {
+		return Err(error::ErrorNotFound("Image size not available"));
+	}
+
 	//TODO: add cache control for static pictures --> 2419200 seconds == 28 days (apparently not yet supported)
-	Ok(actix_files::NamedFile::open(super::gallery::gallery_find_file(&path.guid, &path.size, &path.tail))?)
+	media_file(&super::gallery::gallery_find_file(&db, &path.guid, &path.size, &path.tail))
 }
 
 /// Route: gallery - original image
 pub async fn gallery_direct(path: web::Path<String>) -> Result<actix_files::NamedFile, Error> {
-	Ok(actix_files::NamedFile::open(super::gallery::gallery_find_original(&path.clone()))?)
+	media_file(&super::gallery::gallery_find_original(&path.clone()))
+}
+
+/// Route: share card of a post, e.g. `/og/12-8b68e04f.jpg`
+///
+/// The card is made on the first request, an outdated name gets the current card
+pub async fn og_image(blog: web::Data<Arc<Blog>>, path: web::Path<String>) -> Result<actix_files::NamedFile, Error> {
+	let post = match path.split('-').next().and_then(|id| id.parse::<u32>().ok()).and_then(|id| blog.get_post(id)) {
+		Some(tmp) => tmp,
+		_ => return Err(error::ErrorNotFound("Post not found"))
+	};
+
+	match web::block(move || super::og_image::og_image_file(&post)).await {
+		Ok(file) => media_file(&file),
+		Err(error::BlockingError::Error(err)) => {
+			println!("Could not make the share image: {}", err);
+			Err(error::ErrorNotFound("Share image not available"))
+		}
+		Err(_) => Err(error::ErrorInternalServerError("Share image not available"))
+	}
+}
+
+/// Route: attachment download
+pub async fn files(db: web::Data<Arc<dyn Storage>>, path: web::Path<(String, String)>) -> Result<actix_files::NamedFile, Error> {
+	let attachment = match super::files::load_attachment(&db, &path.0) {
+		Some(tmp) => tmp,
+		_ => return Err(error::ErrorNotFound("File not found"))
+	};
+
+	let mime_type = attachment.mime_type().parse::<mime::Mime>().map_err(|_| error::ErrorInternalServerError("Invalid content type"))?;
+
+	Ok(media_file(&attachment.local_path())?
+		.set_content_type(mime_type)
+		.set_content_disposition(http::header::ContentDisposition {
+			disposition: http::header::DispositionType::Attachment,
+			parameters: vec![http::header::DispositionParam::Filename(String::from(attachment.name()))],
+		}))
 }
 
-/// Route: add an unapproved comment to some post
-pub async fn comment(db: web::Data<Arc<mysql::Pool>>, comment: web::Json<Comment>) -> Result<HttpResponse, Error> {
-	match super::comment::Comment::store_unapproved_comment(&db, comment.post, comment.parent, &comment.author, &comment.email, &comment.text, &comment.nd) {
-		Ok(id) => { Ok(HttpResponse::Ok().json(CommentResult { id, error: String::from("") })) }
-		Err(error) => { Ok(HttpResponse::InternalServerError().json(CommentResult { id: 0, error })) }
+/// Open a media file so it can be served with Range support
+///
+/// NamedFile answers Range and If-Range requests on its own, but the compression middleware would
+/// encode partial responses as well - media is compressed already, so we always send it as is
+fn media_file(path: &str) -> Result<actix_files::NamedFile, Error> {
+	Ok(actix_files::NamedFile::open(path)?
+		.use_etag(true)
+		.use_last_modified(true)
+		.set_content_encoding(http::header::ContentEncoding::Identity))
+}
+
+/// Route: add a comment to some post
+///
+/// First-time commenters are held for moderation, see `Blog::may_auto_approve_comment`
+pub async fn comment(req: HttpRequest, blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, comment: web::Json<Comment>) -> Result<HttpResponse, Error> {
+	if blocklist_check(&db, &get_client_ip(&req), &comment.email) {
+		let error = String::from("Your comment could not be accepted.");
+		return Ok(HttpResponse::Forbidden().json(CommentResult { id: 0, error, edit_token: String::from(""), edit_until: 0 }));
+	}
+
+	let approve = blog.may_auto_approve_comment(&comment.email);
+	let email_score = email_score(&comment.email).await;
+	match super::comment::Comment::store_new_comment(&db, comment.post, comment.parent, &comment.author, &comment.email, &comment.text, &comment.nd, approve, email_score) {
+		Ok((id, status)) => {
+			// Approved comments are shown right away
+			if status == "approved" {
+				match blog.refresh_post_comments(&db, comment.post) {
+					Err(err) => { println!("Could not refresh the comments: {:?}", err); }
+					_ => {}
+				}
+			}
+
+			// Spam is kept from the webhooks
+			match super::comment::admin_fetch_comment(&db, id as u32) {
+				Some(tmp) if tmp.status != "spam" => {
+					webhook_fire(&db, "comment.created", &format!("{} commented: {}", tmp.author_name, tmp.content),
+						json!({"id": tmp.id, "post_id": tmp.post_id, "author": tmp.author_name, "content": tmp.content}));
+					if tmp.status != "approved" {
+						notify(&db, "comment", "New comments to approve", &format!("{} commented on post {}", tmp.author_name, tmp.post_id), "comments");
+					}
+				}
+				_ => {}
+			}
+
+			// The author may change their mind for a few minutes
+			let (edit_token, edit_until) = crate::auth::jwt::create_comment_edit_token(id as u32).unwrap_or((String::from(""), 0));
+			Ok(HttpResponse::Ok().json(CommentResult { id, error: String::from(""), edit_token, edit_until }))
+		}
+		Err(error) => { Ok(HttpResponse::InternalServerError().json(CommentResult { id: 0, error, edit_token: String::from(""), edit_until: 0 })) }
+	}
+}
+
+/// Route: let the author edit their comment, it has to be approved again
+///
+/// The edit gets the same protection as a new comment, see `comment`
+pub async fn comment_edit(req: HttpRequest, blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, edit: web::Json<CommentEdit>) -> Result<HttpResponse, Error> {
+	let own = match super::comment::Comment::find_own_comment(&db, &edit.token) {
+		Ok(tmp) => tmp,
+		Err(err) => { return comment_changed(&blog, &db, Err(err)); }
+	};
+
+	if blocklist_check(&db, &get_client_ip(&req), &own.author_email) {
+		let error = String::from("Your comment could not be accepted.");
+		return Ok(HttpResponse::Forbidden().json(CommentChangeResult { id: 0, error }));
+	}
+
+	let email_score = email_score(&own.author_email).await;
+	let result = super::comment::Comment::edit_own_comment(&db, own, &edit.text, &edit.nd, email_score);
+	comment_changed(&blog, &db, result)
+}
+
+/// Route: let the author delete their comment
+pub async fn comment_delete(blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, delete: web::Json<CommentDelete>) -> Result<HttpResponse, Error> {
+	let result = super::comment::Comment::delete_own_comment(&db, &delete.token);
+	comment_changed(&blog, &db, result)
+}
+
+/// Drop a comment that was public from the caches and answer the request
+///
+/// `result` holds the comment as it was before the change
+fn comment_changed(blog: &Arc<Blog>, db: &Arc<dyn Storage>, result: Result<super::comment::Comment, String>) -> Result<HttpResponse, Error> {
+	match result {
+		Ok(before) => {
+			if before.status == "approved" {
+				match blog.refresh_post_comments(db, before.post_id) {
+					Err(err) => { println!("Could not refresh the comments: {:?}", err); }
+					_ => {}
+				}
+			}
+			Ok(HttpResponse::Ok().json(CommentChangeResult { id: before.id, error: String::from("") }))
+		}
+		Err(error) => { Ok(HttpResponse::BadRequest().json(CommentChangeResult { id: 0, error })) }
+	}
+}
+
+/// Route: a reader submits a photo, it waits in the quarantine until it is moderated
+///
+/// Expects the fields `author`, `email`, `title`, `caption`, `nd` and a single file in `photo`
+pub async fn photo_submit(req: HttpRequest, db: web::Data<Arc<dyn Storage>>, multipart: Multipart) -> Result<HttpResponse, Error> {
+	let remote_ip = get_client_ip(&req);
+	if submission_rate_limited(&db, &remote_ip) {
+		return Ok(photo_submit_error(StatusCode::TOO_MANY_REQUESTS, "You sent enough photos for today, thank you!"));
+	}
+
+	let (form, path) = match read_photo_submission(multipart).await {
+		Ok((form, Some(path))) => (form, path),
+		Ok(_) => { return Ok(photo_submit_error(StatusCode::BAD_REQUEST, "Please choose a photo.")); }
+		Err((status, error)) => { return Ok(photo_submit_error(status, &error)); }
+	};
+	let field = |name: &str| form.get(name).cloned().unwrap_or_default();
+
+	// Same protection as for comments
+	let rejection = if config_get_string("bot_block_solution") != field("nd").to_lowercase().trim() {
+		Some((StatusCode::BAD_REQUEST, "Please check your answer to the spam protection question."))
+	} else if blocklist_check(&db, &remote_ip, &field("email")) {
+		Some((StatusCode::FORBIDDEN, "Your photo could not be accepted."))
+	} else {
+		None
+	};
+	match rejection {
+		Some((status, error)) => {
+			let _ = web::block(move || std::fs::remove_file(path)).await;
+			return Ok(photo_submit_error(status, error));
+		}
+		_ => {}
+	}
+
+	// Decoding the photo takes a moment
+	let db = db.get_ref().clone();
+	let (author, email, title, caption) = (field("author"), field("email"), field("title"), field("caption"));
+	let submit = move || store_photo_submission(&db, &path, &author, &email, &title, &caption, &remote_ip).inspect(|_id| {
+		notify(&db, "submission", "New photo submissions", &format!("{} sent a photo", author), "submissions");
+	});
+	match web::block(submit).await {
+		Ok(id) => { Ok(HttpResponse::Ok().json(PhotoSubmitResult { id, error: String::from("") })) }
+		Err(error::BlockingError::Error(err)) => { Ok(photo_submit_error(StatusCode::BAD_REQUEST, &err)) }
+		Err(_) => { Ok(photo_submit_error(StatusCode::INTERNAL_SERVER_ERROR, "Your photo could not be stored.")) }
+	}
+}
+
+fn photo_submit_error(status: StatusCode, error: &str) -> HttpResponse {
+	HttpResponse::build(status).json(PhotoSubmitResult { id: 0, error: String::from(error) })
+}
+
+/// Collect the text fields and store the photo in the quarantine, nothing is left on disk on failure
+async fn read_photo_submission(mut multipart: Multipart) -> Result<(HashMap<String, String>, Option<String>), (StatusCode, String)> {
+	let mut form = HashMap::new();
+	let mut photo: Option<String> = None;
+
+	match read_photo_submission_fields(&mut multipart, &mut form, &mut photo).await {
+		Ok(_) => Ok((form, photo)),
+		Err(err) => {
+			match photo {
+				Some(path) => { let _ = web::block(move || std::fs::remove_file(path)).await; }
+				_ => {}
+			}
+			Err(err)
+		}
+	}
+}
+
+async fn read_photo_submission_fields(multipart: &mut Multipart, form: &mut HashMap<String, String>, photo: &mut Option<String>) -> Result<(), (StatusCode, String)> {
+	while let Some(item) = multipart.next().await {
+		let mut field = item.map_err(|_| (StatusCode::BAD_REQUEST, String::from("The upload was interrupted.")))?;
+		let (name, file_name) = match field.content_disposition() {
+			Some(tmp) => (String::from(tmp.get_name().unwrap_or("")), tmp.get_filename().map(String::from)),
+			_ => continue
+		};
+
+		match file_name {
+			Some(file_name) => {
+				if name != "photo" || photo.is_some() {
+					return Err((StatusCode::BAD_REQUEST, String::from("Please send a single photo.")));
+				}
+
+				let path = generate_quarantine_file_name(&file_name).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+				*photo = Some(path.clone());
+
+				let written = write_limited_upload_field(&mut field, &path, submission_max_size()).await
+					.map_err(|_| (StatusCode::BAD_REQUEST, String::from("The upload was interrupted.")))?;
+				if !written {
+					return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("The photo may not be larger than {} MB.", submission_max_size() / 1048576)));
+				}
+			}
+			None => {
+				let mut value = Vec::new();
+				while let Some(chunk) = field.next().await {
+					let data = chunk.map_err(|_| (StatusCode::BAD_REQUEST, String::from("The upload was interrupted.")))?;
+					if value.len() + data.len() > FORM_FIELD_MAX_SIZE {
+						return Err((StatusCode::BAD_REQUEST, format!("'{}' is too long.", name)));
+					}
+					value.extend_from_slice(&data);
+				}
+				form.insert(name, String::from_utf8(value).map_err(|_| (StatusCode::BAD_REQUEST, String::from("The form is not valid text.")))?);
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Stream an uploaded field into a new file, gives up once it gets larger than `max_size`
+///
+/// Returns false if the file was too large
+async fn write_limited_upload_field(field: &mut Field, local_file_name: &str, max_size: u64) -> Result<bool, Error> {
+	let local_file_name_clone = String::from(local_file_name);
+	let mut file = web::block(move || std::fs::File::create(local_file_name_clone)).await?;
+	let mut written = 0;
+
+	while let Some(chunk) = field.next().await {
+		let data = chunk?;
+		written += data.len() as u64;
+		if written > max_size {
+			return Ok(false);
+		}
+		file = web::block(move || file.write_all(&data).map(|_| file)).await?;
+	}
+
+	Ok(true)
+}
+
+/// Route: a pingback, the XML-RPC call `pingback.ping` with the linking page and the post it links to
+///
+/// Faults are answered with 200 as XML-RPC wants it
+pub async fn pingback(req: HttpRequest, blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, body: web::Bytes) -> Result<HttpResponse, Error> {
+	if !mentions_enabled() {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let result = match parse_pingback(&String::from_utf8_lossy(&body)) {
+		Ok((source, target)) => match blog.get_post_by_url(&target) {
+			Some((post_id, mut targets)) => {
+				// The client names the URL it found, which may be an older one
+				targets.push(target);
+				receive(&req, &blog, &db, post_id, targets, "pingback", source, String::new(), String::new(), String::new()).await
+			}
+			_ => Err(MentionError::TargetMissing)
+		},
+		Err(err) => Err(err)
+	};
+
+	Ok(HttpResponse::Ok().content_type("text/xml; charset=utf-8").body(pingback_response(&result)))
+}
+
+/// Route: a trackback to the post with the given id, a form with `url`, `title`, `excerpt` and `blog_name`
+pub async fn trackback(req: HttpRequest, blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, post_id: web::Path<u32>, form: web::Form<Trackback>) -> Result<HttpResponse, Error> {
+	if !mentions_enabled() {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let form = form.into_inner();
+	let result = match (form.url, blog.get_post(*post_id)) {
+		(Some(source), Some(post)) => {
+			let targets = vec![format!("https://{}/{}", config_get_string("fqdn"), post.url_canonical)];
+			receive(&req, &blog, &db, post.id, targets, "trackback", source,
+				form.title.unwrap_or_default(), form.excerpt.unwrap_or_default(), form.blog_name.unwrap_or_default()).await
+		}
+		(None, _) => Err(MentionError::SourceMissing),
+		_ => Err(MentionError::TargetMissing)
+	};
+
+	Ok(HttpResponse::Ok().content_type("text/xml; charset=utf-8").body(trackback_response(&result)))
+}
+
+/// Verify a mention and queue it for moderation, the admins are told about new ones
+async fn receive(req: &HttpRequest, blog: &Arc<Blog>, db: &Arc<dyn Storage>, post_id: u32, targets: Vec<String>, kind: &'static str, source: String, title: String, excerpt: String, blog_name: String) -> Result<u64, MentionError> {
+	let remote_ip = get_client_ip(req);
+	if blocklist_check(db, &remote_ip, "") || mention_rate_limited(db, &remote_ip) {
+		return Err(MentionError::Denied);
+	}
+
+	// Fetching the source takes a moment
+	let db_fetch = db.clone();
+	let source_notify = source.clone();
+	let result = match web::block(move || receive_mention(&db_fetch, post_id, &targets, kind, &source, &title, &excerpt, &blog_name, &remote_ip)).await {
+		Ok(id) => Ok(id),
+		Err(error::BlockingError::Error(err)) => Err(err),
+		Err(_) => Err(MentionError::Internal(String::from("the mention was canceled")))
+	};
+
+	match result {
+		Ok(_) => {
+			let title = blog.get_post(post_id).map_or(String::new(), |post| post.title.clone());
+			notify(db, "mention", "New mentions to approve", &format!("{} linked to {}", source_notify, title), "mentions");
+		}
+		Err(MentionError::Internal(ref err)) => { println!("Could not store the {} from {}: {}", kind, source_notify, err); }
+		_ => {}
+	}
+	result
+}
+
+/// Route: vote in a poll, the vote is counted with the next flush of the message queue
+pub async fn poll_vote(req: HttpRequest, blog: web::Data<Arc<Blog>>, vote: web::Json<PollVote>) -> Result<HttpResponse, Error> {
+	match blog.vote_poll(vote.poll, vote.option, get_client_ip(&req)) {
+		Ok(_) => { Ok(HttpResponse::Ok().json(PollVoteResult { error: String::from(""), results: blog.get_poll_results(vote.poll) })) }
+		Err(error) => { Ok(HttpResponse::BadRequest().json(PollVoteResult { error, results: None })) }
+	}
+}
+
+/// Route: reading progress of a post, stored with the next flush of the message queue
+///
+/// Beacons can not set a JSON content type without a preflight, so the body is parsed whatever it says
+pub async fn beacon(req: HttpRequest, blog: web::Data<Arc<Blog>>, body: web::Bytes) -> Result<HttpResponse, Error> {
+	let beacon = match serde_json::from_slice::<EngagementBeacon>(&body) {
+		Ok(tmp) => tmp,
+		_ => { return Ok(HttpResponse::BadRequest().finish()); }
+	};
+
+	match blog.record_engagement(beacon.post, beacon.depth, beacon.time, get_client_ip(&req)) {
+		Ok(_) => { Ok(HttpResponse::NoContent().finish()) }
+		Err(_) => { Ok(HttpResponse::NotFound().finish()) }
+	}
+}
+
+/// Route: the current results of a poll
+pub async fn poll_results(blog: web::Data<Arc<Blog>>, poll: web::Query<QueryPoll>) -> Result<HttpResponse, Error> {
+	match blog.get_poll_results(poll.id) {
+		Some(results) => { Ok(HttpResponse::Ok().json(&*results)) }
+		_ => { Ok(HttpResponse::NotFound().finish()) }
 	}
 }
 
 /// Route: redirect generic
 pub async fn forward(blog: web::Data<Arc<Blog>>, name: web::Path<String>, _page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
-	Ok(HttpResponse::Found().header(http::header::LOCATION, blog.lookup_redirect(&name)).finish())
+	let (status, target) = blog.lookup_redirect(&name);
+	Ok(redirect_response(status, &target))
+}
+
+/// Route: redirect to an outbound link, only for the domains in `links_tracked_domains`
+///
+/// Anything else goes to the home page, this must not become an open redirect
+pub async fn forward_url(link: web::Query<ForwardRequest>) -> Result<HttpResponse, Error> {
+	let target = match LinkRules::from_config().is_tracked(&link.url) {
+		true => link.url.clone(),
+		false => format!("https://{}", config_get_string("fqdn"))
+	};
+	Ok(HttpResponse::Found().header(http::header::LOCATION, target).finish())
 }
 
 /// Route: redirect amazon
-pub async fn forward_amazon(id: web::Path<String>, page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
+pub async fn forward_amazon(_id: web::Path<String>, _page: web::Query<QueryPage>) -> Result<HttpResponse, Error> {
 	//TODO: detect user location using IP
 	//TODO: get the right store address and affiliate id
 	//TODO: redirect as required
@@ -1,16 +1,36 @@
+use std::io;
 use std::io::Write;
 use std::sync::Arc;
 
 use actix_files;
 use actix_multipart::{Field, Multipart};
-use actix_web::{error, Error, HttpRequest, HttpResponse, web};
+use actix_web::{error, http, Error, HttpRequest, HttpResponse, web};
+use actix_web::http::StatusCode;
 use futures::StreamExt;
 use tera::Context;
 
+use crate::app::api::{api_error, api_ok, api_option, api_result, api_store_result, api_unauthorized};
+use crate::app::config::config_get_string;
+use crate::app::settings::settings;
 use crate::blog::Blog;
+use crate::blog::a11y::a11y_audit_html;
 use crate::blog::dashboard::dashboard_get_statistics;
+use crate::blog::export::{export_document, ExportFormat, pdf_export_enabled};
+use crate::blog::files::{finish_attachment_upload, generate_attachment_file_name, PendingAttachment};
+use crate::blog::flags::{feature_flags, flag_delete, flag_store, FeatureFlag};
 use crate::blog::gallery::finish_file_upload;
+use crate::blog::blocklist::{blocklist_delete_entry, blocklist_entries, blocklist_store_entry, BlockEntry};
 use crate::blog::gallery::generate_upload_file_name;
+use crate::blog::notifications::{load_notifications, mark_notifications_read};
+use crate::blog::profile::{PROFILE_RUNS, PROFILE_RUNS_MAX};
+use crate::blog::replace::{replace_in_posts, ReplaceRequest};
+use crate::blog::search::admin_search;
+use crate::blog::types::AdminListRequest;
+use crate::blog::mentions::load_mentions;
+use crate::blog::submissions::{load_photo_submission, load_photo_submissions, SubmissionApproval};
+use crate::blog::spam::{spam_rules, spam_rules_store, SpamRules};
+use crate::blog::webhooks::{delete_webhook, load_webhook_deliveries, load_webhooks, store_webhook, webhook_fire, Webhook};
+use crate::storage::{ReadStorage, Storage};
 
 // ------------------------------
 // -------- FORMS & STUFF -------
@@ -21,6 +41,34 @@ pub struct GetPostRequest {
 	id: u32,
 }
 
+#[derive(Deserialize)]
+pub struct LockPostRequest {
+	id: u32,
+	/// Give up the lock instead, e.g. when the editor is closed
+	#[serde(default)]
+	release: bool,
+	/// Take the lock over from someone else
+	#[serde(default)]
+	force: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ExportPostRequest {
+	id: u32,
+	/// `html` or `pdf`, html if not given
+	format: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ProfileRenderRequest {
+	/// `post`, `lite`, `print`, `index` or the name of a template
+	template: String,
+	/// The post, or the page of the index
+	#[serde(default)]
+	id: u32,
+	runs: Option<u32>,
+}
+
 #[derive(Deserialize)]
 pub struct GetTagRequest {
 	id: String,
@@ -31,32 +79,155 @@ pub struct GetCommentRequest {
 	id: u32,
 }
 
+#[derive(Deserialize)]
+pub struct GetAutosaveRequest {
+	id: u32,
+}
+
+#[derive(Deserialize)]
+pub struct GetImageRequest {
+	guid: String,
+}
+
 #[derive(Deserialize)]
 pub struct ReloadDataRequest {
 	which: String,
 }
 
+#[derive(Deserialize)]
+pub struct DeleteItemRequest {
+	id: u32,
+}
+
+#[derive(Deserialize)]
+pub struct AdminSearchRequest {
+	q: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetNotificationsRequest {
+	unread: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct ReadNotificationsRequest {
+	#[serde(default)]
+	ids: Vec<u64>,
+	/// Marks every notification, `ids` is ignored then
+	#[serde(default)]
+	all: bool,
+}
+
+#[derive(Serialize)]
+struct ReadNotificationsResult {
+	marked: u64,
+}
+
+#[derive(Deserialize)]
+pub struct GetPhotoSubmissionsRequest {
+	status: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PhotoSubmissionRequest {
+	id: u32,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteFlagRequest {
+	name: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetMentionsRequest {
+	status: Option<String>,
+}
+
+/// `approved` or `rejected`
+#[derive(Deserialize)]
+pub struct ModerateMentionRequest {
+	id: u32,
+	status: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmAltTextRequest {
+	guid: String,
+	/// The suggestion as is or edited, empty to reject it
+	alt_text: String,
+}
+
+#[derive(Serialize)]
+struct GetPostResult {
+	#[serde(flatten)]
+	post: super::post::Post,
+	/// Set while someone has the post open in the editor, possibly the current user
+	lock: Option<super::lock::PostLock>,
+}
+
+#[derive(Serialize)]
+struct LockPostResult {
+	/// The post is open in someone else's editor
+	locked: bool,
+	lock: Option<super::lock::PostLock>,
+}
+
 #[derive(Serialize)]
 struct SetPostResult {
 	post_id: u64,
-	error: String,
+	version: u32,
+}
+
+#[derive(Serialize)]
+struct AutosaveResult {
+	post_id: u32,
+	saved_at: u64,
+}
+
+#[derive(Serialize)]
+struct ImageMetadataResult {
+	metadata: super::image_meta::ImageMetadata,
+	location: Option<super::post::PostLocation>,
 }
 
 #[derive(Serialize)]
 struct SetTagResult {
 	tag_id: String,
-	error: String,
+	version: u32,
 }
 
 #[derive(Serialize)]
 struct SetCommentResult {
 	comment_id: u32,
-	error: String,
+	version: u32,
+}
+
+#[derive(Serialize)]
+struct SetItemResult {
+	id: u64,
+}
+
+#[derive(Serialize)]
+struct WebhookList {
+	webhooks: Vec<Webhook>,
+	deliveries: Vec<super::webhooks::WebhookDelivery>,
+}
+
+#[derive(Serialize)]
+struct PollList {
+	polls: Vec<super::poll::Poll>,
+	results: Vec<Arc<super::poll::PollResults>>,
+}
+
+#[derive(Serialize)]
+struct ReloadHookResult {
+	which: String,
+	num: usize,
+	error: Option<String>,
 }
 
 #[derive(Serialize)]
 struct ReloadDataResult {
-	success: bool,
 	num: usize,
 }
 
@@ -72,7 +243,23 @@ pub async fn index2() -> Result<actix_files::NamedFile, Error> {
 
 pub async fn preview_post(ctx: web::Json<super::context::Context>, template: web::Data<Arc<tera::Tera>>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		match template.render("post.html", &Context::from_serialize(&ctx.into_inner()).map_err(|_| error::ErrorInternalServerError("Template error"))?) {
+		// Preview with the template the post will be rendered with
+		let template_name = match &ctx.post {
+			Some(post) if post.has_valid_template(&template) => String::from(post.template_name()),
+			_ => String::from("post.html")
+		};
+
+		// Posts made of blocks are previewed with their rendered content
+		let mut ctx = ctx.into_inner();
+		match &mut ctx.post {
+			Some(post) => {
+				let content = post.rendered_content();
+				Arc::make_mut(post).content = content;
+			}
+			_ => {}
+		}
+
+		match template.render(&template_name, &Context::from_serialize(&ctx).map_err(|_| error::ErrorInternalServerError("Template error"))?) {
 			Ok(s) => { Ok(HttpResponse::Ok().content_type("text/html").body(s)) }
 			_ => { Ok(HttpResponse::InternalServerError().content_type("text/html").body("Template problem")) }
 		}
@@ -81,273 +268,1042 @@ pub async fn preview_post(ctx: web::Json<super::context::Context>, template: web
 	}
 }
 
-pub async fn reload_data(rld: web::Query<ReloadDataRequest>, blog: web::Data<Arc<Blog>>, mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
-	if crate::auth::is_admin(&req) {
-		let res = match rld.which.as_str() {
-			"comments" => { blog.reload_comments(&mysql) }
-			"html" => { blog.invalidate_html_cache() }
-			"menus" => { blog.reload_menus(&mysql) }
-			"posts" => { blog.reload_posts(&mysql) }
-			"redirects" => { blog.reload_redirects(&mysql) }
-			"tags" => { blog.reload_tags(&mysql) }
-			_ => { Ok(0) }
-		};
+/// What `reload_data` and the reload hook can refresh
+const RELOAD_ACTIONS: &[&str] = &["assets", "comments", "experiments", "html", "menus", "polls", "posts", "redirects", "snippets", "tags"];
+
+/// Refresh one kind of data from the database, unknown kinds do nothing
+fn reload_blog_data(blog: &Arc<Blog>, db: &Arc<dyn Storage>, which: &str) -> Result<usize, io::Error> {
+	match which {
+		"assets" => {
+			// Pages link the old fingerprints until they are rendered again
+			let count = crate::app::assets::assets_load(&settings().server_dir_static).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+			blog.invalidate_html_cache()?;
+			Ok(count)
+		}
+		"comments" => { blog.reload_comments(db) }
+		"experiments" => { blog.reload_experiments(db) }
+		"html" => { blog.invalidate_html_cache() }
+		"menus" => { blog.reload_menus(db) }
+		"polls" => { blog.reload_polls(db) }
+		"posts" => { blog.reload_posts(db) }
+		"redirects" => { blog.reload_redirects(db) }
+		"snippets" => { blog.reload_snippets(db) }
+		"tags" => { blog.reload_tags(db) }
+		_ => { Ok(0) }
+	}
+}
+
+/// Route: admin - reload data, API tokens with the `reload_data` scope work as well
+pub async fn reload_data(rld: web::Query<ReloadDataRequest>, blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin_or_token(&req, "reload_data") {
+		let res = reload_blog_data(&blog, &db, &rld.which);
+
+		// Posts and tags reference each other, check them again
+		if rld.which == "posts" || rld.which == "tags" {
+			blog.check_data_consistency();
+		}
 
 		match res {
-			Err(_err) => { Ok(HttpResponse::Ok().json(ReloadDataResult { success: false, num: 0 })) }
-			Ok(tmp) => { Ok(HttpResponse::Ok().json(ReloadDataResult { success: true, num: tmp })) }
+			Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string())) }
+			Ok(tmp) => { Ok(api_ok(ReloadDataResult { num: tmp })) }
 		}
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
+/// Route: reload data for CI jobs and scripts, with `Authorization: Bearer <reload_hook_token>` instead of an admin login
+///
+/// `which` lists the actions separated by commas, e.g. `?which=posts,tags,html`
+pub async fn reload_hook(rld: web::Query<ReloadDataRequest>, blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::has_reload_hook_token(&req) {
+		return Ok(api_unauthorized());
+	}
 
-/// Route: admin - get a list of all posts
-pub async fn get_posts(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
-	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(
-			super::post::admin_fetch_post_list(&mysql)
-		))
-	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+	let actions: Vec<&str> = rld.which.split(',').map(|tmp| tmp.trim()).filter(|tmp| tmp.len() > 0).collect();
+	match actions.iter().find(|action| !RELOAD_ACTIONS.contains(action)) {
+		Some(action) => { return Ok(api_error(StatusCode::BAD_REQUEST, &format!("'{}' is not one of {}", action, RELOAD_ACTIONS.join(", ")))); }
+		_ => {}
+	}
+	if actions.len() == 0 {
+		return Ok(api_error(StatusCode::BAD_REQUEST, "nothing to reload"));
+	}
+
+	crate::auth::audit::log_audit_event(&db, 0, "reload_hook", &actions.join(","));
+
+	let results: Vec<ReloadHookResult> = actions.iter().map(|action| {
+		match reload_blog_data(&blog, &db, action) {
+			Ok(num) => ReloadHookResult { which: String::from(*action), num, error: None },
+			Err(err) => ReloadHookResult { which: String::from(*action), num: 0, error: Some(err.to_string()) },
+		}
+	}).collect();
+
+	// Posts and tags reference each other, check them again
+	if actions.contains(&"posts") || actions.contains(&"tags") {
+		blog.check_data_consistency();
+	}
+
+	Ok(api_ok(results))
+}
+
+
+/// Route: admin - get a page of posts, authors only get their own
+pub async fn get_posts(replica: web::Data<ReadStorage>, list: web::Query<AdminListRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	match crate::auth::get_editor(&req) {
+		Some(jwt) => {
+			Ok(api_option(super::post::admin_fetch_post_list(&replica, jwt.author_scope(), &list), StatusCode::INTERNAL_SERVER_ERROR, "could not load posts"))
+		}
+		_ => { Ok(api_unauthorized()) }
 	}
 }
 
-/// Route: admin - get details for a specific post
-pub async fn get_post(mysql: web::Data<Arc<mysql::Pool>>, post: web::Query<GetPostRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+/// Route: admin - get details for a specific post, authors only get their own
+pub async fn get_post(db: web::Data<Arc<dyn Storage>>, post: web::Query<GetPostRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	match crate::auth::get_editor(&req) {
+		Some(jwt) => {
+			if !may_edit_post(&db, &jwt, post.id) {
+				return Ok(api_error(StatusCode::NOT_FOUND, "post not found"));
+			}
+			let result = super::post::admin_fetch_post(&db, post.id).map(|tmp| GetPostResult { post: tmp, lock: super::lock::load_post_lock(&db, post.id) });
+			Ok(api_option(result, StatusCode::NOT_FOUND, "post not found"))
+		}
+		_ => { Ok(api_unauthorized()) }
+	}
+}
+
+/// Route: admin - lock a post while it is open in the editor, the editor calls it again to keep the lock
+///
+/// Locks only warn, saving works regardless
+pub async fn lock_post(db: web::Data<Arc<dyn Storage>>, lock: web::Json<LockPostRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let jwt = match crate::auth::get_editor(&req) {
+		Some(tmp) => tmp,
+		_ => { return Ok(api_unauthorized()); }
+	};
+	if lock.id == 0 || !may_edit_post(&db, &jwt, lock.id) {
+		return Ok(api_error(StatusCode::NOT_FOUND, "post not found"));
+	}
+
+	let result = match lock.release {
+		true => super::lock::unlock_post(&db, lock.id, jwt.sub).map(|_| None),
+		false => super::lock::lock_post(&db, lock.id, jwt.sub, &jwt.name, lock.force).map(Some),
+	};
+	Ok(api_result(result.map(|lock| LockPostResult {
+		locked: match &lock {
+			Some(tmp) => tmp.user_id != jwt.sub,
+			_ => false
+		},
+		lock,
+	})))
+}
+
+/// Whether the user may see and edit a post, new posts may be written by anybody who can write
+fn may_edit_post(db: &Arc<dyn Storage>, jwt: &crate::auth::jwt::UserJWT, post_id: u32) -> bool {
+	match (jwt.author_scope(), post_id) {
+		(None, _) | (_, 0) => true,
+		(Some(user_id), id) => super::post::admin_fetch_post_author(db, id) == Some(user_id)
+	}
+}
+
+/// Route: admin - update a specific post, authors only their own
+///
+/// API tokens with the `publish_post` scope work as well, they act as the admin who created them
+pub async fn set_post(db: web::Data<Arc<dyn Storage>>, template: web::Data<Arc<tera::Tera>>, mut post: web::Json<super::post::Post>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	match crate::auth::get_editor_or_token(&req, "publish_post") {
+		Some(jwt) => {
+			if !may_edit_post(&db, &jwt, post.id) {
+				return Ok(api_error(StatusCode::FORBIDDEN, "you can only edit your own posts"));
+			}
+			// Authors write as themselves, the admin panel sends the user id as the author name
+			match jwt.author_scope() {
+				Some(user_id) => { post.author_name = user_id.to_string(); }
+				_ => {}
+			}
+
+			if !post.has_valid_template(&template) {
+				return Ok(api_error(StatusCode::BAD_REQUEST, "the template does not exist"));
+			}
+			if !post.has_valid_primary_tag() {
+				return Ok(api_error(StatusCode::BAD_REQUEST, "the primary tag must be one of the tags of the post"));
+			}
+			if !post.has_valid_regions() {
+				return Ok(api_error(StatusCode::BAD_REQUEST, "regions must be two letter country codes"));
+			}
+			if !post.has_valid_canonical_override() {
+				return Ok(api_error(StatusCode::BAD_REQUEST, "the canonical override must be a full URL like https://example.org/post"));
+			}
+
+			// Tell the webhooks once a post goes public
+			let was_public = match post.id {
+				0 => false,
+				id => super::post::admin_fetch_post(&db, id).map(|tmp| tmp.state != "draft").unwrap_or(false)
+			};
+
+			let res = post.update_post_data(&db);
+
+			// The autosaved state is obsolete once the post was saved
+			if res.is_ok() {
+				super::draft::delete_post_draft(&db, post.id, jwt.sub);
+			}
+
+			match &res {
+				Ok((post_id, _)) if !was_public && post.state != "draft" => {
+					let url = format!("https://{}/{}", config_get_string("fqdn"), post.url_canonical);
+					webhook_fire(&db, "post.published", &format!("New post: {} {}", post.title, url),
+						json!({"id": post_id, "title": &post.title, "url": url, "tags": &post.tags}));
+				}
+				_ => {}
+			}
+
+			Ok(api_store_result(res.map(|(post_id, version)| SetPostResult { post_id, version })))
+		}
+		_ => { Ok(api_unauthorized()) }
+	}
+}
+
+/// Route: admin - download a post as a standalone HTML or PDF file, drafts included
+///
+/// Meant for people who should not get a preview link, e.g. sponsors
+/// API tokens with the `export` scope work as well
+pub async fn export_post(blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, template: web::Data<Arc<tera::Tera>>, export: web::Query<ExportPostRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin_or_token(&req, "export") {
+		return Ok(api_unauthorized());
+	}
+
+	let format = match ExportFormat::parse(export.format.as_deref().unwrap_or("html")) {
+		Some(tmp) => tmp,
+		_ => { return Ok(api_error(StatusCode::BAD_REQUEST, "the format must be html or pdf")); }
+	};
+	if format == ExportFormat::Pdf && !pdf_export_enabled() {
+		return Ok(api_error(StatusCode::BAD_REQUEST, "PDF export is not set up, see pdf_converter_path"));
+	}
+	let post = match super::post::admin_fetch_post(&db, export.id) {
+		Some(tmp) => tmp,
+		_ => { return Ok(api_error(StatusCode::NOT_FOUND, "post not found")); }
+	};
+
+	// Named after the last part of the url, without anything a file name should not have
+	let slug: String = post.url_canonical.rsplit('/').next().unwrap_or("").chars()
+		.filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+		.collect();
+	let file_name = match slug.len() {
+		0 => format!("post-{}.{}", post.id, format.extension()),
+		_ => format!("{}.{}", slug, format.extension())
+	};
+
+	let html = match blog.render_post_export(post, &template) {
+		Ok(tmp) => tmp,
+		Err(err) => { return Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)); }
+	};
+
+	// Reading the images and printing the PDF takes a moment
+	let db = db.get_ref().clone();
+	match web::block(move || export_document(&db, &html, format)).await {
+		Ok(data) => {
+			Ok(HttpResponse::Ok()
+				.content_type(format.mime_type())
+				.header(http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name))
+				.body(data))
+		}
+		Err(error::BlockingError::Error(err)) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+		Err(_) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not export the post")) }
+	}
+}
+
+/// Route: admin - store the editor state of a post without publishing it
+pub async fn autosave_post(db: web::Data<Arc<dyn Storage>>, post: web::Json<super::post::Post>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	match crate::auth::get_admin(&req) {
+		Some(jwt) => {
+			match super::draft::store_post_draft(&db, jwt.sub, &post) {
+				Ok(saved_at) => { Ok(api_ok(AutosaveResult { post_id: post.id, saved_at })) }
+				Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+			}
+		}
+		_ => { Ok(api_unauthorized()) }
+	}
+}
+
+/// Route: admin - get the autosaved editor state of a post for the current user
+pub async fn get_autosave(db: web::Data<Arc<dyn Storage>>, post: web::Query<GetAutosaveRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	match crate::auth::get_admin(&req) {
+		Some(jwt) => {
+			Ok(api_option(super::draft::load_post_draft(&db, post.id, jwt.sub), StatusCode::NOT_FOUND, "no autosave for this post"))
+		}
+		_ => { Ok(api_unauthorized()) }
+	}
+}
+
+/// Route: admin - get the revisions kept of a post, newest first
+pub async fn get_revisions(db: web::Data<Arc<dyn Storage>>, post: web::Query<GetPostRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(
-			super::post::admin_fetch_post(&mysql, post.id)
-		))
+		Ok(api_result(super::revision::load_post_revisions(&db, post.id)))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
-/// Route: admin - update a specific post
-pub async fn set_post(mysql: web::Data<Arc<mysql::Pool>>, post: web::Json<super::post::Post>, req: HttpRequest) -> Result<HttpResponse, Error> {
-	if crate::auth::is_admin(&req) {
-		let res = match post.update_post_data(&mysql) {
-			Ok(post_id) => { SetPostResult { post_id, error: String::from("") } }
-			Err(err) => { SetPostResult { post_id: 0, error: err } }
-		};
+/// Route: admin - find and replace in the content of all posts, a preview unless `apply` is set
+pub async fn replace_content(blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, request: web::Json<ReplaceRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	match crate::auth::get_admin(&req) {
+		Some(jwt) => {
+			let result = match replace_in_posts(&db, jwt.sub, &request) {
+				Ok(tmp) => tmp,
+				Err(err) => { return Ok(api_error(StatusCode::BAD_REQUEST, &err)); }
+			};
 
-		Ok(HttpResponse::Ok().json(res))
-	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+			if result.changed > 0 {
+				crate::auth::audit::log_audit_event(&db, jwt.sub, "replace", &format!("replaced '{}' with '{}' in {} posts", request.search, request.replace, result.changed));
+
+				// Listings show excerpts of the content too, so all pages are rendered again
+				match blog.reload_posts(&db).and_then(|_| blog.invalidate_html_cache()) {
+					Err(err) => { println!("Could not reload the posts: {}", err); }
+					_ => {}
+				}
+				blog.check_data_consistency();
+			}
+
+			Ok(api_ok(result))
+		}
+		_ => { Ok(api_unauthorized()) }
 	}
 }
 
 /// Route: admin - get a list of all tags
-pub async fn get_tags(mysql: web::Data<Arc<mysql::Pool>>, blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_tags(replica: web::Data<ReadStorage>, blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
 		let in_use_tags = blog.get_all_in_use_tags();
-		Ok(HttpResponse::Ok().json(
-			super::tag::admin_fetch_tag_list(&mysql, &in_use_tags)
-		))
+		Ok(api_option(super::tag::admin_fetch_tag_list(&replica, &in_use_tags), StatusCode::INTERNAL_SERVER_ERROR, "could not load tags"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - get details for a specific tag
-pub async fn get_tag(mysql: web::Data<Arc<mysql::Pool>>, tag: web::Query<GetTagRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_tag(db: web::Data<Arc<dyn Storage>>, tag: web::Query<GetTagRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(
-			super::tag::admin_fetch_tag(&mysql, &tag.id)
-		))
+		Ok(api_option(super::tag::admin_fetch_tag(&db, &tag.id), StatusCode::NOT_FOUND, "tag not found"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - update a specific tag
-pub async fn set_tag(mysql: web::Data<Arc<mysql::Pool>>, tag: web::Json<super::tag::Tag>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn set_tag(db: web::Data<Arc<dyn Storage>>, tag: web::Json<super::tag::Tag>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		let res = match tag.update_tag_data(&mysql) {
-			Ok(tag_id) => { SetTagResult { tag_id, error: String::from("") } }
-			Err(err) => { SetTagResult { tag_id: String::from(""), error: err } }
-		};
-
-		Ok(HttpResponse::Ok().json(res))
+		Ok(api_store_result(tag.update_tag_data(&db).map(|(tag_id, version)| SetTagResult { tag_id, version })))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
-/// Route: admin - get a list of all comments
-pub async fn get_comments(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+/// Route: admin - get a page of comments
+pub async fn get_comments(replica: web::Data<ReadStorage>, list: web::Query<AdminListRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(
-			super::comment::admin_fetch_comment_list(&mysql)
-		))
+		Ok(api_option(super::comment::admin_fetch_comment_list(&replica, &list), StatusCode::INTERNAL_SERVER_ERROR, "could not load comments"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - get details for a specific comment
-pub async fn get_comment(mysql: web::Data<Arc<mysql::Pool>>, comment: web::Query<GetCommentRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_comment(db: web::Data<Arc<dyn Storage>>, comment: web::Query<GetCommentRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(
-			super::comment::admin_fetch_comment(&mysql, comment.id)
-		))
+		Ok(api_option(super::comment::admin_fetch_comment(&db, comment.id), StatusCode::NOT_FOUND, "comment not found"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - update a specific comment
-pub async fn set_comment(mysql: web::Data<Arc<mysql::Pool>>, comment: web::Json<super::comment::Comment>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn set_comment(db: web::Data<Arc<dyn Storage>>, comment: web::Json<super::comment::Comment>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		let res = match comment.update_comment_data(&mysql) {
-			Ok(comment_id) => { SetCommentResult { comment_id, error: String::from("") } }
-			Err(err) => { SetCommentResult { comment_id: 0, error: err } }
-		};
+		let was_approved = super::comment::admin_fetch_comment(&db, comment.id).map(|tmp| tmp.status == "approved").unwrap_or(false);
+		let res = comment.update_comment_data(&db);
 
-		Ok(HttpResponse::Ok().json(res))
+		match &res {
+			Ok(_) if !was_approved && comment.status == "approved" => {
+				webhook_fire(&db, "comment.approved", &format!("{} commented: {}", comment.author_name, comment.content),
+					json!({"id": comment.id, "post_id": comment.post_id, "author": &comment.author_name, "content": &comment.content}));
+			}
+			_ => {}
+		}
+
+		Ok(api_store_result(res.map(|(comment_id, version)| SetCommentResult { comment_id, version })))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - get details for all menus
-pub async fn get_menus(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_menus(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(super::menu::load_menus_from_sql(&mysql)))
+		Ok(api_option(super::menu::load_menus_from_sql(&replica), StatusCode::INTERNAL_SERVER_ERROR, "could not load menus"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - update a specific menu
-pub async fn set_menu(mysql: web::Data<Arc<mysql::Pool>>, menu: web::Json<super::menu::Menu>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn set_menu(db: web::Data<Arc<dyn Storage>>, menu: web::Json<super::menu::Menu>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		let menu_id = super::menu::update_menu_in_sql(&mysql, &menu);
-		Ok(HttpResponse::Ok().content_type("application/json").body(format!("{{\"id\":{}}}", menu_id)))
+		match super::menu::update_menu_in_sql(&db, &menu) {
+			0 => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not store menu")) }
+			id => { Ok(api_ok(SetItemResult { id })) }
+		}
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - get details for all snippets
-pub async fn get_snippets(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_snippets(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(super::snippet::load_snippets_from_sql(&mysql)))
+		Ok(api_option(super::snippet::load_snippets_from_sql(&replica), StatusCode::INTERNAL_SERVER_ERROR, "could not load snippets"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - update a specific snippet
-pub async fn set_snippet(mysql: web::Data<Arc<mysql::Pool>>, snippet: web::Json<super::snippet::Snippet>, req: HttpRequest) -> Result<HttpResponse, Error> {
+///
+/// Posts using the snippet show the change right away, no reload of the posts is needed
+pub async fn set_snippet(blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, snippet: web::Json<super::snippet::Snippet>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		let snippet_id = super::snippet::update_snippet_in_sql(&mysql, &snippet);
-		Ok(HttpResponse::Ok().content_type("application/json").body(format!("{{\"id\":{}}}", snippet_id)))
+		match super::snippet::update_snippet_in_sql(&db, &snippet) {
+			0 => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not store snippet")) }
+			id => {
+				match blog.reload_snippets(&db) {
+					Err(err) => { println!("Could not reload the snippets: {}", err); }
+					_ => {}
+				}
+				Ok(api_ok(SetItemResult { id }))
+			}
+		}
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - get details for all redirects
-pub async fn get_redirects(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_redirects(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(super::redirect::load_redirects_from_sql(&mysql)))
+		Ok(api_option(super::redirect::load_redirects_from_sql(&replica), StatusCode::INTERNAL_SERVER_ERROR, "could not load redirects"))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - update a specific redirect
-pub async fn set_redirect(mysql: web::Data<Arc<mysql::Pool>>, redirect: web::Json<super::redirect::Redirect>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn set_redirect(db: web::Data<Arc<dyn Storage>>, redirect: web::Json<super::redirect::Redirect>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		let redirect_id = super::redirect::update_redirect_in_sql(&mysql, &redirect);
-		Ok(HttpResponse::Ok().content_type("application/json").body(format!("{{\"id\":{}}}", redirect_id)))
+		let redirect = match redirect.into_inner().normalized() {
+			Ok(tmp) => tmp,
+			Err(err) => { return Ok(api_error(StatusCode::BAD_REQUEST, &err)); }
+		};
+		match super::redirect::update_redirect_in_sql(&db, &redirect) {
+			0 => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not store redirect")) }
+			id => { Ok(api_ok(SetItemResult { id })) }
+		}
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - get the gallery data
-pub async fn get_gallery(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn get_gallery(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(super::gallery::load_gallery_from_sql(&replica)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - list gallery images that were uploaded more than once
+pub async fn gallery_duplicates(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(super::gallery::find_duplicate_images(&replica)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the EXIF data of an image, prepared for the post editor
+pub async fn gallery_metadata(db: web::Data<Arc<dyn Storage>>, image: web::Query<GetImageRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		let result = super::gallery::load_image_metadata(&db, &image.guid).map(|metadata| {
+			ImageMetadataResult { location: metadata.suggested_location(), metadata }
+		});
+		Ok(api_option(result, StatusCode::NOT_FOUND, "image not found"))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - list the posts that show an image, before it is deleted or replaced
+pub async fn gallery_usage(db: web::Data<Arc<dyn Storage>>, image: web::Query<GetImageRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::gallery::find_image_usage(&db, &image.guid) {
+			Ok(usage) => { Ok(api_ok(usage)) }
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the last report of orphaned gallery files and rows
+pub async fn gallery_orphans(blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(super::gallery::load_gallery_from_sql(&mysql)))
+		Ok(api_ok(&*blog.get_gallery_orphans()))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - delete the orphaned gallery files and rows, the gallery is checked again first
+pub async fn gallery_purge_orphans(blog: web::Data<Arc<Blog>>, db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	let admin = match crate::auth::get_admin(&req) {
+		Some(tmp) => tmp,
+		_ => { return Ok(api_unauthorized()); }
+	};
+
+	// Walking the gallery takes a moment
+	let db = db.get_ref().clone();
+	let blog = blog.get_ref().clone();
+	let purge = move || {
+		let result = super::gallery::purge_gallery_orphans(&db)?;
+		crate::auth::audit::log_audit_event(&db, admin.sub, "gallery_purge", &format!("removed {} files and {} rows", result.files_removed, result.rows_removed));
+		blog.find_gallery_orphans(&db)?;
+		Ok::<_, String>(result)
+	};
+	match web::block(purge).await {
+		Ok(result) => { Ok(api_ok(result)) }
+		Err(error::BlockingError::Error(err)) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+		Err(_) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not purge the gallery")) }
+	}
+}
+
+/// Route: admin - set the focal point used when cropping an image
+pub async fn gallery_set_focal_point(db: web::Data<Arc<dyn Storage>>, focal: web::Json<super::gallery::FocalPoint>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::gallery::set_focal_point(&db, &focal) {
+			Ok(()) => { Ok(api_ok(&focal.guid)) }
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the images with an alt text suggested by the captioning service
+pub async fn gallery_alt_text_suggestions(db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_result(super::alt_text::load_alt_text_suggestions(&db)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - store the alt text of an image and drop its suggestion
+pub async fn gallery_confirm_alt_text(db: web::Data<Arc<dyn Storage>>, confirm: web::Json<ConfirmAltTextRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::alt_text::confirm_alt_text(&db, &confirm.guid, &confirm.alt_text) {
+			Ok(()) => { Ok(api_ok(&confirm.guid)) }
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+
+/// Route: admin - get the issues found by the last data validation
+pub async fn get_data_issues(blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(blog.get_data_issues()))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the length of the message queue and how many messages were dropped
+pub async fn get_queue_stats(blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(blog.get_message_queue_stats()))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the schedule and last run of all background jobs
+pub async fn get_jobs(req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(crate::app::scheduler::scheduler_get_jobs()))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the latest notifications, only the unread ones if asked to
+pub async fn get_notifications(replica: web::Data<ReadStorage>, query: web::Query<GetNotificationsRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_result(load_notifications(&replica, query.unread.unwrap_or(false))))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - mark notifications as read, the next event of the same kind starts a new one
+pub async fn read_notifications(db: web::Data<Arc<dyn Storage>>, read: web::Json<ReadNotificationsRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	let ids = if read.all { None } else { Some(&read.ids) };
+	match mark_notifications_read(&db, ids) {
+		Ok(marked) => { Ok(api_ok(ReadNotificationsResult { marked })) }
+		Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+	}
+}
+
+/// Route: admin - get the rules new comments are scored with
+pub async fn get_spam_rules(req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(&*spam_rules()))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - replace the spam rules, they apply to the next comment
+pub async fn set_spam_rules(db: web::Data<Arc<dyn Storage>>, rules: web::Json<SpamRules>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match spam_rules_store(&db, rules.into_inner()) {
+			Ok(rules) => { Ok(api_ok(&*rules)) }
+			Err(_) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not store the spam rules")) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - check the rendered HTML of a post for common accessibility problems
+pub async fn a11y_audit(blog: web::Data<Arc<Blog>>, template: web::Data<Arc<tera::Tera>>, post: web::Query<GetPostRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	match blog.render_post_uncached(post.id, &template) {
+		Some((template_name, Ok((html, _)))) => { Ok(api_ok(a11y_audit_html(post.id, &template_name, &html))) }
+		Some((_, Err(err))) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+		None => { Ok(api_error(StatusCode::NOT_FOUND, "post not found")) }
+	}
+}
+
+/// Route: admin - render a page many times past the cache and report where the time goes
+pub async fn profile_render(blog: web::Data<Arc<Blog>>, template: web::Data<Arc<tera::Tera>>, query: web::Query<ProfileRenderRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	let query = query.into_inner();
+	let runs = query.runs.unwrap_or(PROFILE_RUNS).clamp(1, PROFILE_RUNS_MAX);
+	match web::block(move || blog.profile_render(&template, &query.template, query.id, runs)).await {
+		Ok(profile) => { Ok(api_ok(profile)) }
+		Err(error::BlockingError::Error(err)) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		Err(_) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not profile the render")) }
+	}
+}
+
+/// Route: admin - list the photos readers submitted, the pending ones by default
+pub async fn get_photo_submissions(db: web::Data<Arc<dyn Storage>>, query: web::Query<GetPhotoSubmissionsRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		let status = query.status.clone().unwrap_or(String::from("pending"));
+		Ok(api_ok(load_photo_submissions(&db, &status)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - show a photo that is still in the quarantine
+pub async fn get_photo_submission_file(db: web::Data<Arc<dyn Storage>>, query: web::Query<PhotoSubmissionRequest>, req: HttpRequest) -> Result<actix_files::NamedFile, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Err(error::ErrorUnauthorized("unauthorized"));
+	}
+
+	match load_photo_submission(&db, query.id) {
+		Some(submission) if submission.status == "pending" => { Ok(actix_files::NamedFile::open(submission.quarantine_path())?) }
+		_ => { Err(error::ErrorNotFound("submission not found")) }
+	}
+}
+
+/// Route: admin - move a submitted photo to the gallery, optionally with a crop and attached to a post
+pub async fn approve_photo_submission(db: web::Data<Arc<dyn Storage>>, approval: web::Json<SubmissionApproval>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::submissions::approve_photo_submission(&db, &approval) {
+			Ok(guid) => { Ok(api_ok(guid)) }
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - throw a submitted photo away
+pub async fn reject_photo_submission(db: web::Data<Arc<dyn Storage>>, submission: web::Json<PhotoSubmissionRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::submissions::reject_photo_submission(&db, submission.id) {
+			Ok(_) => { Ok(api_ok(SetItemResult { id: submission.id as u64 })) }
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - list the pingbacks and trackbacks, the pending ones by default
+pub async fn get_mentions(db: web::Data<Arc<dyn Storage>>, query: web::Query<GetMentionsRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		let status = query.status.clone().unwrap_or(String::from("pending"));
+		Ok(api_ok(load_mentions(&db, &status)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - approve or reject a pingback or trackback, the posts show it right away
+pub async fn moderate_mention(db: web::Data<Arc<dyn Storage>>, blog: web::Data<Arc<Blog>>, mention: web::Json<ModerateMentionRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::mentions::moderate_mention(&db, mention.id, &mention.status) {
+			Ok(public) => {
+				if public {
+					match blog.reload_mentions(&db).and_then(|_| blog.invalidate_html_cache()) {
+						Err(err) => { println!("Could not refresh the mentions: {:?}", err); }
+						_ => {}
+					}
+				}
+				Ok(api_ok(SetItemResult { id: mention.id as u64 }))
+			}
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get all polls with their current results
+pub async fn get_polls(replica: web::Data<ReadStorage>, blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match super::poll::load_polls_from_sql(&replica) {
+			Ok(polls) => { Ok(api_ok(PollList { polls, results: blog.get_all_poll_results() })) }
+			Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - create or update a poll, embedding posts show the change after reloading polls and posts
+pub async fn set_poll(db: web::Data<Arc<dyn Storage>>, poll: web::Json<super::poll::Poll>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		if poll.question.trim().len() == 0 || poll.options.len() < 2 {
+			return Ok(api_error(StatusCode::BAD_REQUEST, "a poll needs a question and at least two options"));
+		}
+
+		match super::poll::update_poll_in_sql(&db, &poll) {
+			0 => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not store poll")) }
+			id => { Ok(api_ok(SetItemResult { id })) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get all experiments with their exposures and clicks
+pub async fn get_experiments(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_result(super::experiment::load_experiment_results(&replica, 500)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - start, change or end an experiment, picking a winner ends it
+///
+/// List pages are dropped from the cache, they show the variants
+pub async fn set_experiment(db: web::Data<Arc<dyn Storage>>, blog: web::Data<Arc<Blog>>, experiment: web::Json<super::experiment::Experiment>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	let mut experiment = experiment.into_inner();
+	if experiment.title_b.trim().len() == 0 && experiment.thumbnail_b.trim().len() == 0 {
+		return Ok(api_error(StatusCode::BAD_REQUEST, "variant b needs a title or a thumbnail"));
+	}
+	match experiment.winner.as_str() {
+		"" => {}
+		"a" | "b" => { experiment.running = false; }
+		_ => { return Ok(api_error(StatusCode::BAD_REQUEST, "the winner must be a or b")); }
+	}
+	if super::post::admin_fetch_post_author(&db, experiment.post_id).is_none() {
+		return Ok(api_error(StatusCode::NOT_FOUND, "the post does not exist"));
+	}
+	match blog.get_running_experiment(experiment.post_id) {
+		Some(running) if experiment.running && running.id != experiment.id => {
+			return Ok(api_error(StatusCode::CONFLICT, "the post already has a running experiment"));
+		}
+		_ => {}
+	}
+	if experiment.id == 0 {
+		experiment.started_at = blog.get_time_in_secs();
+	}
+
+	match super::experiment::update_experiment_in_sql(&db, &experiment) {
+		0 => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, "could not store experiment")) }
+		id => {
+			match blog.reload_experiments(&db).and_then(|_| blog.invalidate_html_cache()) {
+				Err(err) => { println!("Could not reload the experiments: {}", err); }
+				_ => {}
+			}
+			Ok(api_ok(SetItemResult { id }))
+		}
+	}
+}
+
+/// Route: admin - get all webhooks and their latest deliveries
+pub async fn get_webhooks(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match (load_webhooks(&replica), load_webhook_deliveries(&replica, 100)) {
+			(Ok(webhooks), Ok(deliveries)) => { Ok(api_ok(WebhookList { webhooks, deliveries })) }
+			(Err(err), _) | (_, Err(err)) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - add or update a webhook
+pub async fn set_webhook(db: web::Data<Arc<dyn Storage>>, webhook: web::Json<Webhook>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		match webhook.into_inner().normalized() {
+			Ok(webhook) => { Ok(api_result(store_webhook(&db, &webhook).map(|id| SetItemResult { id: id as u64 }))) }
+			Err(err) => { Ok(api_error(StatusCode::BAD_REQUEST, &err)) }
+		}
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - remove a webhook
+pub async fn delete_webhook_entry(db: web::Data<Arc<dyn Storage>>, webhook: web::Json<DeleteItemRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_result(delete_webhook(&db, webhook.id).map(|_| SetItemResult { id: webhook.id as u64 })))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - get the blocklist with its hit counters
+pub async fn get_blocklist(req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(blocklist_entries()))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - add an entry to the blocklist or change an existing one
+pub async fn set_blocklist_entry(db: web::Data<Arc<dyn Storage>>, entry: web::Json<BlockEntry>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	let entry = match entry.into_inner().normalized() {
+		Ok(tmp) => tmp,
+		Err(err) => { return Ok(api_error(StatusCode::BAD_REQUEST, &err)); }
+	};
+
+	match blocklist_store_entry(&db, &entry) {
+		Ok(id) => { Ok(api_ok(SetItemResult { id: id as u64 })) }
+		Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+	}
+}
+
+/// Route: admin - remove an entry from the blocklist
+pub async fn delete_blocklist_entry(db: web::Data<Arc<dyn Storage>>, entry: web::Json<DeleteItemRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_result(blocklist_delete_entry(&db, entry.id).map(|_| SetItemResult { id: entry.id as u64 })))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - all feature flags, from the config and the admin panel
+pub async fn get_flags(req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(feature_flags()))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - switch a feature flag, cached pages are rendered again
+pub async fn set_flag(db: web::Data<Arc<dyn Storage>>, blog: web::Data<Arc<Blog>>, flag: web::Json<FeatureFlag>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	let flag = match flag.into_inner().normalized() {
+		Ok(tmp) => tmp,
+		Err(err) => { return Ok(api_error(StatusCode::BAD_REQUEST, &err)); }
+	};
+
+	match flag_store(&db, &flag) {
+		Ok(_) => {
+			flags_changed(&blog);
+			Ok(api_ok(feature_flags()))
+		}
+		Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+	}
+}
+
+/// Route: admin - remove a flag from the admin panel, the config decides again
+pub async fn delete_flag(db: web::Data<Arc<dyn Storage>>, blog: web::Data<Arc<Blog>>, flag: web::Json<DeleteFlagRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	match flag_delete(&db, &flag.name) {
+		Ok(_) => {
+			flags_changed(&blog);
+			Ok(api_ok(feature_flags()))
+		}
+		Err(err) => { Ok(api_error(StatusCode::INTERNAL_SERVER_ERROR, &err)) }
+	}
+}
+
+/// Templates see the flags, pages rendered before the change have to go
+fn flags_changed(blog: &Blog) {
+	match blog.invalidate_html_cache() {
+		Err(err) => { println!("Could not clear the HTML cache: {:?}", err); }
+		_ => {}
 	}
 }
 
 
 /// Route: admin - get a bunch of statistics for the dashboard
-pub async fn dashboard(mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn dashboard(replica: web::Data<ReadStorage>, blog: web::Data<Arc<Blog>>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if crate::auth::is_admin(&req) {
-		Ok(HttpResponse::Ok().json(dashboard_get_statistics(&mysql)))
+		Ok(api_ok(dashboard_get_statistics(&replica, &blog)))
 	} else {
-		Ok(HttpResponse::Unauthorized().content_type("application/json").body("{}"))
+		Ok(api_unauthorized())
+	}
+}
+
+/// Route: admin - search posts, comments, tags and redirects at once
+pub async fn search(replica: web::Data<ReadStorage>, search: web::Query<AdminSearchRequest>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_result(admin_search(&replica, &search.q)))
+	} else {
+		Ok(api_unauthorized())
 	}
 }
 
 /// Route: admin - upload an image to the gallery
-pub async fn gallery_upload(mut multipart: Multipart, mysql: web::Data<Arc<mysql::Pool>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+pub async fn gallery_upload(mut multipart: Multipart, db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
 	if !crate::auth::is_admin(&req) {
-		return Err(error::ErrorUnauthorized(""));
+		return Ok(api_unauthorized());
 	}
 
 	let mut uploads = vec![];
-	//TODO: fix 2 unwraps
 
 	while let Some(item) = multipart.next().await {
 		let mut field = item?;
 
 		// The local path we want to store the uploaded file at
-		let local_file_name = match prepare_upload_file_path(&field) {
+		let local_file_name = match get_upload_file_name(&field).and_then(|name| generate_upload_file_name(&name)) {
 			Ok(tmp_path) => tmp_path,
-			Err(e) => return Err(e),
+			Err(e) => return Ok(api_error(StatusCode::BAD_REQUEST, &e)),
 		};
 
-		// Create the file in the local file system
-		let local_file_name_clone = local_file_name.clone();
-		let mut file = web::block(move || std::fs::File::create(local_file_name_clone))
-			.await
-			.unwrap();
-
-		// Field in turn is stream of *Bytes* object
-		while let Some(chunk) = field.next().await {
-			let data = chunk.unwrap();
-			// filesystem operations are blocking, we have to use threadpool
-			file = web::block(move || file.write_all(&data).map(|_| file)).await?;
-		}
+		write_upload_field(&mut field, &local_file_name).await?;
 
 		// Store the uploaded path in a vector
 		uploads.push(local_file_name);
 	}
 
 	// Have to insert some data into the database at this point
-	let result = finish_file_upload(&uploads, &mysql);
+	let result = finish_file_upload(&uploads, &db);
+
+	Ok(api_ok(result))
+}
+
+/// Route: admin - upload attachments like PDFs or GPX tracks
+pub async fn files_upload(mut multipart: Multipart, db: web::Data<Arc<dyn Storage>>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if !crate::auth::is_admin(&req) {
+		return Ok(api_unauthorized());
+	}
+
+	let mut uploads = vec![];
+
+	while let Some(item) = multipart.next().await {
+		let mut field = item?;
+
+		// Only whitelisted types get a local path
+		let name = match get_upload_file_name(&field) {
+			Ok(tmp) => tmp,
+			Err(e) => return Ok(api_error(StatusCode::BAD_REQUEST, &e)),
+		};
+		let local_file_name = match generate_attachment_file_name(&name) {
+			Ok(tmp_path) => tmp_path,
+			Err(e) => return Ok(api_error(StatusCode::BAD_REQUEST, &e)),
+		};
+
+		write_upload_field(&mut field, &local_file_name).await?;
+
+		uploads.push(PendingAttachment { local_path: local_file_name, name });
+	}
 
-	Ok(HttpResponse::Ok().json(result))
+	// Validate the content and store the files in the database
+	let result = finish_attachment_upload(&uploads, &db);
+
+	Ok(api_ok(result))
 }
 
-/// Prepare the local path and file for the upload
-fn prepare_upload_file_path(field: &Field) -> Result<String, Error> {
+/// Route: admin - list all attachments
+pub async fn get_files(replica: web::Data<ReadStorage>, req: HttpRequest) -> Result<HttpResponse, Error> {
+	if crate::auth::is_admin(&req) {
+		Ok(api_ok(super::files::load_attachments_from_sql(&replica)))
+	} else {
+		Ok(api_unauthorized())
+	}
+}
+
+/// Find the file name specified by the user
+fn get_upload_file_name(field: &Field) -> Result<String, String> {
 	// Get the content disposition
 	let content_disposition = match field.content_disposition() {
 		Some(tmp) => tmp,
-		_ => return Err(error::ErrorInternalServerError("Could not get content disposition"))
+		_ => return Err(String::from("Could not get content disposition"))
 	};
 
-	// Find the file name specified by the user
-	let input_file_name = match content_disposition.get_filename() {
-		Some(filename) => filename.to_string(),
-		None => return Err(error::ErrorInternalServerError("Could not retrieve the file name"))
-	};
+	match content_disposition.get_filename() {
+		Some(filename) => Ok(filename.to_string()),
+		None => Err(String::from("Could not retrieve the file name"))
+	}
+}
 
-	// Get a full path for the new file we will create
-	let local_file_path = match generate_upload_file_name(&input_file_name) {
-		Ok(tmp) => tmp,
-		Err(tmp) => return Err(error::ErrorInternalServerError(tmp))
-	};
+/// Stream an uploaded field into a new file on disk
+async fn write_upload_field(field: &mut Field, local_file_name: &str) -> Result<(), Error> {
+	// Create the file in the local file system
+	let local_file_name_clone = String::from(local_file_name);
+	let mut file = web::block(move || std::fs::File::create(local_file_name_clone)).await?;
+
+	// Field in turn is stream of *Bytes* object
+	while let Some(chunk) = field.next().await {
+		let data = chunk?;
+		// filesystem operations are blocking, we have to use threadpool
+		file = web::block(move || file.write_all(&data).map(|_| file)).await?;
+	}
 
-	Ok(local_file_path)
+	Ok(())
 }
\ No newline at end of file
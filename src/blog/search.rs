@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use crate::storage::{DbParams, DbRow, DbValue, Storage};
+
+/// Hits per kind of item, the panel links to the full lists for more
+const ADMIN_SEARCH_LIMIT: u32 = 20;
+
+/// Shorter search terms would match nearly everything
+const ADMIN_SEARCH_MIN_LENGTH: usize = 2;
+
+
+// ------------------------------
+// --------- ADMIN SEARCH -------
+// ------------------------------
+
+/// Everything matching a search in the admin panel, grouped by kind
+#[derive(Debug, Serialize)]
+pub struct AdminSearchResult {
+	pub posts: Vec<SearchPost>,
+	pub comments: Vec<SearchComment>,
+	pub tags: Vec<SearchTag>,
+	pub redirects: Vec<SearchRedirect>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPost {
+	pub id: u32,
+	pub title: String,
+	pub url_canonical: String,
+	pub state: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchComment {
+	pub id: u32,
+	pub post_id: u32,
+	pub author_name: String,
+	pub status: String,
+	/// The start of the comment
+	pub content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchTag {
+	pub id: String,
+	pub title: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchRedirect {
+	pub id: u32,
+	pub name: String,
+	pub target: String,
+}
+
+impl SearchPost {
+	fn from_sql(mut row: DbRow) -> Option<SearchPost> {
+		Some(SearchPost {
+			id: row.take("id")?,
+			title: row.take("title")?,
+			url_canonical: row.take("url_canonical")?,
+			state: row.take("state")?,
+		})
+	}
+}
+
+impl SearchComment {
+	fn from_sql(mut row: DbRow) -> Option<SearchComment> {
+		Some(SearchComment {
+			id: row.take("id")?,
+			post_id: row.take("post_id")?,
+			author_name: row.take("author_name")?,
+			status: row.take("status")?,
+			content: row.take("content")?,
+		})
+	}
+}
+
+impl SearchTag {
+	fn from_sql(mut row: DbRow) -> Option<SearchTag> {
+		Some(SearchTag {
+			id: row.take("id")?,
+			title: row.take("title")?,
+		})
+	}
+}
+
+impl SearchRedirect {
+	fn from_sql(mut row: DbRow) -> Option<SearchRedirect> {
+		Some(SearchRedirect {
+			id: row.take("id")?,
+			name: row.take("name")?,
+			target: row.take("target")?,
+		})
+	}
+}
+
+
+/// Search posts (title, slug, content), comments (author, content), tags and redirects at once
+///
+/// Nothing is found for less than `ADMIN_SEARCH_MIN_LENGTH` characters
+pub fn admin_search(db: &Arc<dyn Storage>, search: &str) -> Result<AdminSearchResult, String> {
+	let search = search.trim();
+	if search.chars().count() < ADMIN_SEARCH_MIN_LENGTH {
+		return Ok(AdminSearchResult { posts: vec![], comments: vec![], tags: vec![], redirects: vec![] });
+	}
+	let pattern = like_pattern(search);
+
+	let query_a = format!(r###"
+        SELECT id, title, url_canonical, state
+        FROM posts
+        WHERE title LIKE ? ESCAPE '!' OR url_canonical LIKE ? ESCAPE '!' OR content LIKE ? ESCAPE '!'
+        ORDER BY id DESC LIMIT {}
+    "###, ADMIN_SEARCH_LIMIT);
+
+	let query_b = format!(r###"
+        SELECT id, post_id, author_name, status, SUBSTR(content, 1, 80) AS content
+        FROM post_comments
+        WHERE author_name LIKE ? ESCAPE '!' OR content LIKE ? ESCAPE '!'
+        ORDER BY id DESC LIMIT {}
+    "###, ADMIN_SEARCH_LIMIT);
+
+	let query_c = format!(r###"
+        SELECT id, title
+        FROM tags
+        WHERE id LIKE ? ESCAPE '!' OR title LIKE ? ESCAPE '!'
+        ORDER BY id LIMIT {}
+    "###, ADMIN_SEARCH_LIMIT);
+
+	let query_d = format!(r###"
+        SELECT id, name, target
+        FROM redirects
+        WHERE name LIKE ? ESCAPE '!' OR target LIKE ? ESCAPE '!'
+        ORDER BY name LIMIT {}
+    "###, ADMIN_SEARCH_LIMIT);
+
+	let params = |count: usize| DbParams::Positional(vec![DbValue::from(pattern.as_str()); count]);
+
+	Ok(AdminSearchResult {
+		posts: db.query(&query_a, params(3)).map_err(|err| err.to_string())?.into_iter().filter_map(SearchPost::from_sql).collect(),
+		comments: db.query(&query_b, params(2)).map_err(|err| err.to_string())?.into_iter().filter_map(SearchComment::from_sql).collect(),
+		tags: db.query(&query_c, params(2)).map_err(|err| err.to_string())?.into_iter().filter_map(SearchTag::from_sql).collect(),
+		redirects: db.query(&query_d, params(2)).map_err(|err| err.to_string())?.into_iter().filter_map(SearchRedirect::from_sql).collect(),
+	})
+}
+
+/// A LIKE pattern matching the text anywhere, its own wildcards are escaped with `!`
+fn like_pattern(search: &str) -> String {
+	let mut pattern = String::with_capacity(search.len() + 2);
+	pattern.push('%');
+	for c in search.chars() {
+		if c == '!' || c == '%' || c == '_' { pattern.push('!'); }
+		pattern.push(c);
+	}
+	pattern.push('%');
+	pattern
+}
@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::str::CharIndices;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use tera::escape_html;
+
+use crate::app::config::{config_get_string, config_get_string_map};
+use crate::app::timezone::{site_time_from_timestamp, site_time_to_utc};
+use crate::app::utils::secure_random_base62_string;
+use crate::blog::types::poll::Poll;
+use crate::blog::types::post::Post;
+use crate::blog::types::snippet::Snippet;
+
+lazy_static! {
+	/// Conditions from posts and tags carry this, see `mark_conditions`
+	static ref CONDITION_MARK: String = secure_random_base62_string(16);
+}
+
+/// A shortcode found in some content, like `[button label="Read more" primary]`
+///
+/// Flags without a value, like `primary`, are set to `true`
+#[derive(Debug, PartialEq)]
+pub struct Shortcode {
+	pub key: String,
+	pub args: BTreeMap<String, String>,
+}
+
+/// Replace snippets like `[name var="value"]` and polls like `[poll id="1"]` in post or tag content
+///
+/// Snippets in posts may use the post they are in, see `shortcode_placeholders`.
+/// Returns the content and the ids of the polls it shows
+pub fn replace_shortcodes(content: &str, post: Option<&Post>, snippets: &Vec<Snippet>, polls: &HashMap<u32, Arc<Poll>>) -> (String, Vec<u32>) {
+	let no_fields = BTreeMap::new();
+	let custom_fields = post.map_or(&no_fields, |post| &post.custom_fields);
+	let placeholders = shortcode_placeholders(post);
+
+	let mut modified_content = String::with_capacity(content.len());
+	let mut poll_ids = Vec::new();
+	let mut rest = content;
+
+	while let Some(start) = rest.find('[') {
+		modified_content.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		// Anything that does not parse or is unknown stays as it is, e.g. `[1]` for a footnote
+		let (shortcode, length) = match parse_shortcode(rest) {
+			Some(tmp) => tmp,
+			_ => {
+				modified_content.push('[');
+				rest = &rest[1..];
+				continue;
+			}
+		};
+
+		match replace_shortcode(&shortcode, custom_fields, &placeholders, snippets, polls) {
+			Some((replacement, poll_id)) => {
+				modified_content.push_str(&replacement);
+				match poll_id {
+					Some(id) => { poll_ids.push(id); }
+					_ => {}
+				}
+			}
+			_ => { modified_content.push_str(&rest[..length]); }
+		}
+		rest = &rest[length..];
+	}
+	modified_content.push_str(rest);
+
+	(modified_content, poll_ids)
+}
+
+/// The keys of all shortcodes in some content, to tell which snippets it uses
+pub fn shortcode_keys(content: &str) -> Vec<String> {
+	let mut keys = Vec::new();
+	let mut rest = content;
+
+	while let Some(start) = rest.find('[') {
+		rest = &rest[start..];
+		match parse_shortcode(rest) {
+			Some((shortcode, length)) => {
+				if !keys.contains(&shortcode.key) { keys.push(shortcode.key); }
+				rest = &rest[length..];
+			}
+			_ => { rest = &rest[1..]; }
+		}
+	}
+
+	keys
+}
+
+/// The replacement of a single shortcode, plus the poll it shows
+fn replace_shortcode(shortcode: &Shortcode, custom_fields: &BTreeMap<String, serde_json::Value>, placeholders: &Vec<(String, String)>, snippets: &Vec<Snippet>, polls: &HashMap<u32, Arc<Poll>>) -> Option<(String, Option<u32>)> {
+	// Polls are built in, `[poll id="1"]`
+	if shortcode.key == "poll" {
+		let poll = shortcode.args.get("id")
+			.and_then(|tmp| tmp.parse::<u32>().ok())
+			.and_then(|id| polls.get(&id))?;
+		return Some((poll.to_html(), Some(poll.id)));
+	}
+
+	// Conditions are decided on every render, see `evaluate_conditions`
+	if shortcode.key == "if" { return None; }
+
+	// Do we have a snippet with that name?
+	let snippet = snippets.iter().find(|snippet| snippet.name == shortcode.key)?;
+	let mut text = snippet.get_replacement(&shortcode.args, custom_fields);
+
+	for (name, value) in placeholders {
+		text = text.replace(&format!("{{{}}}", name), value);
+	}
+
+	Some((text, None))
+}
+
+/// Values every snippet can use, already escaped for HTML
+///
+/// `{site.fqdn}`, `{site.url}` and the `site_extras` like `{site.newsletter}`, in posts also
+/// `{post.id}`, `{post.title}`, `{post.url}`, `{post.author}`, `{post.date}` and `{post.tag}`
+fn shortcode_placeholders(post: Option<&Post>) -> Vec<(String, String)> {
+	let fqdn = config_get_string("fqdn");
+	let mut placeholders = vec![
+		(String::from("site.fqdn"), fqdn.clone()),
+		(String::from("site.url"), format!("https://{}", fqdn)),
+	];
+	for (name, value) in config_get_string_map("site_extras") {
+		placeholders.push((format!("site.{}", name), value));
+	}
+
+	match post {
+		Some(post) => {
+			placeholders.push((String::from("post.id"), post.id.to_string()));
+			placeholders.push((String::from("post.title"), post.title.clone()));
+			placeholders.push((String::from("post.url"), format!("https://{}/{}", fqdn, post.url_canonical)));
+			placeholders.push((String::from("post.author"), post.authors.first().map_or(post.author_name.clone(), |author| author.name.clone())));
+			placeholders.push((String::from("post.date"), site_time_from_timestamp(post.date_posted).format("%Y-%m-%d").to_string()));
+			placeholders.push((String::from("post.tag"), post.primary_tag().cloned().unwrap_or_default()));
+		}
+		_ => {}
+	}
+
+	placeholders.into_iter().map(|(name, value)| (name, escape_html(&value))).collect()
+}
+
+/// Mark the conditions in post or tag content, so `evaluate_conditions` knows they were written by an editor
+///
+/// Pages are evaluated as a whole, a condition typed into a comment or a search must stay text
+pub fn mark_conditions(content: &str) -> String {
+	let mut modified_content = String::with_capacity(content.len());
+	let mut rest = content;
+
+	while let Some(start) = rest.find('[') {
+		modified_content.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		if rest.starts_with("[/if]") {
+			modified_content.push_str(&format!("[/if:{}]", *CONDITION_MARK));
+			rest = &rest["[/if]".len()..];
+		} else if parse_shortcode(rest).is_some_and(|(shortcode, _)| shortcode.key == "if") {
+			modified_content.push_str(&format!("[if:{}", *CONDITION_MARK));
+			rest = &rest["[if".len()..];
+		} else {
+			modified_content.push('[');
+			rest = &rest[1..];
+		}
+	}
+	modified_content.push_str(rest);
+
+	modified_content
+}
+
+/// Show or hide dated content like `[if before="2025-06-01"]...[/if]`, done whenever a page is rendered
+///
+/// Only conditions from `mark_conditions` count. `after` shows the content from that time on, `before`
+/// until then, both make a window. Times are in the time zone of the site, see `site_time_to_utc`,
+/// either `2025-06-01` or `2025-06-01 18:00`, content with invalid ones is hidden. Conditions may be
+/// nested. Returns the content and the next time it changes, if ever
+pub fn evaluate_conditions(content: &str, now: u64) -> (String, Option<u64>) {
+	let opening = format!("[if:{}", *CONDITION_MARK);
+	let closing = format!("[/if:{}]", *CONDITION_MARK);
+
+	let (modified_content, changes_at) = evaluate_marked_conditions(content, now, &opening, &closing);
+
+	// Conditions that are never closed stay as they were written, without the mark
+	(modified_content.replace(&opening, "[if").replace(&closing, "[/if]"), changes_at)
+}
+
+fn evaluate_marked_conditions(content: &str, now: u64, opening: &str, closing: &str) -> (String, Option<u64>) {
+	let mut modified_content = String::with_capacity(content.len());
+	let mut changes_at = None;
+	let mut rest = content;
+
+	while let Some(start) = rest.find(opening) {
+		modified_content.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		// A condition that is never closed stays as it is
+		let (shortcode, length, inner_length) = match parse_shortcode(rest) {
+			Some((shortcode, length)) if shortcode.key == opening[1..] => match find_closing_if(&rest[length..], opening, closing) {
+				Some(inner_length) => (shortcode, length, inner_length),
+				_ => {
+					modified_content.push_str(opening);
+					rest = &rest[opening.len()..];
+					continue;
+				}
+			},
+			_ => {
+				modified_content.push_str(opening);
+				rest = &rest[opening.len()..];
+				continue;
+			}
+		};
+
+		let (visible, next_change) = evaluate_condition(&shortcode.args, now);
+		changes_at = earliest(changes_at, next_change);
+		if visible {
+			let (inner, next_change) = evaluate_marked_conditions(&rest[length..length + inner_length], now, opening, closing);
+			modified_content.push_str(&inner);
+			changes_at = earliest(changes_at, next_change);
+		}
+		rest = &rest[length + inner_length + closing.len()..];
+	}
+	modified_content.push_str(rest);
+
+	(modified_content, changes_at)
+}
+
+/// Where the closing tag of a condition starts, skipping nested ones
+fn find_closing_if(text: &str, opening: &str, closing: &str) -> Option<usize> {
+	let mut depth = 0;
+	let mut offset = 0;
+	loop {
+		let start = offset + text[offset..].find('[')?;
+		let tail = &text[start..];
+		if tail.starts_with(closing) {
+			if depth == 0 { return Some(start); }
+			depth -= 1;
+		} else if tail.starts_with(opening) && parse_shortcode(tail).is_some_and(|(shortcode, _)| shortcode.key == opening[1..]) {
+			depth += 1;
+		}
+		offset = start + 1;
+	}
+}
+
+/// Whether the content of a condition is shown right now, plus the next time that changes
+fn evaluate_condition(args: &BTreeMap<String, String>, now: u64) -> (bool, Option<u64>) {
+	let mut visible = true;
+	let mut changes_at = None;
+
+	for (name, value) in args {
+		let time = match parse_condition_time(value) {
+			Some(tmp) => tmp,
+			_ => { return (false, None); }
+		};
+		match name.as_str() {
+			"before" => { visible &= now < time; }
+			"after" => { visible &= now >= time; }
+			_ => { return (false, None); }
+		}
+		if time > now { changes_at = earliest(changes_at, Some(time)); }
+	}
+
+	(visible, changes_at)
+}
+
+/// A date like `2025-06-01` or a time like `2025-06-01 18:00` in the timezone of the site, as unix time
+fn parse_condition_time(value: &str) -> Option<u64> {
+	let time = match NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d") {
+		Ok(date) => date.and_hms(0, 0, 0),
+		_ => NaiveDateTime::parse_from_str(value.trim(), "%Y-%m-%d %H:%M").ok()?
+	};
+	match site_time_to_utc(time)?.timestamp() {
+		tmp if tmp >= 0 => Some(tmp as u64),
+		_ => None
+	}
+}
+
+/// The earlier of two optional times
+fn earliest(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+	match (a, b) {
+		(Some(a), Some(b)) => Some(a.min(b)),
+		(None, b) => b,
+		(a, None) => a
+	}
+}
+
+/// Parse the shortcode at the start of `text`, which begins with `[`
+///
+/// Values may be quoted with `"` or `'`, a backslash escapes the next character. Returns the
+/// shortcode and the length of its source, None if this is no complete shortcode
+pub fn parse_shortcode(text: &str) -> Option<(Shortcode, usize)> {
+	let mut chars = text.char_indices().peekable();
+	match chars.next() {
+		Some((_, '[')) => {}
+		_ => { return None; }
+	}
+
+	let key = take_word(&mut chars);
+	if key.len() == 0 { return None; }
+
+	let mut args = BTreeMap::new();
+	loop {
+		while chars.peek().is_some_and(|(_, c)| c.is_whitespace()) { chars.next(); }
+
+		match chars.peek() {
+			Some(&(index, ']')) => { return Some((Shortcode { key, args }, index + 1)); }
+			// A shortcode that is never closed, or one that starts inside of this one
+			None | Some((_, '[')) => { return None; }
+			_ => {}
+		}
+
+		let name = take_word(&mut chars);
+		if name.len() == 0 { return None; }
+
+		if chars.peek().is_some_and(|(_, c)| *c == '=') {
+			chars.next();
+			let value = take_value(&mut chars)?;
+			args.insert(name, value);
+		} else {
+			args.insert(name, String::from("true"));
+		}
+	}
+}
+
+/// A key or argument name, up to the next space, `=`, quote or bracket
+fn take_word(chars: &mut Peekable<CharIndices>) -> String {
+	let mut word = String::new();
+	while let Some(&(_, c)) = chars.peek() {
+		if c.is_whitespace() || c == '=' || c == '"' || c == '\'' || c == '[' || c == ']' { break; }
+		word.push(c);
+		chars.next();
+	}
+	word
+}
+
+/// A quoted value, or an unquoted one up to the next space or `]`
+fn take_value(chars: &mut Peekable<CharIndices>) -> Option<String> {
+	let mut value = String::new();
+
+	let quote = match chars.peek() {
+		Some(&(_, c)) if c == '"' || c == '\'' => {
+			chars.next();
+			Some(c)
+		}
+		_ => None
+	};
+
+	loop {
+		let c = match (chars.peek(), quote) {
+			// An unquoted value ends at the end of the shortcode, which is left for the caller
+			(Some(&(_, c)), None) if c.is_whitespace() || c == ']' => { return Some(value); }
+			(Some(_), _) => { chars.next()?.1 }
+			// Quotes have to be closed
+			(None, _) => { return None; }
+		};
+
+		match c {
+			'\\' => { value.push(chars.next()?.1); }
+			c if Some(c) == quote => { return Some(value); }
+			c => { value.push(c); }
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+		pairs.iter().map(|(name, value)| (String::from(*name), String::from(*value))).collect()
+	}
+
+	#[test]
+	fn parses_quoted_and_unquoted_values() {
+		let (shortcode, length) = parse_shortcode(r#"[button label="Read more" target='_blank' id=3] after"#).unwrap();
+		assert_eq!(shortcode.key, "button");
+		assert_eq!(shortcode.args, args(&[("label", "Read more"), ("target", "_blank"), ("id", "3")]));
+		assert_eq!(length, r#"[button label="Read more" target='_blank' id=3]"#.len());
+	}
+
+	#[test]
+	fn parses_escaped_quotes() {
+		let (shortcode, _) = parse_shortcode(r#"[quote text="She said \"hi\"" by='O\'Brien']"#).unwrap();
+		assert_eq!(shortcode.args, args(&[("text", r#"She said "hi""#), ("by", "O'Brien")]));
+	}
+
+	#[test]
+	fn parses_bare_flags() {
+		let (shortcode, _) = parse_shortcode(r#"[button primary label="Go" large]"#).unwrap();
+		assert_eq!(shortcode.args, args(&[("primary", "true"), ("label", "Go"), ("large", "true")]));
+
+		let (shortcode, length) = parse_shortcode("[newsletter]").unwrap();
+		assert_eq!(shortcode, Shortcode { key: String::from("newsletter"), args: BTreeMap::new() });
+		assert_eq!(length, "[newsletter]".len());
+	}
+
+	#[test]
+	fn rejects_unterminated_tags() {
+		assert_eq!(parse_shortcode(r#"[button label="Go""#), None);
+		assert_eq!(parse_shortcode(r#"[button label="Go]"#), None);
+		assert_eq!(parse_shortcode("[button primary"), None);
+		assert_eq!(parse_shortcode("[button [poll id=1]"), None);
+		assert_eq!(parse_shortcode("[]"), None);
+		assert_eq!(parse_shortcode("no shortcode"), None);
+	}
+
+	#[test]
+	fn keeps_brackets_inside_quoted_values() {
+		let text = r#"[button label="Читать [далее]"] и дальше"#;
+		let (shortcode, length) = parse_shortcode(text).unwrap();
+		assert_eq!(shortcode.args, args(&[("label", "Читать [далее]")]));
+		assert_eq!(&text[..length], r#"[button label="Читать [далее]"]"#);
+	}
+}
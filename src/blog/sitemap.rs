@@ -1,3 +1,15 @@
+use crate::app::config::{config_get_string, config_get_string_map};
+use crate::app::timezone::format_w3c;
+
+/// The change frequencies the sitemap protocol knows
+const CHANGEFREQS: [&str; 7] = ["always", "hourly", "daily", "weekly", "monthly", "yearly", "never"];
+
+/// Google News only takes articles from the last two days
+pub const NEWS_MAX_AGE: u64 = 2 * 86400;
+
+/// And at most this many of them
+pub const NEWS_MAX_URLS: usize = 1000;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SiteMap {
 	pub content: Option<Vec<SiteMapUrl>>,
@@ -7,6 +19,8 @@ pub struct SiteMap {
 pub struct SiteMapUrl {
 	pub loc: String,
 	pub lastmod: u64,
+	/// `lastmod` in the timezone of the site
+	pub lastmod_w3c: String,
 	pub changefreq: Option<String>,
 	pub priority: Option<String>,
 	pub images: Option<Vec<SiteMapImage>>,
@@ -17,4 +31,108 @@ pub struct SiteMapImage {
 	pub loc: String,
 	pub title: Option<String>,
 	pub caption: Option<String>,
-}
\ No newline at end of file
+}
+
+/// A recent post in the news site map
+#[derive(Clone, Debug)]
+pub struct NewsSiteMapUrl {
+	pub loc: String,
+	pub title: String,
+	pub date_posted: u64,
+}
+
+/// The `changefreq` and `priority` of one kind of page, from its `[sitemap.<kind>]` table
+///
+/// Kinds are `posts`, `pages` (the start page), `tags` and `archives` (further index and tag pages).
+/// An empty priority leaves it out, a missing or invalid one falls back to `default_priority`
+pub fn sitemap_settings(kind: &str, default_priority: &str) -> (Option<String>, Option<String>) {
+	let settings = config_get_string_map(&format!("sitemap.{}", kind));
+
+	let changefreq = match settings.get("changefreq").map(|tmp| tmp.trim().to_lowercase()) {
+		Some(tmp) if CHANGEFREQS.contains(&tmp.as_str()) => Some(tmp),
+		Some(tmp) if tmp.len() > 0 => {
+			println!("Ignoring sitemap.{}.changefreq '{}', expected one of {}", kind, tmp, CHANGEFREQS.join(", "));
+			None
+		}
+		_ => None
+	};
+
+	let priority = match settings.get("priority").map(|tmp| tmp.trim()) {
+		Some("") => None,
+		Some(tmp) => match tmp.parse::<f32>() {
+			Ok(value) if (0.0..=1.0).contains(&value) => Some(String::from(tmp)),
+			_ => {
+				println!("Ignoring sitemap.{}.priority '{}', expected a number from 0.0 to 1.0", kind, tmp);
+				Some(String::from(default_priority))
+			}
+		},
+		_ => Some(String::from(default_priority))
+	};
+
+	(changefreq, priority)
+}
+
+
+/// Whether there is a news site map, `[sitemap.news]` needs at least the `publication` name
+pub fn news_site_map_enabled() -> bool {
+	config_get_string_map("sitemap.news").get("publication").is_some_and(|tmp| tmp.trim().len() > 0)
+}
+
+/// The Google News site map of the given posts
+///
+/// The publication is named by `sitemap.news.publication`, its language is `sitemap.news.language`
+/// or the one of `locale`
+pub fn news_site_map(urls: &Vec<NewsSiteMapUrl>) -> String {
+	let settings = config_get_string_map("sitemap.news");
+	let publication = escape_xml(settings.get("publication").map_or("", |tmp| tmp.trim()));
+	let language = match settings.get("language").map(|tmp| tmp.trim().to_lowercase()) {
+		Some(tmp) if tmp.len() > 0 => tmp,
+		_ => match config_get_string("locale").split(['_', '-']).next().map(|tmp| tmp.to_lowercase()) {
+			Some(tmp) if tmp.len() > 0 => tmp,
+			_ => String::from("en")
+		}
+	};
+
+	let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:news="http://www.google.com/schemas/sitemap-news/0.9">
+"#);
+	for url in urls.iter().take(NEWS_MAX_URLS) {
+		xml.push_str(&format!(r#"	<url>
+		<loc>{}</loc>
+		<news:news>
+			<news:publication>
+				<news:name>{}</news:name>
+				<news:language>{}</news:language>
+			</news:publication>
+			<news:publication_date>{}</news:publication_date>
+			<news:title>{}</news:title>
+		</news:news>
+	</url>
+"#, escape_xml(&url.loc), publication, escape_xml(&language), format_w3c(url.date_posted), escape_xml(&url.title)));
+	}
+	xml.push_str("</urlset>\n");
+
+	xml
+}
+
+/// The site map index robots.txt points to, the site map plus the news site map if there is one
+pub fn site_map_index() -> String {
+	let base_url = format!("https://{}/", config_get_string("fqdn"));
+	let mut locs = vec![format!("{}sitemap.xml", base_url)];
+	if news_site_map_enabled() { locs.push(format!("{}sitemap/news.xml", base_url)); }
+
+	let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+"#);
+	for loc in locs {
+		xml.push_str(&format!("\t<sitemap>\n\t\t<loc>{}</loc>\n\t</sitemap>\n", escape_xml(&loc)));
+	}
+	xml.push_str("</sitemapindex>\n");
+
+	xml
+}
+
+/// Escape text for XML, unlike HTML escaping this keeps slashes in URLs as they are
+fn escape_xml(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use actix_web::web;
+use chrono::{Duration, Utc};
+use regex::Regex;
+use trust_dns_resolver::Resolver;
+use trust_dns_resolver::error::ResolveErrorKind;
+
+use crate::app::config::{config_get_bool, config_get_string_vec};
+use crate::storage::Storage;
+
+lazy_static! {
+	static ref SPAM_RULES: RwLock<Arc<SpamRules>> = RwLock::new(Arc::new(SpamRules::default()));
+	static ref LINK_REGEX: Regex = Regex::new(r"(?i)(https?://|www\.)").unwrap();
+	/// Whether a domain takes mail, and when we looked it up
+	static ref MAIL_DOMAINS: RwLock<HashMap<String, (bool, i64)>> = RwLock::new(HashMap::new());
+}
+
+/// Domains set aside for documentation and tests, nobody reads mail there (RFC 2606)
+const RESERVED_DOMAINS: &[&str] = &["example.com", "example.net", "example.org"];
+const RESERVED_TLDS: &[&str] = &["example", "invalid", "local", "localhost", "test"];
+
+/// Seconds a mail server lookup is remembered
+const MAIL_DOMAIN_LIFETIME: i64 = 86400;
+
+/// Domains remembered at most, the oldest lookups are dropped first
+const MAIL_DOMAINS_MAX: usize = 10000;
+
+
+// ------------------------------
+// ------------ RULES -----------
+// ------------------------------
+
+/// How suspicious comments are scored, comments reaching the threshold are marked as spam
+///
+/// The banned words come from `spam_banned_words` in the config until the rules are saved in the admin panel
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SpamRules {
+	/// Score at which a comment is spam, 0 only scores without marking anything
+	pub threshold: u32,
+	/// Links a comment may contain for free
+	pub links_allowed: u32,
+	/// Added for every link above the allowed ones
+	pub link_score: u32,
+	/// Matched case insensitive against the name and the content
+	pub banned_words: Vec<String>,
+	/// Added for every banned word found
+	pub banned_word_score: u32,
+	/// Another comment by the same author within this many seconds is suspicious
+	pub min_seconds_between: u32,
+	pub speed_score: u32,
+	/// Added if the same text was posted under another post
+	pub duplicate_score: u32,
+	/// Added if the domain of the email has no mail server, needs `comment_email_mx_check`
+	pub no_mail_server_score: u32,
+}
+
+impl Default for SpamRules {
+	fn default() -> Self {
+		SpamRules {
+			threshold: 10,
+			links_allowed: 2,
+			link_score: 3,
+			banned_words: config_get_string_vec("spam_banned_words"),
+			banned_word_score: 5,
+			min_seconds_between: 60,
+			speed_score: 5,
+			duplicate_score: 10,
+			no_mail_server_score: 5,
+		}
+	}
+}
+
+impl SpamRules {
+	/// Clean up what the admin panel sent
+	fn normalized(mut self) -> SpamRules {
+		self.banned_words = self.banned_words.iter()
+			.map(|word| word.trim().to_lowercase())
+			.filter(|word| word.len() > 0)
+			.collect();
+		self.banned_words.sort();
+		self.banned_words.dedup();
+		self
+	}
+}
+
+
+/// The rules currently in use
+pub fn spam_rules() -> Arc<SpamRules> {
+	match SPAM_RULES.read() {
+		Ok(guard) => guard.clone(),
+		_ => Arc::new(SpamRules::default())
+	}
+}
+
+/// Load the rules saved in the admin panel, keeps the defaults if there are none
+pub fn spam_rules_load(db: &Arc<dyn Storage>) -> Result<(), String> {
+	let rules = match db.query_first("SELECT rules FROM spam_rules WHERE id=1", db_params!()) {
+		Ok(Some(mut row)) => {
+			let data: String = row.take("rules").unwrap_or_default();
+			serde_json::from_str::<SpamRules>(&data).map_err(|err| format!("Invalid spam rules: {}", err))?
+		}
+		Ok(None) => { return Ok(()); }
+		Err(err) => { return Err(err.to_string()); }
+	};
+
+	match SPAM_RULES.write() {
+		Ok(mut guard) => { *guard = Arc::new(rules.normalized()); }
+		_ => {}
+	}
+
+	Ok(())
+}
+
+/// Save new rules and use them right away
+pub fn spam_rules_store(db: &Arc<dyn Storage>, rules: SpamRules) -> Result<Arc<SpamRules>, String> {
+	let rules = Arc::new(rules.normalized());
+
+	let query = format!(r##"
+    INSERT INTO spam_rules (id, rules, updated_at) VALUES
+    (1, :rules, :updated_at)
+    {}
+    "##, db.upsert("id", &["rules", "updated_at"]));
+
+	let data = serde_json::to_string(&*rules).map_err(|err| err.to_string())?;
+	match db.exec(&query, db_params! {"rules" => &data, "updated_at" => Utc::now().naive_utc()}) {
+		Ok(_) => {}
+		Err(err) => {
+			println!("Error: {:?}", err);
+			return Err(err.to_string());
+		}
+	}
+
+	match SPAM_RULES.write() {
+		Ok(mut guard) => { *guard = rules.clone(); }
+		_ => {}
+	}
+
+	Ok(rules)
+}
+
+
+// ------------------------------
+// ----------- SCORING ----------
+// ------------------------------
+
+/// Score a new or edited comment, the higher the more likely it is spam
+///
+/// `email_score` comes from `email_score`, which has to be awaited beforehand. `comment_id` is the
+/// comment being edited, 0 for a new one, so it does not count against itself.
+/// Returns the score and whether it reaches the threshold
+pub fn spam_score_comment(db: &Arc<dyn Storage>, comment_id: u32, post_id: u32, author: &str, email: &str, content: &str, email_score: u32) -> (u32, bool) {
+	let rules = spam_rules();
+	let mut score = email_score;
+
+	// Links
+	let links = LINK_REGEX.find_iter(content).count() as u32;
+	if links > rules.links_allowed {
+		score += (links - rules.links_allowed) * rules.link_score;
+	}
+
+	// Banned words
+	let text = format!("{} {}", author, content).to_lowercase();
+	score += rules.banned_words.iter().filter(|word| text.contains(word.as_str())).count() as u32 * rules.banned_word_score;
+
+	// Submission speed, by name or email
+	if rules.min_seconds_between > 0 {
+		let since = Utc::now().naive_utc() - Duration::seconds(rules.min_seconds_between as i64);
+		let query = "SELECT COUNT(*) AS count FROM post_comments WHERE (author_name=:author OR (author_email=:email AND author_email<>'')) AND date_posted >= :since AND id<>:id";
+		if count_rows(db, query, db_params! {"author" => author, "email" => email, "since" => since, "id" => comment_id}) > 0 {
+			score += rules.speed_score;
+		}
+	}
+
+	// The same text under another post
+	let query = "SELECT COUNT(*) AS count FROM post_comments WHERE content=:content AND post_id<>:post_id";
+	if count_rows(db, query, db_params! {"content" => content, "post_id" => post_id}) > 0 {
+		score += rules.duplicate_score;
+	}
+
+	(score, rules.threshold > 0 && score >= rules.threshold)
+}
+
+fn count_rows(db: &Arc<dyn Storage>, query: &str, params: crate::storage::DbParams) -> i64 {
+	match db.query_first(query, params) {
+		Ok(Some(mut row)) => row.take("count").unwrap_or(0),
+		Err(err) => {
+			println!("Error: {:?}", err);
+			0
+		}
+		_ => 0
+	}
+}
+
+
+// ------------------------------
+// ----------- EMAIL ------------
+// ------------------------------
+
+/// Check that an email address could exist, empty ones are fine as the email is optional
+///
+/// Addresses at reserved domains like `example.com` are refused as well
+pub fn email_check(email: &str) -> Result<(), String> {
+	let email = email.trim();
+	if email.len() == 0 { return Ok(()); }
+
+	match email_domain(email) {
+		Some(domain) => {
+			let tld = domain.rsplit('.').next().unwrap_or("");
+			if RESERVED_DOMAINS.iter().any(|tmp| domain == *tmp || domain.ends_with(&format!(".{}", tmp))) || RESERVED_TLDS.contains(&tld) {
+				return Err(String::from("Please provide an email address you can be reached at."));
+			}
+			Ok(())
+		}
+		_ => Err(String::from("Please check your email address."))
+	}
+}
+
+/// The lower case domain of a syntactically valid address, None if it is not one
+///
+/// Quoted local parts and IP literals are not accepted, people commenting do not use them
+fn email_domain(email: &str) -> Option<String> {
+	if email.len() > 254 { return None; }
+
+	let pos = email.rfind('@')?;
+	let (local, domain) = (&email[..pos], email[pos + 1..].to_lowercase());
+
+	if local.len() == 0 || local.len() > 64 || local.starts_with('.') || local.ends_with('.') || local.contains("..") { return None; }
+	if !local.chars().all(|c| c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c)) { return None; }
+
+	let labels: Vec<&str> = domain.split('.').collect();
+	if labels.len() < 2 { return None; }
+	for label in &labels {
+		if label.len() == 0 || label.len() > 63 || label.starts_with('-') || label.ends_with('-') { return None; }
+		if !label.chars().all(|c| c.is_alphanumeric() || c == '-') { return None; }
+	}
+
+	// Top level domains have letters, anything else is an IP address or a typo
+	let tld = labels[labels.len() - 1];
+	if tld.chars().count() < 2 || tld.chars().all(|c| c.is_ascii_digit()) { return None; }
+
+	Some(domain)
+}
+
+/// Score for the email of a new comment, `no_mail_server_score` if its domain takes no mail
+///
+/// Only looked up with `comment_email_mx_check`, off the async threads and remembered for a day.
+/// A domain we could not look up is not held against anyone
+pub async fn email_score(email: &str) -> u32 {
+	if !config_get_bool("comment_email_mx_check") { return 0; }
+
+	let domain = match email_domain(email.trim()) {
+		Some(tmp) => tmp,
+		_ => { return 0; }
+	};
+	let now = Utc::now().timestamp();
+
+	let cached = match MAIL_DOMAINS.read() {
+		Ok(guard) => guard.get(&domain).filter(|(_, checked_at)| *checked_at + MAIL_DOMAIN_LIFETIME > now).map(|(takes_mail, _)| *takes_mail),
+		_ => None
+	};
+
+	let takes_mail = match cached {
+		Some(tmp) => tmp,
+		_ => {
+			let lookup_domain = domain.clone();
+			match web::block(move || domain_takes_mail(&lookup_domain)).await {
+				Ok(tmp) => {
+					remember_mail_domain(domain, tmp, now);
+					tmp
+				}
+				Err(err) => {
+					println!("Could not look up the mail server of {}: {:?}", domain, err);
+					true
+				}
+			}
+		}
+	};
+
+	if takes_mail { 0 } else { spam_rules().no_mail_server_score }
+}
+
+/// Whether a domain has a mail server, without MX records the address records are used (RFC 5321)
+fn domain_takes_mail(domain: &str) -> Result<bool, String> {
+	let resolver = Resolver::from_system_conf().map_err(|err| err.to_string())?;
+	let fqdn = format!("{}.", domain);
+
+	match resolver.mx_lookup(fqdn.as_str()) {
+		Ok(records) => {
+			// A single "." means the domain explicitly takes no mail (RFC 7505)
+			let exchanges: Vec<String> = records.iter().map(|mx| mx.exchange().to_string()).collect();
+			return Ok(exchanges.iter().any(|tmp| tmp != "."));
+		}
+		Err(err) => match err.kind() {
+			ResolveErrorKind::NoRecordsFound { .. } => {}
+			_ => { return Err(err.to_string()); }
+		}
+	}
+
+	match resolver.lookup_ip(fqdn.as_str()) {
+		Ok(ips) => Ok(ips.iter().next().is_some()),
+		Err(err) => match err.kind() {
+			ResolveErrorKind::NoRecordsFound { .. } => Ok(false),
+			_ => Err(err.to_string())
+		}
+	}
+}
+
+fn remember_mail_domain(domain: String, takes_mail: bool, now: i64) {
+	match MAIL_DOMAINS.write() {
+		Ok(mut guard) => {
+			if guard.len() >= MAIL_DOMAINS_MAX {
+				guard.retain(|_, (_, checked_at)| *checked_at + MAIL_DOMAIN_LIFETIME > now);
+			}
+			if guard.len() < MAIL_DOMAINS_MAX { guard.insert(domain, (takes_mail, now)); }
+		}
+		_ => {}
+	}
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use crate::app::config::config_get_string;
+use crate::app::timezone::{site_time, site_utc_offset_seconds};
+use crate::blog::Blog;
+use crate::storage::Storage;
+
+/// Top posts and referrers cover this many days, today included
+const STATS_RECENT_DAYS: i64 = 30;
+
+/// Monthly views go back this far
+const STATS_HISTORY_DAYS: i64 = 365;
+
+/// Number of posts in the top list
+const STATS_TOP_POSTS: usize = 10;
+
+/// Referrer hosts by category, subdomains match as well
+const REFERRER_CATEGORIES: &[(&str, &[&str])] = &[
+	("search", &["google.com", "google.de", "google.co.uk", "google.fr", "bing.com", "duckduckgo.com", "ecosia.org", "yahoo.com", "yandex.ru", "baidu.com", "qwant.com", "startpage.com"]),
+	("social", &["facebook.com", "instagram.com", "t.co", "twitter.com", "x.com", "pinterest.com", "pinterest.de", "reddit.com", "linkedin.com", "youtube.com", "tiktok.com"]),
+];
+
+
+// ------------------------------
+// ------------ STATS -----------
+// ------------------------------
+
+/// Numbers for the public stats page, aggregated so no single visitor can be told apart
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PublicStats {
+	pub top_posts: Vec<StatsPost>,
+	pub monthly_views: Vec<StatsMonth>,
+	/// `search`, `social`, `direct`, `internal` and `other`
+	pub referrers: Vec<StatsReferrer>,
+	pub recent_days: u32,
+	pub generated_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatsPost {
+	pub post_id: u32,
+	pub title: String,
+	pub url: String,
+	pub views: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatsMonth {
+	/// e.g. `2020-04`
+	pub month: String,
+	pub views: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StatsReferrer {
+	pub category: String,
+	pub views: u32,
+}
+
+
+/// Query the aggregated views for the stats page, only published posts are listed
+pub fn stats_get_public(db: &Arc<dyn Storage>, blog: &Blog) -> PublicStats {
+	// Days of the site's timezone, see the dashboard
+	let offset = site_utc_offset_seconds();
+	let today = site_time(Utc::now().naive_utc()).naive_local().date().and_hms(0, 0, 0) - Duration::seconds(offset);
+	let since_recent = today - Duration::days(STATS_RECENT_DAYS - 1);
+	let since_history = today - Duration::days(STATS_HISTORY_DAYS - 1);
+
+	// Top posts, more than we show as some may not be public
+	let query_a = r###"
+        SELECT post_id, COUNT(id) AS views
+        FROM post_views
+        WHERE viewed_at >= :since
+        GROUP BY post_id
+        ORDER BY COUNT(id) DESC LIMIT 0,50
+    "###;
+
+	let mut top_posts = Vec::new();
+	match db.query(query_a, db_params! {"since" => since_recent}) {
+		Ok(rows) => {
+			for mut row in rows {
+				let (post_id, views): (u32, u32) = match (row.take("post_id"), row.take("views")) {
+					(Some(a), Some(b)) => (a, b),
+					_ => { continue; }
+				};
+				match blog.get_post(post_id) {
+					Some(post) => { top_posts.push(StatsPost { post_id, title: post.title.clone(), url: format!("/{}", post.url_canonical), views }); }
+					_ => {}
+				}
+				if top_posts.len() == STATS_TOP_POSTS { break; }
+			}
+		}
+		_ => {}
+	}
+
+	// Days work the same on every database, they are summed up by month here
+	let viewed_at = db.shift_seconds("viewed_at", offset);
+	let query_b = format!(r###"
+        SELECT DATE({}) AS date, COUNT(id) AS views
+        FROM post_views
+        WHERE viewed_at >= :since
+        GROUP BY DATE({})
+    "###, viewed_at, viewed_at);
+
+	let mut months: BTreeMap<String, u32> = BTreeMap::new();
+	match db.query(&query_b, db_params! {"since" => since_history}) {
+		Ok(rows) => {
+			for mut row in rows {
+				match (row.take::<NaiveDateTime>("date"), row.take::<u32>("views")) {
+					(Some(date), Some(views)) => { *months.entry(date.format("%Y-%m").to_string()).or_insert(0) += views; }
+					_ => {}
+				}
+			}
+		}
+		_ => {}
+	}
+	let monthly_views = months.into_iter().map(|(month, views)| StatsMonth { month, views }).collect();
+
+	// Only the category of a referrer is shown, never the page it came from
+	let query_c = r###"
+        SELECT referer, COUNT(id) AS views
+        FROM post_views
+        WHERE viewed_at >= :since
+        GROUP BY referer
+    "###;
+
+	let own_host = config_get_string("fqdn").to_lowercase();
+	let mut categories: BTreeMap<&str, u32> = BTreeMap::new();
+	match db.query(query_c, db_params! {"since" => since_recent}) {
+		Ok(rows) => {
+			for mut row in rows {
+				match (row.take::<String>("referer"), row.take::<u32>("views")) {
+					(Some(referer), Some(views)) => { *categories.entry(referrer_category(&referer, &own_host)).or_insert(0) += views; }
+					_ => {}
+				}
+			}
+		}
+		_ => {}
+	}
+	let mut referrers: Vec<StatsReferrer> = categories.into_iter().map(|(category, views)| StatsReferrer { category: String::from(category), views }).collect();
+	referrers.sort_by(|a, b| b.views.cmp(&a.views));
+
+	PublicStats {
+		top_posts,
+		monthly_views,
+		referrers,
+		recent_days: STATS_RECENT_DAYS as u32,
+		generated_at: Utc::now().timestamp() as u64,
+	}
+}
+
+/// Sort a referrer into one of a few categories
+fn referrer_category(referer: &str, own_host: &str) -> &'static str {
+	let lower = referer.trim().to_lowercase();
+	if lower.len() == 0 { return "direct"; }
+
+	let rest = lower.split_once("://").map(|x| x.1).unwrap_or(&lower);
+	let host = rest.split(['/', '?', '#', ':']).next().unwrap_or("");
+	let matches = |domain: &str| host == domain || host.ends_with(&format!(".{}", domain));
+
+	if matches(own_host) { return "internal"; }
+	for (category, domains) in REFERRER_CATEGORIES {
+		if domains.iter().any(|domain| matches(domain)) { return category; }
+	}
+
+	"other"
+}
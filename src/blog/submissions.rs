@@ -0,0 +1,312 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use image::GenericImageView;
+
+use crate::app::config::config_get_i64;
+use crate::app::utils::{get_extension_from_filename, weak_random_base62_string};
+use crate::blog::gallery::{finish_file_upload, generate_upload_file_name, set_focal_point, FocalPoint};
+use crate::blog::types::post::{admin_fetch_post, PostMedia};
+use crate::storage::{DbRow, Storage};
+
+/// Submissions wait here until they are moderated, outside of the gallery so they are never served
+pub const QUARANTINE_PATH: &str = "data/quarantine";
+
+/// Only still images, and only those the gallery can resize
+const SUBMISSION_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// Default upper limit for a submitted file, 10 MB
+pub const SUBMISSION_MAX_SIZE: u64 = 10485760;
+
+/// Default number of submissions per address and day
+const SUBMISSIONS_PER_DAY: i64 = 3;
+
+/// The shorter side of a submitted photo must have at least this many pixels
+const SUBMISSION_MIN_SIDE: u32 = 800;
+
+/// Larger images are most likely meant to exhaust our memory
+const SUBMISSION_MAX_SIDE: u32 = 12000;
+
+
+// ------------------------------
+// ---------- SUBMISSION --------
+// ------------------------------
+
+/// A photo a reader sent in, e.g. for the photo of the week
+#[derive(Serialize, Debug)]
+pub struct PhotoSubmission {
+	pub id: u32,
+	/// `pending`, `approved` or `rejected`
+	pub status: String,
+	pub file: String,
+	pub author_name: String,
+	pub author_email: String,
+	pub title: String,
+	pub caption: String,
+	pub remote_ip: String,
+	pub submitted_at: u64,
+	/// The gallery image, once approved
+	pub guid: Option<String>,
+	/// The post it was attached to, if any
+	pub post_id: Option<u32>,
+}
+
+/// How the admin panel approves a submission
+#[derive(Deserialize, Debug)]
+pub struct SubmissionApproval {
+	pub id: u32,
+	/// Attach the photo to this post, 0 for none
+	#[serde(default)]
+	pub post_id: u32,
+	/// Where to crop around, in percent like `FocalPoint`
+	#[serde(default)]
+	pub focal_x: Option<f32>,
+	#[serde(default)]
+	pub focal_y: Option<f32>,
+}
+
+impl PhotoSubmission {
+	fn from_sql(mut row: DbRow) -> Option<PhotoSubmission> {
+		Some(PhotoSubmission {
+			id: row.take("id")?,
+			status: row.take("status")?,
+			file: row.take("file")?,
+			author_name: row.take("author_name")?,
+			author_email: row.take("author_email")?,
+			title: row.take("title")?,
+			caption: row.take("caption")?,
+			remote_ip: row.take("remote_ip")?,
+			submitted_at: row.take::<NaiveDateTime>("submitted_at")?.timestamp() as u64,
+			guid: row.take("guid")?,
+			post_id: row.take("post_id")?,
+		})
+	}
+
+	/// Where the file is kept until it is moderated
+	pub fn quarantine_path(&self) -> String {
+		format!("{}/{}", QUARANTINE_PATH, self.file)
+	}
+}
+
+
+/// The largest file a reader may submit, `photo_submission_max_size` in bytes
+pub fn submission_max_size() -> u64 {
+	match config_get_i64("photo_submission_max_size") {
+		size if size > 0 => size as u64,
+		_ => SUBMISSION_MAX_SIZE
+	}
+}
+
+/// A new path in the quarantine for an uploaded file, only accepts image extensions
+pub fn generate_quarantine_file_name(uploaded_name: &str) -> Result<String, String> {
+	let extension = match get_extension_from_filename(uploaded_name) {
+		Some(ext) if SUBMISSION_EXTENSIONS.contains(&ext.to_lowercase().as_str()) => ext.to_lowercase(),
+		_ => return Err(String::from("Only JPEG and PNG photos can be submitted.")),
+	};
+
+	fs::create_dir_all(QUARANTINE_PATH).map_err(|err| format!("Could not create the quarantine: {}", err))?;
+
+	for _ in 0..25 {
+		let path_local = format!("{}/{}.{}", QUARANTINE_PATH, weak_random_base62_string(15), extension);
+		if !Path::new(&path_local).exists() {
+			return Ok(path_local);
+		}
+	}
+
+	Err(String::from("All file names collide"))
+}
+
+/// Check whether the address sent too many photos today, `photo_submissions_per_day` overrides the limit
+pub fn submission_rate_limited(db: &Arc<dyn Storage>, remote_ip: &str) -> bool {
+	let limit = match config_get_i64("photo_submissions_per_day") {
+		tmp if tmp > 0 => tmp,
+		_ => SUBMISSIONS_PER_DAY
+	};
+
+	let since = Utc::now().naive_utc() - Duration::days(1);
+	let query = "SELECT COUNT(*) AS count FROM photo_submissions WHERE remote_ip=:remote_ip AND submitted_at >= :since";
+	match db.query_first(query, db_params! {"remote_ip" => remote_ip, "since" => since}) {
+		Ok(Some(mut row)) => row.take::<i64>("count").unwrap_or(0) >= limit,
+		// Better to turn someone away than to be flooded
+		_ => true
+	}
+}
+
+/// Check the uploaded file and queue it for moderation, the file is removed if it is not acceptable
+///
+/// Returns the id of the submission
+pub fn store_photo_submission(db: &Arc<dyn Storage>, path: &str, author: &str, email: &str, title: &str, caption: &str, remote_ip: &str) -> Result<u64, String> {
+	let result = validate_submitted_photo(path, author)
+		.and_then(|_| insert_photo_submission(db, path, author.trim(), email.trim(), title.trim(), caption.trim(), remote_ip));
+
+	if result.is_err() {
+		match fs::remove_file(path) {
+			Err(err) => { println!("Error removing rejected submission: {:?}", err); }
+			_ => {}
+		}
+	}
+
+	result
+}
+
+fn validate_submitted_photo(path: &str, author: &str) -> Result<(), String> {
+	if author.trim().len() == 0 {
+		return Err(String::from("Kindly provide your name."));
+	}
+
+	// The file really has to be an image of a sensible size
+	let img = image::open(path).map_err(|_| String::from("The file is not a photo we can read."))?;
+	let (x, y) = img.dimensions();
+	if x.min(y) < SUBMISSION_MIN_SIDE {
+		return Err(format!("The photo needs to be at least {} pixels wide and high.", SUBMISSION_MIN_SIDE));
+	}
+	if x.max(y) > SUBMISSION_MAX_SIDE {
+		return Err(format!("The photo may not be larger than {} pixels.", SUBMISSION_MAX_SIDE));
+	}
+
+	Ok(())
+}
+
+fn insert_photo_submission(db: &Arc<dyn Storage>, path: &str, author: &str, email: &str, title: &str, caption: &str, remote_ip: &str) -> Result<u64, String> {
+	let file = match Path::new(path).file_name().and_then(|tmp| tmp.to_str()) {
+		Some(tmp) => tmp,
+		_ => return Err(String::from("Invalid file name")),
+	};
+
+	let query = r##"INSERT INTO photo_submissions (status, file, author_name, author_email, title, caption, remote_ip, submitted_at)
+        VALUES (:status, :file, :author_name, :author_email, :title, :caption, :remote_ip, :submitted_at)"##;
+
+	match db.exec(query, db_params! {
+		"status" => "pending", "file" => file, "author_name" => author, "author_email" => email, "title" => title,
+		"caption" => caption, "remote_ip" => remote_ip, "submitted_at" => Utc::now().naive_utc()
+	}) {
+		Ok(res) => { Ok(res.last_insert_id) }
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
+
+
+// ------------------------------
+// --------- MODERATION ---------
+// ------------------------------
+
+/// All submissions with the given status, newest first
+pub fn load_photo_submissions(db: &Arc<dyn Storage>, status: &str) -> Vec<PhotoSubmission> {
+	let query = r##"SELECT id, status, file, author_name, author_email, title, caption, remote_ip, submitted_at, guid, post_id
+        FROM photo_submissions WHERE status=:status ORDER BY id DESC"##;
+
+	match db.query(query, db_params! {"status" => status}) {
+		Ok(rows) => rows.into_iter().filter_map(PhotoSubmission::from_sql).collect(),
+		_ => vec![]
+	}
+}
+
+/// A single submission
+pub fn load_photo_submission(db: &Arc<dyn Storage>, id: u32) -> Option<PhotoSubmission> {
+	let query = r##"SELECT id, status, file, author_name, author_email, title, caption, remote_ip, submitted_at, guid, post_id
+        FROM photo_submissions WHERE id=:id"##;
+
+	match db.query_first(query, db_params! {"id" => id}) {
+		Ok(Some(row)) => PhotoSubmission::from_sql(row),
+		_ => None
+	}
+}
+
+/// Move a pending submission into the gallery, set its crop and attach it to a post
+///
+/// Returns the guid of the gallery image
+pub fn approve_photo_submission(db: &Arc<dyn Storage>, approval: &SubmissionApproval) -> Result<String, String> {
+	let submission = match load_photo_submission(db, approval.id) {
+		Some(tmp) if tmp.status == "pending" => tmp,
+		Some(_) => return Err(String::from("the submission was already moderated")),
+		_ => return Err(String::from("the submission does not exist")),
+	};
+
+	// Check the crop before anything is moved
+	match (approval.focal_x, approval.focal_y) {
+		(Some(x), Some(y)) if !(0.0..=100.0).contains(&x) || !(0.0..=100.0).contains(&y) => {
+			return Err(String::from("the focal point must be within 0 and 100 percent"));
+		}
+		_ => {}
+	}
+
+	// Through the regular upload pipeline, which strips the location and finds duplicates
+	let path_gallery = generate_upload_file_name(&submission.file)?;
+	match fs::rename(submission.quarantine_path(), &path_gallery) {
+		Ok(_) => {}
+		Err(_) => {
+			// The quarantine may be on another file system
+			fs::copy(submission.quarantine_path(), &path_gallery).map_err(|err| format!("Could not move the photo: {}", err))?;
+			let _ = fs::remove_file(submission.quarantine_path());
+		}
+	}
+
+	let image = match finish_file_upload(&vec![path_gallery], db).pop() {
+		Some(tmp) => tmp,
+		_ => return Err(String::from("the photo could not be added to the gallery")),
+	};
+	let guid = String::from(image.guid());
+
+	// Cropped sizes are cut around the focal point
+	match (approval.focal_x, approval.focal_y) {
+		(Some(x), Some(y)) => { set_focal_point(db, &FocalPoint { guid: guid.clone(), x, y })?; }
+		_ => {}
+	}
+
+	if approval.post_id > 0 {
+		attach_submission_to_post(db, approval.post_id, &submission, &guid, image.extension())?;
+	}
+
+	let query = "UPDATE photo_submissions SET status=:status, guid=:guid, post_id=NULLIF(:post_id, 0) WHERE id=:id";
+	match db.exec(query, db_params! {"id" => submission.id, "status" => "approved", "guid" => &guid, "post_id" => approval.post_id}) {
+		Ok(_) => Ok(guid),
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
+
+/// Add the photo to the media of a post, crediting the reader
+fn attach_submission_to_post(db: &Arc<dyn Storage>, post_id: u32, submission: &PhotoSubmission, guid: &str, extension: &str) -> Result<(), String> {
+	let mut post = admin_fetch_post(db, post_id).ok_or_else(|| String::from("the post does not exist"))?;
+
+	let credit = format!("Photo by {}", submission.author_name);
+	post.media.push(PostMedia {
+		class: String::from("reader_photo"),
+		source: format!("/gallery/{}/w1200/photo.{}", guid, extension),
+		title: submission.title.clone(),
+		caption: if submission.caption.len() > 0 { format!("{} - {}", submission.caption, credit) } else { credit },
+		poster: None,
+		source_dark: None,
+	});
+
+	post.update_post_data(db).map(|_| ()).map_err(|err| format!("the post could not be updated: {:?}", err))
+}
+
+/// Throw a pending submission away
+pub fn reject_photo_submission(db: &Arc<dyn Storage>, id: u32) -> Result<(), String> {
+	let submission = match load_photo_submission(db, id) {
+		Some(tmp) if tmp.status == "pending" => tmp,
+		Some(_) => return Err(String::from("the submission was already moderated")),
+		_ => return Err(String::from("the submission does not exist")),
+	};
+
+	match fs::remove_file(submission.quarantine_path()) {
+		Err(err) => { println!("Error removing rejected submission: {:?}", err); }
+		_ => {}
+	}
+
+	match db.exec("UPDATE photo_submissions SET status=:status WHERE id=:id", db_params! {"id" => id, "status" => "rejected"}) {
+		Ok(_) => Ok(()),
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
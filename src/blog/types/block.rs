@@ -0,0 +1,84 @@
+use tera::escape_html;
+
+// ------------------------------
+// ----------- BLOCKS -----------
+// ------------------------------
+
+/// A piece of structured post content, posts with blocks are rendered from them instead of their HTML content
+///
+/// Text of paragraphs and quotes may contain inline HTML, like the regular content
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+	Paragraph { text: String },
+	Image(BlockImage),
+	Gallery { images: Vec<BlockImage> },
+	Map { lat: f32, lng: f32, #[serde(default)] zoom: u8, #[serde(default)] title: String },
+	Quote { text: String, #[serde(default)] cite: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockImage {
+	pub source: String,
+	#[serde(default)]
+	pub title: String,
+	#[serde(default)]
+	pub caption: String,
+	/// Shown instead of `source` to readers who prefer a dark color scheme
+	#[serde(default)]
+	pub source_dark: Option<String>,
+}
+
+impl ContentBlock {
+	/// The HTML for this block
+	pub fn to_html(&self) -> String {
+		match self {
+			ContentBlock::Paragraph { text } => {
+				format!("<p>{}</p>", text)
+			}
+			ContentBlock::Image(image) => {
+				format!("<figure class=\"block-image\">{}</figure>", image.to_html())
+			}
+			ContentBlock::Gallery { images } => {
+				let figures: Vec<String> = images.iter().map(|image| format!("<figure>{}</figure>", image.to_html())).collect();
+				format!("<div class=\"block-gallery\">{}</div>", figures.join(""))
+			}
+			ContentBlock::Map { lat, lng, zoom, title } => {
+				// The map itself is drawn by the frontend
+				let zoom = if *zoom == 0 { 12 } else { *zoom };
+				format!("<div class=\"block-map\" data-lat=\"{}\" data-lng=\"{}\" data-zoom=\"{}\">{}</div>", lat, lng, zoom, escape_html(title))
+			}
+			ContentBlock::Quote { text, cite } => {
+				match cite.len() {
+					0 => format!("<blockquote class=\"block-quote\"><p>{}</p></blockquote>", text),
+					_ => format!("<blockquote class=\"block-quote\"><p>{}</p><cite>{}</cite></blockquote>", text, escape_html(cite)),
+				}
+			}
+		}
+	}
+}
+
+impl BlockImage {
+	fn to_html(&self) -> String {
+		let img = format!("<img src=\"{}\" alt=\"{}\">", escape_html(&self.source), escape_html(&self.title));
+
+		// The browser picks the dark source by itself, the img stays the fallback
+		let mut html = match &self.source_dark {
+			Some(dark) if dark.len() > 0 => {
+				format!("<picture><source srcset=\"{}\" media=\"(prefers-color-scheme: dark)\">{}</picture>", escape_html(dark), img)
+			}
+			_ => img
+		};
+
+		if self.caption.len() > 0 {
+			html.push_str(&format!("<figcaption>{}</figcaption>", escape_html(&self.caption)));
+		}
+
+		html
+	}
+}
+
+/// Render a list of blocks to HTML, one block after another
+pub fn render_blocks(blocks: &[ContentBlock]) -> String {
+	blocks.iter().map(|block| block.to_html()).collect::<Vec<String>>().join("\n")
+}
@@ -1,7 +1,12 @@
+use std::sync::Arc;
+
 use chrono::NaiveDateTime;
-use serde_json::Error as JsonError;
 
 use crate::app::config::config_get_string;
+use crate::auth::jwt::decode_comment_edit_token;
+use crate::blog::spam::{email_check, spam_score_comment};
+use crate::blog::types::{AdminList, AdminListFilter, AdminListRequest, StoreError};
+use crate::storage::{DbRow, Storage};
 
 // ------------------------------
 // ----------- COMMENT ----------
@@ -17,6 +22,14 @@ pub struct Comment {
 	pub author_email: String,
 	pub date_posted: u64,
 	pub content: String,
+
+	/// Incremented on every edit, stale edits are rejected
+	#[serde(default)]
+	pub version: u32,
+
+	/// How likely this is spam, see `spam_score_comment`
+	#[serde(default)]
+	pub spam_score: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -28,11 +41,12 @@ pub struct CommentExcerpt {
 	pub author_email: String,
 	pub date_posted: u64,
 	pub content: String,
+	pub spam_score: u32,
 }
 
 
 impl Comment {
-	pub fn from_sql(mut row: mysql::Row) -> Option<Comment> {
+	pub fn from_sql(mut row: DbRow) -> Option<Comment> {
 		Some(Comment {
 			id: row.take("id")?,
 			parent_id: row.take("parent_id")?,
@@ -40,79 +54,163 @@ impl Comment {
 			status: row.take("status")?,
 			author_name: row.take("author_name")?,
 			author_email: row.take("author_email")?,
-			date_posted: row.take::<NaiveDateTime, _>("date_posted")?.timestamp() as u64,
+			date_posted: row.take::<NaiveDateTime>("date_posted")?.timestamp() as u64,
 			content: row.take("content")?,
+			version: row.take("version")?,
+			spam_score: row.take("spam_score")?,
 		})
 	}
 
 	/// This function will be called by the admin panel to edit an existing comment
-	pub fn update_comment_data(&self, db: &mysql::Pool) -> Result<u32, String> {
+	///
+	/// Edits must carry the version they are based on, returns the id and the new version
+	pub fn update_comment_data(&self, db: &Arc<dyn Storage>) -> Result<(u32, u32), StoreError> {
 		// Build the query
-		let query = "UPDATE post_comments SET status=:status,author_name=:author_name,author_email=:author_email,content=:content WHERE id=:id";
+		let query = "UPDATE post_comments SET status=:status,author_name=:author_name,author_email=:author_email,content=:content,version=version+1 WHERE id=:id AND version=:version";
 
 		// Bind params
-		let params = params! {
+		let params = db_params! {
             "id" => &self.id, "status" => &self.status, "author_name" => &self.author_name,
-            "author_email" => &self.author_email, "date_posted" => &self.date_posted, "content" => &self.content
+            "author_email" => &self.author_email, "content" => &self.content, "version" => &self.version
         };
 
 		// Execute
-		match db.prep_exec(query, &params) {
-			Ok(_res) => {
-				Ok(self.id)
+		match db.exec(query, params) {
+			Ok(res) => {
+				// Nothing matched, the comment was changed since the editor loaded it
+				if res.affected_rows == 0 { return Err(StoreError::Conflict); }
+				Ok((self.id, self.version + 1))
 			}
 			Err(err) => {
 				println!("Error: {:?}", err);
-				Err(String::from(err.to_string()))
+				Err(StoreError::Database(err.to_string()))
 			}
 		}
 	}
 
-	/// Create a new unapproved comment
-	pub fn store_unapproved_comment(db: &mysql::Pool, post_id: u32, parent_id: u32, author: &str, email: &str, text: &str, bot_stop: &str) -> Result<u64, String> {
-		// Check that the bot stop answer matches our current configuration
-		let bot_block_answer = config_get_string("bot_block_solution");
-		if bot_block_answer != bot_stop.to_lowercase().trim() {
-			return Err(String::from("Please check your answer to the spam protection question."));
-		}
+	/// Create a new comment, it waits for moderation unless `approve` is set and it does not look like spam
+	///
+	/// `email_score` is what `email_score` made of the address. Returns the id and the status of the comment
+	pub fn store_new_comment(db: &Arc<dyn Storage>, post_id: u32, parent_id: u32, author: &str, email: &str, text: &str, bot_stop: &str, approve: bool, email_score: u32) -> Result<(u64, &'static str), String> {
+		bot_stop_check(bot_stop)?;
 
 		// There must be an author name
 		let author_name = author.trim();
-		if author_name.len() <= 0 {
+		if author_name.len() == 0 {
 			return Err(String::from("Kindly provide your name."));
 		}
 
+		// The email is optional, but it has to be one that could exist
+		let email = email.trim();
+		email_check(email)?;
+
 		// There must be a post the comment is to be attached to
-		if post_id <= 0 {
+		if post_id == 0 {
 			return Err(String::from("The post could not be found."));
 		}
 
 		// There must be some content for this comment
 		let content = text.trim();
-		if content.len() <= 0 {
+		if content.len() == 0 {
 			return Err(String::from("The comment can not be empty."));
 		}
 
+		// Suspicious comments go straight to the spam folder
+		let (spam_score, is_spam) = spam_score_comment(db, 0, post_id, author_name, email, content, email_score);
+		let status = if is_spam { "spam" } else if approve { "approved" } else { "new" };
+
 		// Build the query
-		let query = "INSERT INTO post_comments (post_id,parent_id,status,author_name,author_email,content) VALUES(:post_id,:parent_id,:status,:author_name,:author_email,:content)";
+		let query = "INSERT INTO post_comments (post_id,parent_id,status,author_name,author_email,content,spam_score) VALUES(:post_id,:parent_id,:status,:author_name,:author_email,:content,:spam_score)";
 
 		// Bind params
-		let params = params! {
-            "post_id" => &post_id, "parent_id" => &parent_id, "status" => "new",
-            "author_name" => &author_name, "author_email" => &email, "content" => &content
+		let params = db_params! {
+            "post_id" => &post_id, "parent_id" => &parent_id, "status" => status,
+            "author_name" => &author_name, "author_email" => &email, "content" => &content, "spam_score" => spam_score
         };
 
 		// Execute
-		match db.prep_exec(query, &params) {
+		match db.exec(query, params) {
 			Ok(res) => {
-				Ok(res.last_insert_id())
+				Ok((res.last_insert_id, status))
 			}
 			Err(err) => {
 				println!("Error: {:?}", err);
-				Err(String::from(err.to_string()))
+				Err(err.to_string())
 			}
 		}
 	}
+
+	/// Let the author change their comment during the edit grace period, it has to be approved again
+	///
+	/// `own` is what `find_own_comment` found for the edit token. The edit goes through the same checks as
+	/// a new comment, `email_score` is for the stored address. Returns the comment as it was before
+	pub fn edit_own_comment(db: &Arc<dyn Storage>, own: Comment, text: &str, bot_stop: &str, email_score: u32) -> Result<Comment, String> {
+		bot_stop_check(bot_stop)?;
+		email_check(&own.author_email)?;
+
+		let content = text.trim();
+		if content.len() == 0 {
+			return Err(String::from("The comment can not be empty."));
+		}
+
+		// An edit can turn a harmless comment into spam
+		let (spam_score, is_spam) = spam_score_comment(db, own.id, own.post_id, &own.author_name, &own.author_email, content, email_score);
+		let status = if is_spam { "spam" } else { "new" };
+
+		let query = "UPDATE post_comments SET content=:content, status=:status, spam_score=:spam_score, version=version+1 WHERE id=:id";
+		match db.exec(query, db_params! {"id" => own.id, "content" => content, "status" => status, "spam_score" => spam_score}) {
+			Ok(_) => Ok(own),
+			Err(err) => {
+				println!("Error: {:?}", err);
+				Err(err.to_string())
+			}
+		}
+	}
+
+	/// Let the author delete their comment during the edit grace period
+	///
+	/// Returns the comment as it was
+	pub fn delete_own_comment(db: &Arc<dyn Storage>, token: &str) -> Result<Comment, String> {
+		let own = Comment::find_own_comment(db, token)?;
+
+		match db.exec("DELETE FROM post_comments WHERE id=:id", db_params! {"id" => own.id}) {
+			Ok(_) => Ok(own),
+			Err(err) => {
+				println!("Error: {:?}", err);
+				Err(err.to_string())
+			}
+		}
+	}
+
+	/// The comment an edit token was issued for, as long as it can still be changed
+	pub fn find_own_comment(db: &Arc<dyn Storage>, token: &str) -> Result<Comment, String> {
+		let id = match decode_comment_edit_token(token) {
+			Some(tmp) => tmp,
+			_ => { return Err(String::from("The comment can no longer be changed.")); }
+		};
+
+		let own = match admin_fetch_comment(db, id) {
+			Some(tmp) => tmp,
+			_ => { return Err(String::from("The comment could not be found.")); }
+		};
+
+		// Comments marked as spam stay that way
+		if own.status != "new" && own.status != "approved" {
+			return Err(String::from("The comment can no longer be changed."));
+		}
+
+		Ok(own)
+	}
+}
+
+/// Check that the bot stop answer matches our current configuration
+fn bot_stop_check(bot_stop: &str) -> Result<(), String> {
+	let bot_block_answer = config_get_string("bot_block_solution");
+	if bot_block_answer != bot_stop.to_lowercase().trim() {
+		return Err(String::from("Please check your answer to the spam protection question."));
+	}
+
+	Ok(())
 }
 
 
@@ -125,22 +223,12 @@ impl Comment {
 /// Attempt to read the table containing blog comments
 ///
 /// Result will be a vector of all `Comment`s found
-pub fn load_comments_from_sql(db: &mysql::Pool) -> Result<Vec<Comment>, JsonError> {
-	let query = "SELECT id,parent_id,post_id,status,author_name,author_email,date_posted,content FROM post_comments WHERE status=:status";
-
-	let comments: Vec<Comment> =
-		db.prep_exec(query, params! {"status" => String::from("approved")})
-			.map(|result| {
-				// In this closure we will map `QueryResult` to `Vec<Comment>`
-				// `QueryResult` is iterator over `MyResult<row, err>` so first call to `map`
-				// will map each `MyResult` to contained `row` (no proper error handling)
-				// and second call to `map` will map each `row` to `Comment`
-				result.map(|x| x.unwrap()).map(|row| {
-					Comment::from_sql(row).unwrap()
-				}).collect() // Collect comments so now `QueryResult` is mapped to `Vec<Comment>`
-			}).unwrap(); // Unwrap `Vec<Comment>`
-
-	Ok(comments)
+pub fn load_comments_from_sql(db: &Arc<dyn Storage>) -> Result<Vec<Comment>, String> {
+	let query = "SELECT id,parent_id,post_id,status,author_name,author_email,date_posted,content,version,spam_score FROM post_comments WHERE status=:status";
+
+	let rows = db.query(query, db_params! {"status" => "approved"}).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(Comment::from_sql).collect())
 }
 
 
@@ -148,16 +236,32 @@ pub fn load_comments_from_sql(db: &mysql::Pool) -> Result<Vec<Comment>, JsonErro
 // ---------- SQL ADMIN ---------
 // ------------------------------
 
-/// Admin function that returns a list of comments, including drafts
-pub fn admin_fetch_comment_list(db: &mysql::Pool) -> Option<Vec<CommentExcerpt>> {
-	let query = r###"
-    SELECT c.id,LEFT(p.title, 25) AS title,c.status,c.author_name,c.author_email,c.date_posted,LEFT(c.content, 50) AS content
+/// Admin function that returns a page of comments, including drafts
+pub fn admin_fetch_comment_list(db: &Arc<dyn Storage>, request: &AdminListRequest) -> Option<AdminList<CommentExcerpt>> {
+	let mut filter = AdminListFilter::default();
+	request.add_filters(&mut filter, "c.status", "p.tags", "c.date_posted");
+
+	let query = format!(r###"
+    SELECT COUNT(*) AS total
     FROM post_comments AS c
     LEFT JOIN posts p ON p.id = c.post_id
-    ORDER BY id DESC
-    "###;
+    {}
+    "###, filter.where_clause());
 
-	let query_result = match db.prep_exec(query, ()) {
+	let total = match db.query_first(&query, filter.params()) {
+		Ok(Some(mut row)) => row.take("total")?,
+		_ => { return None; }
+	};
+
+	let sort_columns = [("id", "c.id"), ("date_posted", "c.date_posted"), ("status", "c.status"), ("author_name", "c.author_name"), ("spam_score", "c.spam_score")];
+	let query = format!(r###"
+    SELECT c.id,SUBSTR(p.title, 1, 25) AS title,c.status,c.author_name,c.author_email,c.date_posted,SUBSTR(c.content, 1, 50) AS content,c.spam_score
+    FROM post_comments AS c
+    LEFT JOIN posts p ON p.id = c.post_id
+    {} {} {}
+    "###, filter.where_clause(), request.order_clause(&sort_columns), request.page_clause());
+
+	let rows = match db.query(&query, filter.params()) {
 		Ok(tmp) => { tmp }
 		_ => { return None; }
 	};
@@ -165,47 +269,32 @@ pub fn admin_fetch_comment_list(db: &mysql::Pool) -> Option<Vec<CommentExcerpt>>
 	let mut comments = vec![];
 
 	// Gather all comments that have extended data set in the database
-	for result_row in query_result {
-		let mut row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
+	for mut row in rows {
 		comments.push(CommentExcerpt {
 			id: row.take("id")?,
 			post_title: row.take("title")?,
 			status: row.take("status")?,
 			author_name: row.take("author_name")?,
 			author_email: row.take("author_email")?,
-			date_posted: row.take::<NaiveDateTime, _>("date_posted")?.timestamp() as u64,
+			date_posted: row.take::<NaiveDateTime>("date_posted")?.timestamp() as u64,
 			content: row.take("content")?,
+			spam_score: row.take("spam_score")?,
 		});
 	}
 
-	Some(comments)
+	Some(AdminList { total, items: comments })
 }
 
 /// Admin function that returns the given comments by its id
-pub fn admin_fetch_comment(db: &mysql::Pool, id: u32) -> Option<Comment> {
+pub fn admin_fetch_comment(db: &Arc<dyn Storage>, id: u32) -> Option<Comment> {
 	let query = r###"
-    SELECT id, parent_id, post_id, status, author_name, author_email, date_posted, content
+    SELECT id, parent_id, post_id, status, author_name, author_email, date_posted, content, version, spam_score
     FROM post_comments
     WHERE id = :id
     "###;
 
-	let query_result = match db.prep_exec(query, params! {"id" => id}) {
-		Ok(tmp) => { tmp }
-		_ => { return None; }
-	};
-
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
-		return Comment::from_sql(row);
+	match db.query_first(query, db_params! {"id" => id}) {
+		Ok(Some(row)) => { Comment::from_sql(row) }
+		_ => { None }
 	}
-
-	None
 }
\ No newline at end of file
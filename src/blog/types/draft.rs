@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::blog::types::post::Post;
+use crate::storage::{DbRow, Storage};
+
+// ------------------------------
+// ----------- DRAFT ------------
+// ------------------------------
+
+/// Work in progress of a single user on a single post
+///
+/// New posts that were never saved use the post id 0
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostDraft {
+	pub post_id: u32,
+	pub user_id: u32,
+	pub saved_at: u64,
+	pub post: Post,
+}
+
+impl PostDraft {
+	pub fn from_sql(mut row: DbRow) -> Option<PostDraft> {
+		Some(PostDraft {
+			post_id: row.take("post_id")?,
+			user_id: row.take("user_id")?,
+			saved_at: row.take::<NaiveDateTime>("saved_at")?.timestamp() as u64,
+			post: match serde_json::from_str(row.take::<String>("data")?.as_str()) {
+				Ok(tmp) => { tmp }
+				_ => { return None; }
+			},
+		})
+	}
+}
+
+
+// ------------------------------
+// ---------- SQL ADMIN ---------
+// ------------------------------
+
+/// Store the editor state of a post for the given user, the published post is not touched
+///
+/// Returns the time the draft was saved at
+pub fn store_post_draft(db: &Arc<dyn Storage>, user_id: u32, post: &Post) -> Result<u64, String> {
+	let query = format!(r##"
+    INSERT INTO post_drafts (post_id, user_id, saved_at, data) VALUES
+    (:post_id, :user_id, :saved_at, :data)
+    {}
+    "##, db.upsert("post_id, user_id", &["saved_at", "data"]));
+
+	let data = serde_json::to_string(post).map_err(|err| err.to_string())?;
+	let saved_at = Utc::now().naive_utc();
+
+	// Execute
+	match db.exec(&query, db_params! {"post_id" => post.id, "user_id" => user_id, "saved_at" => &saved_at, "data" => &data}) {
+		Ok(_res) => { Ok(saved_at.timestamp() as u64) }
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
+
+/// Fetch the draft the given user has for a post
+pub fn load_post_draft(db: &Arc<dyn Storage>, post_id: u32, user_id: u32) -> Option<PostDraft> {
+	let query = "SELECT post_id, user_id, saved_at, data FROM post_drafts WHERE post_id=:post_id AND user_id=:user_id";
+
+	match db.query_first(query, db_params! {"post_id" => post_id, "user_id" => user_id}) {
+		Ok(Some(row)) => { PostDraft::from_sql(row) }
+		_ => { None }
+	}
+}
+
+/// Remove the draft once the user saved the post for real
+pub fn delete_post_draft(db: &Arc<dyn Storage>, post_id: u32, user_id: u32) {
+	match db.exec("DELETE FROM post_drafts WHERE post_id=:post_id AND user_id=:user_id", db_params! {"post_id" => post_id, "user_id" => user_id}) {
+		Ok(_) => {}
+		Err(err) => { println!("Error deleting post draft: {:?}", err); }
+	}
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+
+use crate::app::config::config_get_string;
+use crate::blog::types::post::PostExcerpt;
+use crate::storage::{DbParams, DbRow, DbValue, Storage};
+
+/// Rows per INSERT, older SQLite versions allow at most 999 parameters in a statement
+const EVENTS_PER_INSERT: usize = 100;
+
+// ------------------------------
+// --------- EXPERIMENT ---------
+// ------------------------------
+
+/// An A/B test of the title and thumbnail a post has on list pages
+///
+/// Variant A is the post as it is, variant B replaces whatever is set here. Picking a winner
+/// ends the experiment, the post itself is edited as usual
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Experiment {
+	#[serde(default)]
+	pub id: u32,
+	pub post_id: u32,
+	#[serde(default)]
+	pub title_b: String,
+	#[serde(default)]
+	pub thumbnail_b: String,
+	pub running: bool,
+
+	/// `a` or `b` once the experiment was decided
+	#[serde(default)]
+	pub winner: String,
+	#[serde(default)]
+	pub started_at: u64,
+}
+
+/// The version of the list pages a visitor gets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExperimentVariant {
+	A,
+	B,
+}
+
+/// How often each variant of an experiment was shown and clicked
+#[derive(Serialize, Clone, Debug)]
+pub struct ExperimentResults {
+	pub experiment: Experiment,
+	pub title: String,
+	pub a: ExperimentVariantResults,
+	pub b: ExperimentVariantResults,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ExperimentVariantResults {
+	pub exposures: u64,
+	pub clicks: u64,
+	/// Clicks per exposure in percent
+	pub conversion: f32,
+}
+
+impl Experiment {
+	pub fn from_sql(mut row: DbRow) -> Option<Experiment> {
+		Some(Experiment {
+			id: row.take("id")?,
+			post_id: row.take("post_id")?,
+			title_b: row.take("title_b")?,
+			thumbnail_b: row.take("thumbnail_b")?,
+			running: row.take("running")?,
+			winner: row.take("winner")?,
+			started_at: row.take::<NaiveDateTime>("started_at")?.timestamp() as u64,
+		})
+	}
+
+	/// The excerpt as the variant shows it
+	pub fn apply(&self, excerpt: &Arc<PostExcerpt>, variant: ExperimentVariant) -> Arc<PostExcerpt> {
+		if variant == ExperimentVariant::A { return excerpt.clone(); }
+
+		let mut modified = (**excerpt).clone();
+		if self.title_b.len() > 0 { modified.title = self.title_b.clone(); }
+		if self.thumbnail_b.len() > 0 {
+			modified.thumbnail = self.thumbnail_b.clone();
+			modified.thumbnail_dark = None;
+		}
+		Arc::new(modified)
+	}
+}
+
+impl ExperimentVariant {
+	/// Every visitor gets the same variant for as long as their address and browser stay the same
+	///
+	/// FNV-1a, unlike the std hasher it stays the same across releases
+	pub fn for_visitor(remote_ip: &str, user_agent: &str) -> ExperimentVariant {
+		let mut hash: u64 = 0xcbf29ce484222325;
+		for byte in remote_ip.bytes().chain(user_agent.bytes()) {
+			hash ^= byte as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+
+		match hash % 2 {
+			0 => ExperimentVariant::A,
+			_ => ExperimentVariant::B,
+		}
+	}
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ExperimentVariant::A => "a",
+			ExperimentVariant::B => "b",
+		}
+	}
+}
+
+impl ExperimentVariantResults {
+	fn new(exposures: u64, clicks: u64) -> ExperimentVariantResults {
+		ExperimentVariantResults {
+			exposures,
+			clicks,
+			conversion: if exposures > 0 { clicks as f32 * 100.0 / exposures as f32 } else { 0.0 },
+		}
+	}
+}
+
+/// Whether a request came from one of our own pages, like a list page showing an experiment
+pub fn is_internal_referer(referer: &str) -> bool {
+	let host = referer.split_once("://").map(|x| x.1).and_then(|tmp| tmp.split(['/', '?', '#']).next());
+	match host {
+		Some(host) => host.eq_ignore_ascii_case(&config_get_string("fqdn")),
+		_ => false
+	}
+}
+
+
+/// Load all experiments from the database, the latest first
+pub fn load_experiments_from_sql(db: &Arc<dyn Storage>) -> Result<Vec<Experiment>, String> {
+	let query = "SELECT id, post_id, title_b, thumbnail_b, running, winner, started_at FROM post_experiments ORDER BY id DESC";
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(Experiment::from_sql).collect())
+}
+
+/// Create or update an experiment in the database, new ones start right away
+pub fn update_experiment_in_sql(db: &Arc<dyn Storage>, experiment: &Experiment) -> u64 {
+	// A zero id gets a new one from the database
+	let query = format!(r##"
+    INSERT INTO post_experiments (id, post_id, title_b, thumbnail_b, running, winner, started_at) VALUES
+    (NULLIF(:id, 0), :post_id, :title_b, :thumbnail_b, :running, :winner, :started_at)
+    {}
+    "##, db.upsert("id", &["title_b", "thumbnail_b", "running", "winner"]));
+
+	let started_at = NaiveDateTime::from_timestamp(experiment.started_at as i64, 0);
+
+	match db.exec(&query, db_params! {
+		"id" => experiment.id, "post_id" => experiment.post_id, "title_b" => &experiment.title_b, "thumbnail_b" => &experiment.thumbnail_b,
+		"running" => experiment.running, "winner" => &experiment.winner, "started_at" => started_at
+	}) {
+		Ok(res) => {
+			if experiment.id > 0 { return experiment.id as u64; }
+			res.last_insert_id
+		}
+		Err(err) => {
+			println!("Error: {:?}", err);
+			0
+		}
+	}
+}
+
+/// Count exposures and clicks of the latest experiments
+pub fn load_experiment_results(db: &Arc<dyn Storage>, limit: u32) -> Result<Vec<ExperimentResults>, String> {
+	let query = r###"
+        SELECT experiment_id, variant, kind, COUNT(*) AS count
+        FROM post_experiment_events
+        GROUP BY experiment_id, variant, kind
+    "###;
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+
+	// (experiment, variant, kind) -> count
+	let mut counts: HashMap<(u32, String, String), u64> = HashMap::new();
+	for mut row in rows {
+		match (row.take::<u32>("experiment_id"), row.take::<String>("variant"), row.take::<String>("kind"), row.take::<u64>("count")) {
+			(Some(id), Some(variant), Some(kind), Some(count)) => { counts.insert((id, variant, kind), count); }
+			_ => {}
+		}
+	}
+	let count = |id: u32, variant: &str, kind: &str| counts.get(&(id, String::from(variant), String::from(kind))).cloned().unwrap_or(0);
+
+	let query = format!(r###"
+        SELECT e.id, e.post_id, e.title_b, e.thumbnail_b, e.running, e.winner, e.started_at, COALESCE(p.title, '') AS title
+        FROM post_experiments e
+        LEFT JOIN posts p ON p.id = e.post_id
+        ORDER BY e.id DESC LIMIT {}
+    "###, limit);
+	let rows = db.query(&query, db_params!()).map_err(|err| err.to_string())?;
+
+	let mut results = Vec::with_capacity(rows.len());
+	for mut row in rows {
+		let title: String = row.take("title").unwrap_or_default();
+		match Experiment::from_sql(row) {
+			Some(experiment) => {
+				results.push(ExperimentResults {
+					title,
+					a: ExperimentVariantResults::new(count(experiment.id, "a", "exposure"), count(experiment.id, "a", "click")),
+					b: ExperimentVariantResults::new(count(experiment.id, "b", "exposure"), count(experiment.id, "b", "click")),
+					experiment,
+				});
+			}
+			_ => {}
+		}
+	}
+
+	Ok(results)
+}
+
+/// Insert the queued exposures and clicks, `EVENTS_PER_INSERT` rows per statement
+pub fn log_experiment_events(db: &Arc<dyn Storage>, events: &Vec<(u32, ExperimentVariant, &'static str, u64)>) {
+	// (experiment_id, variant, kind, recorded_at)
+	for chunk in events.chunks(EVENTS_PER_INSERT) {
+		let placeholders = vec!["(?, ?, ?, ?)"; chunk.len()].join(", ");
+		let query = format!("INSERT INTO post_experiment_events (experiment_id, variant, kind, recorded_at) VALUES {}", placeholders);
+
+		let mut params = Vec::with_capacity(chunk.len() * 4);
+		for e in chunk.iter() {
+			params.push(DbValue::from(e.0));
+			params.push(DbValue::from(e.1.as_str()));
+			params.push(DbValue::from(e.2));
+			params.push(DbValue::from(NaiveDateTime::from_timestamp(e.3 as i64, 0)));
+		}
+
+		match db.exec(&query, DbParams::Positional(params)) {
+			Ok(_res) => {}
+			Err(err) => { println!("Could not log {} experiment events: {}", chunk.len(), err); }
+		}
+	}
+}
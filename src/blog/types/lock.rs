@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use crate::app::config::config_get_i64;
+use crate::storage::{DbRow, Storage};
+
+/// The editor renews its lock while the post is open, `post_lock_minutes` overrides how long one lasts
+const POST_LOCK_MINUTES: i64 = 5;
+
+// ------------------------------
+// ------------ LOCK ------------
+// ------------------------------
+
+/// Someone has a post open in the editor
+///
+/// Locks are soft, everybody else is warned but can still save or take the lock over
+#[derive(Serialize, Clone, Debug)]
+pub struct PostLock {
+	pub post_id: u32,
+	pub user_id: u32,
+	pub user_name: String,
+	pub expires_at: u64,
+}
+
+impl PostLock {
+	pub fn from_sql(mut row: DbRow) -> Option<PostLock> {
+		Some(PostLock {
+			post_id: row.take("post_id")?,
+			user_id: row.take("user_id")?,
+			user_name: row.take("user_name")?,
+			expires_at: row.take::<NaiveDateTime>("expires_at")?.timestamp() as u64,
+		})
+	}
+}
+
+
+// ------------------------------
+// ---------- SQL ADMIN ---------
+// ------------------------------
+
+/// The lock on a post, None if nobody has it open
+pub fn load_post_lock(db: &Arc<dyn Storage>, post_id: u32) -> Option<PostLock> {
+	let query = "SELECT post_id, user_id, user_name, expires_at FROM post_locks WHERE post_id=:post_id AND expires_at > :now";
+
+	match db.query_first(query, db_params! {"post_id" => post_id, "now" => Utc::now().naive_utc()}) {
+		Ok(Some(row)) => { PostLock::from_sql(row) }
+		_ => { None }
+	}
+}
+
+/// Lock a post for the given user or renew their lock
+///
+/// Returns the lock the post has afterwards, it still belongs to someone else unless `force` is set
+pub fn lock_post(db: &Arc<dyn Storage>, post_id: u32, user_id: u32, user_name: &str, force: bool) -> Result<PostLock, String> {
+	let now = Utc::now().naive_utc();
+	let minutes = match config_get_i64("post_lock_minutes") {
+		tmp if tmp > 0 => tmp,
+		_ => POST_LOCK_MINUTES
+	};
+	let expires_at = now + Duration::minutes(minutes);
+	let params = || db_params! {"post_id" => post_id, "user_id" => user_id, "user_name" => user_name, "expires_at" => expires_at, "now" => now};
+
+	// An expired lock is as good as none
+	let query = "DELETE FROM post_locks WHERE post_id=:post_id AND expires_at <= :now";
+	db.exec(query, params()).map_err(|err| err.to_string())?;
+
+	let query = if force {
+		format!(r##"
+        INSERT INTO post_locks (post_id, user_id, user_name, expires_at) VALUES (:post_id, :user_id, :user_name, :expires_at)
+        {}
+        "##, db.upsert("post_id", &["user_id", "user_name", "expires_at"]))
+	} else {
+		// Only a lock of our own is renewed
+		match db.exec("UPDATE post_locks SET user_name=:user_name, expires_at=:expires_at WHERE post_id=:post_id AND user_id=:user_id", params()) {
+			Ok(res) if res.affected_rows > 0 => { return load_post_lock(db, post_id).ok_or(String::from("Could not load the lock")); }
+			Err(err) => { return Err(err.to_string()); }
+			_ => {}
+		}
+		format!("{} INTO post_locks (post_id, user_id, user_name, expires_at) VALUES (:post_id, :user_id, :user_name, :expires_at)", db.insert_ignore())
+	};
+	db.exec(&query, params()).map_err(|err| err.to_string())?;
+
+	load_post_lock(db, post_id).ok_or(String::from("Could not load the lock"))
+}
+
+/// Give up the lock on a post, only the user holding it can
+pub fn unlock_post(db: &Arc<dyn Storage>, post_id: u32, user_id: u32) -> Result<(), String> {
+	match db.exec("DELETE FROM post_locks WHERE post_id=:post_id AND user_id=:user_id", db_params! {"post_id" => post_id, "user_id" => user_id}) {
+		Ok(_) => Ok(()),
+		Err(err) => Err(err.to_string())
+	}
+}
+
+/// Remove the locks of editors that were closed without unlocking
+///
+/// Returns the number of locks removed
+pub fn delete_expired_post_locks(db: &Arc<dyn Storage>) -> Result<u64, String> {
+	match db.exec("DELETE FROM post_locks WHERE expires_at <= :now", db_params! {"now" => Utc::now().naive_utc()}) {
+		Ok(res) => Ok(res.affected_rows),
+		Err(err) => Err(err.to_string())
+	}
+}
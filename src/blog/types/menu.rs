@@ -1,5 +1,8 @@
+use std::sync::Arc;
 use std::vec::Vec;
 
+use crate::storage::{DbRow, Storage};
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MenuItem {
 	pub title: String,
@@ -17,11 +20,11 @@ pub struct Menu {
 
 impl Menu {
 	/// Turns a SQL row into a menu
-	pub fn from_sql(mut row: mysql::Row) -> Option<Menu> {
+	pub fn from_sql(mut row: DbRow) -> Option<Menu> {
 		Some(Menu {
 			id: row.take("id")?,
 			name: row.take("name")?,
-			items: match serde_json::from_str(row.take::<String, _>("items")?.as_str()) {
+			items: match serde_json::from_str(row.take::<String>("items")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
@@ -30,20 +33,15 @@ impl Menu {
 }
 
 /// Load all the menus from the database
-pub fn load_menus_from_sql(db: &mysql::Pool) -> Option<Vec<Menu>> {
-	let query_result = match db.prep_exec("SELECT id, name, items FROM menus", ()) {
+pub fn load_menus_from_sql(db: &Arc<dyn Storage>) -> Option<Vec<Menu>> {
+	let rows = match db.query("SELECT id, name, items FROM menus", db_params!()) {
 		Ok(tmp) => { tmp }
 		_ => { return None; }
 	};
 
 	let mut menus = Vec::new();
 
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
+	for row in rows {
 		match Menu::from_sql(row) {
 			Some(tmp) => { menus.push(tmp); }
 			_ => {}
@@ -54,28 +52,23 @@ pub fn load_menus_from_sql(db: &mysql::Pool) -> Option<Vec<Menu>> {
 }
 
 /// Create or update a menu in the database
-pub fn update_menu_in_sql(db: &mysql::Pool, menu: &Menu) -> u64 {
-	let query = r##"
+pub fn update_menu_in_sql(db: &Arc<dyn Storage>, menu: &Menu) -> u64 {
+	// A zero id gets a new one from the database
+	let query = format!(r##"
     INSERT INTO menus (id, name, items) VALUES
-    (:id, :name, :items)
-    ON DUPLICATE KEY UPDATE name=:name, items=:items
-    "##;
+    (NULLIF(:id, 0), :name, :items)
+    {}
+    "##, db.upsert("id", &["name", "items"]));
 
-	let items = match serde_json::to_string(&menu.items) {
-		Ok(tmp) => { tmp }
-		_ => { String::from("") }
-	};
+	let items = serde_json::to_string(&menu.items).unwrap_or_default();
 
-	for mut stmt in db.prepare(query).into_iter() {
-		match stmt.execute(params! {"name" => &menu.name, "items" => &items, "id" => menu.id}) {
-			Ok(res) => {
-				if menu.id > 0 {
-					return menu.id as u64;
-				}
-				return res.last_insert_id();
+	match db.exec(&query, db_params! {"name" => &menu.name, "items" => &items, "id" => menu.id}) {
+		Ok(res) => {
+			if menu.id > 0 {
+				return menu.id as u64;
 			}
-			_ => {}
+			res.last_insert_id
 		}
+		_ => { 0 }
 	}
-	0
-}
\ No newline at end of file
+}
@@ -1,6 +1,133 @@
+pub mod block;
 pub mod comment;
+pub mod draft;
+pub mod experiment;
+pub mod lock;
 pub mod menu;
+pub mod poll;
 pub mod post;
 pub mod redirect;
+pub mod revision;
 pub mod snippet;
-pub mod tag;
\ No newline at end of file
+pub mod tag;
+
+use chrono::NaiveDateTime;
+
+use crate::storage::{DbParams, DbValue};
+
+/// Rows per page of an admin list unless `limit` is given
+const ADMIN_LIST_LIMIT: u32 = 50;
+
+/// The most rows a single page of an admin list may have
+const ADMIN_LIST_MAX_LIMIT: u32 = 500;
+
+/// Reasons storing an item from the admin panel can fail
+#[derive(Debug)]
+pub enum StoreError {
+	/// Someone else changed the item since it was loaded
+	Conflict,
+	/// The database rejected the write
+	Database(String),
+}
+
+/// Paging, filters and sorting for the lists in the admin panel, everything is optional
+#[derive(Debug, Default, Deserialize)]
+pub struct AdminListRequest {
+	pub limit: Option<u32>,
+	pub offset: Option<u32>,
+	/// The `state` of posts or the `status` of comments
+	#[serde(alias = "state")]
+	pub status: Option<String>,
+	/// Posts with this tag, or comments on them
+	pub tag: Option<String>,
+	/// Unix time, only items posted at or after it
+	pub from: Option<u64>,
+	/// Unix time, only items posted at or before it
+	pub to: Option<u64>,
+	/// The column to sort by, descending with a leading `-` like `-date_posted`
+	pub sort: Option<String>,
+}
+
+/// One page of a list in the admin panel, `total` counts all matching rows for the pager
+#[derive(Debug, Serialize)]
+pub struct AdminList<T> {
+	pub total: u64,
+	pub items: Vec<T>,
+}
+
+impl AdminListRequest {
+	/// The LIMIT and OFFSET clause
+	pub fn page_clause(&self) -> String {
+		let limit = match self.limit {
+			Some(tmp) if tmp > 0 => tmp.min(ADMIN_LIST_MAX_LIMIT),
+			_ => ADMIN_LIST_LIMIT
+		};
+		format!("LIMIT {} OFFSET {}", limit, self.offset.unwrap_or(0))
+	}
+
+	/// The ORDER BY clause, `columns` maps the names the panel may sort by to SQL columns
+	///
+	/// Unknown names fall back to the first column, newest first
+	pub fn order_clause(&self, columns: &[(&str, &str)]) -> String {
+		let sort = self.sort.as_ref().map(|tmp| tmp.trim()).unwrap_or("");
+		let (name, direction) = match sort.starts_with('-') {
+			true => (&sort[1..], "DESC"),
+			false => (sort, "ASC")
+		};
+
+		match columns.iter().find(|(tmp, _)| *tmp == name) {
+			Some((_, column)) => format!("ORDER BY {} {}", column, direction),
+			_ => format!("ORDER BY {} DESC", columns[0].1)
+		}
+	}
+
+	/// Add the status, tag and date filters, `status`, `tags` and `date` are the SQL columns to filter on
+	pub fn add_filters(&self, filter: &mut AdminListFilter, status: &str, tags: &str, date: &str) {
+		match &self.status {
+			Some(tmp) if tmp.len() > 0 => { filter.add(&format!("{} = ?", status), tmp.as_str()); }
+			_ => {}
+		}
+		// Tags are stored as a JSON array
+		match &self.tag {
+			Some(tmp) if tmp.len() > 0 => { filter.add(&format!("{} LIKE ?", tags), format!("%\"{}\"%", tmp)); }
+			_ => {}
+		}
+		match self.from {
+			Some(tmp) => { filter.add(&format!("{} >= ?", date), NaiveDateTime::from_timestamp(tmp as i64, 0)); }
+			_ => {}
+		}
+		match self.to {
+			Some(tmp) => { filter.add(&format!("{} <= ?", date), NaiveDateTime::from_timestamp(tmp as i64, 0)); }
+			_ => {}
+		}
+	}
+}
+
+/// The WHERE clause of an admin list and its parameters
+#[derive(Default)]
+pub struct AdminListFilter {
+	conditions: Vec<String>,
+	params: Vec<DbValue>,
+}
+
+impl AdminListFilter {
+	/// Add a condition with a single `?` for the value
+	pub fn add<V: Into<DbValue>>(&mut self, condition: &str, value: V) {
+		self.conditions.push(String::from(condition));
+		self.params.push(value.into());
+	}
+
+	pub fn where_clause(&self) -> String {
+		match self.conditions.len() {
+			0 => String::from(""),
+			_ => format!("WHERE {}", self.conditions.join(" AND "))
+		}
+	}
+
+	pub fn params(&self) -> DbParams {
+		match self.params.len() {
+			0 => DbParams::Empty,
+			_ => DbParams::Positional(self.params.clone())
+		}
+	}
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use tera::escape_html;
+
+use crate::storage::{DbRow, Storage};
+
+// ------------------------------
+// ------------ POLL ------------
+// ------------------------------
+
+/// A question readers can vote on, embedded in posts with `[poll id="1"]`
+///
+/// Votes refer to options by their position, so options should only ever be appended
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Poll {
+	#[serde(default)]
+	pub id: u32,
+	pub question: String,
+	pub options: Vec<String>,
+
+	/// Closed polls still show their results, but take no more votes
+	pub open: bool,
+}
+
+/// The current standing of a poll, for templates and the dashboard
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PollResults {
+	pub poll_id: u32,
+	pub question: String,
+	pub open: bool,
+	pub options: Vec<PollOptionResult>,
+	pub total: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PollOptionResult {
+	pub text: String,
+	pub votes: u64,
+	pub percent: f32,
+}
+
+impl Poll {
+	pub fn from_sql(mut row: DbRow) -> Option<Poll> {
+		Some(Poll {
+			id: row.take("id")?,
+			question: row.take("question")?,
+			options: match serde_json::from_str(row.take::<String>("options")?.as_str()) {
+				Ok(tmp) => { tmp }
+				_ => { vec![] }
+			},
+			open: row.take("open")?,
+		})
+	}
+
+	/// The HTML the shortcode is replaced with, the theme sends the votes to `/poll/vote`
+	pub fn to_html(&self) -> String {
+		let options: Vec<String> = self.options.iter().enumerate().map(|(index, text)| {
+			format!("<label><input type=\"radio\" name=\"poll_{}\" value=\"{}\"{}> {}</label>", self.id, index, if self.open { "" } else { " disabled" }, escape_html(text))
+		}).collect();
+
+		format!(
+			"<form class=\"poll\" data-poll-id=\"{}\" data-open=\"{}\"><p class=\"poll-question\">{}</p>{}</form>",
+			self.id, self.open, escape_html(&self.question), options.join("")
+		)
+	}
+
+	/// Combine the poll with the number of votes per option
+	pub fn results(&self, votes: Option<&HashMap<u32, u64>>) -> PollResults {
+		let counts: Vec<u64> = (0..self.options.len()).map(|index| {
+			votes.and_then(|tmp| tmp.get(&(index as u32))).cloned().unwrap_or(0)
+		}).collect();
+		let total: u64 = counts.iter().sum();
+
+		PollResults {
+			poll_id: self.id,
+			question: self.question.clone(),
+			open: self.open,
+			options: self.options.iter().zip(counts).map(|(text, votes)| PollOptionResult {
+				text: text.clone(),
+				votes,
+				percent: if total > 0 { votes as f32 * 100.0 / total as f32 } else { 0.0 },
+			}).collect(),
+			total,
+		}
+	}
+}
+
+
+/// Load all polls from the database
+pub fn load_polls_from_sql(db: &Arc<dyn Storage>) -> Result<Vec<Poll>, String> {
+	let rows = db.query("SELECT id, question, options, open FROM polls", db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(Poll::from_sql).collect())
+}
+
+/// Count the votes of all polls, by poll and option
+pub fn load_poll_votes_from_sql(db: &Arc<dyn Storage>) -> Result<HashMap<u32, HashMap<u32, u64>>, String> {
+	let query = "SELECT poll_id, option_index, COUNT(*) AS votes FROM poll_votes GROUP BY poll_id, option_index";
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+
+	let mut votes: HashMap<u32, HashMap<u32, u64>> = HashMap::new();
+	for mut row in rows {
+		match (row.take::<u32>("poll_id"), row.take::<u32>("option_index"), row.take::<u64>("votes")) {
+			(Some(poll_id), Some(option), Some(count)) => { votes.entry(poll_id).or_default().insert(option, count); }
+			_ => {}
+		}
+	}
+
+	Ok(votes)
+}
+
+/// Create or update a poll in the database
+pub fn update_poll_in_sql(db: &Arc<dyn Storage>, poll: &Poll) -> u64 {
+	// A zero id gets a new one from the database
+	let query = format!(r##"
+    INSERT INTO polls (id, question, options, open) VALUES
+    (NULLIF(:id, 0), :question, :options, :open)
+    {}
+    "##, db.upsert("id", &["question", "options", "open"]));
+
+	let options = match serde_json::to_string(&poll.options) {
+		Ok(tmp) => { tmp }
+		_ => { String::from("[]") }
+	};
+
+	// Execute
+	match db.exec(&query, db_params! {"id" => poll.id, "question" => &poll.question, "options" => &options, "open" => poll.open}) {
+		Ok(res) => {
+			if poll.id > 0 { return poll.id as u64; }
+			res.last_insert_id
+		}
+		Err(err) => {
+			println!("Error: {:?}", err);
+			0
+		}
+	}
+}
+
+/// Store queued votes, every address gets one vote per poll and later ones are ignored
+pub fn store_poll_votes(db: &Arc<dyn Storage>, votes: &Vec<(u32, u32, u64, String)>) {
+	let query = format!("{} INTO poll_votes (poll_id, remote_ip, option_index, voted_at) VALUES (:poll_id, :remote_ip, :option_index, :voted_at)", db.insert_ignore());
+
+	for (poll_id, option, voted_at, remote_ip) in votes {
+		let voted_at = NaiveDateTime::from_timestamp(*voted_at as i64, 0);
+		match db.exec(&query, db_params! {"poll_id" => poll_id, "remote_ip" => remote_ip, "option_index" => option, "voted_at" => voted_at}) {
+			Err(err) => { println!("Error storing a poll vote: {:?}", err); }
+			_ => {}
+		}
+	}
+}
@@ -1,5 +1,12 @@
-use chrono::{NaiveDateTime, Utc};
-use serde_json::Error as JsonError;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use crate::app::config::config_get_string;
+use crate::blog::types::{AdminList, AdminListFilter, AdminListRequest, StoreError};
+use crate::blog::types::block::{ContentBlock, render_blocks};
+use crate::storage::{DbParams, DbRow, DbValue, Storage};
 
 // ------------------------------
 // ------------ POST ------------
@@ -10,6 +17,11 @@ pub struct Post {
 	pub id: u32,
 	pub author_name: String,
 	pub author_home_post: u32,
+
+	/// Everyone who wrote this post, the main author first
+	#[serde(default)]
+	pub authors: Vec<PostAuthor>,
+
 	pub date_posted: u64,
 	pub date_modified: u64,
 	pub state: String,
@@ -23,18 +35,79 @@ pub struct Post {
 	pub url_canonical: String,
 	pub url_historic: Vec<String>,
 
+	/// Where the post was first published, for articles republished from elsewhere. See `canonical_url()`
+	#[serde(default)]
+	pub canonical_override: Option<String>,
+
 	pub tags: Vec<String>,
 	pub media: Vec<PostMedia>,
 	pub locations: Vec<PostLocation>,
 	pub related_posts: Vec<u32>,
+
+	/// The tag the post belongs to first, one of `tags`. See `primary_tag()`
+	#[serde(default)]
+	pub primary_tag: Option<String>,
+
+	/// Countries the post may be shown in, see `is_available_in()`
+	#[serde(default)]
+	pub regions: Option<PostRegions>,
+
+	/// Pinned posts stay on top of the index and tag listings
+	#[serde(default)]
+	pub pinned: bool,
+
+	/// Incremented on every edit, stale edits are rejected
+	#[serde(default)]
+	pub version: u32,
+
+	/// Renders the post with this template instead of post.html, e.g. for landing pages
+	#[serde(default)]
+	pub template: Option<String>,
+
+	/// Structured data for templates and snippets, e.g. trip dates or a budget
+	#[serde(default)]
+	pub custom_fields: BTreeMap<String, serde_json::Value>,
+
+	/// Structured content, if set the HTML content is rendered from it
+	#[serde(default)]
+	pub blocks: Option<Vec<ContentBlock>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PostMedia {
+	/// e.g. `featured`, `gallery` or `video`
 	pub class: String,
 	pub source: String,
 	pub title: String,
 	pub caption: String,
+
+	/// Still image shown before a video starts playing
+	#[serde(default)]
+	pub poster: Option<String>,
+
+	/// Shown instead of `source` to readers who prefer a dark color scheme
+	#[serde(default)]
+	pub source_dark: Option<String>,
+}
+
+/// Regional restrictions, e.g. for sponsored content, as ISO country codes like `DE`
+///
+/// With an allowlist the post is only shown in those countries, the denylist always applies
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostRegions {
+	#[serde(default)]
+	pub allow: Vec<String>,
+	#[serde(default)]
+	pub deny: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostAuthor {
+	pub id: u32,
+	#[serde(default)]
+	pub name: String,
+	#[serde(default)]
+	pub home_post: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -53,17 +126,13 @@ impl Post {
 			id: self.id,
 			author: self.author_name.clone(),
 			date_posted: self.date_posted,
+			date_modified: self.date_modified,
 			title: self.title.clone(),
-			content: {
-				let mut res = String::from("");
-				for item in self.content.split("<!--more-->") {
-					res = String::from(format!("{}</p>", item));
-					break;
-				}
-				res
-			},
+			content: format!("{}</p>", self.content.split("<!--more-->").next().unwrap_or("")),
 			content_full: self.content.clone(),
 			url_canonical: self.url_canonical.clone(),
+			primary_tag: self.primary_tag().cloned(),
+			pinned: self.pinned,
 			thumbnail: {
 				let mut thumb = String::from("/gallery/not_found.png");
 				for item in &self.media {
@@ -74,58 +143,189 @@ impl Post {
 				}
 				thumb
 			},
+			thumbnail_dark: self.media.iter().find(|item| item.class == "featured").and_then(|item| item.source_dark.clone()),
+		}
+	}
+
+	/// The HTML content, rendered from the blocks for posts that have them
+	pub fn rendered_content(&self) -> String {
+		match &self.blocks {
+			Some(blocks) if blocks.len() > 0 => render_blocks(blocks),
+			_ => self.content.clone()
+		}
+	}
+
+	/// The template this post renders with
+	pub fn template_name(&self) -> &str {
+		match &self.template {
+			Some(tmp) if tmp.len() > 0 => tmp.as_str(),
+			_ => "post.html"
+		}
+	}
+
+	/// The tag the post belongs to first, the first of its tags unless one was chosen
+	pub fn primary_tag(&self) -> Option<&String> {
+		match &self.primary_tag {
+			Some(tmp) if self.tags.contains(tmp) => Some(tmp),
+			_ => self.tags.first()
+		}
+	}
+
+	/// The URL search engines should index, the original of a syndicated post or our own
+	pub fn canonical_url(&self) -> String {
+		match &self.canonical_override {
+			Some(tmp) if tmp.len() > 0 => tmp.clone(),
+			_ => format!("https://{}/{}", config_get_string("fqdn"), self.url_canonical)
+		}
+	}
+
+	/// Whether the post was republished from elsewhere, those stay out of the site maps
+	pub fn is_syndicated(&self) -> bool {
+		self.canonical_override.as_ref().is_some_and(|tmp| tmp.len() > 0)
+	}
+
+	/// A canonical override has to be an absolute http(s) URL
+	pub fn has_valid_canonical_override(&self) -> bool {
+		match &self.canonical_override {
+			Some(tmp) if tmp.len() > 0 => {
+				let rest = tmp.strip_prefix("https://").or(tmp.strip_prefix("http://")).unwrap_or("");
+				rest.len() > 0 && !rest.starts_with('/') && !tmp.chars().any(|c| c.is_whitespace())
+			}
+			_ => true
 		}
 	}
 
-	pub fn from_sql(mut row: mysql::Row) -> Option<Post> {
+	/// The chosen primary tag has to be one of the tags of the post
+	pub fn has_valid_primary_tag(&self) -> bool {
+		match &self.primary_tag {
+			Some(tmp) if tmp.len() > 0 => self.tags.contains(tmp),
+			_ => true
+		}
+	}
+
+	/// Whether the post depends on where the reader is
+	pub fn is_region_restricted(&self) -> bool {
+		match &self.regions {
+			Some(regions) => regions.allow.len() > 0 || regions.deny.len() > 0,
+			_ => false
+		}
+	}
+
+	/// Whether the post may be shown in a country, readers from unknown countries only miss allowlisted posts
+	pub fn is_available_in(&self, country: Option<&str>) -> bool {
+		let regions = match &self.regions {
+			Some(tmp) => tmp,
+			_ => { return true; }
+		};
+
+		match country {
+			Some(country) => {
+				let listed = |list: &Vec<String>| list.iter().any(|tmp| tmp.eq_ignore_ascii_case(country));
+				(regions.allow.len() == 0 || listed(&regions.allow)) && !listed(&regions.deny)
+			}
+			_ => regions.allow.len() == 0
+		}
+	}
+
+	/// Regions have to be two letter country codes
+	pub fn has_valid_regions(&self) -> bool {
+		match &self.regions {
+			Some(regions) => regions.allow.iter().chain(regions.deny.iter()).all(|tmp| tmp.len() == 2 && tmp.chars().all(|c| c.is_ascii_alphabetic())),
+			_ => true
+		}
+	}
+
+	/// A custom template has to be one of the loaded HTML templates
+	pub fn has_valid_template(&self, tera: &tera::Tera) -> bool {
+		match &self.template {
+			Some(tmp) if tmp.len() > 0 => tmp.ends_with(".html") && tera.get_template_names().any(|name| name == tmp),
+			_ => true
+		}
+	}
+
+	pub fn from_sql(mut row: DbRow) -> Option<Post> {
 		Some(Post {
 			id: row.take("id")?,
 			author_name: row.take("author_name")?,
 			author_home_post: row.take("author_home_post")?,
-			date_posted: row.take::<NaiveDateTime, _>("date_posted")?.timestamp() as u64,
-			date_modified: row.take::<NaiveDateTime, _>("date_modified")?.timestamp() as u64,
+			authors: {
+				// Only the ids are stored, names are filled in by `resolve_post_authors`
+				let author_id: u32 = row.take("author_id")?;
+				let mut ids: Vec<u32> = match serde_json::from_str(row.take::<Option<String>>("authors")?.unwrap_or_default().as_str()) {
+					Ok(tmp) => { tmp }
+					_ => { vec![] }
+				};
+				ids.retain(|id| *id != author_id);
+				ids.insert(0, author_id);
+				ids.into_iter().map(|id| PostAuthor { id, name: String::from(""), home_post: 0 }).collect()
+			},
+			date_posted: row.take::<NaiveDateTime>("date_posted")?.timestamp() as u64,
+			date_modified: row.take::<NaiveDateTime>("date_modified")?.timestamp() as u64,
 			state: row.take("state")?,
 			title: row.take("title")?,
 			content: row.take("content")?,
 			meta_title: row.take("meta_title")?,
 			meta_description: row.take("meta_description")?,
-			meta_keywords: match serde_json::from_str(row.take::<String, _>("meta_keywords")?.as_str()) {
+			meta_keywords: match serde_json::from_str(row.take::<String>("meta_keywords")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
 			url_canonical: row.take("url_canonical")?,
-			url_historic: match serde_json::from_str(row.take::<String, _>("url_historic")?.as_str()) {
+			url_historic: match serde_json::from_str(row.take::<String>("url_historic")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
-			tags: match serde_json::from_str(row.take::<String, _>("tags")?.as_str()) {
+			tags: match serde_json::from_str(row.take::<String>("tags")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
-			media: match serde_json::from_str(row.take::<String, _>("media")?.as_str()) {
+			media: match serde_json::from_str(row.take::<String>("media")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
-			locations: match serde_json::from_str(row.take::<String, _>("locations")?.as_str()) {
+			locations: match serde_json::from_str(row.take::<String>("locations")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
-			related_posts: match serde_json::from_str(row.take::<String, _>("related_posts")?.as_str()) {
+			related_posts: match serde_json::from_str(row.take::<String>("related_posts")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
+			canonical_override: row.take::<Option<String>>("canonical_override")?.filter(|tmp| tmp.len() > 0),
+			primary_tag: row.take::<Option<String>>("primary_tag")?.filter(|tmp| tmp.len() > 0),
+			regions: match row.take::<Option<String>>("regions")? {
+				Some(tmp) => { serde_json::from_str(tmp.as_str()).ok() }
+				_ => { None }
+			},
+			pinned: row.take("pinned")?,
+			version: row.take("version")?,
+			template: row.take::<Option<String>>("template")?.filter(|tmp| tmp.len() > 0),
+			custom_fields: match serde_json::from_str(row.take::<Option<String>>("custom_fields")?.unwrap_or_default().as_str()) {
+				Ok(tmp) => { tmp }
+				_ => { BTreeMap::new() }
+			},
+			blocks: match row.take::<Option<String>>("blocks")? {
+				Some(tmp) => { serde_json::from_str(tmp.as_str()).ok() }
+				_ => { None }
+			},
 		})
 	}
 
 	/// This function will be called by the admin panel to create a new or edit an existing post
-	pub fn update_post_data(&self, db: &mysql::Pool) -> Result<u64, String> {
+	///
+	/// Edits must carry the version they are based on, returns the id and the new version
+	pub fn update_post_data(&self, db: &Arc<dyn Storage>) -> Result<(u64, u32), StoreError> {
 		// We will need the current unix time
 		let date_time = Utc::now().naive_utc();
 
-		// The post from the admin panel actually supplies the user id in the userName field
-		let author_id = match self.author_name.parse::<u32>() {
-			Ok(tmp) => tmp,
-			_ => 1
+		// A new post from the admin panel supplies the user id in the userName field, an existing one keeps its main
+		// author, the admin panel loads `author_name` as the display name
+		let author_id = match self.id {
+			0 => self.author_name.parse::<u32>().unwrap_or(1),
+			id => match admin_fetch_post_author(db, id) {
+				Some(tmp) => tmp,
+				_ => { return Err(StoreError::Conflict); }
+			}
 		};
 
 		// Build the query
@@ -135,22 +335,23 @@ impl Post {
 				r##"INSERT INTO posts (
                     author_id, date_posted, date_modified, state,
                     title, content, meta_title, meta_description, meta_keywords,
-                    url_canonical, url_historic,
-                    tags, media, locations, related_posts
+                    url_canonical, url_historic, canonical_override,
+                    tags, primary_tag, regions, media, locations, related_posts, pinned, authors, template, custom_fields, blocks, version
                 )
                 VALUES (
                     :author_id, :date_posted, :date_modified, :state,
                     :title, :content, :meta_title, :meta_description, :meta_keywords,
-                    :url_canonical, :url_historic,
-                    :tags, :media, :locations, :related_posts
+                    :url_canonical, :url_historic, :canonical_override,
+                    :tags, :primary_tag, :regions, :media, :locations, :related_posts, :pinned, :authors, :template, :custom_fields, :blocks, 1
                 )"##
 			}
 			_ => {
 				// This is an update to an existing post
 				r##"UPDATE posts SET date_modified=:date_modified, state=:state,
                 title=:title, content=:content, meta_title=:meta_title, meta_description=:meta_description, meta_keywords=:meta_keywords,
-                url_canonical=:url_canonical, url_historic=:url_historic,
-                tags=:tags, media=:media, locations=:locations, related_posts=:related_posts WHERE id=:id"##
+                url_canonical=:url_canonical, url_historic=:url_historic, canonical_override=:canonical_override,
+                tags=:tags, primary_tag=:primary_tag, regions=:regions, media=:media, locations=:locations, related_posts=:related_posts, pinned=:pinned, authors=:authors,
+                template=:template, custom_fields=:custom_fields, blocks=:blocks, version=version+1 WHERE id=:id AND version=:version"##
 			}
 		};
 
@@ -180,26 +381,59 @@ impl Post {
 			_ => { String::from("[]") }
 		};
 
+		// The main author is stored in its own column, co-authors keep their order
+		let mut author_ids: Vec<u32> = vec![author_id];
+		for author in &self.authors {
+			if !author_ids.contains(&author.id) { author_ids.push(author.id); }
+		}
+		let authors = match serde_json::to_string(&author_ids) {
+			Ok(tmp) => { tmp }
+			_ => { String::from("[]") }
+		};
+		let custom_fields = match serde_json::to_string(&self.custom_fields) {
+			Ok(tmp) => { tmp }
+			_ => { String::from("{}") }
+		};
+
+		// Posts made of blocks keep the rendered HTML as content too, so search finds them
+		let content = self.rendered_content();
+		// Country codes are kept in upper case, posts without restrictions store nothing
+		let regions = match &self.regions {
+			Some(tmp) if self.is_region_restricted() => serde_json::to_string(&PostRegions {
+				allow: tmp.allow.iter().map(|code| code.to_uppercase()).collect(),
+				deny: tmp.deny.iter().map(|code| code.to_uppercase()).collect(),
+			}).ok(),
+			_ => None
+		};
+		let blocks = match &self.blocks {
+			Some(tmp) => serde_json::to_string(tmp).ok(),
+			_ => None
+		};
+
 		// Bind params
-		let params = params! {
+		let params = db_params! {
             "id" => &self.id, "author_id" => &author_id, "date_posted" => &date_time, "date_modified" => &date_time, "state" => &self.state,
-            "title" => &self.title, "content" => &self.content, "meta_title" => &self.meta_title, "meta_description" => &self.meta_description, "meta_keywords" => &meta_keywords,
-            "url_canonical" => &self.url_canonical, "url_historic" => &historic_urls,
-            "tags" => &tags, "media" => &media, "locations" => &locations, "related_posts" => &related_posts
+            "title" => &self.title, "content" => &content, "meta_title" => &self.meta_title, "meta_description" => &self.meta_description, "meta_keywords" => &meta_keywords,
+            "url_canonical" => &self.url_canonical, "url_historic" => &historic_urls, "canonical_override" => self.canonical_override.clone().map(|tmp| String::from(tmp.trim())).filter(|tmp| tmp.len() > 0),
+            "tags" => &tags, "primary_tag" => self.primary_tag.clone().filter(|tmp| tmp.len() > 0), "regions" => &regions, "media" => &media, "locations" => &locations, "related_posts" => &related_posts, "pinned" => &self.pinned, "authors" => &authors, "version" => &self.version,
+            "template" => self.template.clone().filter(|tmp| tmp.len() > 0), "custom_fields" => &custom_fields, "blocks" => &blocks
         };
 
 		// Execute
-		match db.prep_exec(query, &params) {
+		match db.exec(query, params) {
 			Ok(res) => {
-				let post_id = match self.id {
-					0 => { res.last_insert_id() }
-					_ => { self.id as u64 }
-				};
-				Ok(post_id)
+				match self.id {
+					0 => { Ok((res.last_insert_id, 1)) }
+					_ => {
+						// Nothing matched, the post was changed since the editor loaded it
+						if res.affected_rows == 0 { return Err(StoreError::Conflict); }
+						Ok((self.id as u64, self.version + 1))
+					}
+				}
 			}
 			Err(err) => {
 				println!("Error: {:?}", err);
-				Err(String::from(err.to_string()))
+				Err(StoreError::Database(err.to_string()))
 			}
 		}
 	}
@@ -216,11 +450,17 @@ pub struct PostExcerpt {
 	pub id: u32,
 	pub author: String,
 	pub date_posted: u64,
+	pub date_modified: u64,
 	pub title: String,
 	pub content: String,
 	pub content_full: String,
 	pub url_canonical: String,
 	pub thumbnail: String,
+	/// The dark-mode alternative of the thumbnail, if the featured image has one
+	#[serde(default)]
+	pub thumbnail_dark: Option<String>,
+	pub pinned: bool,
+	pub primary_tag: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -236,6 +476,7 @@ pub struct AdminPostExcerpt {
 	pub meta_description: String,
 	pub url_canonical: String,
 	pub tags: Option<Vec<String>>,
+	pub pinned: bool,
 }
 
 
@@ -248,14 +489,14 @@ pub struct AdminPostExcerpt {
 /// Attempt to read the table containing blog posts
 ///
 /// Result will be a vector of all `Post`s found
-pub fn load_posts_from_sql(db: &mysql::Pool) -> Result<Vec<Post>, JsonError> {
+pub fn load_posts_from_sql(db: &Arc<dyn Storage>) -> Result<Vec<Post>, String> {
 	let query = r###"
     SELECT
         a.display_name AS author_name, a.home_post AS author_home_post,
-        p.id, p.date_posted, p.date_modified, p.state, p.title, p.content,
+        p.id, p.author_id, p.date_posted, p.date_modified, p.state, p.title, p.content,
         p.meta_title, p.meta_description, p.meta_keywords,
-        p.url_canonical, p.url_historic,
-        p.tags, p.media, p.locations, p.related_posts
+        p.url_canonical, p.url_historic, p.canonical_override,
+        p.tags, p.primary_tag, p.regions, p.media, p.locations, p.related_posts, p.pinned, p.authors, p.version, p.template, p.custom_fields, p.blocks
     FROM posts p
     INNER JOIN users a ON a.id = p.author_id
     WHERE state NOT IN ('draft')
@@ -263,90 +504,101 @@ pub fn load_posts_from_sql(db: &mysql::Pool) -> Result<Vec<Post>, JsonError> {
     "###;
 	// We use this order so that categories are always showing the latest post first
 
-	let posts_vec: Vec<Post> =
-		db.prep_exec(query, ())
-			.map(|result| {
-				// In this closure we will map `QueryResult` to `Vec<Post>`
-				// `QueryResult` is iterator over `MyResult<row, err>` so first call to `map`
-				// will map each `MyResult` to contained `row` (no proper error handling)
-				// and second call to `map` will map each `row` to `Post`
-				result.map(|x| x.unwrap()).map(|row| {
-					Post::from_sql(row).unwrap()
-				}).collect() // Collect posts so now `QueryResult` is mapped to `Vec<Post>`
-			}).unwrap(); // Unwrap `Vec<Post>`
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+	let mut posts_vec: Vec<Post> = rows.into_iter().filter_map(Post::from_sql).collect();
+
+	resolve_post_authors(db, &mut posts_vec);
 
 	Ok(posts_vec)
 }
 
-/// Find the latest posts
-///
-/// This will use SQL to get the ids of the latest posts
-pub fn fetch_latest_posts(db: &mysql::Pool, limit: u32) -> Result<Vec<u32>, JsonError> {
-	let query = r###"
-    SELECT
-        p.id
-    FROM posts p
-    WHERE 1
-    ORDER BY p.date_posted DESC
-    LIMIT 0, :a
-    "###;
+/// Fill in names and home posts of all authors
+pub fn resolve_post_authors(db: &Arc<dyn Storage>, posts: &mut Vec<Post>) {
+	let authors = load_authors_from_sql(db);
 
-	let posts_vec: Vec<u32> =
-		db.prep_exec(query, params! {"a" => limit})
-			.map(|result| {
-				result.map(|x| x.unwrap()).map(|mut row| {
-					row.take("id").unwrap()
-				}).collect()
-			}).unwrap();
+	for post in posts.iter_mut() {
+		for author in post.authors.iter_mut() {
+			match authors.get(&author.id) {
+				Some(tmp) => {
+					author.name = tmp.name.clone();
+					author.home_post = tmp.home_post;
+				}
+				_ => {}
+			}
+		}
+	}
+}
 
-	Ok(posts_vec)
+/// Load everyone who could be an author
+pub fn load_authors_from_sql(db: &Arc<dyn Storage>) -> HashMap<u32, PostAuthor> {
+	let mut authors = HashMap::new();
+
+	let rows = match db.query("SELECT id, display_name, home_post FROM users", db_params!()) {
+		Ok(tmp) => { tmp }
+		_ => { return authors; }
+	};
+
+	for mut row in rows {
+		match (row.take("id"), row.take("display_name"), row.take("home_post")) {
+			(Some(id), Some(name), Some(home_post)) => { authors.insert(id, PostAuthor { id, name, home_post }); }
+			_ => {}
+		}
+	}
+
+	authors
 }
 
 /// Find the most viewed posts
 ///
 /// This will use SQL to get the ids of the most viewed posts
-pub fn fetch_most_viewed_posts(db: &mysql::Pool, limit: u32) -> Result<Vec<u32>, JsonError> {
+pub fn fetch_most_viewed_posts(db: &Arc<dyn Storage>, limit: u32) -> Result<Vec<u32>, String> {
 	let query = r###"
     SELECT post_id
     FROM post_views
-    WHERE viewed_at > NOW() - INTERVAL 30 DAY
+    WHERE viewed_at > :since
     GROUP BY post_id
     ORDER BY COUNT(*) DESC
-    LIMIT 0, :a
+    LIMIT :a
     "###;
 
-	let posts_vec: Vec<u32> =
-		db.prep_exec(query, params! {"a" => limit})
-			.map(|result| {
-				result.map(|x| x.unwrap()).map(|mut row| {
-					row.take("post_id").unwrap()
-				}).collect()
-			}).unwrap();
+	let since = Utc::now().naive_utc() - Duration::days(30);
+	let rows = db.query(query, db_params! {"since" => since, "a" => limit}).map_err(|err| err.to_string())?;
 
-	Ok(posts_vec)
+	Ok(rows.into_iter().filter_map(|mut row| row.take("post_id")).collect())
+}
+
+/// The views of every post in the last `days` days
+pub fn fetch_post_view_counts(db: &Arc<dyn Storage>, days: i64) -> Result<HashMap<u32, u32>, String> {
+	let query = r###"
+    SELECT post_id, COUNT(*) AS views
+    FROM post_views
+    WHERE viewed_at > :since
+    GROUP BY post_id
+    "###;
+
+	let since = Utc::now().naive_utc() - Duration::days(days);
+	let rows = db.query(query, db_params! {"since" => since}).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(|mut row| Some((row.take("post_id")?, row.take("views")?))).collect())
 }
 
 /// Find posts using the given search string
 ///
 /// This will use SQL to get the ids of the most viewed posts
-pub fn fetch_posts_by_search_string(db: &mysql::Pool, search_string: &str) -> Result<Vec<u32>, JsonError> {
-	let words = search_string.split(" ");
-	let mut count = 0;
+pub fn fetch_posts_by_search_string(db: &Arc<dyn Storage>, search_string: &str) -> Result<Vec<u32>, String> {
+	let words = search_string.split(' ');
 	let mut title = String::from("");
 	let mut content = String::from("");
-	let mut params: Vec<String> = Vec::new();
-
-	for word in words {
-		// Skip if there are too many words
-		if count >= 10 { break; }
-		count += 1;
+	let mut params: Vec<DbValue> = Vec::new();
 
+	// Skip if there are too many words
+	for word in words.take(10) {
 		// Add to a list of params
-		params.push(format!("%{}%", word));
+		params.push(DbValue::from(format!("%{}%", word)));
 
 		if title == "" {
-			title = format!("title LIKE ?");
-			content = format!("content LIKE ?");
+			title = "title LIKE ?".to_string();
+			content = "content LIKE ?".to_string();
 		} else {
 			title = format!("{} AND title LIKE ?", title);
 			content = format!("{} AND content LIKE ?", content);
@@ -363,26 +615,58 @@ pub fn fetch_posts_by_search_string(db: &mysql::Pool, search_string: &str) -> Re
 
 //  println!("Query: {} Params: {:?}", query, params);
 
-	let posts_vec: Vec<u32> =
-		db.prep_exec(query, params)
-			.map(|result| {
-				result.map(|x| x.unwrap()).map(|mut row| {
-					row.take("id").unwrap()
-				}).collect()
-			}).unwrap();
+	let rows = db.query(&query, DbParams::Positional(params)).map_err(|err| err.to_string())?;
 
-	Ok(posts_vec)
+	Ok(rows.into_iter().filter_map(|mut row| row.take("id")).collect())
 }
 
-/// Insert a post view into the table
-pub fn log_post_views(db: &mysql::Pool, views: &Vec<(u32, u64, String, String, String)>) {
-	// (post_id, viewed_at, remote_ip, user_agent, referer)
-	for mut stmt in db.prepare(r"INSERT INTO post_views (post_id, viewed_at, remote_ip, user_agent, referer) VALUES (:id, :time, :remote, :agent, :referer)").into_iter() {
-		for v in views.iter() {
-			match stmt.execute(params! {"id" => v.0, "time" => NaiveDateTime::from_timestamp(v.1 as i64, 0), "remote" => &v.2, "agent" => &v.3, "referer" => &v.4}) {
-				Ok(_res) => {}
-				_ => {}
-			}
+/// Rows per INSERT, older SQLite versions allow at most 999 parameters in a statement
+const VIEWS_PER_INSERT: usize = 100;
+
+/// Insert the post views into the table, `VIEWS_PER_INSERT` rows per statement
+pub fn log_post_views(db: &Arc<dyn Storage>, views: &Vec<(u32, u64, String, String, String, String, String)>) {
+	// (post_id, viewed_at, remote_ip, user_agent, referer, campaign, country)
+	for chunk in views.chunks(VIEWS_PER_INSERT) {
+		let placeholders = vec!["(?, ?, ?, ?, ?, ?, ?)"; chunk.len()].join(", ");
+		let query = format!("INSERT INTO post_views (post_id, viewed_at, remote_ip, user_agent, referer, campaign, country) VALUES {}", placeholders);
+
+		let mut params = Vec::with_capacity(chunk.len() * 7);
+		for v in chunk.iter() {
+			params.push(DbValue::from(v.0));
+			params.push(DbValue::from(NaiveDateTime::from_timestamp(v.1 as i64, 0)));
+			params.push(DbValue::from(&v.2));
+			params.push(DbValue::from(&v.3));
+			params.push(DbValue::from(&v.4));
+			params.push(DbValue::from(&v.5));
+			params.push(DbValue::from(&v.6));
+		}
+
+		match db.exec(&query, DbParams::Positional(params)) {
+			Ok(_res) => {}
+			Err(err) => { println!("Could not log {} post views: {}", chunk.len(), err); }
+		}
+	}
+}
+
+/// Insert the reading progress sent by readers, `VIEWS_PER_INSERT` rows per statement
+pub fn log_post_engagement(db: &Arc<dyn Storage>, engagement: &Vec<(u32, u64, u32, u32, String)>) {
+	// (post_id, recorded_at, scroll_depth, read_time, remote_ip)
+	for chunk in engagement.chunks(VIEWS_PER_INSERT) {
+		let placeholders = vec!["(?, ?, ?, ?, ?)"; chunk.len()].join(", ");
+		let query = format!("INSERT INTO post_engagement (post_id, recorded_at, scroll_depth, read_time, remote_ip) VALUES {}", placeholders);
+
+		let mut params = Vec::with_capacity(chunk.len() * 5);
+		for e in chunk.iter() {
+			params.push(DbValue::from(e.0));
+			params.push(DbValue::from(NaiveDateTime::from_timestamp(e.1 as i64, 0)));
+			params.push(DbValue::from(e.2));
+			params.push(DbValue::from(e.3));
+			params.push(DbValue::from(&e.4));
+		}
+
+		match db.exec(&query, DbParams::Positional(params)) {
+			Ok(_res) => {}
+			Err(err) => { println!("Could not log {} engagement beacons: {}", chunk.len(), err); }
 		}
 	}
 }
@@ -392,79 +676,97 @@ pub fn log_post_views(db: &mysql::Pool, views: &Vec<(u32, u64, String, String, S
 // ---------- SQL ADMIN ---------
 // ------------------------------
 
-/// Admin function that returns a list of posts, including drafts
-pub fn admin_fetch_post_list(db: &mysql::Pool) -> Option<Vec<AdminPostExcerpt>> {
-	let query = r###"
+/// Admin function that returns a page of posts, including drafts
+///
+/// Limited to the posts of one author if `author_id` is set
+pub fn admin_fetch_post_list(db: &Arc<dyn Storage>, author_id: Option<u32>, request: &AdminListRequest) -> Option<AdminList<AdminPostExcerpt>> {
+	let mut filter = AdminListFilter::default();
+	match author_id {
+		Some(id) => { filter.add("p.author_id = ?", id); }
+		_ => {}
+	}
+	request.add_filters(&mut filter, "p.state", "p.tags", "p.date_posted");
+
+	let query = format!(r###"
+    SELECT COUNT(*) AS total
+    FROM posts p
+    {}
+    "###, filter.where_clause());
+
+	let total = match db.query_first(&query, filter.params()) {
+		Ok(Some(mut row)) => row.take("total")?,
+		_ => { return None; }
+	};
+
+	let sort_columns = [("id", "p.id"), ("date_posted", "p.date_posted"), ("date_modified", "p.date_modified"), ("title", "p.title"), ("state", "p.state")];
+	let query = format!(r###"
     SELECT
-        p.id, p.date_posted, p.date_modified, p.state, p.title, p.content, p.meta_title, p.meta_description, p.url_canonical, p.tags, a.display_name AS authorName
+        p.id, p.date_posted, p.date_modified, p.state, p.title, p.content, p.meta_title, p.meta_description, p.url_canonical, p.tags, p.pinned, a.display_name AS authorName
     FROM posts p
     INNER JOIN users a ON a.id = p.author_id
-    ORDER BY id DESC
-    "###;
+    {} {} {}
+    "###, filter.where_clause(), request.order_clause(&sort_columns), request.page_clause());
 
-	let query_result = match db.prep_exec(query, ()) {
+	let rows = match db.query(&query, filter.params()) {
 		Ok(tmp) => { tmp }
 		_ => { return None; }
 	};
 
 	let mut posts = Vec::new();
 
-	for result_row in query_result {
-		let mut row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
+	for mut row in rows {
 		posts.push(AdminPostExcerpt {
 			id: row.take("id")?,
 			author: row.take("authorName")?,
-			date_posted: row.take::<NaiveDateTime, _>("date_posted")?.timestamp() as u64,
-			date_modified: row.take::<NaiveDateTime, _>("date_modified")?.timestamp() as u64,
+			date_posted: row.take::<NaiveDateTime>("date_posted")?.timestamp() as u64,
+			date_modified: row.take::<NaiveDateTime>("date_modified")?.timestamp() as u64,
 			state: row.take("state")?,
 			title: row.take("title")?,
 //          content: row.take("content").?,
 			meta_title: row.take("meta_title")?,
 			meta_description: row.take("meta_description")?,
 			url_canonical: row.take("url_canonical")?,
-			tags: match serde_json::from_str(row.take::<String, _>("tags")?.as_str()) {
+			tags: match serde_json::from_str(row.take::<String>("tags")?.as_str()) {
 				Ok(tmp) => { tmp }
 				_ => { None }
 			},
+			pinned: row.take("pinned")?,
 		});
 	}
 
-	Some(posts)
+	Some(AdminList { total, items: posts })
+}
+
+/// Admin function that returns the id of the user who wrote the given post
+pub fn admin_fetch_post_author(db: &Arc<dyn Storage>, id: u32) -> Option<u32> {
+	match db.query_first("SELECT author_id FROM posts WHERE id = :a", db_params! {"a" => id}) {
+		Ok(Some(mut row)) => row.take("author_id"),
+		_ => None
+	}
 }
 
 /// Admin function that returns the given post by its id
-pub fn admin_fetch_post(db: &mysql::Pool, id: u32) -> Option<Post> {
+pub fn admin_fetch_post(db: &Arc<dyn Storage>, id: u32) -> Option<Post> {
 	let query = r###"
     SELECT
         a.display_name AS author_name, a.home_post AS author_home_post,
-        p.id, p.date_posted, p.date_modified, p.state, p.title, p.content,
+        p.id, p.author_id, p.date_posted, p.date_modified, p.state, p.title, p.content,
         p.meta_title, p.meta_description, p.meta_keywords,
-        p.url_canonical, p.url_historic,
-        p.tags, p.media, p.locations, p.related_posts
+        p.url_canonical, p.url_historic, p.canonical_override,
+        p.tags, p.primary_tag, p.regions, p.media, p.locations, p.related_posts, p.pinned, p.authors, p.version, p.template, p.custom_fields, p.blocks
     FROM posts p
     INNER JOIN users a ON a.id = p.author_id
     WHERE p.id = :a
     "###;
 
-	let query_result = match db.prep_exec(query, params! {"a" => id}) {
-		Ok(tmp) => { tmp }
+	let row = match db.query_first(query, db_params! {"a" => id}) {
+		Ok(Some(tmp)) => { tmp }
 		_ => { return None; }
 	};
 
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
-		return Post::from_sql(row);
-	}
-
-	None
+	let mut posts = vec![Post::from_sql(row)?];
+	resolve_post_authors(db, &mut posts);
+	posts.pop()
 }
 ///// Find posts using the given tag
 /////
@@ -1,36 +1,137 @@
+use std::sync::Arc;
+
+use crate::storage::{DbRow, Storage};
+
+/// How a redirect answers, 410 tells that the content is gone for good and needs no target
+pub const REDIRECT_STATUSES: &[u16] = &[301, 302, 307, 308, 410];
+
+/// How the name of a redirect is matched
+///
+/// `name` is only used by `/fwd/{name}`, the others by any path that is no post or page: `path` is
+/// the exact path, `prefix` every path below it with the rest appended to the target, and `wildcard`
+/// a path like `old/*/photos` where each `*` is put into the target in turn
+pub const REDIRECT_KINDS: &[&str] = &["name", "path", "prefix", "wildcard"];
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Redirect {
 	pub id: u32,
 	pub name: String,
 	pub target: String,
+	/// One of `REDIRECT_KINDS`
+	#[serde(default = "default_kind")]
+	pub kind: String,
+	/// One of `REDIRECT_STATUSES`
+	#[serde(default = "default_status")]
+	pub status: u16,
+}
+
+fn default_kind() -> String {
+	String::from("name")
+}
+
+fn default_status() -> u16 {
+	302
 }
 
 impl Redirect {
 	/// Turns a SQL row into a redirect
-	pub fn from_sql(mut row: mysql::Row) -> Option<Redirect> {
+	pub fn from_sql(mut row: DbRow) -> Option<Redirect> {
 		Some(Redirect {
 			id: row.take("id")?,
 			name: row.take("name")?,
 			target: row.take("target")?,
+			kind: row.take("kind")?,
+			status: row.take("status")?,
 		})
 	}
+
+	/// Check and clean up a redirect from the admin panel
+	///
+	/// Paths are stored the way `NormalizePath` hands them to us: lower case, without the slashes around them
+	pub fn normalized(mut self) -> Result<Redirect, String> {
+		self.target = String::from(self.target.trim());
+		self.name = match self.kind.as_str() {
+			"name" => String::from(self.name.trim()),
+			_ => self.name.trim().trim_matches('/').to_lowercase()
+		};
+
+		if !REDIRECT_KINDS.contains(&self.kind.as_str()) {
+			return Err(format!("'{}' is no kind of redirect, use one of {}", self.kind, REDIRECT_KINDS.join(", ")));
+		}
+		if !REDIRECT_STATUSES.contains(&self.status) {
+			return Err(format!("{} is no redirect status, use 301, 302, 307, 308 or 410", self.status));
+		}
+		if self.name.len() == 0 {
+			return Err(String::from("the redirect needs a name or path"));
+		}
+		if self.kind == "wildcard" && !self.name.contains('*') {
+			return Err(String::from("a wildcard redirect needs a * in its path"));
+		}
+		if self.status != 410 && self.target.len() == 0 {
+			return Err(String::from("the redirect needs a target"));
+		}
+
+		Ok(self)
+	}
+
+	/// The target for a path, if this rule matches it
+	pub fn target_for(&self, path: &str) -> Option<String> {
+		match self.kind.as_str() {
+			"path" if path == self.name => Some(self.target.clone()),
+			"prefix" if path == self.name => Some(self.target.clone()),
+			"prefix" if path.starts_with(&self.name) && path[self.name.len()..].starts_with('/') => {
+				Some(format!("{}/{}", self.target.trim_end_matches('/'), &path[self.name.len() + 1..]))
+			}
+			"wildcard" => {
+				let captures = match_wildcard(&self.name, path)?;
+				let mut target = String::with_capacity(self.target.len());
+				let mut captures = captures.into_iter();
+				for (index, part) in self.target.split('*').enumerate() {
+					if index > 0 { target.push_str(captures.next().unwrap_or("")); }
+					target.push_str(part);
+				}
+				Some(target)
+			}
+			_ => None
+		}
+	}
+}
+
+/// What each `*` of the pattern stands for, None if the path does not match
+///
+/// A `*` takes as little as possible, it may span slashes
+fn match_wildcard<'a>(pattern: &str, path: &'a str) -> Option<Vec<&'a str>> {
+	let mut parts = pattern.split('*');
+	let first = parts.next().unwrap_or("");
+	if !path.starts_with(first) { return None; }
+
+	let mut captures = Vec::new();
+	let mut rest = &path[first.len()..];
+	let parts: Vec<&str> = parts.collect();
+	for (index, part) in parts.iter().enumerate() {
+		// The last part has to end the path, the others are found as early as possible
+		let at = match index == parts.len() - 1 {
+			true if rest.ends_with(part) => rest.len() - part.len(),
+			true => { return None; }
+			false => rest.find(part)?
+		};
+		captures.push(&rest[..at]);
+		rest = &rest[at + part.len()..];
+	}
+
+	Some(captures)
 }
 
 /// Load all the redirects from the database
-pub fn load_redirects_from_sql(db: &mysql::Pool) -> Option<Vec<Redirect>> {
-	let query_result = match db.prep_exec("SELECT id, name, target FROM redirects", ()) {
+pub fn load_redirects_from_sql(db: &Arc<dyn Storage>) -> Option<Vec<Redirect>> {
+	let rows = match db.query("SELECT id, name, target, kind, status FROM redirects", db_params!()) {
 		Ok(tmp) => { tmp }
 		_ => { return None; }
 	};
 
 	let mut redirects = Vec::new();
 
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
+	for row in rows {
 		match Redirect::from_sql(row) {
 			Some(tmp) => { redirects.push(tmp); }
 			_ => {}
@@ -41,22 +142,23 @@ pub fn load_redirects_from_sql(db: &mysql::Pool) -> Option<Vec<Redirect>> {
 }
 
 /// Create or update a redirect in the database
-pub fn update_redirect_in_sql(db: &mysql::Pool, redir: &Redirect) -> u64 {
-	let query = r##"
-    INSERT INTO redirects (id, name, target) VALUES
-    (:id, :name, :target)
-    ON DUPLICATE KEY UPDATE name=:name, target=:target
-    "##;
+pub fn update_redirect_in_sql(db: &Arc<dyn Storage>, redir: &Redirect) -> u64 {
+	// A zero id gets a new one from the database
+	let query = format!(r##"
+    INSERT INTO redirects (id, name, target, kind, status) VALUES
+    (NULLIF(:id, 0), :name, :target, :kind, :status)
+    {}
+    "##, db.upsert("id", &["name", "target", "kind", "status"]));
 
 	// Execute
-	match db.prep_exec(query, params! {"name" => &redir.name, "target" => &redir.target, "id" => redir.id}) {
+	match db.exec(&query, db_params! {"name" => &redir.name, "target" => &redir.target, "kind" => &redir.kind, "status" => redir.status, "id" => redir.id}) {
 		Ok(res) => {
 			if redir.id > 0 { return redir.id as u64; }
-			res.last_insert_id()
+			res.last_insert_id
 		}
 		Err(err) => {
 			println!("Error: {:?}", err);
 			0
 		}
 	}
-}
\ No newline at end of file
+}
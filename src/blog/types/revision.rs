@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::storage::{DbRow, Storage};
+
+// ------------------------------
+// ---------- REVISION ----------
+// ------------------------------
+
+/// The content of a post before a bulk edit, so the change can be undone by hand
+#[derive(Serialize, Debug)]
+pub struct PostRevision {
+	pub id: u64,
+	pub post_id: u32,
+	pub user_id: u32,
+	pub reason: String,
+	pub content: String,
+	pub created_at: u64,
+}
+
+impl PostRevision {
+	pub fn from_sql(mut row: DbRow) -> Option<PostRevision> {
+		Some(PostRevision {
+			id: row.take("id")?,
+			post_id: row.take("post_id")?,
+			user_id: row.take("user_id")?,
+			reason: row.take("reason")?,
+			content: row.take("content")?,
+			created_at: row.take::<NaiveDateTime>("created_at")?.timestamp() as u64,
+		})
+	}
+}
+
+
+// ------------------------------
+// ---------- SQL ADMIN ---------
+// ------------------------------
+
+/// Keep the current content of a post before it is changed
+pub fn store_post_revision(db: &Arc<dyn Storage>, post_id: u32, user_id: u32, reason: &str, content: &str) -> Result<u64, String> {
+	let query = "INSERT INTO post_revisions (post_id, user_id, reason, content, created_at) VALUES (:post_id, :user_id, :reason, :content, :created_at)";
+	let reason: String = reason.chars().take(1000).collect();
+
+	match db.exec(query, db_params! {"post_id" => post_id, "user_id" => user_id, "reason" => &reason, "content" => content, "created_at" => Utc::now().naive_utc()}) {
+		Ok(res) => { Ok(res.last_insert_id) }
+		Err(err) => {
+			println!("Error: {:?}", err);
+			Err(err.to_string())
+		}
+	}
+}
+
+/// All revisions of a post, newest first
+pub fn load_post_revisions(db: &Arc<dyn Storage>, post_id: u32) -> Result<Vec<PostRevision>, String> {
+	let query = "SELECT id, post_id, user_id, reason, content, created_at FROM post_revisions WHERE post_id=:post_id ORDER BY id DESC";
+	let rows = db.query(query, db_params! {"post_id" => post_id}).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(PostRevision::from_sql).collect())
+}
@@ -1,8 +1,10 @@
+use std::collections::BTreeMap;
 use std::vec::Vec;
+use std::sync::Arc;
 
-use regex::Regex;
+use crate::storage::{DbRow, Storage};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Snippet {
 	pub id: u16,
 	pub name: String,
@@ -10,7 +12,7 @@ pub struct Snippet {
 	pub variables: Vec<SnippetVariable>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SnippetVariable {
 	pub name: String,
 	pub default: String,
@@ -18,37 +20,35 @@ pub struct SnippetVariable {
 
 impl Snippet {
 	/// Turns a SQL row into a snippet
-	pub fn from_sql(mut row: mysql::Row) -> Option<Snippet> {
+	pub fn from_sql(mut row: DbRow) -> Option<Snippet> {
 		Some(Snippet {
 			id: row.take("id")?,
 			name: row.take("name")?,
 			replacement: row.take("replacement")?,
-			variables: match serde_json::from_str(row.take::<String, _>("variables")?.as_str()) {
+			variables: match serde_json::from_str(row.take::<String>("variables")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
 		})
 	}
 
-	/// Takes a given tail match and creates a replacement string
-	pub fn get_replacement(&self, tail: &str) -> String {
+	/// Creates the replacement string from the arguments of a shortcode
+	///
+	/// Variables missing from the arguments are taken from the post's custom fields of the same name, then from their default
+	pub fn get_replacement(&self, args: &BTreeMap<String, String>, custom_fields: &BTreeMap<String, serde_json::Value>) -> String {
 		// Start of with our replacement string
 		let mut text = self.replacement.clone();
 
 		// For every variable that exists replace it into the string
 		for var in &self.variables {
-			let mut var_value = var.default.clone();
-
-			// Try to find a specific value in the tail
-			match Regex::new(&format!("{}=\"(?P<capval>[^\"]+)\"", &var.name)) {
-				Ok(regex) => {
-					for cap in regex.captures_iter(tail) {
-//                      println!("Matched in tail - var: {:?}, val: {:?}", &var.name, &cap["capval"]);
-						var_value = String::from(&cap["capval"]);
-					}
+			let var_value = match args.get(&var.name) {
+				Some(tmp) if tmp.len() > 0 => tmp.clone(),
+				_ => match custom_fields.get(&var.name) {
+					Some(serde_json::Value::String(tmp)) => tmp.clone(),
+					Some(serde_json::Value::Null) | None => var.default.clone(),
+					Some(tmp) => tmp.to_string(),
 				}
-				_ => {}
-			}
+			};
 
 			// Replace all occurrences of this variable in our text
 			text = text.replace(&format!("{{{}}}", &var.name), &var_value);
@@ -60,20 +60,15 @@ impl Snippet {
 }
 
 /// Load all the snippets from the database
-pub fn load_snippets_from_sql(db: &mysql::Pool) -> Option<Vec<Snippet>> {
-	let query_result = match db.prep_exec("SELECT id, name, replacement, variables FROM snippets", ()) {
+pub fn load_snippets_from_sql(db: &Arc<dyn Storage>) -> Option<Vec<Snippet>> {
+	let rows = match db.query("SELECT id, name, replacement, variables FROM snippets", db_params!()) {
 		Ok(tmp) => { tmp }
 		_ => { return None; }
 	};
 
 	let mut snippets = Vec::new();
 
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
+	for row in rows {
 		match Snippet::from_sql(row) {
 			Some(tmp) => { snippets.push(tmp); }
 			_ => {}
@@ -84,27 +79,25 @@ pub fn load_snippets_from_sql(db: &mysql::Pool) -> Option<Vec<Snippet>> {
 }
 
 /// Create or update a snippet in the database
-pub fn update_snippet_in_sql(db: &mysql::Pool, snip: &Snippet) -> u64 {
-	let query = r##"
+pub fn update_snippet_in_sql(db: &Arc<dyn Storage>, snip: &Snippet) -> u64 {
+	// A zero id gets a new one from the database
+	let query = format!(r##"
     INSERT INTO snippets (id, name, replacement, variables) VALUES
-    (:id, :name, :replacement, :variables)
-    ON DUPLICATE KEY UPDATE name=:name, replacement=:replacement, variables=:variables
-    "##;
+    (NULLIF(:id, 0), :name, :replacement, :variables)
+    {}
+    "##, db.upsert("id", &["name", "replacement", "variables"]));
 
-	let variables = match serde_json::to_string(&snip.variables) {
-		Ok(tmp) => { tmp }
-		_ => { String::from("") }
-	};
+	let variables = serde_json::to_string(&snip.variables).unwrap_or_default();
 
 	// Execute
-	match db.prep_exec(query, params! {"name" => &snip.name, "replacement" => &snip.replacement, "variables" => &variables, "id" => snip.id}) {
+	match db.exec(&query, db_params! {"name" => &snip.name, "replacement" => &snip.replacement, "variables" => &variables, "id" => snip.id}) {
 		Ok(res) => {
 			if snip.id > 0 { return snip.id as u64; }
-			res.last_insert_id()
+			res.last_insert_id
 		}
 		Err(err) => {
 			println!("Error: {:?}", err);
 			0
 		}
 	}
-}
\ No newline at end of file
+}
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use serde_json::Error as JsonError;
+use crate::blog::types::StoreError;
+use crate::storage::{DbRow, Storage, StorageError};
 
 // ------------------------------
 // ------------ TAG -------------
@@ -14,6 +16,10 @@ pub struct Tag {
 	pub meta_title: String,
 	pub meta_description: String,
 	pub media: Vec<TagMedia>,
+
+	/// Incremented on every edit, stale edits are rejected. Zero for tags without a row
+	#[serde(default)]
+	pub version: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -26,25 +32,37 @@ pub struct TagMedia {
 
 
 impl Tag {
-	pub fn from_sql(mut row: mysql::Row) -> Option<Tag> {
+	pub fn from_sql(mut row: DbRow) -> Option<Tag> {
 		Some(Tag {
 			id: row.take("id")?,
 			title: row.take("title")?,
 			content: row.take("content")?,
 			meta_title: row.take("meta_title")?,
 			meta_description: row.take("meta_description")?,
-			media: match serde_json::from_str(row.take::<String, _>("media")?.as_str()) {
+			media: match serde_json::from_str(row.take::<String>("media")?.as_str()) {
 				Ok(tmp) => { Some(tmp)? }
 				_ => { vec![] }
 			},
+			version: row.take("version")?,
 		})
 	}
 
 	/// This function will be called by the admin panel to create a tag or edit an existing tag
-	pub fn update_tag_data(&self, db: &mysql::Pool) -> Result<String, String> {
+	///
+	/// Edits must carry the version they are based on, returns the id and the new version
+	pub fn update_tag_data(&self, db: &Arc<dyn Storage>) -> Result<(String, u32), StoreError> {
 		// Build the query
-		let query = r##"REPLACE INTO tags (id, title, content, meta_title, meta_description, media)
-            VALUES (:id, :title, :content, :meta_title, :meta_description, :media)"##;
+		let query = match self.version {
+			// A tag without a row, creating it fails if someone else was faster
+			0 => {
+				r##"INSERT INTO tags (id, title, content, meta_title, meta_description, media, version)
+                VALUES (:id, :title, :content, :meta_title, :meta_description, :media, 1)"##
+			}
+			_ => {
+				r##"UPDATE tags SET title=:title, content=:content, meta_title=:meta_title, meta_description=:meta_description, media=:media,
+                version=version+1 WHERE id=:id AND version=:version"##
+			}
+		};
 
 		// Convert some more values
 		let media = match serde_json::to_string(&self.media) {
@@ -53,18 +71,25 @@ impl Tag {
 		};
 
 		// Bind params
-		let params = params! {
-            "id" => &self.id, "title" => &self.title, "content" => &self.content, "meta_title" => &self.meta_title, "meta_description" => &self.meta_description, "media" => &media
+		let params = db_params! {
+            "id" => &self.id, "title" => &self.title, "content" => &self.content, "meta_title" => &self.meta_title, "meta_description" => &self.meta_description, "media" => &media,
+            "version" => &self.version
         };
 
 		// Execute
-		match db.prep_exec(query, &params) {
-			Ok(_res) => {
-				Ok(self.id.clone())
+		match db.exec(query, params) {
+			Ok(res) => {
+				// Nothing matched, the tag was changed since the editor loaded it
+				if res.affected_rows == 0 { return Err(StoreError::Conflict); }
+				Ok((self.id.clone(), self.version + 1))
+			}
+			Err(StorageError::Duplicate(_)) => {
+				// Duplicate key, the tag was created since the editor loaded it
+				Err(StoreError::Conflict)
 			}
 			Err(err) => {
 				println!("Error: {:?}", err);
-				Err(String::from(err.to_string()))
+				Err(StoreError::Database(err.to_string()))
 			}
 		}
 	}
@@ -95,22 +120,12 @@ pub struct AdminTagExcerpt {
 /// Attempt to read the table containing blog tags
 ///
 /// Result will be a vector of all `Tag`s found
-pub fn load_tags_from_sql(db: &mysql::Pool) -> Result<Vec<Tag>, JsonError> {
-	let query = "SELECT id, title, content, meta_title, meta_description, media FROM tags";
-
-	let tags: Vec<Tag> =
-		db.prep_exec(query, ())
-			.map(|result| {
-				// In this closure we will map `QueryResult` to `Vec<Tag>`
-				// `QueryResult` is iterator over `MyResult<row, err>` so first call to `map`
-				// will map each `MyResult` to contained `row` (no proper error handling)
-				// and second call to `map` will map each `row` to `Tag`
-				result.map(|x| x.unwrap()).map(|row| {
-					Tag::from_sql(row).unwrap()
-				}).collect() // Collect tags so now `QueryResult` is mapped to `Vec<Tag>`
-			}).unwrap(); // Unwrap `Vec<Tag>`
-
-	Ok(tags)
+pub fn load_tags_from_sql(db: &Arc<dyn Storage>) -> Result<Vec<Tag>, String> {
+	let query = "SELECT id, title, content, meta_title, meta_description, media, version FROM tags";
+
+	let rows = db.query(query, db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(Tag::from_sql).collect())
 }
 
 
@@ -119,13 +134,13 @@ pub fn load_tags_from_sql(db: &mysql::Pool) -> Result<Vec<Tag>, JsonError> {
 // ------------------------------
 
 /// Admin function that returns a list of tags, including drafts
-pub fn admin_fetch_tag_list(db: &mysql::Pool, in_use_tags: &Vec<String>) -> Option<Vec<AdminTagExcerpt>> {
+pub fn admin_fetch_tag_list(db: &Arc<dyn Storage>, in_use_tags: &Vec<String>) -> Option<Vec<AdminTagExcerpt>> {
 	let query = r###"
-    SELECT id, LEFT(title, 20) AS title, LEFT(content, 20) AS content, LEFT(meta_title, 20) AS meta_title, LEFT(meta_description, 20) AS meta_description
+    SELECT id, SUBSTR(title, 1, 20) AS title, SUBSTR(content, 1, 20) AS content, SUBSTR(meta_title, 1, 20) AS meta_title, SUBSTR(meta_description, 1, 20) AS meta_description
     FROM tags
     "###;
 
-	let query_result = match db.prep_exec(query, ()) {
+	let rows = match db.query(query, db_params!()) {
 		Ok(tmp) => { tmp }
 		_ => { return None; }
 	};
@@ -133,12 +148,7 @@ pub fn admin_fetch_tag_list(db: &mysql::Pool, in_use_tags: &Vec<String>) -> Opti
 	let mut tag_map = HashMap::new();
 
 	// Gather all tags that have extended data set in the database
-	for result_row in query_result {
-		let mut row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
+	for mut row in rows {
 		let tag = AdminTagExcerpt {
 			id: row.take("id")?,
 			title: row.take("title")?,
@@ -178,26 +188,15 @@ pub fn admin_fetch_tag_list(db: &mysql::Pool, in_use_tags: &Vec<String>) -> Opti
 }
 
 /// Admin function that returns the given tag by its id
-pub fn admin_fetch_tag(db: &mysql::Pool, id: &str) -> Option<Tag> {
+pub fn admin_fetch_tag(db: &Arc<dyn Storage>, id: &str) -> Option<Tag> {
 	let query = r###"
-    SELECT id, title, content, meta_title, meta_description, media
+    SELECT id, title, content, meta_title, meta_description, media, version
     FROM tags
     WHERE id = :id
     "###;
 
-	let query_result = match db.prep_exec(query, params! {"id" => id}) {
-		Ok(tmp) => { tmp }
-		_ => { return None; }
-	};
-
-	for result_row in query_result {
-		let row = match result_row {
-			Ok(tmp) => { tmp }
-			_ => { continue; }
-		};
-
-		return Tag::from_sql(row);
+	match db.query_first(query, db_params! {"id" => id}) {
+		Ok(Some(row)) => { Tag::from_sql(row) }
+		_ => { None }
 	}
-
-	None
 }
\ No newline at end of file
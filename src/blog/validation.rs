@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::blog::gallery::gallery_source_exists;
+use crate::blog::types::post::Post;
+use crate::blog::types::tag::Tag;
+
+/// A single problem found while checking the loaded blog data
+#[derive(Serialize, Clone, Debug)]
+pub struct DataIssue {
+	pub kind: String,
+	/// 0 for issues that do not belong to a post
+	pub post_id: u32,
+	pub detail: String,
+}
+
+impl DataIssue {
+	pub fn new(kind: &str, post_id: u32, detail: String) -> DataIssue {
+		DataIssue { kind: String::from(kind), post_id, detail }
+	}
+}
+
+
+/// Check the loaded posts and tags for inconsistencies
+///
+/// Nothing is fixed here, the issues are only collected so they can be reported
+pub fn validate_blog_data(posts: &HashMap<u32, Arc<Post>>, tags: &HashMap<String, Tag>, tag_2_posts: &HashMap<String, Vec<u32>>) -> Vec<DataIssue> {
+	let mut issues = Vec::new();
+
+	// Sort by id so the report is stable between runs
+	let mut post_list: Vec<&Arc<Post>> = posts.values().collect();
+	post_list.sort_by_key(|a| a.id);
+
+	// Map every canonical url to the post that owns it
+	let mut canonical_urls: HashMap<String, u32> = HashMap::new();
+	for post in &post_list {
+		let url = post.url_canonical.to_lowercase();
+
+		match canonical_urls.get(&url) {
+			Some(other_id) => {
+				issues.push(DataIssue::new("duplicate_canonical", post.id, format!("'{}' is also the canonical url of post {}", url, other_id)));
+			}
+			_ => {
+				canonical_urls.insert(url, post.id);
+			}
+		}
+	}
+
+	for post in &post_list {
+		// Historic urls are only consulted after the canonical ones, so a collision hides the historic url
+		for url in &post.url_historic {
+			match canonical_urls.get(&url.to_lowercase()) {
+				Some(other_id) => {
+					issues.push(DataIssue::new("historic_shadowed", post.id, format!("historic url '{}' is the canonical url of post {}", url, other_id)));
+				}
+				_ => {}
+			}
+		}
+
+		// A primary tag that was removed from the tags is ignored, the first tag is used instead
+		if !post.has_valid_primary_tag() {
+			issues.push(DataIssue::new("invalid_primary_tag", post.id, format!("primary tag '{}' is not one of the tags", post.primary_tag.clone().unwrap_or_default())));
+		}
+
+		// Related posts must exist (and be loaded, drafts are not)
+		for related_id in &post.related_posts {
+			if !posts.contains_key(related_id) {
+				issues.push(DataIssue::new("missing_related", post.id, format!("related post {} does not exist", related_id)));
+			}
+		}
+
+		// Media hosted in our own gallery must be present on disk
+		for media in &post.media {
+			if !gallery_source_exists(&media.source) {
+				issues.push(DataIssue::new("missing_media", post.id, format!("gallery file for '{}' is missing", media.source)));
+			}
+			match &media.source_dark {
+				Some(dark) if !gallery_source_exists(dark) => {
+					issues.push(DataIssue::new("missing_media", post.id, format!("gallery file for the dark variant '{}' is missing", dark)));
+				}
+				_ => {}
+			}
+		}
+	}
+
+	// Tags that are used by posts but have no extended data
+	let mut tag_names: Vec<&String> = tag_2_posts.keys().collect();
+	tag_names.sort();
+	for tag in tag_names {
+		if !tags.contains_key(tag) {
+			let first_post = match tag_2_posts.get(tag) {
+				Some(ids) => { *ids.first().unwrap_or(&0) }
+				_ => { 0 }
+			};
+			issues.push(DataIssue::new("missing_tag", first_post, format!("tag '{}' has no row in the tags table", tag)));
+		}
+	}
+
+	issues
+}
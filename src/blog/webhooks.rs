@@ -0,0 +1,287 @@
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::app::config::config_get_i64;
+use crate::app::utils::curl_post_json;
+use crate::storage::{DbRow, Storage};
+
+/// The events a webhook can subscribe to
+pub const WEBHOOK_EVENTS: &[&str] = &["post.published", "comment.created", "comment.approved"];
+
+/// Default number of attempts before a delivery is given up, `webhook_max_attempts` overrides it
+const WEBHOOK_MAX_ATTEMPTS: i64 = 6;
+
+/// The first retry waits this long, every further one twice as long
+const WEBHOOK_RETRY_SECONDS: i64 = 60;
+
+/// Deliveries sent per run of the background job
+const WEBHOOK_BATCH_SIZE: u32 = 50;
+
+/// Slow receivers must not hold up the other deliveries
+const WEBHOOK_TIMEOUT_SECONDS: u64 = 10;
+
+
+// ------------------------------
+// ----------- WEBHOOK ----------
+// ------------------------------
+
+/// An URL that is told about new content, e.g. a Discord or Slack channel or Zapier
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+	#[serde(default)]
+	pub id: u32,
+	pub url: String,
+
+	/// Signs the payload, the signature is sent in `X-Webhook-Signature`
+	#[serde(default)]
+	pub secret: String,
+
+	/// Some of `WEBHOOK_EVENTS`, none for all of them
+	#[serde(default)]
+	pub events: Vec<String>,
+	pub active: bool,
+	#[serde(default)]
+	pub created_at: u64,
+}
+
+/// One event for one webhook, retried until it was accepted
+#[derive(Serialize, Debug)]
+pub struct WebhookDelivery {
+	pub id: u64,
+	pub webhook_id: u32,
+	pub event: String,
+	/// `pending`, `delivered` or `failed`
+	pub status: String,
+	pub attempts: u32,
+	pub last_error: String,
+	pub next_attempt_at: u64,
+	pub created_at: u64,
+}
+
+impl Webhook {
+	fn from_sql(mut row: DbRow) -> Option<Webhook> {
+		Some(Webhook {
+			id: row.take("id")?,
+			url: row.take("url")?,
+			secret: row.take("secret")?,
+			events: match serde_json::from_str(row.take::<String>("events")?.as_str()) {
+				Ok(tmp) => { tmp }
+				_ => { vec![] }
+			},
+			active: row.take("active")?,
+			created_at: row.take::<NaiveDateTime>("created_at")?.timestamp() as u64,
+		})
+	}
+
+	/// Check and clean up a webhook from the admin panel
+	pub fn normalized(mut self) -> Result<Webhook, String> {
+		self.url = String::from(self.url.trim());
+		if !self.url.starts_with("https://") && !self.url.starts_with("http://") {
+			return Err(format!("'{}' is not an http or https URL", self.url));
+		}
+
+		self.events = self.events.iter().map(|event| event.trim().to_lowercase()).collect();
+		match self.events.iter().find(|event| !WEBHOOK_EVENTS.contains(&event.as_str())) {
+			Some(event) => { return Err(format!("'{}' is not one of {}", event, WEBHOOK_EVENTS.join(", "))); }
+			_ => {}
+		}
+		self.events.sort();
+		self.events.dedup();
+
+		Ok(self)
+	}
+
+	fn wants(&self, event: &str) -> bool {
+		self.active && (self.events.len() == 0 || self.events.iter().any(|tmp| tmp == event))
+	}
+}
+
+impl WebhookDelivery {
+	fn from_sql(mut row: DbRow) -> Option<WebhookDelivery> {
+		Some(WebhookDelivery {
+			id: row.take("id")?,
+			webhook_id: row.take("webhook_id")?,
+			event: row.take("event")?,
+			status: row.take("status")?,
+			attempts: row.take("attempts")?,
+			last_error: row.take("last_error")?,
+			next_attempt_at: row.take::<NaiveDateTime>("next_attempt_at")?.timestamp() as u64,
+			created_at: row.take::<NaiveDateTime>("created_at")?.timestamp() as u64,
+		})
+	}
+}
+
+
+/// All webhooks, for the admin panel
+pub fn load_webhooks(db: &Arc<dyn Storage>) -> Result<Vec<Webhook>, String> {
+	let rows = db.query("SELECT id, url, secret, events, active, created_at FROM webhooks ORDER BY id", db_params!()).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(Webhook::from_sql).collect())
+}
+
+/// Add a new webhook or update an existing one, webhooks must be normalized
+///
+/// Returns the id of the webhook
+pub fn store_webhook(db: &Arc<dyn Storage>, webhook: &Webhook) -> Result<u32, String> {
+	let events = serde_json::to_string(&webhook.events).map_err(|err| err.to_string())?;
+
+	let result = match webhook.id {
+		0 => {
+			let query = "INSERT INTO webhooks (url, secret, events, active, created_at) VALUES (:url, :secret, :events, :active, :created_at)";
+			db.exec(query, db_params! {"url" => &webhook.url, "secret" => &webhook.secret, "events" => &events, "active" => webhook.active, "created_at" => Utc::now().naive_utc()})
+				.map(|res| res.last_insert_id as u32)
+		}
+		id => {
+			let query = "UPDATE webhooks SET url=:url, secret=:secret, events=:events, active=:active WHERE id=:id";
+			db.exec(query, db_params! {"id" => id, "url" => &webhook.url, "secret" => &webhook.secret, "events" => &events, "active" => webhook.active})
+				.map(|_| id)
+		}
+	};
+
+	result.map_err(|err| {
+		println!("Error: {:?}", err);
+		err.to_string()
+	})
+}
+
+/// Remove a webhook along with the deliveries still waiting for it
+pub fn delete_webhook(db: &Arc<dyn Storage>, id: u32) -> Result<(), String> {
+	for query in &["DELETE FROM webhook_deliveries WHERE webhook_id=:id", "DELETE FROM webhooks WHERE id=:id"] {
+		match db.exec(query, db_params! {"id" => id}) {
+			Ok(_) => {}
+			Err(err) => {
+				println!("Error: {:?}", err);
+				return Err(err.to_string());
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// The latest deliveries, newest first
+pub fn load_webhook_deliveries(db: &Arc<dyn Storage>, limit: u32) -> Result<Vec<WebhookDelivery>, String> {
+	let query = r##"SELECT id, webhook_id, event, status, attempts, last_error, next_attempt_at, created_at
+        FROM webhook_deliveries ORDER BY id DESC LIMIT :limit"##;
+	let rows = db.query(query, db_params! {"limit" => limit}).map_err(|err| err.to_string())?;
+
+	Ok(rows.into_iter().filter_map(WebhookDelivery::from_sql).collect())
+}
+
+
+// ------------------------------
+// ---------- DELIVERY ----------
+// ------------------------------
+
+/// Queue an event for every webhook subscribed to it, the background job sends them
+///
+/// `text` is a short summary for chat services, `data` the details of the event
+pub fn webhook_fire(db: &Arc<dyn Storage>, event: &str, text: &str, data: serde_json::Value) {
+	let webhooks = match load_webhooks(db) {
+		Ok(tmp) => tmp,
+		Err(err) => {
+			println!("Could not load the webhooks: {}", err);
+			return;
+		}
+	};
+
+	let now = Utc::now().naive_utc();
+
+	// Slack reads `text`, Discord reads `content`
+	let payload = json!({
+		"event": event,
+		"created_at": now.timestamp(),
+		"text": text,
+		"content": text,
+		"data": data,
+	}).to_string();
+
+	let query = r##"INSERT INTO webhook_deliveries (webhook_id, event, payload, status, attempts, last_error, next_attempt_at, created_at)
+        VALUES (:webhook_id, :event, :payload, 'pending', 0, '', :now, :now)"##;
+
+	for webhook in webhooks.iter().filter(|webhook| webhook.wants(event)) {
+		match db.exec(query, db_params! {"webhook_id" => webhook.id, "event" => event, "payload" => &payload, "now" => now}) {
+			Err(err) => { println!("Could not queue the webhook delivery: {:?}", err); }
+			_ => {}
+		}
+	}
+}
+
+/// Send the deliveries that are due, failed ones are retried later with a growing delay
+pub fn webhook_deliver_pending(db: &Arc<dyn Storage>) -> Result<(), String> {
+	let now = Utc::now().naive_utc();
+	let query = r##"SELECT d.id, d.event, d.payload, d.attempts, w.url, w.secret
+        FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+        WHERE d.status = 'pending' AND d.next_attempt_at <= :now
+        ORDER BY d.id LIMIT :limit"##;
+	let rows = db.query(query, db_params! {"now" => now, "limit" => WEBHOOK_BATCH_SIZE}).map_err(|err| err.to_string())?;
+
+	let max_attempts = match config_get_i64("webhook_max_attempts") {
+		tmp if tmp > 0 => tmp as u32,
+		_ => WEBHOOK_MAX_ATTEMPTS as u32
+	};
+
+	let mut failed = 0;
+	for mut row in rows {
+		let (id, event, payload, attempts, url, secret) = match (row.take::<u64>("id"), row.take::<String>("event"), row.take::<String>("payload"),
+			row.take::<u32>("attempts"), row.take::<String>("url"), row.take::<String>("secret")) {
+			(Some(a), Some(b), Some(c), Some(d), Some(e), Some(f)) => (a, b, c, d + 1, e, f),
+			_ => { continue; }
+		};
+
+		let mut headers = vec![format!("X-Webhook-Event: {}", event), format!("X-Webhook-Delivery: {}", id)];
+		if secret.len() > 0 {
+			headers.push(format!("X-Webhook-Signature: sha256={}", hmac_sha256(secret.as_bytes(), payload.as_bytes())));
+		}
+
+		let error = match curl_post_json(&url, &payload, &headers, WEBHOOK_TIMEOUT_SECONDS) {
+			Ok(code) if (200..300).contains(&code) => String::from(""),
+			Ok(code) => format!("HTTP status {}", code),
+			Err(err) => err,
+		};
+
+		let status = match error.len() {
+			0 => "delivered",
+			_ if attempts >= max_attempts => "failed",
+			_ => "pending"
+		};
+		if error.len() > 0 { failed += 1; }
+
+		let next_attempt_at = Utc::now().naive_utc() + Duration::seconds(WEBHOOK_RETRY_SECONDS << (attempts - 1).min(10));
+		let error: String = error.chars().take(1000).collect();
+		let query = "UPDATE webhook_deliveries SET status=:status, attempts=:attempts, last_error=:last_error, next_attempt_at=:next_attempt_at WHERE id=:id";
+		match db.exec(query, db_params! {"id" => id, "status" => status, "attempts" => attempts, "last_error" => &error, "next_attempt_at" => next_attempt_at}) {
+			Err(err) => { println!("Could not update the webhook delivery: {:?}", err); }
+			_ => {}
+		}
+	}
+
+	if failed > 0 {
+		return Err(format!("{} webhook deliveries failed", failed));
+	}
+
+	Ok(())
+}
+
+/// HMAC-SHA256 as hex, lets receivers check the payload came from us
+fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+	// Keys longer than a block are hashed first
+	let mut block = [0u8; 64];
+	if key.len() > block.len() {
+		block[..32].copy_from_slice(&Sha256::digest(key));
+	} else {
+		block[..key.len()].copy_from_slice(key);
+	}
+
+	let mut inner = Sha256::new();
+	inner.update(block.iter().map(|byte| byte ^ 0x36).collect::<Vec<u8>>());
+	inner.update(message);
+
+	let mut outer = Sha256::new();
+	outer.update(block.iter().map(|byte| byte ^ 0x5c).collect::<Vec<u8>>());
+	outer.update(inner.finalize());
+
+	format!("{:x}", outer.finalize())
+}
@@ -1,28 +1,48 @@
+// The codebase matches on single patterns and compares lengths, clippy should not fight that
+#![allow(clippy::single_match, clippy::len_zero, clippy::comparison_to_empty, clippy::collapsible_if, clippy::collapsible_else_if, clippy::collapsible_match)]
+#![allow(clippy::match_like_matches_macro, clippy::needless_return, clippy::manual_map, clippy::new_without_default, clippy::io_other_error)]
+#![allow(clippy::ptr_arg, clippy::too_many_arguments, clippy::type_complexity, clippy::manual_unwrap_or_default, clippy::manual_unwrap_or)]
+#![allow(clippy::manual_strip, clippy::unnecessary_sort_by)]
+
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
-extern crate mysql;
-#[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate tera;
 
+#[macro_use]
+mod storage;
+
 mod app;
 mod auth;
 mod blog;
 
 #[actix_rt::main]
 async fn main() {
+	// Load and check the config, list every problem before giving up
+	app::config::config_load_from_file().unwrap();
+	match app::settings::settings_load() {
+		Err(err) => {
+			println!("{}", err);
+			std::process::exit(1);
+		}
+		_ => {}
+	}
+
 	// This is the HTTP server, all requests will be redirected to HTTPS
-	actix_rt::spawn(async move {
-		match app::start_http_server().await {
-			Err(err) => {
-				println!("HTTP server crashed: {:?}", err);
+	// In plain HTTP mode the app itself uses this port
+	if !app::config::config_get_bool("server_plain_http") {
+		actix_rt::spawn(async move {
+			match app::start_http_server().await {
+				Err(err) => {
+					println!("HTTP server crashed: {:?}", err);
+				}
+				_ => {}
 			}
-			_ => {}
-		}
-	});
+		});
+	}
 
 	// The HTTPS server
 	app::start_https_server().await.unwrap()
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+pub mod mysql_storage;
+pub mod sqlite_storage;
+
+
+/// Build named parameters for a query, e.g. `db_params! {"id" => post.id}`
+#[macro_export]
+macro_rules! db_params {
+	() => { $crate::storage::DbParams::Empty };
+	($($name:expr => $value:expr),+ $(,)?) => {
+		$crate::storage::DbParams::Named(vec![$((String::from($name), $crate::storage::DbValue::from($value))),+])
+	};
+}
+
+
+// ------------------------------
+// ---------- STORAGE -----------
+// ------------------------------
+
+/// The SQL flavour of a backend, for the few statements that differ
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+	MySql,
+	Sqlite,
+}
+
+/// Everything that reads or writes the database goes through this
+///
+/// Queries use `:name` placeholders, write them so both dialects understand them
+pub trait Storage: Send + Sync {
+	/// Run a query and return all rows
+	fn query(&self, query: &str, params: DbParams) -> Result<Vec<DbRow>, StorageError>;
+
+	/// Run a statement that does not return rows
+	fn exec(&self, query: &str, params: DbParams) -> Result<ExecResult, StorageError>;
+
+	fn dialect(&self) -> Dialect;
+
+	/// Run a query and return the first row, if any
+	fn query_first(&self, query: &str, params: DbParams) -> Result<Option<DbRow>, StorageError> {
+		Ok(self.query(query, params)?.into_iter().next())
+	}
+
+	/// The clause that turns an INSERT into an insert-or-update on the given key
+	fn upsert(&self, key: &str, columns: &[&str]) -> String {
+		match self.dialect() {
+			Dialect::MySql => {
+				let updates: Vec<String> = columns.iter().map(|col| format!("{}=VALUES({})", col, col)).collect();
+				format!("ON DUPLICATE KEY UPDATE {}", updates.join(", "))
+			}
+			Dialect::Sqlite => {
+				let updates: Vec<String> = columns.iter().map(|col| format!("{}=excluded.{}", col, col)).collect();
+				format!("ON CONFLICT({}) DO UPDATE SET {}", key, updates.join(", "))
+			}
+		}
+	}
+
+	/// A date column moved by some seconds, e.g. to group UTC timestamps by the days of another timezone
+	fn shift_seconds(&self, column: &str, seconds: i64) -> String {
+		match self.dialect() {
+			Dialect::MySql => format!("DATE_ADD({}, INTERVAL {} SECOND)", column, seconds),
+			Dialect::Sqlite => format!("DATETIME({}, '{:+} seconds')", column, seconds),
+		}
+	}
+
+	/// The INSERT that silently skips rows with an existing key
+	fn insert_ignore(&self) -> &'static str {
+		match self.dialect() {
+			Dialect::MySql => "INSERT IGNORE",
+			Dialect::Sqlite => "INSERT OR IGNORE",
+		}
+	}
+}
+
+/// Open the backend the DSN points to, `mysql://...` or `sqlite://path/to/file`
+pub fn storage_open(dsn: &str) -> Result<Arc<dyn Storage>, String> {
+	if dsn.starts_with("sqlite://") {
+		Ok(Arc::new(self::sqlite_storage::SqliteStorage::open(&dsn["sqlite://".len()..])?))
+	} else if dsn.starts_with("mysql://") {
+		Ok(Arc::new(self::mysql_storage::MySqlStorage::open(dsn)?))
+	} else {
+		Err(format!("Unknown database type in '{}'", dsn))
+	}
+}
+
+/// The database for heavy read queries, the replica if one is configured and the primary otherwise
+///
+/// A replica may lag behind a little, so never write through this
+#[derive(Clone)]
+pub struct ReadStorage(pub Arc<dyn Storage>);
+
+impl Deref for ReadStorage {
+	type Target = Arc<dyn Storage>;
+
+	fn deref(&self) -> &Arc<dyn Storage> {
+		&self.0
+	}
+}
+
+
+// ------------------------------
+// ----------- ERRORS -----------
+// ------------------------------
+
+#[derive(Debug)]
+pub enum StorageError {
+	/// A unique key already exists
+	Duplicate(String),
+	Database(String),
+}
+
+impl fmt::Display for StorageError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StorageError::Duplicate(err) => write!(f, "{}", err),
+			StorageError::Database(err) => write!(f, "{}", err),
+		}
+	}
+}
+
+/// What a statement changed
+pub struct ExecResult {
+	pub affected_rows: u64,
+	pub last_insert_id: u64,
+}
+
+
+// ------------------------------
+// ----------- VALUES -----------
+// ------------------------------
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DbValue {
+	Null,
+	Int(i64),
+	UInt(u64),
+	Float(f64),
+	Text(String),
+	Bytes(Vec<u8>),
+	DateTime(NaiveDateTime),
+}
+
+/// Query parameters, see `db_params!`
+pub enum DbParams {
+	Empty,
+	Named(Vec<(String, DbValue)>),
+	Positional(Vec<DbValue>),
+}
+
+macro_rules! db_value_from {
+	($variant:ident, $target:ty, $($source:ty),+) => {
+		$(impl From<$source> for DbValue {
+			fn from(value: $source) -> DbValue { DbValue::$variant(value as $target) }
+		})+
+	};
+}
+
+db_value_from!(Int, i64, i8, i16, i32, i64);
+db_value_from!(UInt, u64, u8, u16, u32, u64, usize);
+db_value_from!(Float, f64, f32, f64);
+
+impl From<bool> for DbValue {
+	fn from(value: bool) -> DbValue { DbValue::Int(value as i64) }
+}
+
+impl From<&str> for DbValue {
+	fn from(value: &str) -> DbValue { DbValue::Text(String::from(value)) }
+}
+
+impl From<String> for DbValue {
+	fn from(value: String) -> DbValue { DbValue::Text(value) }
+}
+
+impl From<NaiveDateTime> for DbValue {
+	fn from(value: NaiveDateTime) -> DbValue { DbValue::DateTime(value) }
+}
+
+impl<T: Into<DbValue>> From<Option<T>> for DbValue {
+	fn from(value: Option<T>) -> DbValue {
+		match value {
+			Some(tmp) => tmp.into(),
+			None => DbValue::Null,
+		}
+	}
+}
+
+impl<'a, T: Clone + Into<DbValue>> From<&'a T> for DbValue {
+	fn from(value: &'a T) -> DbValue { value.clone().into() }
+}
+
+
+/// Conversion from a column value, `None` if the types do not fit
+pub trait FromDbValue: Sized {
+	fn from_db_value(value: DbValue) -> Option<Self>;
+}
+
+macro_rules! from_db_value_number {
+	($($target:ty),+) => {
+		$(impl FromDbValue for $target {
+			fn from_db_value(value: DbValue) -> Option<$target> {
+				match value {
+					DbValue::Int(tmp) => { if tmp >= <$target>::min_value() as i64 { Some(tmp as $target) } else { None } }
+					DbValue::UInt(tmp) => { Some(tmp as $target) }
+					DbValue::Float(tmp) => { Some(tmp as $target) }
+					// Decimals and sums come back as text from MySQL
+					DbValue::Text(tmp) => { tmp.trim().parse().ok() }
+					DbValue::Bytes(tmp) => { String::from_utf8(tmp).ok()?.trim().parse().ok() }
+					_ => { None }
+				}
+			}
+		})+
+	};
+}
+
+from_db_value_number!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+macro_rules! from_db_value_float {
+	($($target:ty),+) => {
+		$(impl FromDbValue for $target {
+			fn from_db_value(value: DbValue) -> Option<$target> {
+				match value {
+					DbValue::Int(tmp) => { Some(tmp as $target) }
+					DbValue::UInt(tmp) => { Some(tmp as $target) }
+					DbValue::Float(tmp) => { Some(tmp as $target) }
+					DbValue::Text(tmp) => { tmp.trim().parse().ok() }
+					DbValue::Bytes(tmp) => { String::from_utf8(tmp).ok()?.trim().parse().ok() }
+					_ => { None }
+				}
+			}
+		})+
+	};
+}
+
+from_db_value_float!(f32, f64);
+
+impl FromDbValue for bool {
+	fn from_db_value(value: DbValue) -> Option<bool> {
+		i64::from_db_value(value).map(|tmp| tmp != 0)
+	}
+}
+
+impl FromDbValue for String {
+	fn from_db_value(value: DbValue) -> Option<String> {
+		match value {
+			DbValue::Text(tmp) => { Some(tmp) }
+			DbValue::Bytes(tmp) => { String::from_utf8(tmp).ok() }
+			DbValue::Int(tmp) => { Some(tmp.to_string()) }
+			DbValue::UInt(tmp) => { Some(tmp.to_string()) }
+			DbValue::DateTime(tmp) => { Some(tmp.format("%Y-%m-%d %H:%M:%S").to_string()) }
+			_ => { None }
+		}
+	}
+}
+
+impl FromDbValue for NaiveDateTime {
+	fn from_db_value(value: DbValue) -> Option<NaiveDateTime> {
+		match value {
+			DbValue::DateTime(tmp) => { Some(tmp) }
+			// SQLite keeps dates as text
+			DbValue::Text(tmp) => {
+				NaiveDateTime::parse_from_str(&tmp, "%Y-%m-%d %H:%M:%S%.f").ok()
+					.or_else(|| NaiveDate::parse_from_str(&tmp, "%Y-%m-%d").ok().map(|date| date.and_hms(0, 0, 0)))
+			}
+			DbValue::Bytes(tmp) => { NaiveDateTime::from_db_value(DbValue::Text(String::from_utf8(tmp).ok()?)) }
+			_ => { None }
+		}
+	}
+}
+
+impl<T: FromDbValue> FromDbValue for Option<T> {
+	fn from_db_value(value: DbValue) -> Option<Option<T>> {
+		match value {
+			DbValue::Null => { Some(None) }
+			_ => { T::from_db_value(value).map(Some) }
+		}
+	}
+}
+
+
+// ------------------------------
+// ------------ ROWS ------------
+// ------------------------------
+
+/// A single result row, columns are looked up by name
+pub struct DbRow {
+	columns: Arc<HashMap<String, usize>>,
+	values: Vec<Option<DbValue>>,
+}
+
+impl DbRow {
+	pub fn new(columns: Arc<HashMap<String, usize>>, values: Vec<DbValue>) -> DbRow {
+		DbRow { columns, values: values.into_iter().map(Some).collect() }
+	}
+
+	/// Take the value of a column, `None` if it is missing, already taken or of the wrong type
+	pub fn take<T: FromDbValue>(&mut self, column: &str) -> Option<T> {
+		let index = *self.columns.get(column)?;
+		T::from_db_value(self.values.get_mut(index)?.take()?)
+	}
+}
+
+/// Map the column names of a result to their position
+pub fn column_index<I: IntoIterator<Item=String>>(names: I) -> Arc<HashMap<String, usize>> {
+	Arc::new(names.into_iter().enumerate().map(|(index, name)| (name, index)).collect())
+}
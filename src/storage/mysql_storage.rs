@@ -0,0 +1,117 @@
+use chrono::NaiveDate;
+use mysql;
+
+use crate::storage::{column_index, DbParams, DbRow, DbValue, Dialect, ExecResult, Storage, StorageError};
+
+/// MySQL error code for a duplicate unique key
+const ER_DUP_ENTRY: u16 = 1062;
+
+
+/// The MySQL backend, a thin wrapper around the connection pool
+pub struct MySqlStorage {
+	pool: mysql::Pool,
+}
+
+impl MySqlStorage {
+	pub fn open(dsn: &str) -> Result<MySqlStorage, String> {
+		let pool = mysql::Pool::new_manual(3, 10, dsn).map_err(|err| format!("Could not connect to MySQL: {}", err))?;
+		Ok(MySqlStorage { pool })
+	}
+}
+
+impl Storage for MySqlStorage {
+	fn query(&self, query: &str, params: DbParams) -> Result<Vec<DbRow>, StorageError> {
+		let query_result = self.pool.prep_exec(query, to_mysql_params(params)).map_err(to_storage_error)?;
+
+		let mut rows = Vec::new();
+		let mut columns = None;
+
+		for result_row in query_result {
+			let row = result_row.map_err(to_storage_error)?;
+
+			// All rows share the same columns
+			let columns = columns.get_or_insert_with(|| {
+				column_index(row.columns_ref().iter().map(|col| col.name_str().into_owned()))
+			}).clone();
+
+			rows.push(DbRow::new(columns, row.unwrap().into_iter().map(from_mysql_value).collect()));
+		}
+
+		Ok(rows)
+	}
+
+	fn exec(&self, query: &str, params: DbParams) -> Result<ExecResult, StorageError> {
+		let res = self.pool.prep_exec(query, to_mysql_params(params)).map_err(to_storage_error)?;
+
+		Ok(ExecResult {
+			affected_rows: res.affected_rows(),
+			last_insert_id: res.last_insert_id(),
+		})
+	}
+
+	fn dialect(&self) -> Dialect {
+		Dialect::MySql
+	}
+}
+
+
+// ------------------------------
+// --------- CONVERSION ---------
+// ------------------------------
+
+fn to_storage_error(err: mysql::Error) -> StorageError {
+	match err {
+		mysql::Error::MySqlError(ref tmp) if tmp.code == ER_DUP_ENTRY => StorageError::Duplicate(err.to_string()),
+		_ => StorageError::Database(err.to_string()),
+	}
+}
+
+fn to_mysql_params(params: DbParams) -> mysql::Params {
+	match params {
+		DbParams::Empty => mysql::Params::Empty,
+		DbParams::Named(tmp) => {
+			mysql::Params::Named(tmp.into_iter().map(|(name, value)| (name, to_mysql_value(value))).collect())
+		}
+		DbParams::Positional(tmp) => mysql::Params::Positional(tmp.into_iter().map(to_mysql_value).collect()),
+	}
+}
+
+fn to_mysql_value(value: DbValue) -> mysql::Value {
+	match value {
+		DbValue::Null => mysql::Value::NULL,
+		DbValue::Int(tmp) => mysql::Value::from(tmp),
+		DbValue::UInt(tmp) => mysql::Value::from(tmp),
+		DbValue::Float(tmp) => mysql::Value::from(tmp),
+		DbValue::Text(tmp) => mysql::Value::from(tmp),
+		DbValue::Bytes(tmp) => mysql::Value::from(tmp),
+		DbValue::DateTime(tmp) => mysql::Value::from(tmp),
+	}
+}
+
+fn from_mysql_value(value: mysql::Value) -> DbValue {
+	match value {
+		mysql::Value::NULL => DbValue::Null,
+		mysql::Value::Bytes(tmp) => {
+			match String::from_utf8(tmp) {
+				Ok(text) => DbValue::Text(text),
+				Err(err) => DbValue::Bytes(err.into_bytes()),
+			}
+		}
+		mysql::Value::Int(tmp) => DbValue::Int(tmp),
+		mysql::Value::UInt(tmp) => DbValue::UInt(tmp),
+		mysql::Value::Date(year, month, day, hour, minute, second, micro) => {
+			// Zero dates have no chrono equivalent
+			match NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32).and_then(|date| date.and_hms_micro_opt(hour as u32, minute as u32, second as u32, micro)) {
+				Some(tmp) => DbValue::DateTime(tmp),
+				None => DbValue::Null,
+			}
+		}
+		other => {
+			// Floats, none of the tables use TIME columns
+			match mysql::from_value_opt::<f64>(other) {
+				Ok(tmp) => DbValue::Float(tmp),
+				_ => DbValue::Null,
+			}
+		}
+	}
+}
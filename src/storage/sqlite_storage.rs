@@ -0,0 +1,132 @@
+use std::sync::Mutex;
+
+use rusqlite::{Connection, ToSql};
+use rusqlite::types::{Value, ValueRef};
+
+use crate::storage::{column_index, DbParams, DbRow, DbValue, Dialect, ExecResult, Storage, StorageError};
+
+
+/// The SQLite backend for small deployments and tests
+///
+/// SQLite only allows one writer anyway, so a single connection behind a lock is enough
+pub struct SqliteStorage {
+	conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+	/// Open or create the database file, `:memory:` keeps everything in memory
+	pub fn open(path: &str) -> Result<SqliteStorage, String> {
+		let conn = Connection::open(path).map_err(|err| format!("Could not open SQLite database '{}': {}", path, err))?;
+
+		// Wait for other processes instead of failing right away, and keep readers from blocking the writer
+		conn.busy_timeout(std::time::Duration::from_secs(5)).map_err(|err| err.to_string())?;
+		conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;").map_err(|err| err.to_string())?;
+
+		Ok(SqliteStorage { conn: Mutex::new(conn) })
+	}
+}
+
+impl Storage for SqliteStorage {
+	fn query(&self, query: &str, params: DbParams) -> Result<Vec<DbRow>, StorageError> {
+		let conn = self.conn.lock().map_err(|err| StorageError::Database(err.to_string()))?;
+		let mut stmt = conn.prepare(query).map_err(to_storage_error)?;
+
+		let columns = column_index(stmt.column_names().iter().map(|name| name.to_string()));
+		let column_count = stmt.column_count();
+
+		let (named, positional) = bind_params(&stmt, params);
+		let named_refs: Vec<(&str, &dyn ToSql)> = named.iter().map(|(name, value)| (name.as_str(), value as &dyn ToSql)).collect();
+
+		let mut result = match positional {
+			Some(values) => stmt.query(values),
+			None => stmt.query_named(&named_refs),
+		}.map_err(to_storage_error)?;
+
+		let mut rows = Vec::new();
+		while let Some(row) = result.next().map_err(to_storage_error)? {
+			let mut values = Vec::with_capacity(column_count);
+			for index in 0..column_count {
+				values.push(from_sqlite_value(row.get_raw(index)));
+			}
+			rows.push(DbRow::new(columns.clone(), values));
+		}
+
+		Ok(rows)
+	}
+
+	fn exec(&self, query: &str, params: DbParams) -> Result<ExecResult, StorageError> {
+		let conn = self.conn.lock().map_err(|err| StorageError::Database(err.to_string()))?;
+		let mut stmt = conn.prepare(query).map_err(to_storage_error)?;
+
+		let (named, positional) = bind_params(&stmt, params);
+		let named_refs: Vec<(&str, &dyn ToSql)> = named.iter().map(|(name, value)| (name.as_str(), value as &dyn ToSql)).collect();
+
+		let affected_rows = match positional {
+			Some(values) => stmt.execute(values),
+			None => stmt.execute_named(&named_refs),
+		}.map_err(to_storage_error)?;
+
+		Ok(ExecResult {
+			affected_rows: affected_rows as u64,
+			last_insert_id: conn.last_insert_rowid() as u64,
+		})
+	}
+
+	fn dialect(&self) -> Dialect {
+		Dialect::Sqlite
+	}
+}
+
+
+// ------------------------------
+// --------- CONVERSION ---------
+// ------------------------------
+
+fn to_storage_error(err: rusqlite::Error) -> StorageError {
+	match err {
+		rusqlite::Error::SqliteFailure(ref tmp, _) if tmp.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY || tmp.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE => {
+			StorageError::Duplicate(err.to_string())
+		}
+		_ => StorageError::Database(err.to_string()),
+	}
+}
+
+/// Split the parameters into named ones with the `:` prefix and positional ones
+///
+/// MySQL ignores named parameters the statement does not use, SQLite refuses them, so they are dropped here
+fn bind_params(stmt: &rusqlite::Statement, params: DbParams) -> (Vec<(String, Value)>, Option<Vec<Value>>) {
+	match params {
+		DbParams::Empty => (vec![], None),
+		DbParams::Named(tmp) => {
+			let named = tmp.into_iter()
+				.map(|(name, value)| (format!(":{}", name), to_sqlite_value(value)))
+				.filter(|(name, _)| stmt.parameter_index(name).ok().flatten().is_some())
+				.collect();
+			(named, None)
+		}
+		DbParams::Positional(tmp) => (vec![], Some(tmp.into_iter().map(to_sqlite_value).collect())),
+	}
+}
+
+fn to_sqlite_value(value: DbValue) -> Value {
+	match value {
+		DbValue::Null => Value::Null,
+		DbValue::Int(tmp) => Value::Integer(tmp),
+		DbValue::UInt(tmp) => Value::Integer(tmp as i64),
+		DbValue::Float(tmp) => Value::Real(tmp),
+		DbValue::Text(tmp) => Value::Text(tmp),
+		DbValue::Bytes(tmp) => Value::Blob(tmp),
+		// Same format as CURRENT_TIMESTAMP, so dates compare as text
+		DbValue::DateTime(tmp) => Value::Text(tmp.format("%Y-%m-%d %H:%M:%S").to_string()),
+	}
+}
+
+fn from_sqlite_value(value: ValueRef) -> DbValue {
+	match value {
+		ValueRef::Null => DbValue::Null,
+		ValueRef::Integer(tmp) => DbValue::Int(tmp),
+		ValueRef::Real(tmp) => DbValue::Float(tmp),
+		ValueRef::Text(tmp) => DbValue::Text(String::from_utf8_lossy(tmp).into_owned()),
+		ValueRef::Blob(tmp) => DbValue::Bytes(tmp.to_vec()),
+	}
+}